@@ -0,0 +1,387 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! An end-to-end build of a revocation filter, to be adapted by operators of a bulk filter
+//! building service. The stages are:
+//!
+//! 1. Producer threads read revocation records (here, synthetic ones) and send them over a
+//!    bounded channel. A producer blocks when the channel is full, so a slow sort stage
+//!    throttles the producers instead of buffering without limit.
+//! 2. The sort stage sorts the records by issuer with an ItemSorter, which spills to disk
+//!    beyond MAX_ITEMS_IN_MEMORY, and writes them to a file indexed by issuer. The file
+//!    implements ItemSource, so every later stage reads one issuer at a time.
+//! 3. The build stage builds the approximate ribbons of all issuers in parallel, and then
+//!    the exact ribbons.
+//! 4. The verification stage re-queries every record, and derives a VerificationLayer for
+//!    clients.
+//! 5. The emission stage writes the clubcard, the verification layer, and a signed manifest
+//!    of their digests to the output directory. A writer thread receives the artifacts over
+//!    a bounded channel.
+//!
+//! # Usage
+//!
+//! ```bash
+//! cargo run --release --features pipeline --example pipeline -- <output directory>
+//! ```
+
+use clubcard::builder::{ApproximateRibbon, ClubcardBuilder, ExactRibbon, ItemSource};
+use clubcard::encoding::{Decode, Encode};
+use clubcard::itemsort::ItemSorter;
+use clubcard::*;
+use sha2::{Digest, Sha256, Sha512};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::sync_channel;
+use std::{env, process, thread};
+
+const W: usize = 4;
+const PRODUCERS: usize = 4;
+const ISSUERS: usize = 64;
+const SERIALS_PER_ISSUER_PER_PRODUCER: usize = 2500;
+/// Records in flight between the producers and the sort stage.
+const CHANNEL_BOUND: usize = 4096;
+/// Records held in memory by the sort stage before it spills a run to disk.
+const MAX_ITEMS_IN_MEMORY: usize = 100_000;
+const BUILD_THREADS: usize = 4;
+const VERIFICATION_BITS: u8 = 16;
+
+type Issuer = FixedBlockId<32>;
+
+/// A certificate, identified by the hash of its issuer's public key and its serial number.
+struct Record {
+    issuer: Issuer,
+    serial: Vec<u8>,
+    revoked: bool,
+}
+
+impl AsQuery<W> for Record {
+    fn as_query(&self, m: usize) -> Equation<W> {
+        let mut hasher = Sha512::new();
+        hasher.update(self.issuer);
+        hasher.update(&self.serial);
+        let mut eq = Equation::from_digest(&hasher.finalize(), m).unwrap();
+        eq.b = if self.revoked { 0 } else { 1 };
+        eq
+    }
+
+    fn block(&self) -> &[u8] {
+        self.issuer.as_ref()
+    }
+
+    fn discriminant(&self) -> &[u8] {
+        &self.serial
+    }
+}
+
+impl Filterable<W> for Record {
+    fn included(&self) -> bool {
+        self.revoked
+    }
+}
+
+impl Queryable<W> for Record {
+    type UniverseMetadata = ();
+    type PartitionMetadata = ();
+
+    fn in_universe(&self, _meta: &()) -> bool {
+        true
+    }
+}
+
+impl Encode for Record {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.issuer.0.encode(out);
+        self.serial.encode(out);
+        self.revoked.encode(out);
+    }
+}
+
+impl Decode for Record {
+    fn decode(bytes: &mut &[u8]) -> Result<Self, ClubcardError> {
+        Ok(Record {
+            issuer: FixedBlockId(<[u8; 32]>::decode(bytes)?),
+            serial: Vec::decode(bytes)?,
+            revoked: bool::decode(bytes)?,
+        })
+    }
+}
+
+/// Stage 1. Replace with a reader for the real source of records, e.g. CT logs joined with
+/// CRLs. About one certificate in 32 is revoked.
+fn produce(producer: usize, send: impl Fn(Record)) {
+    for issuer in 0..ISSUERS {
+        let issuer = FixedBlockId(Sha256::digest((issuer as u64).to_le_bytes()).into());
+        for i in 0..SERIALS_PER_ISSUER_PER_PRODUCER {
+            let serial = ((producer * SERIALS_PER_ISSUER_PER_PRODUCER + i) as u64)
+                .to_be_bytes()
+                .to_vec();
+            let revoked = Sha256::digest(&serial)[0] < 8;
+            send(Record {
+                issuer,
+                serial,
+                revoked,
+            });
+        }
+    }
+}
+
+/// Records sorted by issuer in a file, with the offset and the number of records of each
+/// issuer. Records are stored as in ItemSorter runs: a little-endian u64 length, then the
+/// encoded record.
+struct SortedFile {
+    path: PathBuf,
+    index: BTreeMap<Vec<u8>, (u64, usize)>,
+}
+
+fn read_record(reader: &mut impl Read) -> Result<Record, ClubcardError> {
+    let mut len = [0u8; 8];
+    reader.read_exact(&mut len)?;
+    let mut bytes = vec![0u8; u64::from_le_bytes(len) as usize];
+    reader.read_exact(&mut bytes)?;
+    Record::decode(&mut &bytes[..])
+}
+
+impl ItemSource<W> for SortedFile {
+    type Item = Record;
+
+    fn blocks(&self) -> Vec<Vec<u8>> {
+        self.index.keys().cloned().collect()
+    }
+
+    fn items_for_block(
+        &self,
+        block: &[u8],
+    ) -> impl Iterator<Item = Result<Record, ClubcardError>> + '_ {
+        let (offset, count) = self.index.get(block).copied().unwrap_or((0, 0));
+        let mut reader = File::open(&self.path).and_then(|mut file| {
+            file.seek(SeekFrom::Start(offset))?;
+            Ok(BufReader::new(file))
+        });
+        let mut remaining = count;
+        std::iter::from_fn(move || {
+            if remaining == 0 {
+                return None;
+            }
+            remaining -= 1;
+            match &mut reader {
+                Ok(reader) => Some(read_record(reader)),
+                Err(e) => {
+                    // Report the error once.
+                    remaining = 0;
+                    Some(Err(ClubcardError::Io(e.kind())))
+                }
+            }
+        })
+    }
+}
+
+/// Stage 2.
+fn sort(
+    records: impl IntoIterator<Item = Record>,
+    workdir: &Path,
+) -> Result<SortedFile, ClubcardError> {
+    let mut sorter = ItemSorter::<W, Record>::new(workdir, MAX_ITEMS_IN_MEMORY);
+    for record in records {
+        sorter.push(record)?;
+    }
+    let path = workdir.join("sorted.bin");
+    let mut out = BufWriter::new(File::create(&path)?);
+    let mut index: BTreeMap<Vec<u8>, (u64, usize)> = BTreeMap::new();
+    let mut offset = 0;
+    let mut bytes = vec![];
+    for record in sorter.finish()? {
+        let record = record?;
+        index
+            .entry(record.block().to_vec())
+            .or_insert((offset, 0))
+            .1 += 1;
+        bytes.clear();
+        record.encode(&mut bytes);
+        out.write_all(&(bytes.len() as u64).to_le_bytes())?;
+        out.write_all(&bytes)?;
+        offset += 8 + bytes.len() as u64;
+    }
+    out.flush()?;
+    Ok(SortedFile { path, index })
+}
+
+/// Apply `f` to every block on BUILD_THREADS threads. The results are in block order.
+fn in_parallel<R: Send>(
+    blocks: &[Vec<u8>],
+    f: impl Fn(&[u8]) -> Result<R, ClubcardError> + Sync,
+) -> Result<Vec<R>, ClubcardError> {
+    let chunk = blocks.len().div_ceil(BUILD_THREADS).max(1);
+    thread::scope(|scope| {
+        let workers: Vec<_> = blocks
+            .chunks(chunk)
+            .map(|chunk| {
+                let f = &f;
+                scope.spawn(move || chunk.iter().map(|block| f(block)).collect::<Vec<_>>())
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect()
+    })
+}
+
+/// Stage 3. The exact ribbons depend on the approximate ribbons of all blocks, so the two
+/// phases are separated by a barrier.
+fn build(source: &SortedFile) -> Result<Clubcard<W, (), ()>, ClubcardError> {
+    let blocks = source.blocks();
+    let mut clubcard_builder = ClubcardBuilder::new();
+    clubcard_builder.set_block_id::<Issuer>();
+    clubcard_builder.set_retain_members(true);
+
+    let approx_ribbons = in_parallel(&blocks, |block| {
+        let mut approx_builder = clubcard_builder.new_approx_builder(block);
+        let mut universe_size = 0;
+        for record in source.items_for_block(block) {
+            let record = record?;
+            universe_size += 1;
+            if record.included() {
+                approx_builder.insert(record);
+            }
+        }
+        approx_builder.set_universe_size(universe_size);
+        Ok(ApproximateRibbon::from(approx_builder))
+    })?;
+    clubcard_builder.collect_approx_ribbons(approx_ribbons);
+
+    let exact_ribbons = in_parallel(&blocks, |block| {
+        let mut exact_builder = clubcard_builder.new_exact_builder(block);
+        for record in source.items_for_block(block) {
+            exact_builder.insert(record?);
+        }
+        Ok(ExactRibbon::from(exact_builder))
+    })?;
+    clubcard_builder.collect_exact_ribbons(exact_ribbons)?;
+    Ok(clubcard_builder.build::<Record>((), ()))
+}
+
+/// Stage 4. Fails with ClubcardError::VerificationFailed for the first block in which the
+/// clubcard disagrees with a record.
+fn verify(
+    clubcard: &Clubcard<W, (), ()>,
+    source: &SortedFile,
+) -> Result<VerificationLayer, ClubcardError> {
+    let checked = in_parallel(&source.blocks(), |block| {
+        let records = source
+            .items_for_block(block)
+            .collect::<Result<Vec<_>, _>>()?;
+        let report = clubcard.verify(records);
+        if !report.is_ok() {
+            return Err(ClubcardError::VerificationFailed(block.to_vec()));
+        }
+        Ok(report.checked)
+    })?;
+    println!("Verified {} records", checked.iter().sum::<usize>());
+    Ok(clubcard.verification_layer(VERIFICATION_BITS)?.unwrap())
+}
+
+/// Signs the manifest of the published artifacts. Replace with the operator's signing key,
+/// e.g. a client for a hardware security module.
+trait Signer {
+    fn sign(&self, message: &[u8]) -> Vec<u8>;
+}
+
+/// A placeholder that "signs" with an unkeyed hash, so that the example runs without a key.
+/// It provides integrity against accidents only.
+struct DigestOnly;
+
+impl Signer for DigestOnly {
+    fn sign(&self, message: &[u8]) -> Vec<u8> {
+        Sha256::digest(message).to_vec()
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Stage 5.
+fn emit(
+    artifacts: Vec<(&'static str, Vec<u8>)>,
+    signer: &dyn Signer,
+    outdir: &Path,
+) -> io::Result<()> {
+    fs::create_dir_all(outdir)?;
+    let (sender, receiver) = sync_channel::<(&'static str, Vec<u8>)>(1);
+    let writer = thread::spawn({
+        let outdir = outdir.to_path_buf();
+        move || -> io::Result<()> {
+            for (name, bytes) in receiver {
+                fs::write(outdir.join(name), bytes)?;
+            }
+            Ok(())
+        }
+    });
+    let mut manifest = String::new();
+    for (name, bytes) in artifacts {
+        manifest += &format!(
+            "{} {} {}\n",
+            name,
+            bytes.len(),
+            hex(&Sha256::digest(&bytes))
+        );
+        if sender.send((name, bytes)).is_err() {
+            break;
+        }
+    }
+    let signature = signer.sign(manifest.as_bytes());
+    let _ = sender.send(("manifest.txt", manifest.into_bytes()));
+    let _ = sender.send(("manifest.sig", signature));
+    drop(sender);
+    writer.join().unwrap()
+}
+
+fn run(outdir: &Path, workdir: &Path) -> Result<(), ClubcardError> {
+    let (sender, receiver) = sync_channel(CHANNEL_BOUND);
+    let producers: Vec<_> = (0..PRODUCERS)
+        .map(|producer| {
+            let sender = sender.clone();
+            thread::spawn(move || produce(producer, |record| sender.send(record).unwrap()))
+        })
+        .collect();
+    drop(sender);
+    let source = sort(receiver, workdir)?;
+    producers
+        .into_iter()
+        .for_each(|producer| producer.join().unwrap());
+    println!("Sorted {} issuers", source.index.len());
+
+    let clubcard = build(&source)?;
+    println!("Generated {}", clubcard);
+
+    let layer = verify(&clubcard, &source)?;
+
+    emit(
+        vec![
+            ("clubcard.bin", clubcard.to_bytes()),
+            ("verification.bin", layer.to_bytes()),
+        ],
+        &DigestOnly,
+        outdir,
+    )?;
+    println!("Wrote artifacts to {}", outdir.display());
+    Ok(())
+}
+
+fn main() {
+    let Some(outdir) = env::args().nth(1) else {
+        println!("Please specify the output directory.");
+        process::exit(1);
+    };
+    let workdir = env::temp_dir().join(format!("clubcard-pipeline-{}", process::id()));
+    fs::create_dir_all(&workdir).expect("Error creating the work directory.");
+    let result = run(Path::new(&outdir), &workdir);
+    let _ = fs::remove_dir_all(&workdir);
+    if let Err(e) = result {
+        println!("Error: {}", e);
+        process::exit(1);
+    }
+}
@@ -2,28 +2,26 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
-/// Compress a set of u32 integers and output the (approximate) size of compressed bytes.
-/// # Arguments
-///
-/// * `filepath` - The input file containing integers in range [0, max(a_i)].
-/// ```txt
-/// a_1 \n
-/// ...
-/// a_n \n
-/// ```
-/// The universe size will be the maximum value of the input file.
-///
-/// # Usage
-///
-/// To run this example, use the following command:
-/// ```bash
-/// cargo run --features builder --example uint_no_partition
-/// ```
-///
+//! Compress a set of u32 integers and output the (approximate) size of compressed bytes.
+//! # Arguments
+//!
+//! * `filepath` - The input file containing integers in range [0, max(a_i)].
+//! ```txt
+//! a_1 \n
+//! ...
+//! a_n \n
+//! ```
+//! The universe size will be the maximum value of the input file.
+//!
+//! # Usage
+//!
+//! To run this example, use the following command:
+//! ```bash
+//! cargo run --features builder --example uint_no_partition
+//! ```
 
 use clubcard::*;
-use sha2::{Digest, Sha256};
-use std::cmp::max;
+use sha2::{Digest, Sha512};
 use std::collections::HashSet;
 use std::{env, fs, io, process};
 use std::io::BufRead;
@@ -53,25 +51,14 @@ impl ApproximateSizeOf for Universe {}
 
 impl AsQuery<W> for Int {
     fn as_query(&self, m: usize) -> Equation<W> {
-        let mut digest = [0u8; 32];
-        let mut hasher = Sha256::new();
-        hasher.update(&self.val);
+        let mut digest = [0u8; 64];
+        let mut hasher = Sha512::new();
+        hasher.update(self.val);
         hasher.finalize_into((&mut digest).into());
 
-        let mut a = [0u64; W]; // block of columns
-        for (i, x) in digest
-            .chunks_exact(8)
-            .map(|x| TryInto::<[u8; 8]>::try_into(x).unwrap())
-            .map(u64::from_le_bytes)
-            .enumerate()
-        {
-            a[i] = x;
-        }
-
-        a[0] |= 1;
-        let s = (a[W - 1] as usize) % max(1, m); //
-        let b = if self.included { 0 } else { 1 };
-        Equation::inhomogeneous(s, a, b)
+        let mut eq = Equation::from_digest(&digest, m).unwrap();
+        eq.b = if self.included { 0 } else { 1 };
+        eq
     }
 
     fn block(&self) -> &[u8] {
@@ -132,7 +119,7 @@ fn main() {
 
     // Build approx filter
     for num in &numbers {
-        let int = Int::new(num.clone(), true);
+        let int = Int::new(*num, true);
         approx_builder.insert(int)
     }
     approx_builder.set_universe_size(universe_size.try_into().unwrap());
@@ -141,10 +128,12 @@ fn main() {
     // Build exact filter
     let mut exact_builder = clubcard_builder.new_exact_builder(&BLOCK);
     for num in 0..universe_size {
-        let int = Int::new(num.clone(), numbers.contains(&num));
+        let int = Int::new(num, numbers.contains(&num));
         exact_builder.insert(int);
     }
-    clubcard_builder.collect_exact_ribbons(vec![ExactRibbon::from(exact_builder)]);
+    clubcard_builder
+        .collect_exact_ribbons(vec![ExactRibbon::from(exact_builder)])
+        .expect("Exact filter universe does not match approximate filter universe.");
 
     let clubcard = clubcard_builder.build::<Int>(
         Universe { bound: universe_size },
@@ -0,0 +1,325 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use crate::builder::{approx_rank, ItemSource};
+use crate::{AsQuery, ClubcardError, Filterable, DEFAULT_EPSILON};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// Statistics about the items of one block of an ItemSource. See analyze_inputs.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BlockInputStats {
+    pub block: Vec<u8>,
+    pub items: usize,
+    pub included: usize,
+    /// The number of items with the discriminant of an earlier item in the block.
+    pub duplicates: usize,
+    /// The number of items with an empty discriminant.
+    pub empty_discriminants: usize,
+    /// The Shannon entropy, in bits per byte, of the bytes of the block's discriminants. Random
+    /// discriminants approach 8. Low values point to sequential or truncated discriminants.
+    /// Zero if the discriminants are empty.
+    pub byte_entropy: f64,
+}
+
+impl BlockInputStats {
+    /// Whether the block has duplicate or empty discriminants, or discriminants with less than
+    /// `min_entropy` bits of entropy per byte.
+    pub fn is_suspicious(&self, min_entropy: f64) -> bool {
+        self.duplicates > 0
+            || self.empty_discriminants > 0
+            || (self.items > 0 && self.byte_entropy < min_entropy)
+    }
+}
+
+/// A report on the items of an ItemSource, produced by analyze_inputs before a build.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct InputReport {
+    /// One entry per block, in the order of ItemSource::blocks.
+    pub blocks: Vec<BlockInputStats>,
+    /// The number of distinct discriminants that occur in more than one block.
+    pub shared_discriminants: usize,
+}
+
+impl InputReport {
+    /// The blocks for which BlockInputStats::is_suspicious returns true.
+    pub fn suspicious_blocks(&self, min_entropy: f64) -> impl Iterator<Item = &BlockInputStats> {
+        self.blocks
+            .iter()
+            .filter(move |stats| stats.is_suspicious(min_entropy))
+    }
+}
+
+/// Read every block of `source` once and collect statistics that point to problems with the
+/// input, such as duplicate or empty discriminants, which inflate the filter or cause
+/// exceptions. Fails with the first error returned by the source.
+pub fn analyze_inputs<const W: usize, S>(source: &S) -> Result<InputReport, ClubcardError>
+where
+    S: ItemSource<W>,
+{
+    let mut report = InputReport::default();
+    // The number of blocks in which each discriminant occurs, up to 2.
+    let mut occurrences: HashMap<Vec<u8>, u8> = HashMap::new();
+    for block in source.blocks() {
+        let mut stats = BlockInputStats {
+            block: block.clone(),
+            items: 0,
+            included: 0,
+            duplicates: 0,
+            empty_discriminants: 0,
+            byte_entropy: 0.0,
+        };
+        let mut seen = HashSet::new();
+        let mut byte_counts = [0usize; 256];
+        for item in source.items_for_block(&block) {
+            let item = item?;
+            let discriminant = item.discriminant();
+            stats.items += 1;
+            stats.included += item.included() as usize;
+            stats.empty_discriminants += discriminant.is_empty() as usize;
+            for byte in discriminant {
+                byte_counts[*byte as usize] += 1;
+            }
+            if !seen.insert(discriminant.to_vec()) {
+                stats.duplicates += 1;
+            }
+        }
+        for discriminant in seen {
+            let count = occurrences.entry(discriminant).or_insert(0);
+            *count = (*count + 1).min(2);
+        }
+        let total = byte_counts.iter().sum::<usize>() as f64;
+        stats.byte_entropy = byte_counts
+            .iter()
+            .filter(|count| **count > 0)
+            .map(|count| {
+                let p = *count as f64 / total;
+                -p * p.log2()
+            })
+            .sum::<f64>()
+            .max(0.0);
+        report.blocks.push(stats);
+    }
+    report.shared_discriminants = occurrences.values().filter(|count| **count > 1).count();
+    Ok(report)
+}
+
+/// The number of bytes of an index entry without exceptions or an input digest, not counting
+/// the block identifier.
+const INDEX_ENTRY_BYTES: usize = 5 * 8 + 1 + 8 + 1;
+
+/// The size of one block of a clubcard. See recommend_coalescing.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockCounts {
+    pub block: Vec<u8>,
+    pub universe: usize,
+    pub members: usize,
+}
+
+/// The estimated number of bits that a block adds to a clubcard built with DEFAULT_EPSILON,
+/// including its index entry. Returns None if `members` exceeds `universe`.
+pub fn estimated_block_bits(block_len: usize, universe: usize, members: usize) -> Option<f64> {
+    let index_bits = 8 * (INDEX_ENTRY_BYTES + 8 + block_len);
+    let r = members.min(universe.checked_sub(members)?);
+    if r == 0 {
+        return Some(index_bits as f64);
+    }
+    let rank = approx_rank(r, universe);
+    let approx_bits = (r * rank) as f64;
+    let exact_bits = r as f64 + (universe - r) as f64 * 0.5f64.powi(rank as i32);
+    Some((1.0 + DEFAULT_EPSILON) * (approx_bits + exact_bits) + index_bits as f64)
+}
+
+/// A recommendation from recommend_coalescing.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CoalescingReport {
+    /// Blocks with a universe smaller than this are coalesced. Zero if coalescing does not
+    /// help.
+    pub threshold: usize,
+    /// The groups of at least two blocks that should share a block.
+    pub groups: Vec<Vec<Vec<u8>>>,
+    /// The estimated size, in bits, of the blocks as they are.
+    pub bits_before: f64,
+    /// The estimated size, in bits, of the blocks after coalescing.
+    pub bits_after: f64,
+}
+
+/// Group the blocks whose universe is smaller than `threshold`, in order of member density,
+/// into groups whose universe reaches `threshold`. A remainder that does not reach it joins the
+/// last group. Returns the groups and their estimated size in bits. No block may have more
+/// members than its universe.
+fn coalesce(blocks: &[BlockCounts], threshold: usize) -> (Vec<Vec<&BlockCounts>>, f64) {
+    let mut small: Vec<&BlockCounts> = blocks.iter().filter(|b| b.universe < threshold).collect();
+    small.sort_by(|a, b| {
+        let density = |x: &BlockCounts| x.members as f64 / x.universe.max(1) as f64;
+        density(a).total_cmp(&density(b))
+    });
+    let mut groups: Vec<Vec<&BlockCounts>> = vec![];
+    let mut open: Vec<&BlockCounts> = vec![];
+    for block in small {
+        open.push(block);
+        if open.iter().map(|b| b.universe).sum::<usize>() >= threshold {
+            groups.push(std::mem::take(&mut open));
+        }
+    }
+    match groups.last_mut() {
+        Some(last) => last.extend(open),
+        None if !open.is_empty() => groups.push(open),
+        None => (),
+    }
+    let mut bits = 0.0;
+    for block in blocks.iter().filter(|b| b.universe >= threshold) {
+        bits += estimated_block_bits(block.block.len(), block.universe, block.members).unwrap();
+    }
+    for group in &groups {
+        bits += estimated_block_bits(
+            group.iter().map(|b| b.block.len()).max().unwrap_or(0),
+            group.iter().map(|b| b.universe).sum(),
+            group.iter().map(|b| b.members).sum(),
+        )
+        .unwrap();
+    }
+    (groups, bits)
+}
+
+/// Recommend which blocks to coalesce into shared blocks, e.g. the blocks of issuers with few
+/// certificates, to minimize the estimated size of the clubcard. Small blocks are dominated by
+/// their index entries and by the rounding of their rank. Every universe size that occurs is
+/// tried as the threshold. Fails with ClubcardError::InvalidBlock if a block has more members
+/// than its universe.
+pub fn recommend_coalescing(blocks: &[BlockCounts]) -> Result<CoalescingReport, ClubcardError> {
+    if let Some(block) = blocks.iter().find(|b| b.members > b.universe) {
+        return Err(ClubcardError::InvalidBlock(block.block.clone()));
+    }
+    let (_, bits_before) = coalesce(blocks, 0);
+    let mut report = CoalescingReport {
+        threshold: 0,
+        groups: vec![],
+        bits_before,
+        bits_after: bits_before,
+    };
+    let mut thresholds: Vec<usize> = blocks.iter().map(|b| b.universe + 1).collect();
+    thresholds.sort_unstable();
+    thresholds.dedup();
+    for threshold in thresholds {
+        let (groups, bits) = coalesce(blocks, threshold);
+        if bits < report.bits_after {
+            report.threshold = threshold;
+            report.bits_after = bits;
+            report.groups = groups
+                .iter()
+                .filter(|group| group.len() > 1)
+                .map(|group| group.iter().map(|b| b.block.clone()).collect())
+                .collect();
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_vectors::{generated, BLOCKS};
+    use crate::testing::oracle::random_universe;
+    use crate::Membership;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_estimated_block_bits() {
+        assert!(estimated_block_bits(0, 10, 0) == estimated_block_bits(0, 10, 10));
+        assert!(estimated_block_bits(0, 1 << 20, 1 << 10).unwrap() > (1 << 10) as f64 * 11.0);
+        assert!(estimated_block_bits(0, 10, 11).is_none());
+
+        for seed in 0..4 {
+            let (clubcard, queries) = generated(seed);
+            for (block, (universe_size, _)) in BLOCKS.iter().enumerate() {
+                let block = [block as u8];
+                let members = queries
+                    .iter()
+                    .filter(|(query, expected)| {
+                        query.block == block && *expected == Membership::Member
+                    })
+                    .count();
+                let stats = clubcard.block_stats(&block).unwrap();
+                let actual = (stats.bits() + 8 * (INDEX_ENTRY_BYTES + 8 + 1)) as f64;
+                let estimate = estimated_block_bits(1, *universe_size as usize, members).unwrap();
+                assert!(
+                    (actual - estimate).abs() * 4.0 <= actual,
+                    "{actual} {estimate}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_recommend_coalescing() {
+        let large = |i: u8, members: usize| BlockCounts {
+            block: vec![i],
+            universe: 1 << 20,
+            members,
+        };
+        // Coalescing blocks of different densities costs more than the index entry it saves.
+        let report = recommend_coalescing(&[large(0, 1 << 10), large(1, 1 << 15)]).unwrap();
+        assert!(report.threshold == 0 && report.groups.is_empty());
+        assert!(report.bits_before == report.bits_after);
+
+        let mut blocks: Vec<BlockCounts> = (0..100)
+            .map(|i| BlockCounts {
+                block: vec![i; 32],
+                universe: 20,
+                members: 1,
+            })
+            .collect();
+        blocks.push(large(0, 1 << 10));
+        let report = recommend_coalescing(&blocks).unwrap();
+        assert!(report.threshold > 20 && report.threshold <= (1 << 20) + 1);
+        assert!(report.bits_after < report.bits_before / 2.0);
+        let grouped: usize = report.groups.iter().map(|group| group.len()).sum();
+        assert!(grouped >= 100);
+        assert!(recommend_coalescing(&[]).unwrap().groups.is_empty());
+        assert!(
+            recommend_coalescing(&[large(0, (1 << 20) + 1)]).err()
+                == Some(ClubcardError::InvalidBlock(vec![0]))
+        );
+    }
+
+    #[test]
+    fn test_analyze_inputs() {
+        let mut source = BTreeMap::new();
+        for item in random_universe::<1>(0, &[1000, 1000], 0.5) {
+            source
+                .entry(item.block.clone())
+                .or_insert_with(Vec::new)
+                .push(item);
+        }
+        let blocks: Vec<Vec<u8>> = source.keys().cloned().collect();
+
+        // The discriminants of random_universe are sequential u64s, so both blocks have low
+        // entropy and share every discriminant.
+        let report = analyze_inputs(&source).unwrap();
+        assert!(report.shared_discriminants == 1000);
+        assert!(report.blocks.iter().all(|stats| stats.items == 1000));
+        assert!(report.blocks.iter().all(|stats| stats.byte_entropy < 3.0));
+        assert!(report.suspicious_blocks(4.0).count() == 2);
+        assert!(report.suspicious_blocks(2.0).count() == 0);
+
+        let first = source.get_mut(&blocks[0]).unwrap();
+        for (i, item) in first.iter_mut().enumerate() {
+            item.discriminant = (i as u64)
+                .wrapping_mul(0x9e3779b97f4a7c15)
+                .to_le_bytes()
+                .to_vec();
+        }
+        first[1].discriminant = first[0].discriminant.clone();
+        first[2].discriminant = vec![];
+        let included = first.iter().filter(|item| item.included).count();
+        let report = analyze_inputs(&source).unwrap();
+        let stats = &report.blocks[0];
+        assert!(stats.duplicates == 1 && stats.empty_discriminants == 1);
+        assert!(stats.byte_entropy > 7.0);
+        assert!(stats.included == included);
+        assert!(report.shared_discriminants < 10);
+        assert!(report.suspicious_blocks(1.0).count() == 1);
+    }
+}
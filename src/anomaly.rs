@@ -0,0 +1,161 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use crate::{Clubcard, ClubcardError, Membership, Queryable};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+type AnomalyCallback = Box<dyn Fn(&AnomalyReport) + Send + Sync>;
+
+/// A burst of queries in one block that the clubcard could not answer, reported by an
+/// AnomalyMonitor. Many NotInUniverse results for one block usually mean that the clubcard is
+/// stale or that the caller is deriving items incorrectly. Many NoData results mean that the
+/// block is missing from the clubcard.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AnomalyReport {
+    pub block: Vec<u8>,
+    /// Either Membership::NotInUniverse or Membership::NoData.
+    pub result: Membership,
+    /// The number of queries in the block that returned `result` in the current window.
+    pub count: usize,
+    /// The number of queries, in all blocks, in the current window.
+    pub queries: usize,
+}
+
+struct Window {
+    queries: usize,
+    /// Counts of (block, result is NoData), and whether the count has been reported.
+    counts: HashMap<(Vec<u8>, bool), (usize, bool)>,
+}
+
+/// Counts the query results that are neither Member nor Nonmember, per block, over windows of
+/// a fixed number of queries. The callback is invoked once per window for each block and
+/// result whose count reaches the threshold.
+pub struct AnomalyMonitor {
+    threshold: usize,
+    window_size: usize,
+    callback: AnomalyCallback,
+    window: Mutex<Window>,
+}
+
+impl AnomalyMonitor {
+    /// Fails with ClubcardError::InvalidArgument unless 0 < threshold <= window_size.
+    pub fn new(
+        threshold: usize,
+        window_size: usize,
+        callback: impl Fn(&AnomalyReport) + Send + Sync + 'static,
+    ) -> Result<Self, ClubcardError> {
+        if threshold == 0 || threshold > window_size {
+            return Err(ClubcardError::InvalidArgument(
+                "the threshold must be positive and at most the window size",
+            ));
+        }
+        Ok(AnomalyMonitor {
+            threshold,
+            window_size,
+            callback: Box::new(callback),
+            window: Mutex::new(Window {
+                queries: 0,
+                counts: HashMap::new(),
+            }),
+        })
+    }
+
+    /// Record the result of a query in `block`, and return it.
+    pub fn observe(&self, block: &[u8], result: Membership) -> Membership {
+        let report = {
+            let mut window = self.window.lock().unwrap();
+            if window.queries == self.window_size {
+                window.queries = 0;
+                window.counts.clear();
+            }
+            window.queries += 1;
+            let no_data = match result {
+                Membership::NotInUniverse => false,
+                Membership::NoData => true,
+                _ => return result,
+            };
+            let queries = window.queries;
+            let (count, reported) = window
+                .counts
+                .entry((block.to_vec(), no_data))
+                .or_insert((0, false));
+            *count += 1;
+            if *reported || *count < self.threshold {
+                return result;
+            }
+            *reported = true;
+            AnomalyReport {
+                block: block.to_vec(),
+                result,
+                count: *count,
+                queries,
+            }
+        };
+        // The lock is released so that the callback may query through this monitor.
+        (self.callback)(&report);
+        result
+    }
+}
+
+impl<const W: usize, UniverseMetadata, PartitionMetadata>
+    Clubcard<W, UniverseMetadata, PartitionMetadata>
+{
+    /// Same as contains, but records the result in `monitor`.
+    pub fn contains_monitored<T>(&self, item: &T, monitor: &AnomalyMonitor) -> Membership
+    where
+        T: Queryable<W, UniverseMetadata = UniverseMetadata, PartitionMetadata = PartitionMetadata>,
+    {
+        monitor.observe(item.block(), self.contains(item))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_anomaly_monitor() {
+        let reports = Arc::new(Mutex::new(vec![]));
+        let sink = reports.clone();
+        let monitor = AnomalyMonitor::new(2, 4, move |report: &AnomalyReport| {
+            sink.lock().unwrap().push(report.clone())
+        })
+        .unwrap();
+
+        assert!(monitor.observe(b"a", Membership::NotInUniverse) == Membership::NotInUniverse);
+        monitor.observe(b"b", Membership::NotInUniverse);
+        monitor.observe(b"a", Membership::Member);
+        assert!(reports.lock().unwrap().is_empty());
+        monitor.observe(b"a", Membership::NotInUniverse);
+        assert!(
+            *reports.lock().unwrap()
+                == vec![AnomalyReport {
+                    block: b"a".to_vec(),
+                    result: Membership::NotInUniverse,
+                    count: 2,
+                    queries: 4,
+                }]
+        );
+
+        // A new window. NoData is counted separately, and each count is reported once.
+        for _ in 0..3 {
+            monitor.observe(b"a", Membership::NoData);
+        }
+        monitor.observe(b"a", Membership::NotInUniverse);
+        let reports = reports.lock().unwrap();
+        assert!(reports.len() == 2);
+        assert!(reports[1].result == Membership::NoData);
+        assert!(reports[1].count == 2);
+        assert!(reports[1].queries == 2);
+
+        for (threshold, window_size) in [(0, 4), (5, 4), (0, 0)] {
+            assert!(matches!(
+                AnomalyMonitor::new(threshold, window_size, |_: &AnomalyReport| ()),
+                Err(ClubcardError::InvalidArgument(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,78 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use crate::{Clubcard, Filterable};
+use sha2::{Digest, Sha256};
+
+/// The digest that a ClubcardBuilder in audit mode records for a block whose universe is
+/// `items` (see ClubcardBuilder::set_audit). This is the SHA-256 hash of the discriminant and
+/// the included() bit of each item, with the items sorted, so it does not depend on the order
+/// in which the items were passed to the builder.
+pub fn input_digest<'a, const W: usize, T: Filterable<W> + 'a>(
+    items: impl IntoIterator<Item = &'a T>,
+) -> [u8; 32] {
+    digest_inputs(
+        items
+            .into_iter()
+            .map(|item| (item.discriminant().to_vec(), item.included()))
+            .collect(),
+    )
+}
+
+/// The digest of a list of (discriminant, included) pairs. See input_digest.
+pub(crate) fn digest_inputs(mut inputs: Vec<(Vec<u8>, bool)>) -> [u8; 32] {
+    inputs.sort_unstable();
+    let mut hasher = Sha256::new();
+    for (discriminant, included) in &inputs {
+        hasher.update((discriminant.len() as u64).to_le_bytes());
+        hasher.update(discriminant);
+        hasher.update([*included as u8]);
+    }
+    hasher.finalize().into()
+}
+
+impl<const W: usize, UniverseMetadata, PartitionMetadata>
+    Clubcard<W, UniverseMetadata, PartitionMetadata>
+{
+    /// Whether `block` was built from the universe `items`, according to the digest that the
+    /// builder recorded in audit mode. The items must be the ones that were passed to the
+    /// block's exact ribbon builder, e.g. all of the certificates that the block covers, with
+    /// their revocation status. Returns None if the block is not in the clubcard or if no
+    /// digest was recorded for it.
+    pub fn attest_block<'a, T: Filterable<W> + 'a>(
+        &self,
+        block: &[u8],
+        items: impl IntoIterator<Item = &'a T>,
+    ) -> Option<bool> {
+        let expected = self.index.get(block)?.input_digest?;
+        Some(input_digest(items) == expected)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::tests::{build_from_source, test_items};
+    use crate::builder::ClubcardBuilder;
+
+    #[test]
+    fn test_audit() {
+        let items = test_items(128);
+        let clubcard = build_from_source(ClubcardBuilder::new(), &items);
+        assert!(clubcard.attest_block(&[], &items).is_none());
+
+        let mut clubcard_builder = ClubcardBuilder::new();
+        clubcard_builder.set_audit(true);
+        let clubcard = build_from_source(clubcard_builder, &items);
+        let clubcard = Clubcard::<1, (), ()>::from_bytes(&clubcard.to_bytes()).unwrap();
+        assert!(clubcard.attest_block(&[], &items) == Some(true));
+        assert!(clubcard.attest_block(&[], items.iter().rev()) == Some(true));
+        assert!(clubcard.attest_block(&[], &items[1..]) == Some(false));
+        assert!(clubcard.attest_block(&[0], &items).is_none());
+
+        let mut changed = items.clone();
+        changed[0].b ^= 1;
+        assert!(clubcard.attest_block(&[], &changed) == Some(false));
+    }
+}
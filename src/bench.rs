@@ -0,0 +1,75 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Query latency measurements that can run in any test harness, e.g. to check a latency budget
+//! against a production clubcard in an integration test.
+
+use crate::{Clubcard, Queryable};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+/// The distribution of the latency of individual Clubcard::contains calls.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LatencyStats {
+    /// The number of timed queries.
+    pub samples: usize,
+    pub min: Duration,
+    pub median: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+    pub mean: Duration,
+}
+
+/// Time each of `queries` against `clubcard`, `iterations` times over. Each call is timed
+/// separately, so the results include the overhead of reading the clock. Returns the default
+/// (all zero) statistics if there is nothing to time.
+pub fn measure_query_latency<const W: usize, UniverseMetadata, PartitionMetadata, T>(
+    clubcard: &Clubcard<W, UniverseMetadata, PartitionMetadata>,
+    queries: &[T],
+    iterations: usize,
+) -> LatencyStats
+where
+    T: Queryable<W, UniverseMetadata = UniverseMetadata, PartitionMetadata = PartitionMetadata>,
+{
+    let mut times = Vec::with_capacity(queries.len() * iterations);
+    for _ in 0..iterations {
+        for query in queries {
+            let start = Instant::now();
+            black_box(clubcard.contains(black_box(query)));
+            times.push(start.elapsed());
+        }
+    }
+    if times.is_empty() {
+        return LatencyStats::default();
+    }
+    times.sort_unstable();
+    let quantile = |q: f64| times[((times.len() - 1) as f64 * q).round() as usize];
+    LatencyStats {
+        samples: times.len(),
+        min: times[0],
+        median: quantile(0.5),
+        p99: quantile(0.99),
+        max: times[times.len() - 1],
+        mean: Duration::from_nanos(
+            (times.iter().map(Duration::as_nanos).sum::<u128>() / times.len() as u128) as u64,
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_vectors::generated;
+
+    #[test]
+    fn test_measure_query_latency() {
+        let (clubcard, queries) = generated(0);
+        let (items, _): (Vec<_>, Vec<_>) = queries.into_iter().unzip();
+        let stats = measure_query_latency(&clubcard, &items, 3);
+        assert!(stats.samples == 3 * items.len());
+        assert!(stats.min <= stats.median && stats.median <= stats.p99 && stats.p99 <= stats.max);
+        assert!(stats.min <= stats.mean && stats.mean <= stats.max);
+        assert!(measure_query_latency(&clubcard, &items, 0) == LatencyStats::default());
+    }
+}
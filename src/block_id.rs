@@ -0,0 +1,87 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use crate::ClubcardError;
+use std::fmt;
+
+/// A block identifier with a fixed convention, e.g. a 32-byte issuer hash. Clubcards key blocks
+/// by arbitrary byte strings, so an identifier with the wrong encoding silently lands in a
+/// block of its own. Give the convention to ClubcardBuilder::set_block_id to reject such
+/// blocks at build time, and parse identifiers with from_block before querying.
+pub trait BlockId: AsRef<[u8]> + Sized {
+    /// Fails with ClubcardError::MalformedBlockId if `block` does not follow the convention.
+    fn from_block(block: &[u8]) -> Result<Self, ClubcardError>;
+
+    fn is_valid(block: &[u8]) -> bool {
+        Self::from_block(block).is_ok()
+    }
+}
+
+/// A block identifier of exactly N bytes, e.g. FixedBlockId<32> for the SHA-256 hash of an
+/// issuer's subject public key info.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FixedBlockId<const N: usize>(pub [u8; N]);
+
+impl<const N: usize> fmt::Debug for FixedBlockId<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "FixedBlockId({:?})", &self.0[..])
+    }
+}
+
+impl<const N: usize> AsRef<[u8]> for FixedBlockId<N> {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl<const N: usize> From<[u8; N]> for FixedBlockId<N> {
+    fn from(bytes: [u8; N]) -> Self {
+        FixedBlockId(bytes)
+    }
+}
+
+impl<const N: usize> TryFrom<&[u8]> for FixedBlockId<N> {
+    type Error = ClubcardError;
+
+    fn try_from(block: &[u8]) -> Result<Self, ClubcardError> {
+        Self::from_block(block)
+    }
+}
+
+impl<const N: usize> BlockId for FixedBlockId<N> {
+    fn from_block(block: &[u8]) -> Result<Self, ClubcardError> {
+        block
+            .try_into()
+            .map(FixedBlockId)
+            .map_err(|_| ClubcardError::MalformedBlockId(vec![block.to_vec()]))
+    }
+}
+
+/// Any byte string, i.e. no convention.
+impl BlockId for Vec<u8> {
+    fn from_block(block: &[u8]) -> Result<Self, ClubcardError> {
+        Ok(block.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixed_block_id() {
+        let id = FixedBlockId::<32>::from_block(&[7; 32]).unwrap();
+        assert!(id == FixedBlockId([7; 32]));
+        assert!(id.as_ref() == [7; 32]);
+        assert!(FixedBlockId::<32>::try_from(&[7u8; 32][..]) == Ok(id));
+        for len in [0, 31, 33] {
+            assert!(!FixedBlockId::<32>::is_valid(&vec![7; len]));
+            assert!(
+                FixedBlockId::<32>::from_block(&vec![7; len])
+                    == Err(ClubcardError::MalformedBlockId(vec![vec![7; len]]))
+            );
+        }
+        assert!(<Vec<u8>>::is_valid(&[]));
+    }
+}
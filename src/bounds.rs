@@ -0,0 +1,67 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Information-theoretic bounds on the size of a clubcard.
+
+use crate::Clubcard;
+use std::f64::consts::LOG2_E;
+
+/// The approximate number of bits that any encoding of a subset of size `members` of a universe
+/// of size `universe` needs, R log2(U / R) + R log2(e), where R is the smaller of `members` and
+/// `universe - members`, since a clubcard can encode the complement of its members. Returns
+/// None if `members` exceeds `universe`.
+pub fn min_bits(universe: usize, members: usize) -> Option<f64> {
+    let r = members.min(universe.checked_sub(members)?) as f64;
+    if r == 0.0 {
+        return Some(0.0);
+    }
+    Some(r * (universe as f64 / r).log2() + r * LOG2_E)
+}
+
+impl<const W: usize, UniverseMetadata, PartitionMetadata>
+    Clubcard<W, UniverseMetadata, PartitionMetadata>
+{
+    /// The ratio of the number of solution bits in this clubcard (see BlockStats::bits) to
+    /// min_bits(universe, members), where `universe` and `members` are the totals over all
+    /// blocks. Returns None if the bound is zero, or if `members` exceeds `universe`.
+    pub fn efficiency(&self, universe: usize, members: usize) -> Option<f64> {
+        let bound = min_bits(universe, members)?;
+        if bound == 0.0 {
+            return None;
+        }
+        let bits: usize = self.blocks().map(|entry| entry.stats().bits()).sum();
+        Some(bits as f64 / bound)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::tests::{build_single_block, test_items};
+    use crate::Filterable;
+
+    #[test]
+    fn test_min_bits() {
+        assert!(min_bits(0, 0) == Some(0.0));
+        assert!(min_bits(100, 0) == Some(0.0));
+        assert!(min_bits(100, 100) == Some(0.0));
+        assert!(min_bits(100, 10) == min_bits(100, 90));
+        assert!(min_bits(100, 101).is_none());
+        let bits = min_bits(1 << 20, 1 << 10).unwrap();
+        assert!((bits - 1024.0 * (10.0 + LOG2_E)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_efficiency() {
+        let n = 1024;
+        let items = test_items(n);
+        let members = items.iter().filter(|x| x.included()).count();
+
+        let clubcard = build_single_block(&items);
+        let efficiency = clubcard.efficiency(n, members).unwrap();
+        assert!(efficiency > 1.0 && efficiency < 1.5);
+        assert!(clubcard.efficiency(n, 0).is_none());
+        assert!(clubcard.efficiency(n, n + 1).is_none());
+    }
+}
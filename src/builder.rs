@@ -2,12 +2,21 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
+pub use crate::config::{BuildConfig, DEFAULT_EPSILON, LABEL_ANNOTATION};
 use crate::{
-    clubcard::ClubcardIndex, Clubcard, ClubcardIndexEntry, Equation, Filterable, Queryable,
+    clubcard::{block_checksum, ClubcardIndex},
+    encoding::Encode,
+    BlockId, Clubcard, ClubcardError, ClubcardIndexEntry, Equation, Filterable, MembersSidecar,
+    Queryable, MAX_BLOCKS,
 };
-use rand::{thread_rng, Rng};
-use std::collections::BTreeMap;
+use rand::{thread_rng, Rng, RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use serde::{Deserialize, Serialize};
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::fmt;
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
 
 /// Marker type for checking that, for example, only Exact ribbons are passed to functions such as
 /// Clubcard::collect_exact_ribbons.
@@ -35,9 +44,34 @@ pub struct RibbonBuilder<'a, const W: usize, T: Filterable<W>> {
     filter: Option<&'a PartitionedRibbonFilter<W, T, Approximate>>,
     /// size of the universe that contains self.items
     universe_size: usize,
+    /// number of calls to insert, including items rejected by the filter.
+    inserted: usize,
     /// Whether queries against this ribbon indicate membership in R (inverted = false) or
     /// membership in U \ R (inverted = true).
     inverted: bool,
+    /// Whether to drop items with duplicate discriminants.
+    dedup: bool,
+    /// The maximum discriminant length accepted by an exact ribbon builder.
+    max_discriminant_len: Option<usize>,
+    /// Discriminants of items that were set aside because they exceeded max_discriminant_len.
+    oversized: Vec<Vec<u8>>,
+    /// Space overhead of the ribbon.
+    epsilon: f64,
+    /// Whether an exact ribbon should retain the discriminants of its members.
+    retain_members: bool,
+    /// The discriminant and included() bit of every inserted item, if the builder is in audit
+    /// mode.
+    audit: Option<Vec<(Vec<u8>, bool)>>,
+    /// Approximate ribbons for universes of at most this size get rank 0.
+    single_layer_threshold: Option<usize>,
+    /// Whether the approximate ribbon gets rank 0 regardless of the size of its universe.
+    single_layer: bool,
+    /// Whether the ribbon measures the time spent hashing items.
+    instrumented: bool,
+    /// Returns false for items that are not in the declared universe.
+    universe_check: Option<&'a UniverseCheck<T>>,
+    /// Discriminants of included items that failed universe_check.
+    uncovered: Vec<Vec<u8>>,
 }
 
 impl<'a, const W: usize, T: Filterable<W>> RibbonBuilder<'a, W, T> {
@@ -50,12 +84,65 @@ impl<'a, const W: usize, T: Filterable<W>> RibbonBuilder<'a, W, T> {
             items: vec![],
             filter,
             universe_size: 0,
+            inserted: 0,
             inverted: false,
+            dedup: false,
+            max_discriminant_len: None,
+            oversized: vec![],
+            epsilon: DEFAULT_EPSILON,
+            retain_members: false,
+            audit: None,
+            single_layer_threshold: None,
+            single_layer: false,
+            instrumented: false,
+            universe_check: None,
+            uncovered: vec![],
+        }
+    }
+
+    fn with_config(mut self, config: &BuildConfig) -> Self {
+        self.dedup = config.dedup;
+        self.epsilon = config.epsilon;
+        if self.filter.is_some() {
+            self.max_discriminant_len = config.max_discriminant_len;
+        }
+        self
+    }
+
+    fn with_capacity(mut self, capacity: usize) -> Self {
+        self.items.reserve_exact(capacity);
+        self
+    }
+
+    /// Discard all queued items and the universe size, keeping the allocated capacity so that
+    /// the builder can be refilled without reallocating.
+    pub fn clear(&mut self) {
+        self.items.clear();
+        self.universe_size = 0;
+        self.inserted = 0;
+        self.oversized.clear();
+        self.uncovered.clear();
+        if let Some(audit) = &mut self.audit {
+            audit.clear();
         }
     }
 
     /// Queue `item` for insertion into the ribbon (if it is contained in the provided filter).
     pub fn insert(&mut self, item: T) {
+        self.inserted += 1;
+        if let Some(audit) = &mut self.audit {
+            audit.push((item.discriminant().to_vec(), item.included()));
+        }
+        if item.included() && self.universe_check.is_some_and(|check| !check(&item)) {
+            self.uncovered.push(item.discriminant().to_vec());
+        }
+        if self
+            .max_discriminant_len
+            .is_some_and(|max| item.discriminant().len() > max)
+        {
+            self.oversized.push(item.discriminant().to_vec());
+            return;
+        }
         if let Some(filter) = self.filter {
             if filter.contains(&item) {
                 self.items.push(item);
@@ -70,6 +157,24 @@ impl<'a, const W: usize, T: Filterable<W>> RibbonBuilder<'a, W, T> {
     pub fn set_universe_size(&mut self, universe_size: usize) {
         self.universe_size = universe_size;
     }
+
+    /// If deduplication is enabled, remove the queued items that share a discriminant with an
+    /// earlier item and return their number. Otherwise return 0 without looking at the items.
+    fn remove_duplicates(&mut self) -> usize {
+        if !self.dedup {
+            return 0;
+        }
+        let mut seen = HashSet::with_capacity(self.items.len());
+        let keep: Vec<bool> = self
+            .items
+            .iter()
+            .map(|item| seen.insert(item.discriminant()))
+            .collect();
+        let mut keep = keep.into_iter();
+        let before = self.items.len();
+        self.items.retain(|_| keep.next().unwrap());
+        before - self.items.len()
+    }
 }
 
 impl<'a, const W: usize, T: Filterable<W>> From<RibbonBuilder<'a, W, T>>
@@ -82,23 +187,44 @@ impl<'a, const W: usize, T: Filterable<W>> From<RibbonBuilder<'a, W, T>>
     /// false positive rate roughly 2^-r = |R| / (|U| - |R|).
     /// The size of this ribbon is proportional to r|R|.
     fn from(mut builder: RibbonBuilder<'a, W, T>) -> ApproximateRibbon<W, T> {
+        let start = Instant::now();
+        let duplicates = builder.remove_duplicates();
         assert!(builder.items.len() <= builder.universe_size);
-        if builder.items.len() == builder.universe_size {
-            ApproximateRibbon::new(&builder.id, 0, builder.universe_size, !builder.inverted)
+        let mut out = if builder.items.len() == builder.universe_size {
+            ApproximateRibbon::new(
+                &builder.id,
+                0,
+                builder.universe_size,
+                !builder.inverted,
+                builder.epsilon,
+            )
         } else {
             let mut out = ApproximateRibbon::new(
                 &builder.id,
                 builder.items.len(),
                 builder.universe_size,
                 builder.inverted,
+                builder.epsilon,
             );
+            if builder.single_layer
+                || builder
+                    .single_layer_threshold
+                    .is_some_and(|max| builder.universe_size <= max)
+            {
+                out.rank = 0;
+            }
+            out.instrumented = builder.instrumented;
             for item in builder.items.drain(..) {
                 out.insert(item);
             }
             // Insertions should not fail for a homogeneous system.
             assert!(out.exceptions.is_empty());
             out
-        }
+        };
+        out.duplicates = duplicates;
+        out.universe_size = builder.universe_size;
+        out.insert_time = start.elapsed();
+        out
     }
 }
 
@@ -109,15 +235,54 @@ impl<'a, const W: usize, T: Filterable<W>> From<RibbonBuilder<'a, W, T>> for Exa
     /// result of filtering a larger universe with a false positive rate of 2^-r. This allows for
     /// exact encoding of R-membership using a pair of filters of total size ~(r+2)|R|.
     fn from(mut builder: RibbonBuilder<'a, W, T>) -> ExactRibbon<W, T> {
+        let start = Instant::now();
+        let duplicates = builder.remove_duplicates();
         assert!(builder.universe_size == 0 || builder.universe_size == builder.items.len());
+        let members = builder.retain_members.then(|| {
+            builder
+                .items
+                .iter()
+                .filter(|item| item.included())
+                .map(|item| item.discriminant().to_vec())
+                .collect()
+        });
+        #[cfg(feature = "sha2")]
+        let input_digest = builder.audit.take().map(crate::audit::digest_inputs);
+        #[cfg(not(feature = "sha2"))]
+        let input_digest = None;
         if let Some(filter) = builder.filter {
             if filter.block_is_empty(&builder.id) {
                 // The approximate filter is empty, so it gives a definitive result on every
                 // item and there's nothing to encode in the exact filter.
-                return ExactRibbon::new(&builder.id, 0, filter.block_is_inverted(&builder.id));
+                let mut out = ExactRibbon::new(
+                    &builder.id,
+                    0,
+                    filter.block_is_inverted(&builder.id),
+                    builder.epsilon,
+                );
+                out.duplicates = duplicates;
+                out.universe_size = builder.inserted;
+                out.oversized = builder.oversized;
+                out.uncovered = builder.uncovered;
+                out.members = members;
+                out.input_digest = input_digest;
+                out.insert_time = start.elapsed();
+                return out;
             }
         }
-        let mut out = ExactRibbon::new(&builder.id, builder.items.len(), builder.inverted);
+        let mut out = ExactRibbon::new(
+            &builder.id,
+            builder.items.len(),
+            builder.inverted,
+            builder.epsilon,
+        );
+        out.duplicates = duplicates;
+        out.universe_size = builder.inserted;
+        out.oversized = std::mem::take(&mut builder.oversized);
+        out.uncovered = std::mem::take(&mut builder.uncovered);
+        out.members = members;
+        out.input_digest = input_digest;
+        out.instrumented = builder.instrumented;
         // By inserting the included items first, we ensure that any exceptions that occur during
         // insertion are for excluded items.
         let mut excluded = vec![];
@@ -131,10 +296,68 @@ impl<'a, const W: usize, T: Filterable<W>> From<RibbonBuilder<'a, W, T>> for Exa
         for item in excluded.drain(..) {
             out.insert(item);
         }
+        out.insert_time = start.elapsed();
         out
     }
 }
 
+/// The rows of a ribbon's linear system in echelon form: row i is either zero or an aligned
+/// equation that starts at position i. Since the starting position of a row is implied by its
+/// index, only the coefficients and the constant term are stored, and the constant terms are
+/// bit-packed. This is the bulk of a builder's working memory.
+#[derive(Clone, Debug, Default)]
+pub struct EchelonRows<const W: usize> {
+    /// The coefficients of each row.
+    a: Vec<[u64; W]>,
+    /// The constant term of row i is bit i % 64 of b[i / 64].
+    b: Vec<u64>,
+}
+
+impl<const W: usize> EchelonRows<W> {
+    /// `len` zero rows.
+    pub fn new(len: usize) -> Self {
+        let mut rows = EchelonRows::default();
+        rows.resize(len);
+        rows
+    }
+
+    pub fn len(&self) -> usize {
+        self.a.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.a.is_empty()
+    }
+
+    /// Is row i zero?
+    pub fn is_zero(&self, i: usize) -> bool {
+        self.a[i] == [0u64; W]
+    }
+
+    /// Row i, as an equation that starts at position i.
+    pub fn row(&self, i: usize) -> Equation<W> {
+        Equation {
+            s: i,
+            a: self.a[i],
+            b: ((self.b[i / 64] >> (i % 64)) & 1) as u8,
+        }
+    }
+
+    /// Replace row eq.s with `eq`, which must be aligned.
+    fn set(&mut self, eq: Equation<W>) {
+        debug_assert!(eq.is_aligned());
+        let (limb, pos) = (eq.s / 64, eq.s % 64);
+        self.a[eq.s] = eq.a;
+        self.b[limb] = (self.b[limb] & !(1 << pos)) | (((eq.b & 1) as u64) << pos);
+    }
+
+    /// Truncate or extend with zero rows to `len` rows.
+    fn resize(&mut self, len: usize) {
+        self.a.resize(len, [0u64; W]);
+        self.b.resize(len.div_ceil(64), 0);
+    }
+}
+
 /// A compact representation of a linear system AX = B
 pub struct Ribbon<const W: usize, T: Filterable<W>, ApproxOrExact> {
     /// A block identifier. Used to build an index for partitioned filters.
@@ -146,12 +369,35 @@ pub struct Ribbon<const W: usize, T: Filterable<W>, ApproxOrExact> {
     /// The rank is round(-log2(subset_size / (universe_size - subset_size)))
     rank: usize,
     /// A linear system in which each equation has s in {0, ..., m-1}
-    rows: Vec<Equation<W>>,
+    rows: EchelonRows<W>,
     /// A (typically short) list of items that failed insertion
     exceptions: Vec<T>,
+    /// The number of items that have been inserted (including exceptions)
+    size: usize,
     /// Whether queries against this ribbon indicate membership in R (inverted = false) or
     /// membership in U \ R (inverted = true).
     inverted: bool,
+    /// The number of items passed to the builder that had the discriminant of an earlier item.
+    duplicates: usize,
+    /// The size of the universe. For an approximate ribbon, this is the size that was declared
+    /// with RibbonBuilder::set_universe_size. For an exact ribbon, it is the number of items
+    /// that were passed to RibbonBuilder::insert.
+    universe_size: usize,
+    /// Time spent inserting items into this ribbon.
+    insert_time: Duration,
+    /// Whether insert measures hash_time.
+    instrumented: bool,
+    /// Time spent hashing items in insert, if the ribbon is instrumented. This is part of
+    /// insert_time.
+    hash_time: Duration,
+    /// Discriminants of items that were rejected by the builder for being too long.
+    oversized: Vec<Vec<u8>>,
+    /// Discriminants of included items that are not in the declared universe.
+    uncovered: Vec<Vec<u8>>,
+    /// Discriminants of the members of an exact ribbon, if the builder retained them.
+    members: Option<Vec<Vec<u8>>>,
+    /// The digest of the items inserted into an exact ribbon, if the builder was in audit mode.
+    input_digest: Option<[u8; 32]>,
     /// Marker for whether this is an Approximate or an Exact filter.
     phantom: std::marker::PhantomData<ApproxOrExact>,
 }
@@ -160,74 +406,192 @@ impl<const W: usize, T: Filterable<W>, ApproxOrExact> fmt::Display for Ribbon<W,
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "ribbon({:?}): m: {}, rows: {}, rank: {}, exceptions: {}, epsilon: {}, overhead {}",
+            "ribbon({:?}): m: {}, rows: {}, rank: {}, exceptions: {}, duplicates: {}, epsilon: {}, overhead {}",
             self.id,
             self.m,
             self.rows.len(),
             self.rank,
             self.exceptions.len(),
+            self.duplicates,
             self.epsilon,
-            (self.rows.iter().filter(|eq| eq.is_zero()).count() as f64 / (self.rows.len() as f64))
+            ((0..self.rows.len()).filter(|&i| self.rows.is_zero(i)).count() as f64 / (self.rows.len() as f64))
         )
     }
 }
 
+/// Size and occupancy statistics for a Ribbon.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RibbonStats {
+    /// The number of rows that the ribbon was sized for, i.e. (1+epsilon) * items.
+    pub m: usize,
+    /// The number of rows in the system. This exceeds m when equations starting near the end of
+    /// the ribbon spill past row m.
+    pub rows: usize,
+    /// The number of solution columns, i.e. the number of bits stored per row.
+    pub rank: usize,
+    /// The number of items that were inserted.
+    pub items: usize,
+    /// The number of items that could not be inserted.
+    pub exceptions: usize,
+}
+
+impl RibbonStats {
+    /// The size of the solution in bits.
+    pub fn bits(&self) -> usize {
+        self.rank * self.rows
+    }
+
+    /// The number of solution bits per inserted item.
+    pub fn bits_per_item(&self) -> f64 {
+        self.bits() as f64 / self.items as f64
+    }
+
+    /// The fraction of rows that are pivots of inserted items.
+    pub fn load_factor(&self) -> f64 {
+        (self.items - self.exceptions) as f64 / self.rows as f64
+    }
+
+    /// The number of rows beyond m that were used.
+    pub fn slack(&self) -> usize {
+        self.rows - self.m
+    }
+}
+
+/// The number of columns in the approximate filter for a block with `subset_size` members in a
+/// universe of size `universe_size`.
+pub(crate) fn approx_rank(subset_size: usize, universe_size: usize) -> usize {
+    if subset_size == 0 || 2 * subset_size >= universe_size {
+        0
+    } else {
+        (((universe_size - subset_size) as f64) / (subset_size as f64))
+            .log2()
+            .floor() as usize
+    }
+}
+
 impl<const W: usize, T: Filterable<W>> ApproximateRibbon<W, T> {
     /// Construct an empty ribbon to encode a set R of size `subset_size` in a universe U of size
     /// `universe_size`.
-    fn new(id: &[u8], subset_size: usize, universe_size: usize, inverted: bool) -> Self {
+    fn new(
+        id: &[u8],
+        subset_size: usize,
+        universe_size: usize,
+        inverted: bool,
+        epsilon: f64,
+    ) -> Self {
         assert!(subset_size <= universe_size);
 
-        // TODO: Tune epsilon as a function of the inputs. Numerical experiments?
-        let epsilon = 0.02;
         let m = ((1.0 + epsilon) * (subset_size as f64)).floor() as usize;
 
-        let rank = if subset_size == 0 || 2 * subset_size >= universe_size {
-            0
-        } else {
-            (((universe_size - subset_size) as f64) / (subset_size as f64))
-                .log2()
-                .floor() as usize
-        };
+        let rank = approx_rank(subset_size, universe_size);
 
         Ribbon {
             id: AsRef::<[u8]>::as_ref(id).to_vec(),
-            rows: vec![Equation::zero(); m],
+            rows: EchelonRows::new(m),
             m,
             epsilon,
             rank,
             exceptions: vec![],
+            size: 0,
             inverted,
+            duplicates: 0,
+            universe_size: 0,
+            insert_time: Duration::ZERO,
+            instrumented: false,
+            hash_time: Duration::ZERO,
+            oversized: vec![],
+            uncovered: vec![],
+            members: None,
+            input_digest: None,
             phantom: std::marker::PhantomData,
         }
     }
+
+    /// The number of members of the block. A block that covers its entire universe is encoded
+    /// as an inverted ribbon without items, so this is not the number of inserted items.
+    fn members(&self) -> usize {
+        if self.inverted {
+            self.universe_size - self.size
+        } else {
+            self.size
+        }
+    }
 }
 
 impl<const W: usize, T: Filterable<W>> ExactRibbon<W, T> {
     /// Construct an empty ribbon to encode a set R of size `subset_size` in a universe U of size
     /// `universe_size`.
-    fn new(id: &impl AsRef<[u8]>, size: usize, inverted: bool) -> Self {
-        // TODO: Tune epsilon as a function of the inputs. Numerical experiments?
-        let epsilon = 0.02;
+    fn new(id: &impl AsRef<[u8]>, size: usize, inverted: bool, epsilon: f64) -> Self {
         let m = ((1.0 + epsilon) * (size as f64)).floor() as usize;
 
         Ribbon {
             id: AsRef::<[u8]>::as_ref(id).to_vec(),
-            rows: vec![Equation::zero(); m],
+            rows: EchelonRows::new(m),
             m,
             epsilon,
             rank: 1,
             exceptions: vec![],
+            size: 0,
             inverted,
+            duplicates: 0,
+            universe_size: 0,
+            insert_time: Duration::ZERO,
+            instrumented: false,
+            hash_time: Duration::ZERO,
+            oversized: vec![],
+            uncovered: vec![],
+            members: None,
+            input_digest: None,
             phantom: std::marker::PhantomData,
         }
     }
 }
 
 impl<const W: usize, T: Filterable<W>, ApproxOrExact> Ribbon<W, T, ApproxOrExact> {
+    /// The number of items that were passed to the builder with the discriminant of an earlier
+    /// item in the same block. Duplicates are only counted when BuildConfig::dedup is set.
+    pub fn duplicates(&self) -> usize {
+        self.duplicates
+    }
+
+    /// A summary of this ribbon for a BuildReport. This is available as soon as the ribbon has
+    /// been built, so problems with a block can be detected before the ribbons are collected.
+    pub fn report(&self) -> RibbonReport {
+        RibbonReport {
+            block: self.id.clone(),
+            stats: self.stats(),
+            duplicates: self.duplicates,
+            insert_time: self.insert_time,
+            hash_time: self.hash_time,
+            solve_time: Duration::ZERO,
+        }
+    }
+
+    pub fn stats(&self) -> RibbonStats {
+        RibbonStats {
+            m: self.m,
+            rows: self.rows.len(),
+            rank: self.rank,
+            items: self.size,
+            exceptions: self.exceptions.len(),
+        }
+    }
+
     /// Hash the item to an Equation and insert it into the system.
     fn insert(&mut self, item: T) -> bool {
-        let mut eq = item.as_query(self.m);
+        self.size += 1;
+        let mut eq = if self.instrumented {
+            let start = Instant::now();
+            let eq = item.as_query(self.m);
+            self.hash_time += start.elapsed();
+            eq
+        } else {
+            item.as_query(self.m)
+        };
+        debug_assert!(
+            eq.is_homogeneous(),
+            "AsQuery::as_query must return a homogeneous equation"
+        );
         eq.b = if item.included() { 0 } else { 1 };
         assert!(eq.is_zero() || eq.a[0] & 1 == 1);
         let rv = self.insert_equation(eq);
@@ -245,24 +609,49 @@ impl<const W: usize, T: Filterable<W>, ApproxOrExact> Ribbon<W, T, ApproxOrExact
             }
             if eq.s >= self.rows.len() {
                 // TODO: could be smarter here
-                self.rows.resize_with(eq.s + 1, Equation::zero);
+                self.rows.resize(eq.s + 1);
             }
-            let cur = &mut self.rows[eq.s];
-            if cur.is_zero() {
-                *cur = eq;
+            if self.rows.is_zero(eq.s) {
+                self.rows.set(eq);
                 return true; /* inserted */
             }
-            eq.add(cur);
+            eq.add(&self.rows.row(eq.s));
         }
     }
 
-    /// Solve the system using back-substitution. If this is a block in a larger system, the `tail`
+    /// Solve the system with `solver`. If this is a block in a larger system, the `tail`
     /// argument should be set to the the solution vector for the block to the right of this one.
-    fn solve(&self, tail: &[u64]) -> Vec<u64> {
-        let mut z = vec![0u64; ((self.rows.len() + 63) / 64) + tail.len()];
-        // insert tail into z starting at bit self.rows.len()
-        let k = self.rows.len() / 64;
-        let p = self.rows.len() % 64;
+    fn solve(&self, tail: &[u64], rng: &mut impl Rng, solver: &dyn RibbonSolver<W>) -> Vec<u64> {
+        let z = solver.solve(&self.rows, tail, rng);
+        debug_assert!((0..self.rows.len()).all(|i| {
+            let row = self.rows.row(i);
+            row.is_zero() || row.eval(&z) == row.b
+        }));
+        z
+    }
+}
+
+/// A strategy for solving the linear system of one ribbon. Items are eliminated as they are
+/// inserted, so the system is given in echelon form (see EchelonRows). The default is
+/// BackSubstitution. See ClubcardBuilder::set_solver.
+pub trait RibbonSolver<const W: usize>: Send + Sync {
+    /// Return a vector z of at least rows.len() + 64 * tail.len() bits such that row.eval(z) ==
+    /// row.b for every non-zero row, and such that bits rows.len().. of z are equal to `tail`,
+    /// the solution for the blocks to the right of this one. Free variables should be assigned
+    /// using `rng`, so that seeded builds are reproducible.
+    fn solve(&self, rows: &EchelonRows<W>, tail: &[u64], rng: &mut dyn RngCore) -> Vec<u64>;
+}
+
+/// Back-substitution with random free variables.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BackSubstitution;
+
+impl<const W: usize> RibbonSolver<W> for BackSubstitution {
+    fn solve(&self, rows: &EchelonRows<W>, tail: &[u64], rng: &mut dyn RngCore) -> Vec<u64> {
+        let mut z = vec![0u64; rows.len().div_ceil(64) + tail.len()];
+        // insert tail into z starting at bit rows.len()
+        let k = rows.len() / 64;
+        let p = rows.len() % 64;
         if p == 0 {
             z[k..(tail.len() + k)].copy_from_slice(tail);
         } else {
@@ -273,22 +662,24 @@ impl<const W: usize, T: Filterable<W>, ApproxOrExact> Ribbon<W, T, ApproxOrExact
         }
 
         // Solve by back substitution
-        for i in (0..self.rows.len()).rev() {
+        for i in (0..rows.len()).rev() {
             let limb = i / 64;
             let pos = i % 64;
-            let z_i = if self.rows[i].is_zero() {
+            let z_i = if rows.is_zero(i) {
                 // Row i has a zero in column i, so we're free to choose.
-                // TODO: We want multiple calls to solve() to give a different
+                // We want multiple calls to solve() to give a different
                 // solutions (when the system is suitably under-determined),
-                // but it might be nice if this was deterministic.
-                thread_rng().gen::<u8>()
+                // so the choice is random. Callers that need a deterministic
+                // result can pass a seeded rng.
+                rng.gen::<u8>()
             } else {
                 // Let z' be the vector we get by setting bit i of z to z'_i.
                 // Since z_i is zero, and row i has a one in column i, we have
                 // row_i(z') = z'_i ^ row_i(z).
                 // We want row_i(z') = b, so we must choose
                 // z'_i = row_i(z) ^ b.
-                self.rows[i].eval(&z) ^ self.rows[i].b
+                let row = rows.row(i);
+                row.eval(&z) ^ row.b
             };
             z[limb] |= ((z_i & 1) as u64) << pos;
         }
@@ -303,6 +694,9 @@ struct PartitionedRibbonFilterIndexEntry {
     rank: usize,
     exceptions: Vec<Vec<u8>>,
     inverted: bool,
+    universe_size: usize,
+    /// Time spent solving the block, summed over its columns.
+    solve_time: Duration,
 }
 
 type PartitionedRibbonFilterIndex =
@@ -372,10 +766,22 @@ impl<const W: usize, T: Filterable<W>, ApproxOrExact> From<Vec<Ribbon<W, T, Appr
     for PartitionedRibbonFilter<W, T, ApproxOrExact>
 {
     fn from(
+        blocks: Vec<Ribbon<W, T, ApproxOrExact>>,
+    ) -> PartitionedRibbonFilter<W, T, ApproxOrExact> {
+        PartitionedRibbonFilter::from_ribbons(blocks, &mut thread_rng(), &BackSubstitution)
+    }
+}
+
+impl<const W: usize, T: Filterable<W>, ApproxOrExact> PartitionedRibbonFilter<W, T, ApproxOrExact> {
+    /// Solve the block system formed by `blocks` with `solver`, using `rng` to assign free
+    /// variables.
+    fn from_ribbons(
         mut blocks: Vec<Ribbon<W, T, ApproxOrExact>>,
+        rng: &mut impl Rng,
+        solver: &dyn RibbonSolver<W>,
     ) -> PartitionedRibbonFilter<W, T, ApproxOrExact> {
         // Sort ribbons by descending rank (descending simplifies indexing).
-        blocks.sort_unstable_by(|a, b| b.rank.cmp(&a.rank));
+        blocks.sort_unstable_by_key(|block| std::cmp::Reverse(block.rank));
 
         // Solve the (block) system.
         // The blocks are sorted by descending rank. We need at least one solution (i.e. column
@@ -388,17 +794,20 @@ impl<const W: usize, T: Filterable<W>, ApproxOrExact> From<Vec<Ribbon<W, T, Appr
         // Since we serialize the block identifiers, offsets, and ranks in the final filter, we
         // don't need to encode the zeros.
         let mut solution = vec![];
+        let mut solve_times = vec![Duration::ZERO; blocks.len()];
         let max_rank = blocks.first().map_or(0, |first| first.rank);
         for i in 0..max_rank {
             // Back substitution across blocks.
             let mut tail = vec![];
             if max_rank > 1 {
                 // randomizing the tail increases the odds that the solutions will be distinct
-                tail.push(thread_rng().gen::<u64>());
+                tail.push(rng.gen::<u64>());
             }
             for j in (0..blocks.len()).rev() {
                 if blocks[j].rank > i {
-                    tail = blocks[j].solve(&tail);
+                    let start = Instant::now();
+                    tail = blocks[j].solve(&tail, rng, solver);
+                    solve_times[j] += start.elapsed();
                 }
             }
             solution.push(tail);
@@ -408,7 +817,7 @@ impl<const W: usize, T: Filterable<W>, ApproxOrExact> From<Vec<Ribbon<W, T, Appr
         // block's offset in the solution vector.
         let mut index = PartitionedRibbonFilterIndex::new();
         let mut offset = 0;
-        for block in &blocks {
+        for (block, solve_time) in blocks.iter().zip(solve_times) {
             let exceptions = block
                 .exceptions
                 .iter()
@@ -422,6 +831,8 @@ impl<const W: usize, T: Filterable<W>, ApproxOrExact> From<Vec<Ribbon<W, T, Appr
                     rank: block.rank,
                     exceptions,
                     inverted: block.inverted,
+                    universe_size: block.universe_size,
+                    solve_time,
                 },
             );
             offset += block.rows.len();
@@ -436,6 +847,261 @@ impl<const W: usize, T: Filterable<W>, ApproxOrExact> From<Vec<Ribbon<W, T, Appr
     }
 }
 
+/// A summary of one ribbon in a BuildReport.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RibbonReport {
+    pub block: Vec<u8>,
+    #[serde(flatten)]
+    pub stats: RibbonStats,
+    /// See Ribbon::duplicates.
+    pub duplicates: usize,
+    /// Time spent inserting items into the ribbon.
+    pub insert_time: Duration,
+    /// Time spent hashing items, as part of insert_time. Only measured if the builder was
+    /// instrumented (see ClubcardBuilder::set_instrumented).
+    #[serde(default)]
+    pub hash_time: Duration,
+    /// Time spent solving the ribbon, as part of the solve time of its layer. This is zero
+    /// until the ribbon has been collected.
+    #[serde(default)]
+    pub solve_time: Duration,
+}
+
+/// A summary of a clubcard build, collected by ClubcardBuilder.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct BuildReport {
+    /// One entry per approximate ribbon, in the order they were collected.
+    pub approx_ribbons: Vec<RibbonReport>,
+    /// One entry per exact ribbon, in the order they were collected.
+    pub exact_ribbons: Vec<RibbonReport>,
+    /// Time spent solving the approximate ribbons.
+    pub approx_solve_time: Duration,
+    /// Time spent solving the exact ribbons.
+    pub exact_solve_time: Duration,
+    /// Time spent assembling the clubcard from the solved ribbons in build_with_report.
+    #[serde(default)]
+    pub assemble_time: Duration,
+    /// Time spent serializing the clubcard, if it was serialized with BuildReport::to_bytes.
+    #[serde(default)]
+    pub serialize_time: Duration,
+    /// The blocks with more members than the limit given to ClubcardBuilder::set_max_members,
+    /// in the order they were collected.
+    #[serde(default)]
+    pub over_capacity: Vec<CapacityWarning>,
+}
+
+/// A block with more members than the limit given to ClubcardBuilder::set_max_members.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CapacityWarning {
+    pub block: Vec<u8>,
+    pub members: usize,
+    pub limit: usize,
+}
+
+impl BuildReport {
+    /// Same as Clubcard::to_bytes, but records the time taken in serialize_time.
+    pub fn to_bytes<const W: usize, UniverseMetadata, PartitionMetadata>(
+        &mut self,
+        clubcard: &Clubcard<W, UniverseMetadata, PartitionMetadata>,
+    ) -> Vec<u8>
+    where
+        UniverseMetadata: Encode,
+        PartitionMetadata: Encode,
+    {
+        let start = Instant::now();
+        let bytes = clubcard.to_bytes();
+        self.serialize_time = start.elapsed();
+        bytes
+    }
+
+    /// The timings in the "folded stacks" format that flamegraph tools read: one line per
+    /// phase, with the semicolon-separated path to the phase and its duration in microseconds.
+    /// Per-block lines are labeled with the hex encoding of the block identifier. Hashing is
+    /// listed under insertion, and the remainder of the insertion time is listed as "other".
+    pub fn folded_stacks(&self) -> String {
+        let mut out = String::new();
+        let mut line = |path: &str, time: Duration| {
+            if !time.is_zero() {
+                out += &format!("build;{} {}\n", path, time.as_micros());
+            }
+        };
+        for (name, ribbons, solve_time) in [
+            ("approx", &self.approx_ribbons, self.approx_solve_time),
+            ("exact", &self.exact_ribbons, self.exact_solve_time),
+        ] {
+            let mut solved = Duration::ZERO;
+            for r in ribbons {
+                let block: String = r.block.iter().map(|b| format!("{:02x}", b)).collect();
+                line(&format!("{};insert;{};hash", name, block), r.hash_time);
+                line(
+                    &format!("{};insert;{};other", name, block),
+                    r.insert_time.saturating_sub(r.hash_time),
+                );
+                line(&format!("{};solve;{}", name, block), r.solve_time);
+                solved += r.solve_time;
+            }
+            line(
+                &format!("{};solve;other", name),
+                solve_time.saturating_sub(solved),
+            );
+        }
+        line("assemble", self.assemble_time);
+        line("serialize", self.serialize_time);
+        out
+    }
+}
+
+/// Copy the per-block solve times from `index` into `reports`.
+fn record_solve_times(reports: &mut [RibbonReport], index: &PartitionedRibbonFilterIndex) {
+    for report in reports {
+        if let Some(entry) = index.get(&report.block) {
+            report.solve_time = entry.solve_time;
+        }
+    }
+}
+
+impl fmt::Display for BuildReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (name, ribbons, solve_time) in [
+            ("approx", &self.approx_ribbons, self.approx_solve_time),
+            ("exact", &self.exact_ribbons, self.exact_solve_time),
+        ] {
+            writeln!(f, "{} ribbons (solved in {:?}):", name, solve_time)?;
+            for r in ribbons {
+                writeln!(
+                    f,
+                    "- {:?}: items: {}, rows: {}, rank: {}, exceptions: {}, duplicates: {}, inserted in {:?} (hashed in {:?}), solved in {:?}",
+                    r.block,
+                    r.stats.items,
+                    r.stats.rows,
+                    r.stats.rank,
+                    r.stats.exceptions,
+                    r.duplicates,
+                    r.insert_time,
+                    r.hash_time,
+                    r.solve_time
+                )?;
+            }
+        }
+        writeln!(f, "assembled in {:?}", self.assemble_time)?;
+        writeln!(f, "serialized in {:?}", self.serialize_time)?;
+        Ok(())
+    }
+}
+
+/// A block that may be included in a clubcard. See select_blocks.
+#[derive(Clone, Debug)]
+pub struct BlockCandidate {
+    pub block: Vec<u8>,
+    pub universe_size: usize,
+    /// The number of items in the block that are included in R.
+    pub members: usize,
+    /// The value of covering one member of this block.
+    pub priority: f64,
+}
+
+impl BlockCandidate {
+    /// The expected contribution of this block to the size of a serialized clubcard, in
+    /// bytes. This includes the block's index entry and its share of X and Y. Returns None if
+    /// members exceeds universe_size.
+    pub fn estimated_size(&self) -> Option<usize> {
+        if self.members > self.universe_size {
+            return None;
+        }
+        // Mirror ApproximateRibbon::from and ExactRibbon::from with the default epsilon.
+        let rows = |n: usize| ((1.0 + DEFAULT_EPSILON) * n as f64).floor() as usize;
+        let (approx_bits, exact_items) = if self.members == 0 || self.members == self.universe_size
+        {
+            // The approximate filter is empty and the exact filter is not used.
+            (0, 0)
+        } else {
+            let rank = approx_rank(self.members, self.universe_size);
+            let false_positives =
+                (self.universe_size - self.members) as f64 / (1u64 << rank.min(63)) as f64;
+            (
+                rank * rows(self.members),
+                self.members + false_positives.ceil() as usize,
+            )
+        };
+        let filter_bytes = (approx_bits + rows(exact_items)).div_ceil(8);
+        // Block identifier, five usizes, a bool, and an empty list of exceptions.
+        let index_bytes = 8 + self.block.len() + 5 * 8 + 1 + 8;
+        Some(filter_bytes + index_bytes)
+    }
+}
+
+/// The result of select_blocks.
+#[derive(Clone, Debug, Default)]
+pub struct BlockSelection {
+    pub included: Vec<Vec<u8>>,
+    pub excluded: Vec<Vec<u8>>,
+    /// The sum of the estimated sizes of the included blocks.
+    pub estimated_size: usize,
+}
+
+/// Choose blocks to include in a clubcard so as to maximize the total priority-weighted number
+/// of members, subject to the sum of the estimated sizes of the included blocks being at most
+/// `budget` bytes. Blocks are considered greedily in order of value per byte. The excluded
+/// blocks can be dropped with ClubcardBuilder::set_block_filter. Fails with
+/// ClubcardError::InvalidBlock if a candidate has more members than its universe.
+pub fn select_blocks(
+    candidates: &[BlockCandidate],
+    budget: usize,
+) -> Result<BlockSelection, ClubcardError> {
+    let mut order: Vec<(f64, usize, &BlockCandidate)> = Vec::with_capacity(candidates.len());
+    for candidate in candidates {
+        let size = candidate
+            .estimated_size()
+            .ok_or_else(|| ClubcardError::InvalidBlock(candidate.block.clone()))?;
+        let value = candidate.priority * candidate.members as f64;
+        order.push((value / size as f64, size, candidate));
+    }
+    order.sort_by(|a, b| b.0.total_cmp(&a.0).then_with(|| a.2.block.cmp(&b.2.block)));
+
+    let mut selection = BlockSelection::default();
+    for (_, size, candidate) in order {
+        if selection.estimated_size + size <= budget {
+            selection.estimated_size += size;
+            selection.included.push(candidate.block.clone());
+        } else {
+            selection.excluded.push(candidate.block.clone());
+        }
+    }
+    Ok(selection)
+}
+
+/// A source of builder items that can be read more than once, e.g. a file, a database table,
+/// or a map from block identifiers to items. See ClubcardBuilder::collect_ribbons_from_source.
+pub trait ItemSource<const W: usize> {
+    type Item: Filterable<W>;
+
+    /// The identifiers of the blocks in the source.
+    fn blocks(&self) -> Vec<Vec<u8>>;
+
+    /// All of the items in the universe of `block`, included or not. Every call with the same
+    /// block must return the same items.
+    fn items_for_block(
+        &self,
+        block: &[u8],
+    ) -> impl Iterator<Item = Result<Self::Item, ClubcardError>> + '_;
+}
+
+impl<const W: usize, T: Filterable<W> + Clone> ItemSource<W> for BTreeMap<Vec<u8>, Vec<T>> {
+    type Item = T;
+
+    fn blocks(&self) -> Vec<Vec<u8>> {
+        self.keys().cloned().collect()
+    }
+
+    fn items_for_block(&self, block: &[u8]) -> impl Iterator<Item = Result<T, ClubcardError>> + '_ {
+        self.get(block).into_iter().flatten().cloned().map(Ok)
+    }
+}
+
+type BlockFilter = Box<dyn Fn(&[u8]) -> bool + Send + Sync>;
+type ProgressCallback = Box<dyn Fn(&RibbonReport) + Send + Sync>;
+type UniverseCheck<T> = dyn Fn(&T) -> bool + Send + Sync;
+
 /// A pair of ribbon filters that, together, solve the exact membership query problem.
 pub struct ClubcardBuilder<const W: usize, T: Filterable<W>> {
     /// An approximate membership query filter to whittle down the universe
@@ -444,6 +1110,42 @@ pub struct ClubcardBuilder<const W: usize, T: Filterable<W>> {
     /// An exact membership query filter to confirm membership in R for items that
     /// pass through the approximate filter.
     exact_filter: Option<PartitionedRibbonFilter<W, T, Exact>>,
+    /// Source of randomness for the free variables in the solutions. This is a ChaCha20Rng
+    /// rather than a StdRng, whose algorithm may change between versions of rand, so that a
+    /// seeded builder gives the same clubcard everywhere.
+    rng: ChaCha20Rng,
+    /// Options passed to block builders.
+    config: BuildConfig,
+    /// Statistics about the collected ribbons.
+    report: BuildReport,
+    /// Blocks for which this returns false are dropped from the clubcard.
+    block_filter: Option<BlockFilter>,
+    /// Called with the report for each ribbon built by collect_ribbons_from_source.
+    progress: Option<ProgressCallback>,
+    /// Solver for the individual ribbons.
+    solver: Box<dyn RibbonSolver<W>>,
+    /// Whether exact ribbon builders retain the discriminants of members.
+    retain_members: bool,
+    /// The members retained by the collected exact ribbons.
+    members: Option<MembersSidecar>,
+    /// Whether exact ribbon builders record the digest of their inputs.
+    audit: bool,
+    /// The input digests recorded by the collected exact ribbons.
+    input_digests: BTreeMap<Vec<u8>, [u8; 32]>,
+    /// Blocks whose universe has at most this many items are encoded with a single layer.
+    single_layer_threshold: Option<usize>,
+    /// Returns true for blocks that are encoded with a single layer regardless of size.
+    single_layer_blocks: Option<BlockFilter>,
+    /// Whether ribbons measure the time spent hashing items.
+    instrumented: bool,
+    /// Whether the clubcard must have at most one block.
+    single_block: bool,
+    /// Returns false for items that are not in the declared universe.
+    universe_check: Option<Box<UniverseCheck<T>>>,
+    /// The maximum number of members per block, and whether exceeding it fails the build.
+    max_members: Option<(usize, bool)>,
+    /// Returns false for blocks that do not follow the convention given to set_block_id.
+    block_id_check: Option<fn(&[u8]) -> bool>,
 }
 
 impl<const W: usize, T: Filterable<W>> Default for ClubcardBuilder<W, T> {
@@ -451,6 +1153,23 @@ impl<const W: usize, T: Filterable<W>> Default for ClubcardBuilder<W, T> {
         ClubcardBuilder {
             approx_filter: None,
             exact_filter: None,
+            rng: ChaCha20Rng::from_entropy(),
+            config: BuildConfig::default(),
+            report: BuildReport::default(),
+            block_filter: None,
+            progress: None,
+            solver: Box::new(BackSubstitution),
+            retain_members: false,
+            members: None,
+            audit: false,
+            input_digests: BTreeMap::new(),
+            single_layer_threshold: None,
+            single_layer_blocks: None,
+            instrumented: false,
+            single_block: false,
+            universe_check: None,
+            max_members: None,
+            block_id_check: None,
         }
     }
 }
@@ -460,21 +1179,507 @@ impl<const W: usize, T: Filterable<W>> ClubcardBuilder<W, T> {
         ClubcardBuilder::default()
     }
 
+    /// Construct a builder that produces the same clubcard every time it is given the same
+    /// inputs (in the same order).
+    pub fn new_with_seed(seed: u64) -> Self {
+        ClubcardBuilder::new_with_config(BuildConfig {
+            seed: Some(seed),
+            ..Default::default()
+        })
+    }
+
+    pub fn new_with_config(config: BuildConfig) -> Self {
+        assert!(config.epsilon >= 0.0);
+        ClubcardBuilder {
+            rng: config
+                .seed
+                .map_or_else(ChaCha20Rng::from_entropy, ChaCha20Rng::seed_from_u64),
+            config,
+            ..Default::default()
+        }
+    }
+
+    /// The configuration of this builder.
+    pub fn config(&self) -> &BuildConfig {
+        &self.config
+    }
+
     pub fn new_approx_builder(&self, block: &[u8]) -> RibbonBuilder<'static, W, T> {
         assert!(self.approx_filter.is_none());
-        RibbonBuilder::new(block, None)
+        let mut builder = RibbonBuilder::new(block, None).with_config(&self.config);
+        builder.single_layer_threshold = self.single_layer_threshold;
+        builder.single_layer = self.is_single_layer_block(block);
+        builder.instrumented = self.instrumented;
+        builder
     }
 
     pub fn new_exact_builder<'a>(&'a self, block: &[u8]) -> RibbonBuilder<'a, W, T> {
-        RibbonBuilder::new(block, self.approx_filter.as_ref())
+        let mut builder =
+            RibbonBuilder::new(block, self.approx_filter.as_ref()).with_config(&self.config);
+        builder.retain_members = self.retain_members;
+        builder.instrumented = self.instrumented;
+        builder.universe_check = self.universe_check.as_deref();
+        if self.audit {
+            builder.audit = Some(vec![]);
+        }
+        builder
+    }
+
+    /// Same as new_approx_builder, but with space reserved for `capacity` items.
+    pub fn new_approx_builder_with_capacity(
+        &self,
+        block: &[u8],
+        capacity: usize,
+    ) -> RibbonBuilder<'static, W, T> {
+        self.new_approx_builder(block).with_capacity(capacity)
+    }
+
+    /// Same as new_exact_builder, but with space reserved for `capacity` items (i.e. items
+    /// that pass through the approximate filter).
+    pub fn new_exact_builder_with_capacity<'a>(
+        &'a self,
+        block: &[u8],
+        capacity: usize,
+    ) -> RibbonBuilder<'a, W, T> {
+        self.new_exact_builder(block).with_capacity(capacity)
+    }
+
+    /// Discard the collected ribbons so that this builder can be used for a new clubcard. The
+    /// configuration and the random number generator state are retained.
+    pub fn reset(&mut self) {
+        self.approx_filter = None;
+        self.exact_filter = None;
+        self.report = BuildReport::default();
+        self.members = None;
+        self.input_digests.clear();
+    }
+
+    /// Only include the blocks for which `filter` returns true (e.g. an allow-list or a
+    /// deny-list of issuers). Ribbons for excluded blocks are dropped when they are collected,
+    /// in both the approximate and the exact phase.
+    pub fn set_block_filter(&mut self, filter: impl Fn(&[u8]) -> bool + Send + Sync + 'static) {
+        assert!(self.approx_filter.is_none());
+        self.block_filter = Some(Box::new(filter));
+    }
+
+    /// Whether ribbons for `block` will be included in the clubcard.
+    pub fn includes_block(&self, block: &[u8]) -> bool {
+        self.block_filter
+            .as_ref()
+            .map_or(true, |filter| filter(block))
+    }
+
+    /// Call `progress` with the report for each ribbon that collect_ribbons_from_source builds,
+    /// as soon as the ribbon has been built.
+    pub fn set_progress(&mut self, progress: impl Fn(&RibbonReport) + Send + Sync + 'static) {
+        self.progress = Some(Box::new(progress));
+    }
+
+    /// Solve ribbons with `solver` instead of BackSubstitution.
+    pub fn set_solver(&mut self, solver: impl RibbonSolver<W> + 'static) {
+        self.solver = Box::new(solver);
+    }
+
+    /// Retain the discriminants of the members of each block, and attach them to the clubcard
+    /// as a MembersSidecar. This only affects exact ribbon builders that are created after the
+    /// call. The sidecar has no entry for a block whose exact ribbon did not retain its
+    /// members.
+    pub fn set_retain_members(&mut self, retain: bool) {
+        self.retain_members = retain;
+    }
+
+    /// Record the digest of each block's universe (see input_digest) in the block's index entry,
+    /// so that anyone with the inputs can check the clubcard with Clubcard::attest_block. This
+    /// only affects exact ribbon builders that are created after the call, and it costs a copy
+    /// of the discriminant of every item while the exact ribbons are built.
+    #[cfg(feature = "sha2")]
+    pub fn set_audit(&mut self, audit: bool) {
+        self.audit = audit;
+    }
+
+    /// Encode blocks whose universe has at most `max` items with a single layer: the
+    /// approximate ribbon gets rank 0, so every item passes through to the exact ribbon, which
+    /// then covers the whole universe. For small universes this can be smaller than two layers,
+    /// and it saves a step at query time. The choice is recorded in the block's index entry
+    /// (approx_filter_rank = 0), so queries need no special handling. This only affects
+    /// approximate ribbon builders that are created after the call.
+    pub fn set_single_layer_threshold(&mut self, max: usize) {
+        self.single_layer_threshold = Some(max);
+    }
+
+    /// Encode the blocks for which `filter` returns true with a single layer, as in
+    /// set_single_layer_threshold, whatever the size of their universe. This suits blocks whose
+    /// universe is a closed set that the builder enumerates in full: the exact ribbon is then
+    /// correct on every item of the universe, and queries for items outside of it are rejected
+    /// by the universe metadata. Blocks whose universe is not enumerated in full must not be
+    /// selected, since the exact ribbon answers arbitrarily for items it has not seen. This only
+    /// affects approximate ribbon builders that are created after the call.
+    pub fn set_single_layer_blocks(
+        &mut self,
+        filter: impl Fn(&[u8]) -> bool + Send + Sync + 'static,
+    ) {
+        self.single_layer_blocks = Some(Box::new(filter));
+    }
+
+    /// Measure the time spent hashing items in each ribbon (RibbonReport::hash_time). This
+    /// costs two clock reads per item, so it is off by default. This only affects ribbon
+    /// builders that are created after the call.
+    pub fn set_instrumented(&mut self, instrumented: bool) {
+        self.instrumented = instrumented;
+    }
+
+    /// Require that the clubcard has at most one block, i.e. that at most one ribbon is collected
+    /// in each phase. Such a clubcard can be queried with Clubcard::contains_single_block. If
+    /// more ribbons are collected, collect_exact_ribbons fails with
+    /// ClubcardError::InvalidArgument.
+    pub fn set_single_block(&mut self, single_block: bool) {
+        self.single_block = single_block;
+    }
+
+    /// Flag blocks with more than `max` members, e.g. an issuer that revoked far more
+    /// certificates than usual, in BuildReport::over_capacity when their approximate ribbons are
+    /// collected. If `fail` is true, collect_exact_ribbons also fails with
+    /// ClubcardError::TooManyMembers, so that the clubcard is not built without review.
+    pub fn set_max_members(&mut self, max: usize, fail: bool) {
+        self.max_members = Some((max, fail));
     }
 
-    pub fn collect_approx_ribbons(&mut self, ribbons: Vec<ApproximateRibbon<W, T>>) {
-        self.approx_filter = Some(PartitionedRibbonFilter::from(ribbons));
+    /// Require every block to be a valid B, e.g. FixedBlockId<32> for issuer hashes, so that a
+    /// block identifier with the wrong length or encoding does not silently become a block of
+    /// its own. collect_exact_ribbons fails with ClubcardError::MalformedBlockId otherwise.
+    pub fn set_block_id<B: BlockId>(&mut self) {
+        self.block_id_check = Some(B::is_valid);
     }
 
-    pub fn collect_exact_ribbons(&mut self, ribbons: Vec<Ribbon<W, T, Exact>>) {
-        self.exact_filter = Some(PartitionedRibbonFilter::from(ribbons));
+    /// Name the clubcard, e.g. "delta-42", so that it can be identified with Clubcard::label.
+    /// The label is stored in the build configuration as the annotation LABEL_ANNOTATION.
+    pub fn set_label(&mut self, label: impl Into<String>) {
+        self.config
+            .annotations
+            .insert(LABEL_ANNOTATION.to_string(), label.into());
+    }
+
+    /// Check that every included item passed to an exact ribbon builder is in `universe`, the
+    /// universe metadata that the clubcard will be built with. Queries for an included item
+    /// outside of the universe return NotInUniverse, so such an item would silently never be
+    /// reported as a member. ClubcardBuilder::collect_exact_ribbons rejects ribbons with such
+    /// items. This only affects exact ribbon builders that are created after the call.
+    pub fn set_universe_check(&mut self, universe: T::UniverseMetadata)
+    where
+        T: Queryable<W>,
+        T::UniverseMetadata: Send + Sync + 'static,
+    {
+        self.universe_check = Some(Box::new(move |item: &T| item.in_universe(&universe)));
+    }
+
+    fn is_single_layer_block(&self, block: &[u8]) -> bool {
+        self.single_layer_blocks
+            .as_ref()
+            .is_some_and(|filter| filter(block))
+    }
+
+    fn is_single_layer(&self, universe_size: usize) -> bool {
+        self.single_layer_threshold
+            .is_some_and(|max| universe_size <= max)
+    }
+
+    fn exceeds_max_exceptions(&self, ribbon: &ExactRibbon<W, T>) -> bool {
+        self.config
+            .max_exceptions
+            .is_some_and(|max| ribbon.exceptions.len() > max)
+    }
+
+    /// Statistics about the ribbons that have been collected so far.
+    pub fn report(&self) -> &BuildReport {
+        &self.report
+    }
+
+    pub fn collect_approx_ribbons(&mut self, mut ribbons: Vec<ApproximateRibbon<W, T>>) {
+        ribbons.retain(|ribbon| self.includes_block(&ribbon.id));
+        self.report.approx_ribbons = ribbons.iter().map(|ribbon| ribbon.report()).collect();
+        if let Some((limit, _)) = self.max_members {
+            self.report.over_capacity = ribbons
+                .iter()
+                .filter(|ribbon| ribbon.members() > limit)
+                .map(|ribbon| CapacityWarning {
+                    block: ribbon.id.clone(),
+                    members: ribbon.members(),
+                    limit,
+                })
+                .collect();
+        }
+        let start = Instant::now();
+        let approx_filter =
+            PartitionedRibbonFilter::from_ribbons(ribbons, &mut self.rng, &*self.solver);
+        self.report.approx_solve_time = start.elapsed();
+        record_solve_times(&mut self.report.approx_ribbons, &approx_filter.index);
+        self.approx_filter = Some(approx_filter);
+    }
+
+    /// Build and solve the approximate ribbons from a stream of items that is sorted by block,
+    /// without buffering the items of a block. `block_sizes` maps every block id to the number
+    /// of items that the stream contains for that block and the size of the block's universe.
+    /// Blocks that have no items in the stream receive empty ribbons. Items are not
+    /// deduplicated.
+    ///
+    /// Fails with ClubcardError::InvalidBlock if the size given for a block exceeds the size of
+    /// its universe or differs from the number of items in the stream for it, or if the stream
+    /// has items for a block that is out of order or not in `block_sizes`. No ribbons are
+    /// collected in that case.
+    pub fn collect_approx_ribbons_from_sorted<I>(
+        &mut self,
+        block_sizes: &BTreeMap<Vec<u8>, (usize, usize)>,
+        items: I,
+    ) -> Result<(), ClubcardError>
+    where
+        I: IntoIterator<Item = T>,
+    {
+        let mut items = items.into_iter().peekable();
+        let mut ribbons = Vec::with_capacity(block_sizes.len());
+        for (block, &(size, universe_size)) in block_sizes {
+            let invalid = || ClubcardError::InvalidBlock(block.clone());
+            if size > universe_size {
+                return Err(invalid());
+            }
+            if !self.includes_block(block) {
+                let count =
+                    std::iter::from_fn(|| items.next_if(|item| item.block() == &block[..])).count();
+                if count != size {
+                    return Err(invalid());
+                }
+                continue;
+            }
+            let start = Instant::now();
+            // As in ApproximateRibbon::from, a block that covers its entire universe is encoded
+            // as an inverted empty ribbon.
+            let total = size == universe_size;
+            let mut ribbon = if total {
+                ApproximateRibbon::new(block, 0, universe_size, true, self.config.epsilon)
+            } else {
+                ApproximateRibbon::new(block, size, universe_size, false, self.config.epsilon)
+            };
+            if self.is_single_layer_block(block) || self.is_single_layer(universe_size) {
+                ribbon.rank = 0;
+            }
+            ribbon.instrumented = self.instrumented;
+            let mut count = 0;
+            while let Some(item) = items.next_if(|item| item.block() == &block[..]) {
+                count += 1;
+                if !total {
+                    ribbon.insert(item);
+                }
+            }
+            if count != size {
+                return Err(invalid());
+            }
+            // Insertions should not fail for a homogeneous system.
+            assert!(ribbon.exceptions.is_empty());
+            ribbon.universe_size = universe_size;
+            ribbon.insert_time = start.elapsed();
+            ribbons.push(ribbon);
+        }
+        // The items are sorted by block and every block is listed iff all items were consumed.
+        if let Some(item) = items.next() {
+            return Err(ClubcardError::InvalidBlock(item.block().to_vec()));
+        }
+        self.collect_approx_ribbons(ribbons);
+        Ok(())
+    }
+
+    /// Build and solve the approximate and exact ribbons for every block in `source`, reading
+    /// each block twice. The universe of each block is the set of items that the source returns
+    /// for it. Fails with the first error returned by the source, or as collect_exact_ribbons.
+    /// A block that exceeds BuildConfig::max_exceptions fails the build as soon as its exact
+    /// ribbon is built, without reading the remaining blocks.
+    pub fn collect_ribbons_from_source<S>(&mut self, source: &S) -> Result<(), ClubcardError>
+    where
+        S: ItemSource<W, Item = T>,
+    {
+        let blocks: Vec<Vec<u8>> = source
+            .blocks()
+            .into_iter()
+            .filter(|block| self.includes_block(block))
+            .collect();
+
+        assert!(self.approx_filter.is_none());
+        let mut approx_ribbons = Vec::with_capacity(blocks.len());
+        for block in &blocks {
+            let mut approx_builder = RibbonBuilder::new(block, None).with_config(&self.config);
+            approx_builder.single_layer_threshold = self.single_layer_threshold;
+            approx_builder.single_layer = self.is_single_layer_block(block);
+            approx_builder.instrumented = self.instrumented;
+            let mut universe_size = 0;
+            for item in source.items_for_block(block) {
+                let item = item?;
+                universe_size += 1;
+                if item.included() {
+                    approx_builder.insert(item);
+                }
+            }
+            approx_builder.set_universe_size(universe_size);
+            let ribbon = ApproximateRibbon::from(approx_builder);
+            if let Some(progress) = &self.progress {
+                progress(&ribbon.report());
+            }
+            approx_ribbons.push(ribbon);
+        }
+        self.collect_approx_ribbons(approx_ribbons);
+
+        let mut exact_ribbons = Vec::with_capacity(blocks.len());
+        for block in &blocks {
+            let mut exact_builder = self.new_exact_builder(block);
+            for item in source.items_for_block(block) {
+                exact_builder.insert(item?);
+            }
+            let ribbon = ExactRibbon::from(exact_builder);
+            if let Some(progress) = &self.progress {
+                progress(&ribbon.report());
+            }
+            if self.exceeds_max_exceptions(&ribbon) {
+                return Err(ClubcardError::TooManyExceptions(vec![ribbon.id]));
+            }
+            exact_ribbons.push(ribbon);
+        }
+        self.collect_exact_ribbons(exact_ribbons)
+    }
+
+    /// Same as collect_ribbons_from_source, for items that producer threads send over a
+    /// channel. The items are partitioned by block as they arrive, and the ribbons are built
+    /// once every sender has been dropped. The exact ribbons depend on the approximate ribbons
+    /// of all blocks, so no block can be finalized before then.
+    pub fn collect_ribbons_from_channel(&mut self, items: Receiver<T>) -> Result<(), ClubcardError>
+    where
+        T: Clone,
+    {
+        let mut source: BTreeMap<Vec<u8>, Vec<T>> = BTreeMap::new();
+        for item in items {
+            match source.get_mut(item.block()) {
+                Some(block) => block.push(item),
+                None => {
+                    source.insert(item.block().to_vec(), vec![item]);
+                }
+            }
+        }
+        self.collect_ribbons_from_source(&source)
+    }
+
+    /// Solve the exact ribbons. Fails with ClubcardError::UniverseSizeMismatch if the number of
+    /// items inserted into some exact ribbon differs from the universe size declared for the
+    /// corresponding approximate ribbon, or if there is no corresponding approximate ribbon.
+    /// Ribbons for blocks that are excluded by set_block_filter are dropped before these checks.
+    /// Fails with ClubcardError::DiscriminantTooLong if any ribbon rejected an item under
+    /// BuildConfig::max_discriminant_len, with ClubcardError::UncoveredMembers if any ribbon
+    /// saw an included item outside the universe given to set_universe_check, with
+    /// ClubcardError::TooManyExceptions if any ribbon has more exceptions than
+    /// BuildConfig::max_exceptions, with ClubcardError::TooManyMembers as described in
+    /// set_max_members, with ClubcardError::MalformedBlockId as described in set_block_id, and
+    /// with ClubcardError::InvalidArgument as described in set_single_block.
+    pub fn collect_exact_ribbons(
+        &mut self,
+        mut ribbons: Vec<Ribbon<W, T, Exact>>,
+    ) -> Result<(), ClubcardError> {
+        ribbons.retain(|ribbon| self.includes_block(&ribbon.id));
+        let num_approx_blocks = self
+            .approx_filter
+            .as_ref()
+            .map_or(0, |filter| filter.index.len());
+        if self.single_block && (num_approx_blocks > 1 || ribbons.len() > 1) {
+            return Err(ClubcardError::InvalidArgument(
+                "set_single_block allows at most one block",
+            ));
+        }
+        if let Some(check) = self.block_id_check {
+            let approx_blocks = self
+                .approx_filter
+                .iter()
+                .flat_map(|filter| filter.index.keys());
+            let malformed: BTreeSet<Vec<u8>> = approx_blocks
+                .chain(ribbons.iter().map(|ribbon| &ribbon.id))
+                .filter(|block| !check(block))
+                .cloned()
+                .collect();
+            if !malformed.is_empty() {
+                return Err(ClubcardError::MalformedBlockId(
+                    malformed.into_iter().collect(),
+                ));
+            }
+        }
+        let oversized: Vec<(Vec<u8>, Vec<u8>)> = ribbons
+            .iter()
+            .flat_map(|ribbon| {
+                ribbon
+                    .oversized
+                    .iter()
+                    .map(|discriminant| (ribbon.id.clone(), discriminant.clone()))
+            })
+            .collect();
+        if !oversized.is_empty() {
+            return Err(ClubcardError::DiscriminantTooLong(oversized));
+        }
+        let uncovered: Vec<(Vec<u8>, Vec<u8>)> = ribbons
+            .iter()
+            .flat_map(|ribbon| {
+                ribbon
+                    .uncovered
+                    .iter()
+                    .map(|discriminant| (ribbon.id.clone(), discriminant.clone()))
+            })
+            .collect();
+        if !uncovered.is_empty() {
+            return Err(ClubcardError::UncoveredMembers(uncovered));
+        }
+        let failed: Vec<Vec<u8>> = ribbons
+            .iter()
+            .filter(|ribbon| self.exceeds_max_exceptions(ribbon))
+            .map(|ribbon| ribbon.id.clone())
+            .collect();
+        if !failed.is_empty() {
+            return Err(ClubcardError::TooManyExceptions(failed));
+        }
+        if self.max_members.is_some_and(|(_, fail)| fail) && !self.report.over_capacity.is_empty() {
+            return Err(ClubcardError::TooManyMembers(
+                self.report
+                    .over_capacity
+                    .iter()
+                    .map(|warning| warning.block.clone())
+                    .collect(),
+            ));
+        }
+
+        let approx_filter = self.approx_filter.as_ref();
+        let mismatched: Vec<Vec<u8>> = ribbons
+            .iter()
+            .filter(|ribbon| {
+                approx_filter
+                    .and_then(|filter| filter.index.get(&ribbon.id))
+                    .map_or(true, |entry| entry.universe_size != ribbon.universe_size)
+            })
+            .map(|ribbon| ribbon.id.clone())
+            .collect();
+        if !mismatched.is_empty() {
+            return Err(ClubcardError::UniverseSizeMismatch(mismatched));
+        }
+        self.report.exact_ribbons = ribbons.iter().map(|ribbon| ribbon.report()).collect();
+        if self.retain_members {
+            let mut members = MembersSidecar::default();
+            for ribbon in &mut ribbons {
+                if let Some(discriminants) = ribbon.members.take() {
+                    members.insert(ribbon.id.clone(), discriminants);
+                }
+            }
+            self.members = Some(members);
+        }
+        self.input_digests = ribbons
+            .iter()
+            .filter_map(|ribbon| Some((ribbon.id.clone(), ribbon.input_digest?)))
+            .collect();
+        let start = Instant::now();
+        let exact_filter =
+            PartitionedRibbonFilter::from_ribbons(ribbons, &mut self.rng, &*self.solver);
+        self.report.exact_solve_time = start.elapsed();
+        record_solve_times(&mut self.report.exact_ribbons, &exact_filter.index);
+        self.exact_filter = Some(exact_filter);
+        Ok(())
     }
 
     pub fn build<U: Queryable<W>>(
@@ -482,10 +1687,45 @@ impl<const W: usize, T: Filterable<W>> ClubcardBuilder<W, T> {
         universe: U::UniverseMetadata,
         partition: U::PartitionMetadata,
     ) -> Clubcard<W, U::UniverseMetadata, U::PartitionMetadata> {
+        self.build_with_report::<U>(universe, partition).0
+    }
+
+    /// Same as build, but with a map from block identifiers to per-block information as the
+    /// partition metadata. Entries for blocks that are not in the clubcard (e.g. blocks that
+    /// were excluded by set_block_filter) are dropped. The information can be retrieved with
+    /// Clubcard::block_info and BlockHandle::info.
+    pub fn build_with_block_info<U, K, M>(
+        self,
+        universe: U::UniverseMetadata,
+        mut info: BTreeMap<K, M>,
+    ) -> Clubcard<W, U::UniverseMetadata, BTreeMap<K, M>>
+    where
+        U: Queryable<W, PartitionMetadata = BTreeMap<K, M>>,
+        K: Ord + Borrow<[u8]>,
+    {
+        let clubcard = self.build::<U>(universe, BTreeMap::new());
+        info.retain(|block, _| clubcard.index.contains_key(block.borrow()));
+        Clubcard {
+            partition: info,
+            ..clubcard
+        }
+    }
+
+    /// Same as build, but also returns the BuildReport.
+    pub fn build_with_report<U: Queryable<W>>(
+        self,
+        universe: U::UniverseMetadata,
+        partition: U::PartitionMetadata,
+    ) -> (
+        Clubcard<W, U::UniverseMetadata, U::PartitionMetadata>,
+        BuildReport,
+    ) {
+        let start = Instant::now();
         let mut index: ClubcardIndex = BTreeMap::new();
 
         assert!(self.approx_filter.is_some());
         let approx_filter = self.approx_filter.unwrap();
+        assert!(approx_filter.index.len() <= MAX_BLOCKS);
         for (block, entry) in approx_filter.index {
             let meta = ClubcardIndexEntry {
                 approx_filter_offset: entry.offset,
@@ -495,6 +1735,9 @@ impl<const W: usize, T: Filterable<W>> ClubcardBuilder<W, T> {
                 exact_filter_m: 0,
                 inverted: entry.inverted,
                 exceptions: entry.exceptions,
+                input_digest: None,
+                checksum: None,
+                tombstoned: false,
             };
             index.insert(block, meta);
         }
@@ -508,30 +1751,42 @@ impl<const W: usize, T: Filterable<W>> ClubcardBuilder<W, T> {
             meta.exact_filter_m = entry.m;
             assert!(meta.inverted == entry.inverted);
             meta.exceptions.extend(entry.exceptions);
+            meta.input_digest = self.input_digests.get(&block).copied();
         }
 
-        assert!(exact_filter.solution.len() == 1);
-        let exact_filter = exact_filter.solution.pop().unwrap();
+        // There is no solution if there are no blocks.
+        assert!(exact_filter.solution.len() <= 1);
+        let exact_filter = exact_filter.solution.pop().unwrap_or_default();
+        for meta in index.values_mut() {
+            meta.checksum = Some(block_checksum(&approx_filter.solution, &exact_filter, meta));
+        }
 
-        Clubcard {
+        let clubcard = Clubcard {
             universe,
             partition,
             index,
             approx_filter: approx_filter.solution,
             exact_filter,
-        }
+            build_config: self.config.clone(),
+            members: self.members,
+        };
+        debug_assert!(clubcard.validate().is_ok());
+        let mut report = self.report;
+        report.assemble_time = start.elapsed();
+        (clubcard, report)
     }
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use crate::builder::*;
+    use crate::test_vectors::{generated, BLOCKS};
     use crate::*;
     use rand::distributions::{Distribution, Uniform};
     use rand::Rng;
 
     // Construct the equation a(x) = x_i
-    fn std_eq<const W: usize>(i: usize) -> Equation<W> {
+    pub(crate) fn std_eq<const W: usize>(i: usize) -> Equation<W> {
         let mut a = [0u64; W];
         a[0] = 1;
         Equation::homogeneous(i, a)
@@ -551,7 +1806,7 @@ mod tests {
 
     impl<const W: usize> AsQuery<W> for Equation<W> {
         fn as_query(&self, _m: usize) -> Equation<W> {
-            self.clone()
+            Equation::homogeneous(self.s, self.a)
         }
 
         fn block(&self) -> &[u8] {
@@ -578,6 +1833,72 @@ mod tests {
         }
     }
 
+    // Build a single block clubcard over the universe `items`.
+    pub(crate) fn build_single_block<const W: usize>(items: &[Equation<W>]) -> Clubcard<W, (), ()> {
+        let mut clubcard_builder = ClubcardBuilder::new();
+        let mut approx_builder = clubcard_builder.new_approx_builder(&[]);
+        for item in items.iter().filter(|x| x.included()) {
+            approx_builder.insert(item.clone());
+        }
+        approx_builder.set_universe_size(items.len());
+        clubcard_builder.collect_approx_ribbons(vec![ApproximateRibbon::from(approx_builder)]);
+
+        let mut exact_builder = clubcard_builder.new_exact_builder(&[]);
+        for item in items {
+            exact_builder.insert(item.clone());
+        }
+        clubcard_builder
+            .collect_exact_ribbons(vec![ExactRibbon::from(exact_builder)])
+            .unwrap();
+        let (clubcard, report) = clubcard_builder.build_with_report::<Equation<W>>((), ());
+        assert!(report.approx_ribbons.len() == 1);
+        assert!(report.exact_ribbons.len() == 1);
+        assert!(report.exact_ribbons[0].stats.items <= items.len());
+        clubcard
+    }
+
+    // A universe of n items, in which every 7th item is included. The items have distinct
+    // discriminants (the bytes of a).
+    pub(crate) fn test_items(n: usize) -> Vec<Equation<1>> {
+        (0..n)
+            .map(|i| {
+                let mut eq = std_eq(i);
+                eq.a[0] = 2 * i as u64 + 1;
+                eq.b = (i % 7 != 0) as u8;
+                eq
+            })
+            .collect()
+    }
+
+    // Build a single block clubcard over the universe `items` with collect_ribbons_from_source.
+    pub(crate) fn build_from_source(
+        mut clubcard_builder: ClubcardBuilder<1, Equation<1>>,
+        items: &[Equation<1>],
+    ) -> Clubcard<1, (), ()> {
+        let source = BTreeMap::from([(vec![], items.to_vec())]);
+        clubcard_builder
+            .collect_ribbons_from_source(&source)
+            .unwrap();
+        clubcard_builder.build::<Equation<1>>((), ())
+    }
+
+    #[test]
+    fn test_echelon_rows() {
+        let mut rows = EchelonRows::<2>::new(100);
+        assert!(rows.len() == 100);
+        assert!((0..100).all(|i| rows.is_zero(i)));
+        for s in [0, 63, 64, 99] {
+            rows.set(Equation::inhomogeneous(s, [1, 2], 1));
+        }
+        rows.set(Equation::inhomogeneous(64, [3, 0], 0));
+        assert!(rows.row(63) == Equation::inhomogeneous(63, [1, 2], 1));
+        assert!(rows.row(64) == Equation::inhomogeneous(64, [3, 0], 0));
+        assert!(rows.row(99).b == 1);
+        assert!(rows.is_zero(1) && rows.row(1).b == 0);
+        rows.resize(200);
+        assert!(rows.row(99).b == 1 && rows.is_zero(199));
+    }
+
     #[test]
     fn test_solve_identity() {
         let n = 1024;
@@ -606,15 +1927,15 @@ mod tests {
     fn test_solve_random() {
         let n = 1024;
         const W: usize = 2;
-        let mut r = Ribbon::<W, Equation<W>, Exact>::new(&[0], n, false);
-        let mut s_dist = Uniform::new(0, r.m);
+        let mut r = Ribbon::<W, Equation<W>, Exact>::new(&[0], n, false, DEFAULT_EPSILON);
+        let s_dist = Uniform::new(0, r.m);
         let mut eqs = Vec::with_capacity(n);
         for _ in 0..n {
-            let eq = rand(&mut s_dist);
+            let eq = rand(&s_dist);
             eqs.push(eq.clone());
             r.insert(eq);
         }
-        let x = r.solve(&[]);
+        let x = r.solve(&[], &mut rand::thread_rng(), &BackSubstitution);
         for eq in &eqs {
             assert!(eq.eval(&x) == eq.b);
         }
@@ -647,7 +1968,7 @@ mod tests {
             let eq = std_eq(i);
             assert!(approx_filter.contains(&eq));
         }
-        assert!(approx_filter.solution.len() == 0);
+        assert!(approx_filter.solution.is_empty());
 
         let mut exact_builder = RibbonBuilder::new(&[], Some(&approx_filter));
         for i in 0usize..n {
@@ -670,7 +1991,7 @@ mod tests {
             assert!(exact_filter.contains(&eq));
         }
         assert!(exact_filter.solution.len() == 1);
-        assert!(exact_filter.solution[0].len() == 0);
+        assert!(exact_filter.solution[0].is_empty());
     }
 
     #[test]
@@ -688,10 +2009,788 @@ mod tests {
         let entry = filter.index.get(&vec![]).expect("should have metadata");
         assert!(entry.rank == 0);
         assert!(!entry.inverted);
-        assert!(filter.solution.len() == 0);
+        assert!(filter.solution.is_empty());
         for i in 0usize..n {
             let eq = std_eq(i);
             assert!(filter.contains(&eq));
         }
     }
+
+    #[test]
+    fn test_verify() {
+        let n = 1024;
+        let items = test_items(n);
+
+        let clubcard = build_single_block(&items);
+
+        let report = clubcard.verify(items.iter().cloned());
+        assert!(report.is_ok());
+        assert!(report.checked == n);
+
+        let handle = clubcard.block_handle(&[]).expect("should have block");
+        for item in &items {
+            assert!(handle.unchecked_contains(item) == item.included());
+        }
+        assert!(clubcard.block_handle(&[1]).is_none());
+
+        let flipped = items.iter().map(|x| {
+            let mut x = x.clone();
+            x.b ^= 1;
+            x
+        });
+        let report = clubcard.verify(flipped);
+        assert!(report.false_negatives.len() == items.iter().filter(|x| !x.included()).count());
+        assert!(report.false_positives.len() == items.iter().filter(|x| x.included()).count());
+    }
+
+    #[test]
+    fn test_contains_uniform_time() {
+        let n = 1024;
+        let items = test_items(n);
+
+        let clubcard = build_single_block(&items);
+        for item in &items {
+            let mut other = item.clone();
+            other.b ^= 1;
+            assert!(clubcard.contains_uniform_time(item) == clubcard.contains(item));
+            assert!(clubcard.contains_uniform_time(&other) == clubcard.contains(&other));
+        }
+
+        let clubcard = build_single_block::<1>(&[]);
+        assert!(clubcard.contains_uniform_time(&items[0]) == clubcard.contains(&items[0]));
+        let clubcard = Clubcard::<1, (), ()> {
+            index: BTreeMap::new(),
+            ..clubcard
+        };
+        assert!(clubcard.contains_uniform_time(&items[0]) == Membership::NoData);
+    }
+
+    #[test]
+    fn test_validate() {
+        let n = 1024;
+        let items = test_items(n);
+
+        let clubcard = build_single_block(&items);
+        assert!(clubcard.validate().is_ok());
+        assert!(clubcard.index[&vec![]].approx_filter_rank > 0);
+
+        let invalid = Err(ClubcardError::InvalidBlock(vec![]));
+
+        let mut bad = build_single_block(&items);
+        bad.index.get_mut(&vec![]).unwrap().approx_filter_rank = bad.approx_filter.len() + 1;
+        assert!(bad.validate() == invalid);
+        assert!(bad.validate_block(&[]) == invalid);
+
+        let mut bad = build_single_block(&items);
+        bad.index.get_mut(&vec![]).unwrap().approx_filter_offset = usize::MAX;
+        assert!(bad.validate() == invalid);
+        assert!(bad.validate_block(&[]) == invalid);
+
+        let mut bad = build_single_block(&items);
+        bad.exact_filter.truncate(1);
+        assert!(bad.validate() == invalid);
+        assert!(bad.validate_block(&[]) == invalid);
+
+        let mut bad = build_single_block(&items);
+        let meta = bad.index[&vec![]].clone();
+        bad.index.insert(vec![1], meta);
+        assert!(bad.validate() == Err(ClubcardError::InvalidBlock(vec![1])));
+        assert!(bad.validate_block(&[1]).is_ok());
+        assert!(bad.validate_block(&[2]) == Err(ClubcardError::InvalidBlock(vec![2])));
+
+        let corrupt = Err(ClubcardError::CorruptBlock(vec![]));
+        let mut bad = build_single_block(&items);
+        bad.exact_filter[0] ^= 1 << bad.index[&vec![]].exact_filter_offset;
+        assert!(bad.validate() == corrupt);
+        assert!(bad.validate_block(&[]) == corrupt);
+        bad.index.get_mut(&vec![]).unwrap().checksum = None;
+        assert!(bad.validate().is_ok());
+    }
+
+    #[test]
+    fn test_equivalent() {
+        let n = 1024;
+        let items = test_items(n);
+
+        let clubcard = build_single_block(&items);
+        let copy = Clubcard::<1, (), ()>::from_bytes(&clubcard.to_bytes()).unwrap();
+        assert!(clubcard == copy);
+
+        // A rebuild uses a different seed, so it is not equal, but it answers queries for the
+        // items in its universe in the same way.
+        let rebuilt = build_single_block(&items);
+        assert!(clubcard != rebuilt);
+        assert!(clubcard.equivalent(&rebuilt, items.iter().cloned()));
+
+        let mut other = items.clone();
+        other[0].b ^= 1;
+        let changed = build_single_block(&other);
+        assert!(!clubcard.equivalent(&changed, items.iter().cloned()));
+    }
+
+    #[test]
+    fn test_item_source() {
+        let n = 1024;
+        let items = test_items(n);
+        let clubcard = build_from_source(ClubcardBuilder::new(), &items);
+        assert!(clubcard.verify(items.iter().cloned()).is_ok());
+        assert!(clubcard.equivalent(&build_single_block(&items), items.iter().cloned()));
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let producers: Vec<_> = items
+            .chunks(n / 4)
+            .map(|chunk| {
+                let sender = sender.clone();
+                let chunk = chunk.to_vec();
+                std::thread::spawn(move || {
+                    chunk.into_iter().for_each(|eq| sender.send(eq).unwrap())
+                })
+            })
+            .collect();
+        drop(sender);
+        let mut clubcard_builder = ClubcardBuilder::new();
+        clubcard_builder
+            .collect_ribbons_from_channel(receiver)
+            .unwrap();
+        producers
+            .into_iter()
+            .for_each(|producer| producer.join().unwrap());
+        let clubcard = clubcard_builder.build::<Equation<1>>((), ());
+        assert!(clubcard.verify(items.iter().cloned()).is_ok());
+
+        struct Failing;
+
+        impl ItemSource<1> for Failing {
+            type Item = Equation<1>;
+
+            fn blocks(&self) -> Vec<Vec<u8>> {
+                vec![vec![]]
+            }
+
+            fn items_for_block(
+                &self,
+                _block: &[u8],
+            ) -> impl Iterator<Item = Result<Equation<1>, ClubcardError>> + '_ {
+                [Ok(std_eq(0)), Err(ClubcardError::UnexpectedEof)].into_iter()
+            }
+        }
+
+        let mut clubcard_builder = ClubcardBuilder::new();
+        assert!(
+            clubcard_builder.collect_ribbons_from_source(&Failing)
+                == Err(ClubcardError::UnexpectedEof)
+        );
+    }
+
+    #[test]
+    fn test_single_layer_threshold() {
+        // 1/7 of the universe is included, so a two layer encoding has rank 2.
+        for (n, threshold, rank) in [(256, None, 2), (256, Some(256), 0), (4096, Some(256), 2)] {
+            let items = test_items(n);
+            let mut clubcard_builder = ClubcardBuilder::new();
+            if let Some(threshold) = threshold {
+                clubcard_builder.set_single_layer_threshold(threshold);
+            }
+            let clubcard = build_from_source(clubcard_builder, &items);
+            assert!(clubcard.blocks().next().unwrap().meta.approx_filter_rank == rank);
+            assert!(clubcard.verify(items.iter().cloned()).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_single_layer_blocks() {
+        // 1/7 of the universe is included, so a two layer encoding has rank 2.
+        let items = test_items(4096);
+        for (selected, rank) in [(vec![], 0), (vec![0], 2)] {
+            let mut clubcard_builder = ClubcardBuilder::new();
+            clubcard_builder.set_single_layer_blocks(move |block| block == selected);
+            let clubcard = build_from_source(clubcard_builder, &items);
+            assert!(clubcard.blocks().next().unwrap().meta.approx_filter_rank == rank);
+            assert!(clubcard.verify(items.iter().cloned()).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_instrumented() {
+        let source = BTreeMap::from([(vec![], test_items(128))]);
+        for instrumented in [false, true] {
+            let mut clubcard_builder = ClubcardBuilder::new();
+            clubcard_builder.set_instrumented(instrumented);
+            clubcard_builder
+                .collect_ribbons_from_source(&source)
+                .unwrap();
+            let (clubcard, mut report) = clubcard_builder.build_with_report::<Equation<1>>((), ());
+            report.to_bytes(&clubcard);
+            for r in report.approx_ribbons.iter().chain(&report.exact_ribbons) {
+                assert!(r.hash_time.is_zero() != instrumented);
+                assert!(r.hash_time <= r.insert_time);
+                assert!(!r.solve_time.is_zero());
+            }
+            assert!(report.approx_ribbons[0].solve_time <= report.approx_solve_time);
+            assert!(!report.serialize_time.is_zero());
+
+            let folded = report.folded_stacks();
+            assert!(folded.contains("build;exact;solve; "));
+            assert!(folded.contains("build;serialize "));
+            assert!(folded.contains("build;approx;insert;;hash ") == instrumented);
+            assert!(folded.lines().all(|line| line
+                .rsplit_once(' ')
+                .unwrap()
+                .1
+                .parse::<u128>()
+                .is_ok()));
+        }
+    }
+
+    #[test]
+    fn test_max_members() {
+        // 15 of the 100 items are members. In the total universe, all of them are, and the
+        // approximate ribbon is inverted and has no items.
+        let items = test_items(100);
+        let mut total = items.clone();
+        for item in &mut total {
+            item.b = 0;
+        }
+
+        for (items, members) in [(items, 15), (total, 100)] {
+            let source = BTreeMap::from([(vec![], items)]);
+            let warning = vec![CapacityWarning {
+                block: vec![],
+                members,
+                limit: members - 1,
+            }];
+            for (max, fail, over_capacity, expected) in [
+                (members, true, vec![], Ok(())),
+                (members - 1, false, warning.clone(), Ok(())),
+                (
+                    members - 1,
+                    true,
+                    warning,
+                    Err(ClubcardError::TooManyMembers(vec![vec![]])),
+                ),
+            ] {
+                let mut clubcard_builder = ClubcardBuilder::new();
+                clubcard_builder.set_max_members(max, fail);
+                assert!(clubcard_builder.collect_ribbons_from_source(&source) == expected);
+                assert!(clubcard_builder.report().over_capacity == over_capacity);
+            }
+        }
+
+        let mut clubcard_builder = ClubcardBuilder::<1, Equation<1>>::new();
+        clubcard_builder.set_max_members(99, false);
+        let block_sizes = BTreeMap::from([(vec![], (100, 100))]);
+        clubcard_builder
+            .collect_approx_ribbons_from_sorted(&block_sizes, (0..100).map(std_eq))
+            .unwrap();
+        assert!(clubcard_builder.report().over_capacity[0].members == 100);
+    }
+
+    #[test]
+    fn test_set_block_id() {
+        let items = test_items(100);
+        for (block, expected) in [
+            (vec![1; 32], Ok(())),
+            (vec![], Err(ClubcardError::MalformedBlockId(vec![vec![]]))),
+            (
+                vec![1; 33],
+                Err(ClubcardError::MalformedBlockId(vec![vec![1; 33]])),
+            ),
+        ] {
+            let source = BTreeMap::from([(block.clone(), items.clone())]);
+            let mut clubcard_builder = ClubcardBuilder::new();
+            clubcard_builder.set_block_id::<crate::FixedBlockId<32>>();
+            assert!(clubcard_builder.collect_ribbons_from_source(&source) == expected);
+
+            let mut clubcard_builder = ClubcardBuilder::new();
+            clubcard_builder.set_block_id::<Vec<u8>>();
+            assert!(clubcard_builder
+                .collect_ribbons_from_source(&source)
+                .is_ok());
+        }
+    }
+
+    #[test]
+    fn test_max_exceptions() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        // Two items with the same equation and different membership cannot both be encoded,
+        // so each pair below produces one exception in the exact ribbon.
+        let mut items = test_items(256);
+        for i in [0, 7] {
+            let mut eq = items[i].clone();
+            eq.b = 1;
+            items.push(eq);
+        }
+        let source = BTreeMap::from([(vec![], items)]);
+
+        for (max_exceptions, expected) in [
+            (None, Ok(())),
+            (Some(2), Ok(())),
+            (Some(1), Err(ClubcardError::TooManyExceptions(vec![vec![]]))),
+        ] {
+            let reports = Arc::new(AtomicUsize::new(0));
+            let mut clubcard_builder = ClubcardBuilder::new_with_config(BuildConfig {
+                max_exceptions,
+                ..Default::default()
+            });
+            let counter = reports.clone();
+            clubcard_builder.set_progress(move |report| {
+                assert!(report.block.is_empty());
+                counter.fetch_add(1, Ordering::Relaxed);
+            });
+            assert!(clubcard_builder.collect_ribbons_from_source(&source) == expected);
+            assert!(reports.load(Ordering::Relaxed) == 2);
+        }
+    }
+
+    #[test]
+    fn test_set_solver() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        struct CountingSolver(Arc<AtomicUsize>);
+
+        impl RibbonSolver<1> for CountingSolver {
+            fn solve(
+                &self,
+                rows: &EchelonRows<1>,
+                tail: &[u64],
+                rng: &mut dyn RngCore,
+            ) -> Vec<u64> {
+                self.0.fetch_add(1, Ordering::Relaxed);
+                BackSubstitution.solve(rows, tail, rng)
+            }
+        }
+
+        let items = test_items(1024);
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut clubcard_builder = ClubcardBuilder::new();
+        clubcard_builder.set_solver(CountingSolver(calls.clone()));
+        let clubcard = build_from_source(clubcard_builder, &items);
+        assert!(clubcard.verify(items).is_ok());
+        // One call per column of X, and one for Y.
+        let rank = clubcard.index[&vec![]].approx_filter_rank;
+        assert!(calls.load(Ordering::Relaxed) == rank + 1);
+    }
+
+    #[test]
+    fn test_layers() {
+        let n = 1024;
+        let items = test_items(n);
+
+        let clubcard = build_single_block(&items);
+
+        let mut approx_false_positives = 0;
+        for item in &items {
+            let approx = clubcard.approx_contains(item);
+            let exact = clubcard.exact_bit(item).expect("should have block");
+            if item.included() {
+                assert!(approx && exact == 0);
+            } else if approx {
+                approx_false_positives += 1;
+                assert!(exact == 1);
+            }
+        }
+        assert!(approx_false_positives < items.iter().filter(|x| !x.included()).count());
+    }
+
+    #[test]
+    fn test_duplicates() {
+        // Two items with the same discriminant but conflicting inclusion status.
+        let included = Equation::inhomogeneous(0, [0b11], 0);
+        let excluded = Equation::inhomogeneous(0, [0b11], 1);
+
+        let mut builder = RibbonBuilder::<1, Equation<1>>::new(&[], None);
+        builder.insert(included.clone());
+        builder.insert(excluded.clone());
+        let ribbon = ExactRibbon::from(builder);
+        assert!(ribbon.duplicates() == 0);
+        assert!(ribbon.exceptions.len() == 1);
+
+        let config = BuildConfig {
+            dedup: true,
+            ..Default::default()
+        };
+        let mut builder = RibbonBuilder::<1, Equation<1>>::new(&[], None).with_config(&config);
+        builder.insert(included);
+        builder.insert(excluded);
+        let ribbon = ExactRibbon::from(builder);
+        assert!(ribbon.duplicates() == 1);
+        assert!(ribbon.exceptions.is_empty());
+    }
+
+    #[test]
+    fn test_universe_size_mismatch() {
+        let n = 1024;
+        let mut clubcard_builder = ClubcardBuilder::<1, Equation<1>>::new();
+        let mut approx_builder = clubcard_builder.new_approx_builder(&[0]);
+        approx_builder.insert(std_eq(0));
+        approx_builder.set_universe_size(n);
+        clubcard_builder.collect_approx_ribbons(vec![ApproximateRibbon::from(approx_builder)]);
+
+        // One item short of the declared universe, and an unknown block.
+        let mut short_builder = clubcard_builder.new_exact_builder(&[0]);
+        for i in 0..n - 1 {
+            short_builder.insert(std_eq(i));
+        }
+        let unknown_builder = clubcard_builder.new_exact_builder(&[1]);
+        let ribbons = vec![
+            ExactRibbon::from(short_builder),
+            ExactRibbon::from(unknown_builder),
+        ];
+        assert!(
+            clubcard_builder.collect_exact_ribbons(ribbons)
+                == Err(ClubcardError::UniverseSizeMismatch(vec![vec![0], vec![1]]))
+        );
+
+        let mut exact_builder = clubcard_builder.new_exact_builder(&[0]);
+        for i in 0..n {
+            exact_builder.insert(std_eq(i));
+        }
+        assert!(clubcard_builder
+            .collect_exact_ribbons(vec![ExactRibbon::from(exact_builder)])
+            .is_ok());
+    }
+
+    #[test]
+    fn test_block_filter() {
+        let n = 64;
+        let mut clubcard_builder = ClubcardBuilder::<1, Equation<1>>::new();
+        clubcard_builder.set_block_filter(|block| block != [1]);
+        assert!(clubcard_builder.includes_block(&[0]));
+        assert!(!clubcard_builder.includes_block(&[1]));
+
+        let mut approx_ribbons = vec![];
+        for block in 0..2u8 {
+            let mut approx_builder = clubcard_builder.new_approx_builder(&[block]);
+            approx_builder.insert(std_eq(0));
+            approx_builder.set_universe_size(n);
+            approx_ribbons.push(ApproximateRibbon::from(approx_builder));
+        }
+        clubcard_builder.collect_approx_ribbons(approx_ribbons);
+        assert!(clubcard_builder.report().approx_ribbons.len() == 1);
+
+        // The exact ribbon for the excluded block would otherwise be reported as unknown.
+        let mut exact_ribbons = vec![];
+        for block in 0..2u8 {
+            let mut exact_builder = clubcard_builder.new_exact_builder(&[block]);
+            for i in 0..n {
+                exact_builder.insert(std_eq(i));
+            }
+            exact_ribbons.push(ExactRibbon::from(exact_builder));
+        }
+        assert!(clubcard_builder
+            .collect_exact_ribbons(exact_ribbons)
+            .is_ok());
+
+        let clubcard = clubcard_builder.build::<Equation<1>>((), ());
+        assert!(clubcard.index.contains_key(&vec![0]));
+        assert!(!clubcard.index.contains_key(&vec![1]));
+    }
+
+    #[test]
+    fn test_block_info() {
+        struct InfoQuery;
+
+        impl AsQuery<1> for InfoQuery {
+            fn as_query(&self, m: usize) -> Equation<1> {
+                Equation::homogeneous(m / 2, [1])
+            }
+
+            fn block(&self) -> &[u8] {
+                &[0]
+            }
+
+            fn discriminant(&self) -> &[u8] {
+                &[]
+            }
+        }
+
+        impl Queryable<1> for InfoQuery {
+            type UniverseMetadata = ();
+            type PartitionMetadata = BTreeMap<[u8; 1], &'static str>;
+
+            fn in_universe(&self, _meta: &()) -> bool {
+                true
+            }
+        }
+
+        let mut clubcard_builder = ClubcardBuilder::<1, Equation<1>>::new();
+        clubcard_builder.set_block_filter(|block| block != [1]);
+        let mut approx_ribbons = vec![];
+        for block in 0..2u8 {
+            let mut approx_builder = clubcard_builder.new_approx_builder(&[block]);
+            approx_builder.set_universe_size(1);
+            approx_ribbons.push(ApproximateRibbon::from(approx_builder));
+        }
+        clubcard_builder.collect_approx_ribbons(approx_ribbons);
+        let mut exact_builder = clubcard_builder.new_exact_builder(&[0]);
+        exact_builder.insert(Equation::inhomogeneous(0, [1], 1));
+        clubcard_builder
+            .collect_exact_ribbons(vec![ExactRibbon::from(exact_builder)])
+            .unwrap();
+
+        let info = BTreeMap::from([([0], "zero"), ([1], "one"), ([2], "two")]);
+        let clubcard = clubcard_builder.build_with_block_info::<InfoQuery, _, _>((), info);
+        assert!(clubcard.partition().len() == 1);
+        assert!(clubcard.block_info(&[0]) == Some(&"zero"));
+        assert!(clubcard.block_info(&[1]).is_none());
+        let handle = clubcard.block_handle(InfoQuery.block()).unwrap();
+        assert!(handle.block() == [0]);
+        assert!(handle.info() == Some(&"zero"));
+        assert!(clubcard.contains(&InfoQuery) == Membership::Nonmember);
+        assert!(clubcard.contains_with_info(&InfoQuery) == (Membership::Nonmember, Some(&"zero")));
+    }
+
+    #[test]
+    fn test_build_config() {
+        let config = BuildConfig {
+            epsilon: 0.5,
+            seed: Some(1),
+            ..Default::default()
+        };
+        let mut clubcard_builder = ClubcardBuilder::<1, Equation<1>>::new_with_config(config);
+        assert!(clubcard_builder.config().epsilon == 0.5);
+        let mut approx_builder = clubcard_builder.new_approx_builder(&[]);
+        for i in 0..100 {
+            approx_builder.insert(std_eq(i));
+        }
+        approx_builder.set_universe_size(1000);
+        let ribbon = ApproximateRibbon::from(approx_builder);
+        assert!(ribbon.stats().m == 150);
+        clubcard_builder.collect_approx_ribbons(vec![ribbon]);
+
+        let mut exact_builder = clubcard_builder.new_exact_builder(&[]);
+        exact_builder.insert(std_eq(0));
+        exact_builder.insert(std_eq(1));
+        assert!(ExactRibbon::from(exact_builder).stats().m == 3);
+
+        assert!(clubcard_builder.config().label().is_none());
+        clubcard_builder.set_label("delta-42");
+        assert!(clubcard_builder.config().label() == Some("delta-42"));
+    }
+
+    #[test]
+    fn test_reset_and_clear() {
+        let mut clubcard_builder = ClubcardBuilder::<1, Equation<1>>::new();
+        let mut approx_builder = clubcard_builder.new_approx_builder_with_capacity(&[], 16);
+        let capacity = approx_builder.items.capacity();
+        assert!(capacity >= 16);
+        for i in 0..16 {
+            approx_builder.insert(std_eq(i));
+        }
+        approx_builder.set_universe_size(32);
+        approx_builder.clear();
+        assert!(approx_builder.items.is_empty());
+        assert!(approx_builder.items.capacity() == capacity);
+        assert!(approx_builder.universe_size == 0);
+
+        approx_builder.set_universe_size(32);
+        clubcard_builder.collect_approx_ribbons(vec![ApproximateRibbon::from(approx_builder)]);
+        assert!(clubcard_builder.approx_filter.is_some());
+        clubcard_builder.reset();
+        assert!(clubcard_builder.approx_filter.is_none());
+        assert!(clubcard_builder.exact_filter.is_none());
+    }
+
+    #[test]
+    fn test_concurrent_queries() {
+        let n = 1024;
+        let items: Vec<Equation<1>> = (0..n)
+            .map(|i| {
+                let mut eq = std_eq(i);
+                eq.b = (i % 3 != 0) as u8;
+                eq
+            })
+            .collect();
+
+        let clubcard = std::sync::Arc::new(build_single_block(&items));
+
+        let threads: Vec<_> = (0..4)
+            .map(|t| {
+                let clubcard = clubcard.clone();
+                let items = items.clone();
+                std::thread::spawn(move || {
+                    for item in items.iter().skip(t).step_by(4) {
+                        assert!(clubcard.contains(item) == item.included().into());
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_select_blocks() {
+        let n = 1024;
+        let candidate = |block: u8, members: usize, priority: f64| BlockCandidate {
+            block: vec![block],
+            universe_size: n,
+            members,
+            priority,
+        };
+
+        assert!(candidate(0, 0, 1.0).estimated_size().unwrap() < 100);
+        assert!(candidate(0, n, 1.0).estimated_size().unwrap() < 100);
+        assert!(candidate(0, n + 1, 1.0).estimated_size().is_none());
+
+        let candidates = [
+            candidate(0, 100, 1.0),
+            candidate(1, 100, 2.0),
+            candidate(2, 400, 1.0),
+        ];
+        let size = |i: usize| candidates[i].estimated_size().unwrap();
+        // Block 2 has the most members per byte, then block 1, then block 0.
+        let selection = select_blocks(&candidates, size(2) + size(1)).unwrap();
+        assert!(selection.included == vec![vec![2], vec![1]]);
+        assert!(selection.excluded == vec![vec![0]]);
+        assert!(selection.estimated_size == size(2) + size(1));
+
+        let selection = select_blocks(&candidates, 0).unwrap();
+        assert!(selection.included.is_empty());
+        assert!(selection.excluded.len() == 3);
+
+        let invalid = [candidate(0, 100, 1.0), candidate(3, n + 1, 1.0)];
+        assert!(select_blocks(&invalid, 0).err() == Some(ClubcardError::InvalidBlock(vec![3])));
+    }
+
+    #[test]
+    fn test_ribbon_stats() {
+        let n = 1024;
+        let mut builder = RibbonBuilder::new(&[], None);
+        builder.set_universe_size(16 * n);
+        for i in 0usize..n {
+            let eq: Equation<1> = std_eq(i);
+            builder.insert(eq);
+        }
+        let stats = ApproximateRibbon::from(builder).stats();
+        assert!(stats.items == n);
+        assert!(stats.rank == 3);
+        assert!(stats.m == 1044);
+        assert!(stats.rows == 1044);
+        assert!(stats.slack() == 0);
+        assert!(stats.bits() == 3 * 1044);
+        assert!(stats.load_factor() == n as f64 / 1044.0);
+    }
+
+    #[test]
+    fn test_approx_ribbons_from_sorted() {
+        let n = 1024;
+        let members: Vec<Equation<1>> = (0..n).step_by(5).map(std_eq).collect();
+        let block_sizes = BTreeMap::from([(vec![], (members.len(), n))]);
+
+        let mut clubcard_builder = ClubcardBuilder::<1, Equation<1>>::new();
+        clubcard_builder
+            .collect_approx_ribbons_from_sorted(&block_sizes, members.clone())
+            .unwrap();
+        let approx_filter = clubcard_builder.approx_filter.as_ref().unwrap();
+        let entry = approx_filter.index.get(&vec![]).unwrap();
+        assert!(entry.universe_size == n);
+        assert!(entry.rank == 1);
+        for member in &members {
+            assert!(approx_filter.contains(member));
+        }
+
+        let len = members.len();
+        for block_sizes in [
+            BTreeMap::from([(vec![], (n + 1, n))]),
+            BTreeMap::from([(vec![], (len + 1, n))]),
+            BTreeMap::new(),
+        ] {
+            let mut clubcard_builder = ClubcardBuilder::<1, Equation<1>>::new();
+            assert!(
+                clubcard_builder.collect_approx_ribbons_from_sorted(&block_sizes, members.clone())
+                    == Err(ClubcardError::InvalidBlock(vec![]))
+            );
+            assert!(clubcard_builder.approx_filter.is_none());
+        }
+    }
+
+    #[test]
+    fn test_max_discriminant_len() {
+        let n = 16;
+        let mut clubcard_builder =
+            ClubcardBuilder::<1, Equation<1>>::new_with_config(BuildConfig {
+                max_discriminant_len: Some(0),
+                ..Default::default()
+            });
+        let mut approx_builder = clubcard_builder.new_approx_builder(&[]);
+        approx_builder.insert(std_eq(0));
+        approx_builder.set_universe_size(n);
+        clubcard_builder.collect_approx_ribbons(vec![ApproximateRibbon::from(approx_builder)]);
+
+        // Every item has a non-empty discriminant.
+        let mut exact_builder = clubcard_builder.new_exact_builder(&[]);
+        for i in 0..n {
+            exact_builder.insert(std_eq(i));
+        }
+        let ribbon = ExactRibbon::from(exact_builder);
+        let Err(ClubcardError::DiscriminantTooLong(oversized)) =
+            clubcard_builder.collect_exact_ribbons(vec![ribbon])
+        else {
+            panic!("expected DiscriminantTooLong");
+        };
+        assert!(oversized.len() == n);
+        assert!(oversized[0] == (vec![], std_eq::<1>(0).discriminant().to_vec()));
+    }
+
+    #[test]
+    fn test_universe_check() {
+        use crate::testing::oracle::random_universe;
+
+        let items = random_universe::<1>(0, &[256], 0.5);
+        let source = BTreeMap::from([(items[0].block.clone(), items.clone())]);
+        let mut clubcard_builder = ClubcardBuilder::new();
+        clubcard_builder.set_universe_check(50);
+        let Err(ClubcardError::UncoveredMembers(uncovered)) =
+            clubcard_builder.collect_ribbons_from_source(&source)
+        else {
+            panic!("expected UncoveredMembers");
+        };
+        let expected: Vec<(Vec<u8>, Vec<u8>)> = items
+            .iter()
+            .filter(|item| item.included && item.timestamp >= 50)
+            .map(|item| (item.block.clone(), item.discriminant.clone()))
+            .collect();
+        assert!(!expected.is_empty());
+        assert!(uncovered == expected);
+
+        let mut clubcard_builder = ClubcardBuilder::new();
+        clubcard_builder.set_universe_check(100);
+        assert!(clubcard_builder
+            .collect_ribbons_from_source(&source)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_estimated_size() {
+        for seed in 0..4 {
+            let (clubcard, queries) = generated(seed);
+            for (block, (universe_size, _)) in BLOCKS.iter().enumerate() {
+                let block = [block as u8];
+                let members = queries
+                    .iter()
+                    .filter(|(query, expected)| {
+                        query.block == block && *expected == Membership::Member
+                    })
+                    .count();
+                let candidate = BlockCandidate {
+                    block: block.to_vec(),
+                    universe_size: *universe_size as usize,
+                    members,
+                    priority: 1.0,
+                };
+                let stats = clubcard.block_stats(&block).unwrap();
+                let actual = stats.bits().div_ceil(8) + 8 + 1 + 5 * 8 + 1 + 8;
+                let estimate = candidate.estimated_size().unwrap();
+                assert!(
+                    actual.abs_diff(estimate) * 4 <= actual,
+                    "{actual} {estimate}"
+                );
+            }
+        }
+    }
 }
@@ -0,0 +1,215 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use crate::{ClubcardHandle, Membership, Queryable};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+/// (block, discriminant)
+type CacheKey = (Vec<u8>, Vec<u8>);
+
+/// A least-recently-used map from items to query results for one version of a clubcard.
+struct Lru {
+    /// The sequence number of the clubcard that produced the cached results.
+    sequence: u64,
+    capacity: usize,
+    /// Incremented on every access. Entries are evicted in order of their last access time.
+    clock: u64,
+    entries: HashMap<CacheKey, (Membership, u64)>,
+    by_access: BTreeMap<u64, CacheKey>,
+}
+
+impl Lru {
+    fn get(&mut self, key: &CacheKey) -> Option<Membership> {
+        let (result, accessed) = self.entries.get_mut(key)?;
+        let key = self.by_access.remove(accessed).unwrap();
+        self.clock += 1;
+        *accessed = self.clock;
+        self.by_access.insert(self.clock, key);
+        Some(*result)
+    }
+
+    fn insert(&mut self, key: CacheKey, result: Membership) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.clock += 1;
+        if let Some((_, accessed)) = self.entries.insert(key.clone(), (result, self.clock)) {
+            self.by_access.remove(&accessed);
+        }
+        self.by_access.insert(self.clock, key);
+        if self.entries.len() > self.capacity {
+            let (_, oldest) = self.by_access.pop_first().unwrap();
+            self.entries.remove(&oldest);
+        }
+    }
+
+    fn clear(&mut self, sequence: u64) {
+        self.sequence = sequence;
+        self.entries.clear();
+        self.by_access.clear();
+    }
+}
+
+/// A ClubcardHandle with an LRU cache of query results.
+///
+/// Results are cached by (block, discriminant) and are tagged with the sequence number of the
+/// clubcard that produced them, so the cache is emptied the first time a query observes a new
+/// clubcard. Only the filter lookup is cached. The universe check depends on more of the item
+/// than its block and discriminant, so it is repeated on every query.
+pub struct CachedClubcard<const W: usize, UniverseMetadata, PartitionMetadata> {
+    handle: Arc<ClubcardHandle<W, UniverseMetadata, PartitionMetadata>>,
+    cache: Mutex<Lru>,
+}
+
+impl<const W: usize, UniverseMetadata, PartitionMetadata>
+    CachedClubcard<W, UniverseMetadata, PartitionMetadata>
+{
+    /// Cache the results of up to `capacity` queries against the clubcards in `handle`. With a
+    /// capacity of 0, nothing is cached.
+    pub fn new(
+        handle: Arc<ClubcardHandle<W, UniverseMetadata, PartitionMetadata>>,
+        capacity: usize,
+    ) -> Self {
+        let sequence = handle.sequence();
+        CachedClubcard {
+            handle,
+            cache: Mutex::new(Lru {
+                sequence,
+                capacity,
+                clock: 0,
+                entries: HashMap::with_capacity(capacity),
+                by_access: BTreeMap::new(),
+            }),
+        }
+    }
+
+    pub fn handle(&self) -> &Arc<ClubcardHandle<W, UniverseMetadata, PartitionMetadata>> {
+        &self.handle
+    }
+
+    /// The number of cached results.
+    pub fn len(&self) -> usize {
+        self.cache.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Same as Clubcard::contains on the current clubcard of the handle.
+    pub fn contains<T>(&self, item: &T) -> Membership
+    where
+        T: Queryable<W, UniverseMetadata = UniverseMetadata, PartitionMetadata = PartitionMetadata>,
+    {
+        let (sequence, clubcard) = self.handle.load_with_sequence();
+        if !item.in_universe(clubcard.universe()) {
+            return Membership::NotInUniverse;
+        }
+
+        let key = (item.block().to_vec(), item.discriminant().to_vec());
+        {
+            let mut cache = self.cache.lock().unwrap();
+            if cache.sequence < sequence {
+                cache.clear(sequence);
+            }
+            if cache.sequence == sequence {
+                if let Some(result) = cache.get(&key) {
+                    return result;
+                }
+            }
+        }
+
+        let result = match clubcard.block_handle(item.block()) {
+            Some(handle) => handle.contains(item),
+            None => Membership::NoData,
+        };
+
+        let mut cache = self.cache.lock().unwrap();
+        if cache.sequence < sequence {
+            cache.clear(sequence);
+        }
+        // A result from a clubcard that has already been replaced is not cached.
+        if cache.sequence == sequence {
+            cache.insert(key, result);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AsQuery, Clubcard, Equation};
+
+    struct Query {
+        discriminant: [u8; 1],
+    }
+
+    impl AsQuery<1> for Query {
+        fn as_query(&self, _m: usize) -> Equation<1> {
+            Equation::homogeneous(0, [1])
+        }
+
+        fn block(&self) -> &[u8] {
+            &[]
+        }
+
+        fn discriminant(&self) -> &[u8] {
+            &self.discriminant
+        }
+    }
+
+    impl Queryable<1> for Query {
+        type UniverseMetadata = u8;
+        type PartitionMetadata = ();
+
+        fn in_universe(&self, bound: &u8) -> bool {
+            self.discriminant[0] < *bound
+        }
+    }
+
+    fn empty_clubcard(bound: u8) -> Clubcard<1, u8, ()> {
+        Clubcard::empty(bound, ())
+    }
+
+    fn query(discriminant: u8) -> Query {
+        Query {
+            discriminant: [discriminant],
+        }
+    }
+
+    #[test]
+    fn test_cache() {
+        let handle = Arc::new(ClubcardHandle::new(1, empty_clubcard(4)));
+        let cached = CachedClubcard::new(handle.clone(), 2);
+        assert!(cached.contains(&query(4)) == Membership::NotInUniverse);
+        assert!(cached.is_empty());
+
+        assert!(cached.contains(&query(0)) == Membership::NoData);
+        assert!(cached.contains(&query(1)) == Membership::NoData);
+        assert!(cached.contains(&query(0)) == Membership::NoData);
+        assert!(cached.len() == 2);
+
+        // query(1) is the least recently used.
+        assert!(cached.contains(&query(2)) == Membership::NoData);
+        let cache = cached.cache.lock().unwrap();
+        assert!(cache.entries.contains_key(&(vec![], vec![0])));
+        assert!(!cache.entries.contains_key(&(vec![], vec![1])));
+        drop(cache);
+
+        // The universe check is not cached.
+        handle.swap(2, empty_clubcard(1)).unwrap();
+        assert!(cached.contains(&query(2)) == Membership::NotInUniverse);
+        assert!(cached.len() == 2);
+        assert!(cached.contains(&query(0)) == Membership::NoData);
+        assert!(cached.len() == 1);
+        assert!(cached.cache.lock().unwrap().sequence == 2);
+
+        let uncached = CachedClubcard::new(handle, 0);
+        assert!(uncached.contains(&query(0)) == Membership::NoData);
+        assert!(uncached.contains(&query(0)) == Membership::NoData);
+        assert!(uncached.is_empty());
+    }
+}
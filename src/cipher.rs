@@ -0,0 +1,360 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use crate::clubcard::{block_checksum, block_span, encode_clubcard, ClubcardIndex};
+use crate::config::BuildConfig;
+use crate::encoding::{Decode, Encode};
+use crate::{Clubcard, ClubcardError, ClubcardIndexEntry};
+
+/// The key of the annotation that holds the nonce of a clubcard serialized with
+/// Clubcard::to_bytes_encrypted.
+pub const NONCE_ANNOTATION: &str = "nonce";
+
+/// A column of the filters of a serialized clubcard: one of the columns of the matrix X, or
+/// the vector Y.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FilterColumn {
+    Approx(usize),
+    Exact,
+}
+
+/// A keystream that is XORed into the filters of a serialized clubcard, e.g. the output of a
+/// stream cipher under a per-recipient key, together with a MAC that authenticates each block.
+/// The header, including the index, stays in the clear.
+///
+/// Every block has its own keystream for each column, keyed by the nonce of the clubcard and
+/// the block's identifier, so that LazyClubcard can decrypt one block without reading the
+/// others. The nonce is stored in the header (see NONCE_ANNOTATION) and must not be reused for
+/// two clubcards encrypted under the same key; a counter or the publication time will do.
+pub trait FilterCipher: Send + Sync {
+    /// Fill `out` with words `first..first + out.len()` of the keystream of `block` in
+    /// `column`. Word k is XORed into bits `64 * k..64 * k + 64` of the bits that the block
+    /// occupies in the column.
+    fn keystream(
+        &self,
+        nonce: u64,
+        block: &[u8],
+        column: FilterColumn,
+        first: usize,
+        out: &mut [u64],
+    );
+
+    /// A MAC of `checksum` for `block`. The checksum covers the plaintext bits that queries
+    /// against the block read, which include the first bits of the blocks after it.
+    fn tag(&self, nonce: u64, block: &[u8], checksum: u64) -> u64;
+}
+
+/// The bits of each column that each block occupies, sorted by position.
+pub(crate) struct BlockSpans {
+    /// The first bit, the end, the rank and the identifier of each block in X.
+    approx: Vec<(usize, usize, usize, Vec<u8>)>,
+    /// The same for Y, whose rank is 1.
+    exact: Vec<(usize, usize, usize, Vec<u8>)>,
+}
+
+impl BlockSpans {
+    pub(crate) fn new(index: &ClubcardIndex) -> Self {
+        let mut approx = vec![];
+        let mut exact = vec![];
+        for (block, meta) in index {
+            let span = |offset: usize, m: usize| (offset, offset.saturating_add(m));
+            let (start, end) = span(meta.approx_filter_offset, meta.approx_filter_m);
+            approx.push((start, end, meta.approx_filter_rank, block.clone()));
+            let (start, end) = span(meta.exact_filter_offset, meta.exact_filter_m);
+            exact.push((start, end, 1, block.clone()));
+        }
+        approx.sort_unstable();
+        exact.sort_unstable();
+        BlockSpans { approx, exact }
+    }
+
+    /// XOR the keystream of every block into `words`, which hold bits `first_bit..` of
+    /// `column`. This both encrypts and decrypts.
+    pub(crate) fn apply(
+        &self,
+        cipher: &dyn FilterCipher,
+        nonce: u64,
+        column: FilterColumn,
+        first_bit: usize,
+        words: &mut [u64],
+    ) {
+        let (spans, i) = match column {
+            FilterColumn::Approx(i) => (&self.approx, i),
+            FilterColumn::Exact => (&self.exact, 0),
+        };
+        let end_bit = first_bit.saturating_add(64 * words.len());
+        // Spans do not overlap in a valid clubcard, so they are sorted by their ends too. In
+        // an invalid one, blocks may be left encrypted, and then fail authentication.
+        let skip = spans.partition_point(|span| span.1 <= first_bit);
+        for (start, end, rank, block) in &spans[skip..] {
+            if *start >= end_bit {
+                break;
+            }
+            if i < *rank {
+                let position = *start as i128 - first_bit as i128;
+                xor_block(cipher, nonce, block, column, words, position, end - start);
+            }
+        }
+    }
+}
+
+/// XOR the keystream of `block` in `column`, which is `len` bits long, into `words` starting at
+/// bit `start` of `words`. Bits outside of `words` are skipped.
+fn xor_block(
+    cipher: &dyn FilterCipher,
+    nonce: u64,
+    block: &[u8],
+    column: FilterColumn,
+    words: &mut [u64],
+    start: i128,
+    len: usize,
+) {
+    let end = 64 * words.len() as i128 - start;
+    if end <= 0 || len == 0 {
+        return;
+    }
+    let first = match start {
+        start if start < 0 => (-start / 64) as usize,
+        _ => 0,
+    };
+    let last = len.div_ceil(64).min((end as usize).div_ceil(64));
+    if first >= last {
+        return;
+    }
+    let mut keystream = vec![0u64; last - first];
+    cipher.keystream(nonce, block, column, first, &mut keystream);
+    for (k, key) in (first..).zip(keystream) {
+        let key = match len - 64 * k {
+            rest if rest < 64 => key & ((1 << rest) - 1),
+            _ => key,
+        };
+        let position = start + 64 * k as i128;
+        if position < 0 {
+            words[0] ^= key >> (-position) as u32;
+            continue;
+        }
+        let (i, shift) = (position as usize / 64, position as usize % 64);
+        words[i] ^= key << shift;
+        if let Some(word) = words.get_mut(i + 1).filter(|_| shift > 0) {
+            *word ^= key >> (64 - shift);
+        }
+    }
+}
+
+/// The checksum of the bits that queries against the block with index entry `meta` read.
+fn span_checksum<const W: usize>(
+    approx_filter: &[Vec<u64>],
+    exact_filter: &[u64],
+    meta: &ClubcardIndexEntry,
+) -> u64 {
+    let span = ClubcardIndexEntry {
+        approx_filter_m: block_span::<W>(meta.approx_filter_m),
+        exact_filter_m: block_span::<W>(meta.exact_filter_m),
+        ..meta.clone()
+    };
+    block_checksum(approx_filter, exact_filter, &span)
+}
+
+/// The nonce stored in the build configuration of an encrypted clubcard.
+pub(crate) fn nonce(build_config: &BuildConfig) -> Result<u64, ClubcardError> {
+    build_config
+        .annotations
+        .get(NONCE_ANNOTATION)
+        .and_then(|nonce| nonce.parse().ok())
+        .ok_or(ClubcardError::Malformed)
+}
+
+/// Check the tag of every block of `clubcard`, which has just been decrypted, and replace it
+/// with the checksum of the block. Fails with ClubcardError::CorruptBlock for the first block
+/// whose tag does not match, which is also what happens when the cipher or the nonce is wrong.
+pub(crate) fn authenticate<const W: usize, UniverseMetadata, PartitionMetadata>(
+    cipher: &dyn FilterCipher,
+    nonce: u64,
+    clubcard: &mut Clubcard<W, UniverseMetadata, PartitionMetadata>,
+) -> Result<(), ClubcardError> {
+    let approx_filter = &clubcard.approx_filter;
+    let exact_filter = &clubcard.exact_filter;
+    for (block, meta) in clubcard.index.iter_mut() {
+        let checksum = span_checksum::<W>(approx_filter, exact_filter, meta);
+        if meta.checksum != Some(cipher.tag(nonce, block, checksum)) {
+            return Err(ClubcardError::CorruptBlock(block.clone()));
+        }
+        meta.checksum = Some(block_checksum(approx_filter, exact_filter, meta));
+    }
+    Ok(())
+}
+
+impl<const W: usize, UniverseMetadata, PartitionMetadata>
+    Clubcard<W, UniverseMetadata, PartitionMetadata>
+where
+    UniverseMetadata: Encode,
+    PartitionMetadata: Encode,
+{
+    /// Same as to_bytes, with the filters encrypted by `cipher` under `nonce`, and the checksum
+    /// of each block replaced by its tag. The nonce is stored in the header. Read the output
+    /// with from_bytes_encrypted or open_lazy_encrypted.
+    pub fn to_bytes_encrypted(&self, cipher: &dyn FilterCipher, nonce: u64) -> Vec<u8> {
+        let mut build_config = self.build_config.clone();
+        build_config
+            .annotations
+            .insert(NONCE_ANNOTATION.to_string(), nonce.to_string());
+        let mut index = self.index.clone();
+        for (block, meta) in index.iter_mut() {
+            let checksum = span_checksum::<W>(&self.approx_filter, &self.exact_filter, meta);
+            meta.checksum = Some(cipher.tag(nonce, block, checksum));
+        }
+        let spans = BlockSpans::new(&self.index);
+        let mut approx_filter = self.approx_filter.clone();
+        for (i, column) in approx_filter.iter_mut().enumerate() {
+            spans.apply(cipher, nonce, FilterColumn::Approx(i), 0, column);
+        }
+        let mut exact_filter = self.exact_filter.clone();
+        spans.apply(cipher, nonce, FilterColumn::Exact, 0, &mut exact_filter);
+        encode_clubcard::<W>(
+            &build_config,
+            &self.universe,
+            &self.partition,
+            &index,
+            &approx_filter,
+            &exact_filter,
+        )
+    }
+}
+
+impl<const W: usize, UniverseMetadata, PartitionMetadata>
+    Clubcard<W, UniverseMetadata, PartitionMetadata>
+where
+    UniverseMetadata: Decode,
+    PartitionMetadata: Decode,
+{
+    /// Deserialize a clubcard that was serialized with to_bytes_encrypted. Fails with
+    /// ClubcardError::Malformed if the header has no nonce, and with ClubcardError::CorruptBlock
+    /// if a block does not match its tag, e.g. because the cipher is wrong.
+    pub fn from_bytes_encrypted(
+        bytes: &[u8],
+        cipher: &dyn FilterCipher,
+    ) -> Result<Self, ClubcardError> {
+        let mut clubcard = Self::from_bytes(bytes)?;
+        let nonce = nonce(&clubcard.build_config)?;
+        let spans = BlockSpans::new(&clubcard.index);
+        for (i, column) in clubcard.approx_filter.iter_mut().enumerate() {
+            spans.apply(cipher, nonce, FilterColumn::Approx(i), 0, column);
+        }
+        spans.apply(
+            cipher,
+            nonce,
+            FilterColumn::Exact,
+            0,
+            &mut clubcard.exact_filter,
+        );
+        authenticate(cipher, nonce, &mut clubcard)?;
+        clubcard.build_config.annotations.remove(NONCE_ANNOTATION);
+        Ok(clubcard)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_vectors::{generate, W};
+    use crate::ClubcardHeader;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    #[test]
+    fn test_encrypted() {
+        struct Keystream(u64);
+
+        impl Keystream {
+            fn rng(&self, nonce: u64, block: &[u8], tag: u64) -> ChaCha20Rng {
+                let mut seed = [0u8; 32];
+                seed[..8].copy_from_slice(&self.0.to_le_bytes());
+                seed[8..16].copy_from_slice(&nonce.to_le_bytes());
+                seed[16..24].copy_from_slice(&tag.to_le_bytes());
+                for (i, byte) in block.iter().enumerate() {
+                    seed[24 + i % 8] ^= byte.rotate_left(i as u32 / 8);
+                }
+                ChaCha20Rng::from_seed(seed)
+            }
+        }
+
+        impl FilterCipher for Keystream {
+            fn keystream(
+                &self,
+                nonce: u64,
+                block: &[u8],
+                column: FilterColumn,
+                first: usize,
+                out: &mut [u64],
+            ) {
+                let tag = match column {
+                    FilterColumn::Approx(i) => i as u64,
+                    FilterColumn::Exact => u64::MAX,
+                };
+                let mut rng = self.rng(nonce, block, tag);
+                for _ in 0..first {
+                    rng.gen::<u64>();
+                }
+                rng.fill(out);
+            }
+
+            fn tag(&self, nonce: u64, block: &[u8], checksum: u64) -> u64 {
+                self.rng(nonce, block, checksum).gen()
+            }
+        }
+
+        let (bytes, queries) = generate(0);
+        let clubcard = Clubcard::<W, u32, ()>::from_bytes(&bytes).unwrap();
+        let encrypted = clubcard.to_bytes_encrypted(&Keystream(1), 7);
+        assert!(encrypted != bytes);
+        let header = ClubcardHeader::<u32, ()>::from_bytes(&encrypted).unwrap();
+        assert!(header.build_config.annotations[NONCE_ANNOTATION] == "7");
+        // A different nonce gives a different keystream.
+        assert!(clubcard.to_bytes_encrypted(&Keystream(1), 8) != encrypted);
+
+        let decrypted = Clubcard::<W, u32, ()>::from_bytes_encrypted(&encrypted, &Keystream(1));
+        assert!(decrypted.unwrap().to_bytes() == bytes);
+        let lazy = Clubcard::<W, u32, ()>::open_lazy_encrypted(
+            std::io::Cursor::new(&encrypted),
+            Box::new(Keystream(1)),
+        )
+        .unwrap();
+        for (query, expected) in &queries {
+            assert!(lazy.contains(query).unwrap() == *expected);
+        }
+        lazy.validate_all().unwrap();
+        assert!(lazy.build_config() == &clubcard.build_config);
+
+        // A wrong key, or a flipped bit in a block's bits, fails authentication.
+        assert!(matches!(
+            Clubcard::<W, u32, ()>::from_bytes_encrypted(&encrypted, &Keystream(2)),
+            Err(ClubcardError::CorruptBlock(_))
+        ));
+        let lazy = Clubcard::<W, u32, ()>::open_lazy_encrypted(
+            std::io::Cursor::new(&encrypted),
+            Box::new(Keystream(2)),
+        )
+        .unwrap();
+        assert!(matches!(
+            lazy.validate_all(),
+            Err(ClubcardError::CorruptBlock(_))
+        ));
+        let mut tampered = encrypted.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(matches!(
+            Clubcard::<W, u32, ()>::from_bytes_encrypted(&tampered, &Keystream(1)),
+            Err(ClubcardError::CorruptBlock(_))
+        ));
+
+        // The nonce is needed to decrypt.
+        assert!(
+            Clubcard::<W, u32, ()>::from_bytes_encrypted(&bytes, &Keystream(1)).err()
+                == Some(ClubcardError::Malformed)
+        );
+        assert!(
+            Clubcard::<W, u32, ()>::from_bytes_encrypted(&encrypted[..100], &Keystream(1)).err()
+                == Some(ClubcardError::UnexpectedEof)
+        );
+    }
+}
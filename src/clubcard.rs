@@ -2,18 +2,78 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
-use crate::query::Queryable;
+use crate::cipher::FilterColumn;
+use crate::config::BuildConfig;
+use crate::encoding::{take, Decode, Encode};
+use crate::equation::Equation;
+use crate::error::ClubcardError;
+use crate::query::{AsQuery, Filterable, Queryable};
+use crate::sidecar::MembersSidecar;
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use std::borrow::Borrow;
+use std::cmp::max;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt;
 use std::mem::size_of;
+use std::ops::Range;
 
-#[derive(PartialEq, Eq)]
+/// The serialization format version written by Clubcard::to_bytes. The format is unstable, and
+/// its versions count down from 0xffff.
+pub const CLUBCARD_VERSION: u16 = 0xfffb;
+
+/// The first version of the format, which has no build configuration and no header length:
+/// the version is followed by the universe metadata, the partition metadata, the index, the
+/// matrix X and the matrix Y.
+const CLUBCARD_VERSION_0: u16 = 0xffff;
+
+/// The second version of the format. Same as the third version, except that the index entries
+/// have no input digest.
+const CLUBCARD_VERSION_1: u16 = 0xfffe;
+
+/// The third version of the format. Same as the fourth version, except that the header does
+/// not record the width W.
+const CLUBCARD_VERSION_2: u16 = 0xfffd;
+
+/// The fourth version of the format. Same as the current version, except that the index
+/// entries have no checksum.
+const CLUBCARD_VERSION_3: u16 = 0xfffc;
+
+/// Whether the header of format `version` records the width W.
+pub(crate) fn records_width(version: u16) -> bool {
+    version == CLUBCARD_VERSION || version == CLUBCARD_VERSION_3
+}
+
+/// The versions that Clubcard::from_bytes can read. See Clubcard::upgrade_bytes.
+pub const SUPPORTED_VERSIONS: [u16; 5] = [
+    CLUBCARD_VERSION,
+    CLUBCARD_VERSION_3,
+    CLUBCARD_VERSION_2,
+    CLUBCARD_VERSION_1,
+    CLUBCARD_VERSION_0,
+];
+
+/// The largest number of blocks in a clubcard. ClubcardBuilder does not build, and
+/// Clubcard::from_bytes does not read, a clubcard with more blocks.
+pub const MAX_BLOCKS: usize = 1 << 24;
+
+/// The largest serialized clubcard, in bytes, that Clubcard::from_bytes and Clubcard::open_lazy
+/// accept.
+pub const MAX_SERIALIZED_LEN: u64 = 1 << 32;
+
+/// The result of a query. With Encode, a Membership is written as one byte: its discriminant,
+/// i.e. 0 for Member, 1 for Nonmember, 2 for NotInUniverse, 3 for NoData, and 4 for
+/// Unavailable. The discriminants are stable across versions of this crate. With serde, it is
+/// a unit variant named after the value, e.g. "NoData" in JSON.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[repr(u8)]
 pub enum Membership {
-    Member,
-    Nonmember,
-    NotInUniverse,
-    NoData,
+    Member = 0,
+    Nonmember = 1,
+    NotInUniverse = 2,
+    NoData = 3,
+    /// The item's block is in the full clubcard, but it has not been fetched. Only returned by
+    /// PartialClubcard.
+    Unavailable = 4,
 }
 
 impl From<bool> for Membership {
@@ -25,8 +85,48 @@ impl From<bool> for Membership {
     }
 }
 
+/// A Membership as an integer, for bindings to other languages. Any u8 is a valid RawMembership,
+/// so it can be passed across an FFI boundary where receiving an out-of-range Membership would
+/// be undefined behavior. Values outside of 0..=4 are rejected when converting back.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct RawMembership(pub u8);
+
+impl From<Membership> for RawMembership {
+    fn from(x: Membership) -> RawMembership {
+        RawMembership(x as u8)
+    }
+}
+
+impl TryFrom<RawMembership> for Membership {
+    type Error = ClubcardError;
+
+    fn try_from(x: RawMembership) -> Result<Membership, ClubcardError> {
+        match x.0 {
+            0 => Ok(Membership::Member),
+            1 => Ok(Membership::Nonmember),
+            2 => Ok(Membership::NotInUniverse),
+            3 => Ok(Membership::NoData),
+            4 => Ok(Membership::Unavailable),
+            _ => Err(ClubcardError::Malformed),
+        }
+    }
+}
+
+impl Encode for Membership {
+    fn encode(&self, out: &mut Vec<u8>) {
+        RawMembership::from(*self).0.encode(out);
+    }
+}
+
+impl Decode for Membership {
+    fn decode(bytes: &mut &[u8]) -> Result<Self, ClubcardError> {
+        Membership::try_from(RawMembership(u8::decode(bytes)?))
+    }
+}
+
 /// Metadata needed to compute membership in a clubcard.
-#[derive(Default, Serialize, Deserialize)]
+#[derive(Clone, Default, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ClubcardIndexEntry {
     /// Description of the hash function h.
     pub approx_filter_m: usize,
@@ -42,12 +142,283 @@ pub struct ClubcardIndexEntry {
     pub inverted: bool,
     /// A list of elements of Ui \ Ri that are not correctly encoded by this block.
     pub exceptions: Vec<Vec<u8>>,
+    /// The digest of the items that the block was built from, if the builder was in audit
+    /// mode. See Clubcard::attest_block. Like the checksum, this is only serialized by
+    /// Clubcard::to_bytes, so that the serde representation stays that of version 0.3.1.
+    #[serde(skip)]
+    pub input_digest: Option<[u8; 32]>,
+    /// The 64-bit FNV-1a hash of the bits of X and Y that the block occupies, recorded by the
+    /// builder. Clubcard::validate_block fails with ClubcardError::CorruptBlock if the filters
+    /// do not match it. None for clubcards in a format that predates checksums, and for
+    /// clubcards that were deserialized with serde.
+    #[serde(skip)]
+    pub checksum: Option<u64>,
+    /// Whether queries to this block return NotInUniverse, e.g. because the block's issuer is
+    /// pending removal. Set by Clubcard::apply_tombstones and not serialized.
+    #[serde(skip)]
+    pub tombstoned: bool,
+}
+
+impl Encode for ClubcardIndexEntry {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.approx_filter_m.encode(out);
+        self.exact_filter_m.encode(out);
+        self.approx_filter_rank.encode(out);
+        self.approx_filter_offset.encode(out);
+        self.exact_filter_offset.encode(out);
+        self.inverted.encode(out);
+        self.exceptions.encode(out);
+        self.input_digest.encode(out);
+        self.checksum.encode(out);
+    }
+}
+
+impl Decode for ClubcardIndexEntry {
+    fn decode(bytes: &mut &[u8]) -> Result<Self, ClubcardError> {
+        Ok(ClubcardIndexEntry {
+            approx_filter_m: Decode::decode(bytes)?,
+            exact_filter_m: Decode::decode(bytes)?,
+            approx_filter_rank: Decode::decode(bytes)?,
+            approx_filter_offset: Decode::decode(bytes)?,
+            exact_filter_offset: Decode::decode(bytes)?,
+            inverted: Decode::decode(bytes)?,
+            exceptions: Decode::decode(bytes)?,
+            input_digest: Decode::decode(bytes)?,
+            checksum: Decode::decode(bytes)?,
+            tombstoned: false,
+        })
+    }
+}
+
+/// An index entry in the format of CLUBCARD_VERSION_0 and CLUBCARD_VERSION_1, which lack the
+/// input digest and the checksum.
+struct LegacyIndexEntry(ClubcardIndexEntry);
+
+impl Decode for LegacyIndexEntry {
+    fn decode(bytes: &mut &[u8]) -> Result<Self, ClubcardError> {
+        Ok(LegacyIndexEntry(ClubcardIndexEntry {
+            approx_filter_m: Decode::decode(bytes)?,
+            exact_filter_m: Decode::decode(bytes)?,
+            approx_filter_rank: Decode::decode(bytes)?,
+            approx_filter_offset: Decode::decode(bytes)?,
+            exact_filter_offset: Decode::decode(bytes)?,
+            inverted: Decode::decode(bytes)?,
+            exceptions: Decode::decode(bytes)?,
+            input_digest: None,
+            checksum: None,
+            tombstoned: false,
+        }))
+    }
+}
+
+fn decode_legacy_index(bytes: &mut &[u8]) -> Result<ClubcardIndex, ClubcardError> {
+    let index: BTreeMap<Vec<u8>, LegacyIndexEntry> = Decode::decode(bytes)?;
+    Ok(index
+        .into_iter()
+        .map(|(block, entry)| (block, entry.0))
+        .collect())
+}
+
+/// An index entry in the format of CLUBCARD_VERSION_2 and CLUBCARD_VERSION_3, which lack the
+/// checksum.
+struct UncheckedIndexEntry(ClubcardIndexEntry);
+
+impl Decode for UncheckedIndexEntry {
+    fn decode(bytes: &mut &[u8]) -> Result<Self, ClubcardError> {
+        let LegacyIndexEntry(mut entry) = LegacyIndexEntry::decode(bytes)?;
+        entry.input_digest = Decode::decode(bytes)?;
+        Ok(UncheckedIndexEntry(entry))
+    }
+}
+
+fn decode_unchecked_index(bytes: &mut &[u8]) -> Result<ClubcardIndex, ClubcardError> {
+    let index: BTreeMap<Vec<u8>, UncheckedIndexEntry> = Decode::decode(bytes)?;
+    Ok(index
+        .into_iter()
+        .map(|(block, entry)| (block, entry.0))
+        .collect())
+}
+
+/// The words of bits `start..start + len` of `column`, with bits past the end of the column
+/// read as zero.
+fn bit_words(column: &[u64], start: usize, len: usize) -> impl Iterator<Item = u64> + '_ {
+    (0..len.div_ceil(64)).map(move |k| {
+        let (i, shift) = ((start + 64 * k) / 64, start % 64);
+        let word = |j: usize| column.get(j).copied().unwrap_or(0);
+        let mut out = word(i) >> shift;
+        if shift > 0 {
+            out |= word(i + 1) << (64 - shift);
+        }
+        match len - 64 * k {
+            rest if rest < 64 => out & ((1 << rest) - 1),
+            _ => out,
+        }
+    })
+}
+
+/// The checksum of the bits of `approx_filter` and `exact_filter` that the block described by
+/// `meta` occupies. See ClubcardIndexEntry::checksum. The checksum does not depend on where the
+/// block lies in the filters, so it survives Clubcard::export_blocks and Clubcard::concat.
+pub(crate) fn block_checksum(
+    approx_filter: &[Vec<u64>],
+    exact_filter: &[u64],
+    meta: &ClubcardIndexEntry,
+) -> u64 {
+    let approx = approx_filter
+        .iter()
+        .take(meta.approx_filter_rank)
+        .flat_map(|column| bit_words(column, meta.approx_filter_offset, meta.approx_filter_m));
+    let exact = bit_words(exact_filter, meta.exact_filter_offset, meta.exact_filter_m);
+    approx.chain(exact).fold(0xcbf29ce484222325, |hash, word| {
+        word.to_le_bytes()
+            .iter()
+            .fold(hash, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100000001b3))
+    })
+}
+
+/// Size statistics for a block of a Clubcard. See Clubcard::block_stats.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockStats {
+    /// The number of rows of X used by this block.
+    pub approx_filter_m: usize,
+    /// The number of columns of X used by this block.
+    pub approx_filter_rank: usize,
+    /// The number of rows of Y used by this block.
+    pub exact_filter_m: usize,
+    /// The number of exceptions stored for this block.
+    pub exceptions: usize,
+}
+
+impl BlockStats {
+    /// The (approximate) number of solution bits that encode this block, excluding metadata.
+    pub fn bits(&self) -> usize {
+        self.approx_filter_rank * self.approx_filter_m + self.exact_filter_m
+    }
+
+    /// The false positive rate that the approximate layer of this block was designed for,
+    /// 2^-rank, or 0 if the approximate filter is empty. This bounds the fraction of the
+    /// block's non-members whose membership depends on the exact layer.
+    pub fn design_fp_rate(&self) -> f64 {
+        match self.approx_filter_m {
+            0 => 0.0,
+            _ => 0.5f64.powi(self.approx_filter_rank as i32),
+        }
+    }
+}
+
+/// An upper bound on the work of a query against a Clubcard, not counting the index lookup
+/// and Queryable::in_universe. See Clubcard::max_query_work.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct QueryWork {
+    /// Calls to AsQuery::as_query.
+    pub hashes: usize,
+    /// Bytes of the filters that are read.
+    pub filter_bytes: usize,
+    /// Bytes of exceptions that are compared with the item's discriminant.
+    pub exception_bytes: usize,
+}
+
+/// The work done by one query. See Clubcard::contains_counting.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct QueryCost {
+    /// Calls to AsQuery::as_query.
+    pub hashes: usize,
+    /// The layers that were consulted: 0 if the query was answered without reading the filters
+    /// (e.g. from the universe metadata, the index, or an empty block), 1 if the approximate
+    /// layer ruled the item out, and 2 otherwise.
+    pub layers: usize,
+    /// The columns of X and Y that were evaluated.
+    pub columns: usize,
+    /// The 64-byte cache lines that hold the words of the filters that were read. This is an
+    /// estimate of memory traffic that ignores prefetching and lines that are already cached.
+    pub cache_lines: usize,
+    /// The exceptions that were compared with the item's discriminant.
+    pub exceptions: usize,
+}
+
+/// The number of 64-byte cache lines that hold the words of `column` that `query.eval` reads.
+fn cache_lines_read<const W: usize>(query: &Equation<W>, column: &[u64]) -> usize {
+    let first = query.s / 64;
+    if first >= column.len() {
+        return 0;
+    }
+    let last = ((query.s + 64 * W - 1) / 64).min(column.len() - 1);
+    let base = column.as_ptr() as usize;
+    (base + 8 * last + 7) / 64 - (base + 8 * first) / 64 + 1
+}
+
+impl From<&ClubcardIndexEntry> for BlockStats {
+    fn from(meta: &ClubcardIndexEntry) -> Self {
+        BlockStats {
+            approx_filter_m: meta.approx_filter_m,
+            approx_filter_rank: meta.approx_filter_rank,
+            exact_filter_m: meta.exact_filter_m,
+            exceptions: meta.exceptions.len(),
+        }
+    }
+}
+
+/// An entry of the index of a Clubcard. See Clubcard::blocks.
+#[derive(Clone, Copy, Debug)]
+pub struct BlockEntry<'a> {
+    pub block: &'a [u8],
+    pub meta: &'a ClubcardIndexEntry,
+}
+
+impl BlockEntry<'_> {
+    pub fn stats(&self) -> BlockStats {
+        self.meta.into()
+    }
+
+    /// The bits of each of the first approx_filter_rank columns of X that encode this block.
+    pub fn approx_filter_bits(&self) -> Range<usize> {
+        self.meta.approx_filter_offset..self.meta.approx_filter_offset + self.meta.approx_filter_m
+    }
+
+    /// The bits of Y that encode this block.
+    pub fn exact_filter_bits(&self) -> Range<usize> {
+        self.meta.exact_filter_offset..self.meta.exact_filter_offset + self.meta.exact_filter_m
+    }
+}
+
+/// The result of Clubcard::verify. Mismatched items are identified by (block, discriminant).
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    /// The number of items that were checked.
+    pub checked: usize,
+    /// Included items that the clubcard does not contain.
+    pub false_negatives: Vec<(Vec<u8>, Vec<u8>)>,
+    /// Excluded items that the clubcard contains.
+    pub false_positives: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl VerifyReport {
+    /// Whether every checked item was encoded correctly.
+    pub fn is_ok(&self) -> bool {
+        self.false_negatives.is_empty() && self.false_positives.is_empty()
+    }
+}
+
+impl fmt::Display for VerifyReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "checked: {}, false negatives: {}, false positives: {}",
+            self.checked,
+            self.false_negatives.len(),
+            self.false_positives.len()
+        )
+    }
 }
 
 /// Lookup table from block identifiers to block metadata.
 pub type ClubcardIndex = BTreeMap</* block id */ Vec<u8>, ClubcardIndexEntry>;
 
 /// A queryable Clubcard
+///
+/// A Clubcard is immutable once built, and queries take `&self`. It is Send + Sync whenever its
+/// metadata types are, so a single instance can be shared between threads (e.g. in an Arc)
+/// without a lock.
 #[derive(Serialize, Deserialize)]
 pub struct Clubcard<const W: usize, UniverseMetadata, PartitionMetadata> {
     /// Metadata for determining whether a Queryable is in the encoded universe.
@@ -60,6 +431,31 @@ pub struct Clubcard<const W: usize, UniverseMetadata, PartitionMetadata> {
     pub(crate) approx_filter: Vec<Vec<u64>>,
     /// The matrix Y
     pub(crate) exact_filter: Vec<u64>,
+    /// The configuration of the builder that produced this clubcard. Only serialized by
+    /// Clubcard::to_bytes.
+    #[serde(skip)]
+    pub(crate) build_config: BuildConfig,
+    /// The members of each block, if they were retained. Not serialized.
+    #[serde(skip)]
+    pub(crate) members: Option<MembersSidecar>,
+}
+
+/// Two clubcards are equal if they have the same metadata, index, and filters, i.e. if they
+/// have the same serialization. See Clubcard::equivalent for a weaker notion of equality.
+impl<const W: usize, UniverseMetadata, PartitionMetadata> PartialEq
+    for Clubcard<W, UniverseMetadata, PartitionMetadata>
+where
+    UniverseMetadata: PartialEq,
+    PartitionMetadata: PartialEq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.universe == other.universe
+            && self.partition == other.partition
+            && self.index == other.index
+            && self.approx_filter == other.approx_filter
+            && self.exact_filter == other.exact_filter
+            && self.build_config == other.build_config
+    }
 }
 
 impl<const W: usize, UniverseMetadata, PartitionMetadata> fmt::Display
@@ -94,44 +490,37 @@ impl<const W: usize, UniverseMetadata, PartitionMetadata>
     where
         T: Queryable<W, PartitionMetadata = PartitionMetadata>,
     {
-        let Some(meta) = self.index.get(item.block()) else {
-            return false;
-        };
-
-        let result = (|| {
-            // All queries evaluate to 0 on an empty filter, but logically
-            // such a filter does not include anything. So we handle it as a
-            // special case.
-            if meta.approx_filter_m == 0 {
-                return false;
-            }
-
-            // Check if h(item) * X is 0
-            let approx_query = item.as_approx_query(meta);
-            for i in 0..meta.approx_filter_rank {
-                if approx_query.eval(&self.approx_filter[i]) != 0 {
-                    return false;
-                }
-            }
+        self.unchecked_contains_any(item)
+    }
 
-            // Check if g(item) * X is 0
-            let exact_query = item.as_exact_query(meta);
-            if exact_query.eval(&self.exact_filter) != 0 {
-                return false;
-            }
+    /// Same as unchecked_contains, but without requiring that the item is a Queryable.
+    fn unchecked_contains_any<T: AsQuery<W>>(&self, item: &T) -> bool {
+        self.block_handle(item.block())
+            .is_some_and(|handle| handle.unchecked_contains_any(item))
+    }
 
-            for exception in &meta.exceptions {
-                if exception == item.discriminant() {
-                    return false;
-                }
-            }
-            true
-        })();
+    /// Query the approximate layer alone. See BlockHandle::approx_contains. Returns false if
+    /// the item's block is not in the index.
+    pub fn approx_contains<T>(&self, item: &T) -> bool
+    where
+        T: Queryable<W, PartitionMetadata = PartitionMetadata>,
+    {
+        self.block_handle(item.block())
+            .is_some_and(|handle| handle.approx_contains(item))
+    }
 
-        result ^ meta.inverted
+    /// Query the exact layer alone. See BlockHandle::exact_bit. Returns None if the item's
+    /// block is not in the index.
+    pub fn exact_bit<T>(&self, item: &T) -> Option<u8>
+    where
+        T: Queryable<W, PartitionMetadata = PartitionMetadata>,
+    {
+        self.block_handle(item.block())
+            .map(|handle| handle.exact_bit(item))
     }
 
     /// Check that the item is in the appropriate universe, and then perform a membership query.
+    /// Queries do not allocate, unless the item's Queryable implementation does.
     pub fn contains<T>(&self, item: &T) -> Membership
     where
         T: Queryable<W, UniverseMetadata = UniverseMetadata, PartitionMetadata = PartitionMetadata>,
@@ -140,13 +529,404 @@ impl<const W: usize, UniverseMetadata, PartitionMetadata>
             return Membership::NotInUniverse;
         };
 
-        if !self.index.contains_key(item.block()) {
-            return Membership::NoData;
+        match self.index.get(item.block()) {
+            None => return Membership::NoData,
+            Some(meta) if meta.tombstoned => return Membership::NotInUniverse,
+            Some(_) => (),
         };
 
         self.unchecked_contains(item).into()
     }
 
+    /// Same as contains, and the work that the query did, for comparing clubcards with other
+    /// filters. This is slower than contains.
+    pub fn contains_counting<T>(&self, item: &T) -> (Membership, QueryCost)
+    where
+        T: Queryable<W, UniverseMetadata = UniverseMetadata, PartitionMetadata = PartitionMetadata>,
+    {
+        let mut cost = QueryCost::default();
+        if !item.in_universe(&self.universe) {
+            return (Membership::NotInUniverse, cost);
+        }
+        let result = match self.block_handle(item.block()) {
+            None => Membership::NoData,
+            Some(handle) if handle.meta.tombstoned => Membership::NotInUniverse,
+            Some(handle) => handle.unchecked_contains_counting(item, &mut cost).into(),
+        };
+        (result, cost)
+    }
+
+    /// Same as contains, for a clubcard with a single block, but the index is not consulted:
+    /// every item is queried against the sole block, whatever its own block identifier. This
+    /// suits clubcards that are built with ClubcardBuilder::set_single_block. Returns NoData for
+    /// in-universe items if the clubcard does not have exactly one block.
+    pub fn contains_single_block<T>(&self, item: &T) -> Membership
+    where
+        T: Queryable<W, UniverseMetadata = UniverseMetadata, PartitionMetadata = PartitionMetadata>,
+    {
+        if !item.in_universe(&self.universe) {
+            return Membership::NotInUniverse;
+        };
+
+        if self.index.len() != 1 {
+            return Membership::NoData;
+        }
+        let (block, meta) = self.index.first_key_value().unwrap();
+        if meta.tombstoned {
+            return Membership::NotInUniverse;
+        }
+        let handle = BlockHandle {
+            clubcard: self,
+            block,
+            meta,
+        };
+        handle.unchecked_contains_any(item).into()
+    }
+
+    /// Same as contains, but every stage of the query is evaluated regardless of the outcome
+    /// of earlier stages, and the results are combined without branching. The running time
+    /// then depends on the number of columns in X and on the number of exceptions in the
+    /// item's block, but not on whether the item is in the universe, whether its block is
+    /// present, or whether it is a member. This is slower than contains. The index lookup, the
+    /// universe check, and the item's hash function are only as uniform as their
+    /// implementations.
+    pub fn contains_uniform_time<T>(&self, item: &T) -> Membership
+    where
+        T: Queryable<W, UniverseMetadata = UniverseMetadata, PartitionMetadata = PartitionMetadata>,
+    {
+        const RESULTS: [Membership; 4] = [
+            Membership::NotInUniverse,
+            Membership::NoData,
+            Membership::Nonmember,
+            Membership::Member,
+        ];
+        let empty = ClubcardIndexEntry::default();
+
+        let found = self.index.get(item.block());
+        let has_block = found.is_some() as usize;
+        let tombstoned = found.is_some_and(|meta| meta.tombstoned);
+        let in_universe = (item.in_universe(&self.universe) & !tombstoned) as usize;
+        // The filters of a tombstoned block may be invalid, see from_bytes_salvage.
+        let meta = found.filter(|_| !tombstoned).unwrap_or(&empty);
+
+        // as_query is never called with m = 0 by the other query paths, so we do not call it
+        // with m = 0 here either. The result is masked when the block is empty.
+        let mut approx_query = item.as_query(max(1, meta.approx_filter_m));
+        approx_query.s += meta.approx_filter_offset;
+        let mut approx = 0;
+        for (i, column) in self.approx_filter.iter().enumerate() {
+            approx |= ((i < meta.approx_filter_rank) as u8) & approx_query.eval(column);
+        }
+
+        let mut exact_query = item.as_query(max(1, meta.exact_filter_m));
+        exact_query.s += meta.exact_filter_offset;
+        let exact = exact_query.eval(&self.exact_filter);
+
+        let mut exception = 0;
+        for x in &meta.exceptions {
+            exception |= (x == item.discriminant()) as u8;
+        }
+
+        let nonempty = (meta.approx_filter_m != 0) as u8;
+        let member = (nonempty & !approx & !exact & !exception & 1) ^ meta.inverted as u8;
+        RESULTS[in_universe * (1 + has_block * (1 + member as usize))]
+    }
+
+    /// Same as calling contains on each of `items`, with the results in the same order. The
+    /// items are grouped by block, so each block is looked up once and the queries against a
+    /// block are evaluated together. This improves locality for large batches.
+    pub fn contains_batch<T>(&self, items: &[T]) -> Vec<Membership>
+    where
+        T: Queryable<W, UniverseMetadata = UniverseMetadata, PartitionMetadata = PartitionMetadata>,
+    {
+        let mut order: Vec<usize> = (0..items.len()).collect();
+        order.sort_by(|&i, &j| items[i].block().cmp(items[j].block()));
+        let mut out = vec![Membership::NoData; items.len()];
+        let mut current = None;
+        for i in order {
+            let block = items[i].block();
+            if current.as_ref().map(|(current, _)| *current) != Some(block) {
+                current = Some((block, self.block_handle(block)));
+            }
+            out[i] = match current.as_ref().and_then(|(_, handle)| handle.as_ref()) {
+                Some(handle) => handle.contains(&items[i]),
+                None if !items[i].in_universe(&self.universe) => Membership::NotInUniverse,
+                None => Membership::NoData,
+            };
+        }
+        out
+    }
+
+    /// Query several candidate items that stand for the same object, e.g. a certificate that
+    /// chains to more than one issuer, and combine the results. The precedence is Member, then
+    /// Nonmember, then NoData, then NotInUniverse: the object is a member if any candidate is,
+    /// and otherwise it is a nonmember if any candidate is covered by this clubcard. Returns
+    /// NotInUniverse if `items` is empty.
+    pub fn contains_any<T>(&self, items: &[T]) -> Membership
+    where
+        T: Queryable<W, UniverseMetadata = UniverseMetadata, PartitionMetadata = PartitionMetadata>,
+    {
+        let precedence = |result: &Membership| match result {
+            Membership::Member => 0,
+            Membership::Nonmember => 1,
+            Membership::NoData => 2,
+            Membership::NotInUniverse => 3,
+            Membership::Unavailable => 4,
+        };
+        let mut best = Membership::NotInUniverse;
+        for item in items {
+            let result = self.contains(item);
+            if result == Membership::Member {
+                return result;
+            }
+            if precedence(&result) < precedence(&best) {
+                best = result;
+            }
+        }
+        best
+    }
+
+    /// The number of blocks in the index.
+    pub fn num_blocks(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Look up the metadata for `block` once, so that it can be reused across many queries
+    /// against the same block. Returns None if the block is not in the index.
+    pub fn block_handle(
+        &self,
+        block: &[u8],
+    ) -> Option<BlockHandle<'_, W, UniverseMetadata, PartitionMetadata>> {
+        let (block, meta) = self.index.get_key_value(block)?;
+        Some(BlockHandle {
+            clubcard: self,
+            block,
+            meta,
+        })
+    }
+
+    /// The most work that a query against this clubcard can take, from the parameters of its
+    /// blocks. A query hashes the item and reads up to W + 1 words of each column of X that
+    /// the block uses. If every column evaluates to 0, it hashes the item again, reads up to
+    /// W + 1 words of Y, and compares the discriminant with the block's exceptions. Each field
+    /// is the maximum over the blocks, which need not be attained by a single block.
+    pub fn max_query_work(&self) -> QueryWork {
+        let mut work = QueryWork::default();
+        for meta in self.index.values().filter(|meta| meta.approx_filter_m > 0) {
+            work.hashes = 2;
+            work.filter_bytes = work
+                .filter_bytes
+                .max((meta.approx_filter_rank + 1) * (W + 1) * 8);
+            work.exception_bytes = work
+                .exception_bytes
+                .max(meta.exceptions.iter().map(|x| x.len()).sum());
+        }
+        work
+    }
+
+    /// Size statistics for `block`. Returns None if the block is not in the index.
+    pub fn block_stats(&self, block: &[u8]) -> Option<BlockStats> {
+        self.index.get(block).map(BlockStats::from)
+    }
+
+    /// The entries of the index, in order of block identifier.
+    pub fn blocks(&self) -> impl Iterator<Item = BlockEntry<'_>> {
+        self.index
+            .iter()
+            .map(|(block, meta)| BlockEntry { block, meta })
+    }
+
+    /// Check the structural invariants of the index against the filters: the rank of every
+    /// block is at most the number of columns in X, every block lies within the columns that
+    /// it is queried against, and no two blocks overlap. Queries against a clubcard that fails
+    /// this check may panic or return garbage, so clubcards from an untrusted source should be
+    /// validated once after they are deserialized. Tombstoned blocks are not checked, since
+    /// they are never queried.
+    pub fn validate(&self) -> Result<(), ClubcardError> {
+        let mut approx_spans = vec![];
+        let mut exact_spans = vec![];
+        for (block, meta) in self.index.iter().filter(|(_, meta)| !meta.tombstoned) {
+            let (approx_span, exact_span) = self.block_spans(block, meta)?;
+            self.check_checksum(block, meta)?;
+            approx_spans.extend(approx_span.map(|span| (span.start, span.end, block)));
+            exact_spans.extend(exact_span.map(|span| (span.start, span.end, block)));
+        }
+        for mut spans in [approx_spans, exact_spans] {
+            spans.sort_unstable();
+            for pair in spans.windows(2) {
+                if pair[0].1 > pair[1].0 {
+                    return Err(ClubcardError::InvalidBlock(pair[1].2.clone()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The part of validate that concerns `block` alone: its rank is at most the number of
+    /// columns in X, it lies within the columns that it is queried against, and its bits match
+    /// the checksum in its index entry, if there is one. This suffices for queries to the block
+    /// to be safe, so a client can check each block on first use instead of validating the
+    /// whole clubcard up front. Fails with ClubcardError::InvalidBlock if the block is invalid
+    /// or not in the index, and with ClubcardError::CorruptBlock if only the checksum fails, in
+    /// which case the client can fetch the block's data again.
+    pub fn validate_block(&self, block: &[u8]) -> Result<(), ClubcardError> {
+        let (block, meta) = self
+            .index
+            .get_key_value(block)
+            .ok_or_else(|| ClubcardError::InvalidBlock(block.to_vec()))?;
+        self.block_spans(block, meta)?;
+        self.check_checksum(block, meta)
+    }
+
+    fn check_checksum(&self, block: &[u8], meta: &ClubcardIndexEntry) -> Result<(), ClubcardError> {
+        match meta.checksum {
+            Some(checksum)
+                if checksum != block_checksum(&self.approx_filter, &self.exact_filter, meta) =>
+            {
+                Err(ClubcardError::CorruptBlock(block.to_vec()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Read one word from every page of the filters that queries against the blocks in the
+    /// index read, using up to `threads` threads (0 is treated as 1), so that a clubcard in
+    /// freshly allocated or swapped out memory takes its page faults here instead of on the
+    /// first queries. Invalid and tombstoned blocks are skipped. Returns the number of words
+    /// read.
+    pub fn warm(&self, threads: usize) -> usize {
+        let threads = threads.max(1);
+        const PAGE_WORDS: usize = 4096 / 8;
+        let mut pieces: Vec<&[u64]> = vec![];
+        for (block, meta) in self.index.iter().filter(|(_, meta)| !meta.tombstoned) {
+            let Ok((approx_span, exact_span)) = self.block_spans(block, meta) else {
+                continue;
+            };
+            let columns = &self.approx_filter[..meta.approx_filter_rank];
+            for (span, column) in approx_span
+                .iter()
+                .flat_map(|span| columns.iter().map(move |column| (span, column)))
+                .chain(exact_span.iter().map(|span| (span, &self.exact_filter)))
+            {
+                let end = (span.end + 64 * W).div_ceil(64).min(column.len());
+                pieces.push(&column[span.start / 64..end]);
+            }
+        }
+        let mut groups: Vec<Vec<&[u64]>> = (0..threads).map(|_| vec![]).collect();
+        for (i, piece) in pieces.into_iter().enumerate() {
+            groups[i % threads].push(piece);
+        }
+        std::thread::scope(|scope| {
+            let workers: Vec<_> = groups
+                .into_iter()
+                .map(|group| {
+                    scope.spawn(move || {
+                        let mut touched = 0;
+                        for piece in group {
+                            for word in piece.iter().step_by(PAGE_WORDS) {
+                                std::hint::black_box(*word);
+                                touched += 1;
+                            }
+                        }
+                        touched
+                    })
+                })
+                .collect();
+            workers.into_iter().map(|x| x.join().unwrap()).sum()
+        })
+    }
+
+    /// The bits of X and of Y that queries against `block` read, if any. Fails if they are
+    /// not within the filters.
+    fn block_spans(
+        &self,
+        block: &[u8],
+        meta: &ClubcardIndexEntry,
+    ) -> Result<BlockSpans, ClubcardError> {
+        let invalid = || ClubcardError::InvalidBlock(block.to_vec());
+        let approx_end = meta
+            .approx_filter_offset
+            .checked_add(meta.approx_filter_m)
+            .ok_or_else(invalid)?;
+        let exact_end = meta
+            .exact_filter_offset
+            .checked_add(meta.exact_filter_m)
+            .ok_or_else(invalid)?;
+        if meta.approx_filter_rank > self.approx_filter.len() {
+            return Err(invalid());
+        }
+        let mut approx_span = None;
+        if meta.approx_filter_m > 0 && meta.approx_filter_rank > 0 {
+            let columns = &self.approx_filter[..meta.approx_filter_rank];
+            if columns
+                .iter()
+                .any(|column| column.len().saturating_mul(64) < approx_end)
+            {
+                return Err(invalid());
+            }
+            approx_span = Some(meta.approx_filter_offset..approx_end);
+        }
+        let mut exact_span = None;
+        if meta.exact_filter_m > 0 {
+            if self.exact_filter.len().saturating_mul(64) < exact_end {
+                return Err(invalid());
+            }
+            exact_span = Some(meta.exact_filter_offset..exact_end);
+        }
+        Ok((approx_span, exact_span))
+    }
+
+    /// Re-query every item and report those for which the clubcard disagrees with
+    /// Filterable::included. The items should be the ones that were passed to the exact
+    /// ribbon builders.
+    pub fn verify<T, I>(&self, items: I) -> VerifyReport
+    where
+        T: Filterable<W>,
+        I: IntoIterator<Item = T>,
+    {
+        let mut report = VerifyReport::default();
+        for item in items {
+            report.checked += 1;
+            match (item.included(), self.unchecked_contains_any(&item)) {
+                (true, false) => report
+                    .false_negatives
+                    .push((item.block().to_vec(), item.discriminant().to_vec())),
+                (false, true) => report
+                    .false_positives
+                    .push((item.block().to_vec(), item.discriminant().to_vec())),
+                _ => (),
+            }
+        }
+        report
+    }
+
+    /// Whether this clubcard and `other` give the same answer to every query in `queries`.
+    /// Two clubcards built from the same data with different seeds are equivalent on the items
+    /// that were passed to the exact ribbon builders, but not on other items, so the queries
+    /// should be drawn from those items.
+    pub fn equivalent<T, I>(&self, other: &Self, queries: I) -> bool
+    where
+        T: Queryable<W, UniverseMetadata = UniverseMetadata, PartitionMetadata = PartitionMetadata>,
+        I: IntoIterator<Item = T>,
+    {
+        queries
+            .into_iter()
+            .all(|query| self.contains(&query) == other.contains(&query))
+    }
+
+    /// A clubcard with no blocks and the default configuration.
+    pub(crate) fn empty(universe: UniverseMetadata, partition: PartitionMetadata) -> Self {
+        Clubcard {
+            universe,
+            partition,
+            index: ClubcardIndex::new(),
+            approx_filter: vec![],
+            exact_filter: vec![],
+            build_config: Default::default(),
+            members: None,
+        }
+    }
+
     pub fn universe(&self) -> &UniverseMetadata {
         &self.universe
     }
@@ -154,37 +934,1746 @@ impl<const W: usize, UniverseMetadata, PartitionMetadata>
     pub fn partition(&self) -> &PartitionMetadata {
         &self.partition
     }
-}
 
-/// Helper trait for (approximate) heap memory usage analysis in Firefox
-pub trait ApproximateSizeOf {
-    fn approximate_size_of(&self) -> usize
-    where
-        Self: Sized,
-    {
-        size_of::<Self>()
+    /// The configuration of the builder that produced this clubcard. Clubcards that were not
+    /// produced by a ClubcardBuilder have the default configuration.
+    pub fn build_config(&self) -> &BuildConfig {
+        &self.build_config
     }
-}
-
-impl ApproximateSizeOf for () {}
 
-impl ApproximateSizeOf for ClubcardIndex {
-    fn approximate_size_of(&self) -> usize {
-        size_of::<ClubcardIndex>() + self.len() * size_of::<ClubcardIndexEntry>()
+    /// The label set with ClubcardBuilder::set_label, if any.
+    pub fn label(&self) -> Option<&str> {
+        self.build_config.label()
     }
 }
 
-impl<const W: usize, UniverseMetadata, PartitionMetadata> ApproximateSizeOf
-    for Clubcard<W, UniverseMetadata, PartitionMetadata>
+impl<const W: usize, UniverseMetadata, PartitionMetadata>
+    Clubcard<W, UniverseMetadata, PartitionMetadata>
 where
-    UniverseMetadata: ApproximateSizeOf,
-    PartitionMetadata: ApproximateSizeOf,
+    UniverseMetadata: Clone,
+    PartitionMetadata: Clone,
 {
-    fn approximate_size_of(&self) -> usize {
-        self.universe.approximate_size_of()
-            + self.partition.approximate_size_of()
-            + self.index.approximate_size_of()
-            + self.approx_filter.iter().map(|x| x.len()).sum::<usize>()
-            + self.exact_filter.len()
+    /// Construct a clubcard that contains only the given blocks (blocks that are not in the
+    /// index are ignored). Queries against the result agree with queries against self for
+    /// items in these blocks, and return NoData for other items.
+    ///
+    /// This is a building block for remote query protocols: a client can fetch the blocks
+    /// that it needs from a server without revealing the discriminants of the items that it
+    /// queries.
+    pub fn export_blocks<'a>(&self, blocks: impl IntoIterator<Item = &'a [u8]>) -> Self {
+        let mut index = ClubcardIndex::new();
+        let mut approx_filter: Vec<Vec<u64>> = vec![];
+        let mut exact_filter = vec![];
+        let mut approx_offset = 0;
+        let mut exact_offset = 0;
+        for block in blocks {
+            let Some((block, meta)) = self.index.get_key_value(block) else {
+                continue;
+            };
+            if index.contains_key(block) {
+                continue;
+            }
+            let approx_span = block_span::<W>(meta.approx_filter_m);
+            for i in 0..meta.approx_filter_rank {
+                if approx_filter.len() == i {
+                    approx_filter.push(vec![]);
+                }
+                copy_bits(
+                    &mut approx_filter[i],
+                    approx_offset,
+                    &self.approx_filter[i],
+                    meta.approx_filter_offset,
+                    approx_span,
+                );
+            }
+            let exact_span = block_span::<W>(meta.exact_filter_m);
+            copy_bits(
+                &mut exact_filter,
+                exact_offset,
+                &self.exact_filter,
+                meta.exact_filter_offset,
+                exact_span,
+            );
+            index.insert(
+                block.clone(),
+                ClubcardIndexEntry {
+                    approx_filter_m: meta.approx_filter_m,
+                    exact_filter_m: meta.exact_filter_m,
+                    approx_filter_rank: meta.approx_filter_rank,
+                    approx_filter_offset: approx_offset,
+                    exact_filter_offset: exact_offset,
+                    inverted: meta.inverted,
+                    exceptions: meta.exceptions.clone(),
+                    input_digest: meta.input_digest,
+                    checksum: meta.checksum,
+                    tombstoned: meta.tombstoned,
+                },
+            );
+            approx_offset += approx_span;
+            exact_offset += exact_span;
+        }
+
+        let members = self.members.as_ref().map(|members| MembersSidecar {
+            blocks: members
+                .blocks
+                .iter()
+                .filter(|(block, _)| index.contains_key(*block))
+                .map(|(block, members)| (block.clone(), members.clone()))
+                .collect(),
+        });
+        Clubcard {
+            universe: self.universe.clone(),
+            partition: self.partition.clone(),
+            index,
+            approx_filter,
+            exact_filter,
+            build_config: self.build_config.clone(),
+            members,
+        }
+    }
+
+    /// Construct a clubcard without the given blocks, e.g. to drop a distrusted issuer without
+    /// a rebuild. The remaining blocks are compacted as in export_blocks.
+    pub fn remove_blocks<'a>(&self, blocks: impl IntoIterator<Item = &'a [u8]>) -> Self {
+        let removed: BTreeSet<&[u8]> = blocks.into_iter().collect();
+        self.export_blocks(
+            self.index
+                .keys()
+                .map(|block| &block[..])
+                .filter(|block| !removed.contains(block)),
+        )
+    }
+}
+
+impl<const W: usize, UniverseMetadata, PartitionMetadata>
+    Clubcard<W, UniverseMetadata, PartitionMetadata>
+where
+    UniverseMetadata: PartialEq,
+    PartitionMetadata: PartialEq,
+{
+    /// Combine clubcards with disjoint sets of blocks, e.g. the outputs of export_blocks, into
+    /// one clubcard. The parts must have equal universe metadata, partition metadata, and build
+    /// configurations. Fails with ClubcardError::InvalidBlock if a block appears in more than
+    /// one part. The result has a members sidecar only if every part has one.
+    pub fn concat(parts: impl IntoIterator<Item = Self>) -> Result<Self, ClubcardError> {
+        let mut parts = parts.into_iter();
+        let mut out = parts.next().ok_or(ClubcardError::Malformed)?;
+        for part in parts {
+            if part.universe != out.universe
+                || part.partition != out.partition
+                || part.build_config != out.build_config
+            {
+                return Err(ClubcardError::Malformed);
+            }
+            out.members = match (out.members.take(), part.members) {
+                (Some(mut members), Some(part_members)) => {
+                    members.blocks.extend(part_members.blocks);
+                    Some(members)
+                }
+                _ => None,
+            };
+            // Bits past the end of a column are zero, and a query can read up to 64W - 1 bits
+            // past the end of its block, so each part is followed by W words of zeros.
+            let approx_base = out.approx_filter.iter().map(Vec::len).max().unwrap_or(0) + W;
+            for column in &mut out.approx_filter {
+                column.resize(approx_base, 0);
+            }
+            let exact_base = out.exact_filter.len() + W;
+            out.exact_filter.resize(exact_base, 0);
+
+            for (i, column) in part.approx_filter.into_iter().enumerate() {
+                if out.approx_filter.len() == i {
+                    out.approx_filter.push(vec![0; approx_base]);
+                }
+                out.approx_filter[i].extend(column);
+            }
+            out.exact_filter.extend(part.exact_filter);
+            for (block, mut meta) in part.index {
+                if out.index.contains_key(&block) {
+                    return Err(ClubcardError::InvalidBlock(block));
+                }
+                meta.approx_filter_offset += 64 * approx_base;
+                meta.exact_filter_offset += 64 * exact_base;
+                out.index.insert(block, meta);
+            }
+        }
+        Ok(out)
+    }
+}
+
+impl<const W: usize, UniverseMetadata, PartitionMetadata>
+    Clubcard<W, UniverseMetadata, PartitionMetadata>
+where
+    UniverseMetadata: Clone + PartialEq,
+    PartitionMetadata: Clone + PartialEq,
+{
+    /// Replace the blocks that appear in `replacement` with their versions from `replacement`,
+    /// and keep the other blocks of this clubcard. This avoids a full rebuild when the data for
+    /// a few blocks changes. The replacement is built as usual, with a ClubcardBuilder that is
+    /// only given ribbons for the changed blocks. It must have the same universe metadata,
+    /// partition metadata, and build configuration as this clubcard (see concat), so build it
+    /// with `ClubcardBuilder::new_with_config(self.build_config().clone())`.
+    pub fn replace_blocks(&self, replacement: Self) -> Result<Self, ClubcardError> {
+        let kept = self.export_blocks(
+            self.index
+                .keys()
+                .filter(|block| !replacement.index.contains_key(*block))
+                .map(|block| &block[..]),
+        );
+        Self::concat([kept, replacement])
+    }
+
+    /// Add the blocks of `additions`, e.g. blocks for newly enrolled issuers, to this clubcard.
+    /// The additions are built as in replace_blocks. Fails with ClubcardError::InvalidBlock if
+    /// a block is already in this clubcard; use replace_blocks to change existing blocks.
+    pub fn append_blocks(self, additions: Self) -> Result<Self, ClubcardError> {
+        Self::concat([self, additions])
+    }
+}
+
+/// Query the block described by `meta`, where `eval` evaluates an equation against a column
+/// of the filters. Returns the result and whether the query reached the exact layer.
+pub(crate) fn query_block<const W: usize, T: AsQuery<W>>(
+    meta: &ClubcardIndexEntry,
+    item: &T,
+    eval: impl Fn(FilterColumn, &Equation<W>) -> u8,
+) -> (bool, bool) {
+    let (result, exact) = (|| {
+        // All queries evaluate to 0 on an empty filter, but logically such a filter does not
+        // include anything.
+        if meta.approx_filter_m == 0 {
+            return (false, false);
+        }
+
+        // Check if h(item) * X is 0
+        let approx_query = item.as_approx_query(meta);
+        if (0..meta.approx_filter_rank)
+            .any(|i| eval(FilterColumn::Approx(i), &approx_query) != 0)
+        {
+            return (false, false);
+        }
+
+        // Check if g(item) * Y is 0
+        if eval(FilterColumn::Exact, &item.as_exact_query(meta)) != 0 {
+            return (false, true);
+        }
+
+        for exception in &meta.exceptions {
+            if exception == item.discriminant() {
+                return (false, true);
+            }
+        }
+        (true, true)
+    })();
+
+    (result ^ meta.inverted, exact)
+}
+
+/// The bits of X and of Y that queries against a block read. See Clubcard::validate.
+type BlockSpans = (Option<Range<usize>>, Option<Range<usize>>);
+
+/// The number of bits of a solution vector that queries against a block with `m` rows can
+/// depend on. An equation that starts at position s depends on bits s..s + 64W of the solution,
+/// so this includes the 64W - 1 bits that follow the block.
+pub(crate) fn block_span<const W: usize>(m: usize) -> usize {
+    if m == 0 {
+        0
+    } else {
+        m + 64 * W - 1
+    }
+}
+
+/// Copy bits start..start + len of src to positions dst_start..dst_start + len of dst, growing
+/// dst as needed. Bits past the end of src are treated as zero.
+fn copy_bits(dst: &mut Vec<u64>, dst_start: usize, src: &[u64], start: usize, len: usize) {
+    let end = dst_start + len;
+    if dst.len() < end.div_ceil(64) {
+        dst.resize(end.div_ceil(64), 0);
+    }
+    for k in 0..len {
+        let i = start + k;
+        let bit = src.get(i / 64).map_or(0, |limb| (limb >> (i % 64)) & 1);
+        let j = dst_start + k;
+        dst[j / 64] |= bit << (j % 64);
+    }
+}
+
+/// Clubcards whose partition metadata maps blocks to per-block information, e.g.
+/// `Clubcard<4, U, BTreeMap<[u8; 32], BlockInfo>>`. See ClubcardBuilder::build_with_block_info.
+impl<const W: usize, UniverseMetadata, K, M> Clubcard<W, UniverseMetadata, BTreeMap<K, M>>
+where
+    K: Ord + Borrow<[u8]>,
+{
+    /// The information attached to `block`, if any.
+    pub fn block_info(&self, block: &[u8]) -> Option<&M> {
+        self.partition.get(block)
+    }
+
+    /// Same as contains, but also returns the information attached to the item's block, so
+    /// that per-block data such as a salt or a polarity flag can be applied to the result
+    /// without a second lookup.
+    pub fn contains_with_info<T>(&self, item: &T) -> (Membership, Option<&M>)
+    where
+        T: Queryable<W, UniverseMetadata = UniverseMetadata, PartitionMetadata = BTreeMap<K, M>>,
+    {
+        (self.contains(item), self.partition.get(item.block()))
+    }
+}
+
+impl<'a, const W: usize, UniverseMetadata, K, M>
+    BlockHandle<'a, W, UniverseMetadata, BTreeMap<K, M>>
+where
+    K: Ord + Borrow<[u8]>,
+{
+    /// The information attached to this block, if any.
+    pub fn info(&self) -> Option<&'a M> {
+        self.clubcard.partition.get(self.block)
+    }
+}
+
+impl<const W: usize, UniverseMetadata, PartitionMetadata>
+    Clubcard<W, UniverseMetadata, PartitionMetadata>
+where
+    UniverseMetadata: Encode,
+    PartitionMetadata: Encode,
+{
+    /// Serialize this clubcard. The output starts with a two byte little-endian version
+    /// (CLUBCARD_VERSION) and the length of the header as a u64. The header holds the build
+    /// configuration, the width W, the universe metadata, the partition metadata, and the index,
+    /// and is followed by the matrix X and the matrix Y. The header can be read on its own with
+    /// ClubcardHeader::from_bytes. The build configuration and the width come first so that
+    /// they can be read without knowing the types of the metadata.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encode_clubcard::<W>(
+            &self.build_config,
+            &self.universe,
+            &self.partition,
+            &self.index,
+            &self.approx_filter,
+            &self.exact_filter,
+        )
+    }
+}
+
+/// The serialization of a clubcard with the given parts. See Clubcard::to_bytes.
+pub(crate) fn encode_clubcard<const W: usize>(
+    build_config: &BuildConfig,
+    universe: &impl Encode,
+    partition: &impl Encode,
+    index: &ClubcardIndex,
+    approx_filter: &[Vec<u64>],
+    exact_filter: &[u64],
+) -> Vec<u8> {
+    let mut header = vec![];
+    build_config.encode(&mut header);
+    W.encode(&mut header);
+    universe.encode(&mut header);
+    partition.encode(&mut header);
+    index.encode(&mut header);
+
+    let mut out = vec![];
+    CLUBCARD_VERSION.encode(&mut out);
+    header.len().encode(&mut out);
+    out.extend_from_slice(&header);
+    approx_filter.encode(&mut out);
+    exact_filter.encode(&mut out);
+    out
+}
+
+impl<const W: usize, UniverseMetadata, PartitionMetadata>
+    Clubcard<W, UniverseMetadata, PartitionMetadata>
+where
+    UniverseMetadata: Decode,
+    PartitionMetadata: Decode,
+{
+    /// Deserialize a clubcard that was serialized with Clubcard::to_bytes, by this or an
+    /// earlier version of this crate (see SUPPORTED_VERSIONS). Clubcards in an earlier format
+    /// are converted to the current one; fields that the earlier format lacks, such as the build
+    /// configuration, take their default values. Fails with ClubcardError::WidthMismatch if the
+    /// clubcard was serialized with a different W.
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<Self, ClubcardError> {
+        if bytes.len() as u64 > MAX_SERIALIZED_LEN {
+            return Err(ClubcardError::LimitExceeded);
+        }
+        let bytes = &mut bytes;
+        let clubcard = match u16::decode(&mut &bytes[..])? {
+            CLUBCARD_VERSION | CLUBCARD_VERSION_3 | CLUBCARD_VERSION_2 | CLUBCARD_VERSION_1 => {
+                let header = ClubcardHeader::decode(bytes)?;
+                header.check_width::<W>()?;
+                Clubcard {
+                    build_config: header.build_config,
+                    members: None,
+                    universe: header.universe,
+                    partition: header.partition,
+                    index: header.index,
+                    approx_filter: Decode::decode(bytes)?,
+                    exact_filter: Decode::decode(bytes)?,
+                }
+            }
+            CLUBCARD_VERSION_0 => {
+                u16::decode(bytes)?;
+                Clubcard {
+                    universe: Decode::decode(bytes)?,
+                    partition: Decode::decode(bytes)?,
+                    index: match decode_legacy_index(bytes)? {
+                        index if index.len() > MAX_BLOCKS => {
+                            return Err(ClubcardError::LimitExceeded)
+                        }
+                        index => index,
+                    },
+                    approx_filter: Decode::decode(bytes)?,
+                    exact_filter: Decode::decode(bytes)?,
+                    build_config: BuildConfig::default(),
+                    members: None,
+                }
+            }
+            version => return Err(ClubcardError::UnsupportedVersion(version)),
+        };
+        if !bytes.is_empty() {
+            return Err(ClubcardError::Malformed);
+        }
+        Ok(clubcard)
+    }
+
+    /// Same as from_bytes, but the filters are decoded by up to `threads` threads (0 is treated
+    /// as 1). Decoding the filters dominates the time taken to load a large clubcard.
+    pub fn from_bytes_parallel(mut bytes: &[u8], threads: usize) -> Result<Self, ClubcardError> {
+        let threads = threads.max(1);
+        if bytes.len() as u64 > MAX_SERIALIZED_LEN {
+            return Err(ClubcardError::LimitExceeded);
+        }
+        if u16::decode(&mut &bytes[..])? == CLUBCARD_VERSION_0 {
+            return Self::from_bytes(bytes);
+        }
+        let bytes = &mut bytes;
+        let header = ClubcardHeader::decode(bytes)?;
+        header.check_width::<W>()?;
+
+        // The words of each column of X, and then of Y.
+        let mut columns = vec![];
+        let rank = usize::decode(bytes)?;
+        for _ in 0..=rank {
+            let len = usize::decode(bytes)?;
+            let len = len.checked_mul(8).ok_or(ClubcardError::Malformed)?;
+            columns.push(take(bytes, len)?);
+        }
+        if !bytes.is_empty() {
+            return Err(ClubcardError::Malformed);
+        }
+
+        let mut filters: Vec<Vec<u64>> = columns
+            .iter()
+            .map(|column| vec![0; column.len() / 8])
+            .collect();
+        let total: usize = filters.iter().map(Vec::len).sum();
+        let chunk = total.div_ceil(threads).max(1);
+        let mut groups: Vec<Vec<(&[u8], &mut [u64])>> = (0..threads).map(|_| vec![]).collect();
+        let pieces = columns
+            .iter()
+            .zip(filters.iter_mut())
+            .flat_map(|(src, dst)| src.chunks(8 * chunk).zip(dst.chunks_mut(chunk)));
+        for (i, piece) in pieces.enumerate() {
+            groups[i % threads].push(piece);
+        }
+        std::thread::scope(|scope| {
+            for group in groups {
+                scope.spawn(move || {
+                    for (src, dst) in group {
+                        for (word, bytes) in dst.iter_mut().zip(src.chunks_exact(8)) {
+                            *word = u64::from_le_bytes(bytes.try_into().unwrap());
+                        }
+                    }
+                });
+            }
+        });
+
+        let exact_filter = filters.pop().unwrap();
+        Ok(Clubcard {
+            build_config: header.build_config,
+            members: None,
+            universe: header.universe,
+            partition: header.partition,
+            index: header.index,
+            approx_filter: filters,
+            exact_filter,
+        })
+    }
+
+    /// Same as from_bytes followed by validate, except that the blocks that fail validation
+    /// are tombstoned (so queries to them return NotInUniverse) and returned, rather than
+    /// failing. This still fails if the header or the filters cannot be decoded.
+    pub fn from_bytes_salvage(bytes: &[u8]) -> Result<(Self, Vec<Vec<u8>>), ClubcardError> {
+        let mut clubcard = Self::from_bytes(bytes)?;
+        let mut corrupted = vec![];
+        for block in clubcard.index.keys() {
+            match clubcard.validate_block(block) {
+                Ok(()) => (),
+                Err(ClubcardError::InvalidBlock(block) | ClubcardError::CorruptBlock(block)) => {
+                    corrupted.push(block)
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        for block in &corrupted {
+            clubcard.index.get_mut(block).unwrap().tombstoned = true;
+        }
+        // The remaining blocks are valid on their own. As in validate, a block whose span
+        // overlaps that of an earlier block is invalid. Tombstoning a block for an overlap in the
+        // exact filter does not create overlaps in the approximate filter, so one pass over
+        // each filter suffices.
+        for exact in [false, true] {
+            let mut spans = vec![];
+            for (block, meta) in clubcard.index.iter().filter(|(_, meta)| !meta.tombstoned) {
+                let (approx_span, exact_span) = clubcard.block_spans(block, meta)?;
+                let span = if exact { exact_span } else { approx_span };
+                spans.extend(span.map(|span| (span.start, span.end, block.clone())));
+            }
+            spans.sort_unstable();
+            let mut end = 0;
+            let mut overlapping = vec![];
+            for (start, stop, block) in spans {
+                if start < end {
+                    overlapping.push(block);
+                } else {
+                    end = stop;
+                }
+            }
+            for block in overlapping {
+                clubcard.index.get_mut(&block).unwrap().tombstoned = true;
+                corrupted.push(block);
+            }
+        }
+        corrupted.sort_unstable();
+        Ok((clubcard, corrupted))
+    }
+}
+
+impl<const W: usize, UniverseMetadata, PartitionMetadata>
+    Clubcard<W, UniverseMetadata, PartitionMetadata>
+where
+    UniverseMetadata: Encode + Decode,
+    PartitionMetadata: Encode + Decode,
+{
+    /// Re-encode a serialized clubcard in the current format, e.g. to migrate an archive. See
+    /// from_bytes.
+    pub fn upgrade_bytes(bytes: &[u8]) -> Result<Vec<u8>, ClubcardError> {
+        Ok(Self::from_bytes(bytes)?.to_bytes())
+    }
+}
+
+/// The header of a serialized clubcard, i.e. everything but the filters. See Clubcard::to_bytes.
+pub struct ClubcardHeader<UniverseMetadata, PartitionMetadata> {
+    pub build_config: BuildConfig,
+    /// The width W of the clubcard. None for clubcards in a format that does not record it.
+    pub width: Option<usize>,
+    pub universe: UniverseMetadata,
+    pub partition: PartitionMetadata,
+    pub index: ClubcardIndex,
+}
+
+impl<UniverseMetadata, PartitionMetadata> ClubcardHeader<UniverseMetadata, PartitionMetadata> {
+    /// The number of bytes that encoded_len needs.
+    pub const PREFIX_LEN: usize = 10;
+
+    /// The length of the header of a serialized clubcard, including the version and the length
+    /// itself, given the first PREFIX_LEN bytes of the serialization.
+    pub fn encoded_len(mut prefix: &[u8]) -> Result<usize, ClubcardError> {
+        let bytes = &mut prefix;
+        let version = u16::decode(bytes)?;
+        if version == CLUBCARD_VERSION_0 || !SUPPORTED_VERSIONS.contains(&version) {
+            return Err(ClubcardError::UnsupportedVersion(version));
+        }
+        usize::decode(bytes)?
+            .checked_add(Self::PREFIX_LEN)
+            .ok_or(ClubcardError::Malformed)
+    }
+
+    /// The entries of the index, in order of block identifier. See Clubcard::blocks.
+    pub fn blocks(&self) -> impl Iterator<Item = BlockEntry<'_>> {
+        self.index
+            .iter()
+            .map(|(block, meta)| BlockEntry { block, meta })
+    }
+
+    /// Fails with ClubcardError::WidthMismatch if the header records a width other than W.
+    pub(crate) fn check_width<const W: usize>(&self) -> Result<(), ClubcardError> {
+        match self.width {
+            Some(found) if found != W => Err(ClubcardError::WidthMismatch { expected: W, found }),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<UniverseMetadata, PartitionMetadata> ClubcardHeader<UniverseMetadata, PartitionMetadata>
+where
+    UniverseMetadata: Decode,
+    PartitionMetadata: Decode,
+{
+    /// Parse the header of a serialized clubcard. Only the first encoded_len bytes of `bytes`
+    /// are read, so the filters do not need to be present.
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<Self, ClubcardError> {
+        Self::decode(&mut bytes)
+    }
+}
+
+/// Decodes the version, the length of the header, and the header.
+impl<UniverseMetadata, PartitionMetadata> Decode
+    for ClubcardHeader<UniverseMetadata, PartitionMetadata>
+where
+    UniverseMetadata: Decode,
+    PartitionMetadata: Decode,
+{
+    fn decode(bytes: &mut &[u8]) -> Result<Self, ClubcardError> {
+        let len = Self::encoded_len(bytes)?;
+        let version = u16::decode(&mut &bytes[..])?;
+        let mut header = bytes
+            .get(Self::PREFIX_LEN..len)
+            .ok_or(ClubcardError::UnexpectedEof)?;
+        *bytes = &bytes[len..];
+        let header_bytes = &mut header;
+        let header = ClubcardHeader {
+            build_config: Decode::decode(header_bytes)?,
+            width: match records_width(version) {
+                true => Some(Decode::decode(header_bytes)?),
+                false => None,
+            },
+            universe: Decode::decode(header_bytes)?,
+            partition: Decode::decode(header_bytes)?,
+            index: match version {
+                CLUBCARD_VERSION_1 => decode_legacy_index(header_bytes)?,
+                CLUBCARD_VERSION_2 | CLUBCARD_VERSION_3 => decode_unchecked_index(header_bytes)?,
+                _ => Decode::decode(header_bytes)?,
+            },
+        };
+        if !header_bytes.is_empty() {
+            return Err(ClubcardError::Malformed);
+        }
+        if header.index.len() > MAX_BLOCKS {
+            return Err(ClubcardError::LimitExceeded);
+        }
+        Ok(header)
+    }
+}
+
+/// A reference to a single block of a Clubcard. Queries through a BlockHandle skip the index
+/// lookup, so the caller must ensure that the items passed to it belong to this block.
+pub struct BlockHandle<'a, const W: usize, UniverseMetadata, PartitionMetadata> {
+    clubcard: &'a Clubcard<W, UniverseMetadata, PartitionMetadata>,
+    block: &'a [u8],
+    meta: &'a ClubcardIndexEntry,
+}
+
+impl<'a, const W: usize, UniverseMetadata, PartitionMetadata>
+    BlockHandle<'a, W, UniverseMetadata, PartitionMetadata>
+{
+    /// The identifier of this block.
+    pub fn block(&self) -> &'a [u8] {
+        self.block
+    }
+
+    /// The metadata for this block.
+    pub fn meta(&self) -> &'a ClubcardIndexEntry {
+        self.meta
+    }
+
+    /// Whether h(item) * X is 0 for every column X of the approximate filter of this block.
+    /// This is the approximate layer on its own: it ignores inversion and exceptions, and it
+    /// is false for blocks with an empty approximate filter.
+    pub(crate) fn approx_contains_any<T: AsQuery<W>>(&self, item: &T) -> bool {
+        let meta = self.meta;
+        // All queries evaluate to 0 on an empty filter, but logically
+        // such a filter does not include anything. So we handle it as a
+        // special case.
+        if meta.approx_filter_m == 0 {
+            return false;
+        }
+
+        let approx_query = item.as_approx_query(meta);
+        (0..meta.approx_filter_rank)
+            .all(|i| approx_query.eval(&self.clubcard.approx_filter[i]) == 0)
+    }
+
+    /// The value of g(item) * Y, where Y is the exact filter.
+    fn exact_bit_any<T: AsQuery<W>>(&self, item: &T) -> u8 {
+        item.as_exact_query(self.meta)
+            .eval(&self.clubcard.exact_filter)
+    }
+
+    pub(crate) fn unchecked_contains_any<T: AsQuery<W>>(&self, item: &T) -> bool {
+        self.unchecked_contains_staged(item).0
+    }
+
+    /// Same as unchecked_contains_any, but adds the work done to `cost`.
+    fn unchecked_contains_counting<T: AsQuery<W>>(&self, item: &T, cost: &mut QueryCost) -> bool {
+        let meta = self.meta;
+        let result = (|| {
+            if meta.approx_filter_m == 0 {
+                return false;
+            }
+            cost.hashes += 1;
+            cost.layers = 1;
+            let approx_query = item.as_approx_query(meta);
+            for column in &self.clubcard.approx_filter[..meta.approx_filter_rank] {
+                cost.columns += 1;
+                cost.cache_lines += cache_lines_read(&approx_query, column);
+                if approx_query.eval(column) != 0 {
+                    return false;
+                }
+            }
+
+            cost.hashes += 1;
+            cost.layers = 2;
+            let exact_query = item.as_exact_query(meta);
+            cost.columns += 1;
+            cost.cache_lines += cache_lines_read(&exact_query, &self.clubcard.exact_filter);
+            if exact_query.eval(&self.clubcard.exact_filter) != 0 {
+                return false;
+            }
+
+            for exception in &meta.exceptions {
+                cost.exceptions += 1;
+                if exception == item.discriminant() {
+                    return false;
+                }
+            }
+            true
+        })();
+        result ^ meta.inverted
+    }
+
+    /// Same as unchecked_contains_any, and whether the query reached the exact layer.
+    pub(crate) fn unchecked_contains_staged<T: AsQuery<W>>(&self, item: &T) -> (bool, bool) {
+        query_block(self.meta, item, |column, query| match column {
+            FilterColumn::Approx(i) => query.eval(&self.clubcard.approx_filter[i]),
+            FilterColumn::Exact => query.eval(&self.clubcard.exact_filter),
+        })
+    }
+
+    /// Query the approximate layer of this block, without checking whether the item is in the
+    /// universe. Useful for measuring the realized false positive rate of the approximate
+    /// layer. Inversion and exceptions are not taken into account.
+    pub fn approx_contains<T>(&self, item: &T) -> bool
+    where
+        T: Queryable<W, PartitionMetadata = PartitionMetadata>,
+    {
+        self.approx_contains_any(item)
+    }
+
+    /// Query the exact layer of this block, without checking whether the item is in the
+    /// universe. The result is only meaningful for items that pass the approximate layer.
+    pub fn exact_bit<T>(&self, item: &T) -> u8
+    where
+        T: Queryable<W, PartitionMetadata = PartitionMetadata>,
+    {
+        self.exact_bit_any(item)
+    }
+
+    /// Perform a membership query without checking whether the item is in the universe. See
+    /// Clubcard::unchecked_contains.
+    pub fn unchecked_contains<T>(&self, item: &T) -> bool
+    where
+        T: Queryable<W, PartitionMetadata = PartitionMetadata>,
+    {
+        debug_assert!(self
+            .clubcard
+            .index
+            .get(item.block())
+            .is_some_and(|meta| std::ptr::eq(meta, self.meta)));
+        self.unchecked_contains_any(item)
+    }
+
+    /// Check that the item is in the appropriate universe, and then perform a membership query.
+    pub fn contains<T>(&self, item: &T) -> Membership
+    where
+        T: Queryable<W, UniverseMetadata = UniverseMetadata, PartitionMetadata = PartitionMetadata>,
+    {
+        if !item.in_universe(&self.clubcard.universe) || self.meta.tombstoned {
+            return Membership::NotInUniverse;
+        };
+        self.unchecked_contains(item).into()
+    }
+    /// The members among `items`, which must belong to this block, in order. Same as keeping
+    /// the items for which contains returns Member, but the index is never consulted, e.g. for
+    /// finding the revoked certificates among a batch from one issuer.
+    pub fn filter_members<'b, T, I>(&'b self, items: I) -> impl Iterator<Item = &'b T> + 'b
+    where
+        T: Queryable<W, UniverseMetadata = UniverseMetadata, PartitionMetadata = PartitionMetadata>
+            + 'b,
+        I: IntoIterator<Item = &'b T>,
+        I::IntoIter: 'b,
+    {
+        let tombstoned = self.meta.tombstoned;
+        items.into_iter().filter(move |item| {
+            debug_assert!(item.block() == self.block);
+            !tombstoned
+                && item.in_universe(&self.clubcard.universe)
+                && self.unchecked_contains_any(*item)
+        })
+    }
+}
+
+// Compile-time check that clubcards and block handles with thread-safe metadata can be shared
+// between threads.
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Clubcard<4, (), ()>>();
+    assert_send_sync::<BlockHandle<'static, 4, (), ()>>();
+};
+
+/// Helper trait for (approximate) heap memory usage analysis in Firefox
+pub trait ApproximateSizeOf {
+    fn approximate_size_of(&self) -> usize
+    where
+        Self: Sized,
+    {
+        size_of::<Self>()
+    }
+}
+
+impl ApproximateSizeOf for () {}
+
+impl ApproximateSizeOf for ClubcardIndex {
+    fn approximate_size_of(&self) -> usize {
+        size_of::<ClubcardIndex>() + self.len() * size_of::<ClubcardIndexEntry>()
+    }
+}
+
+impl<const W: usize, UniverseMetadata, PartitionMetadata> ApproximateSizeOf
+    for Clubcard<W, UniverseMetadata, PartitionMetadata>
+where
+    UniverseMetadata: ApproximateSizeOf,
+    PartitionMetadata: ApproximateSizeOf,
+{
+    fn approximate_size_of(&self) -> usize {
+        self.universe.approximate_size_of()
+            + self.partition.approximate_size_of()
+            + self.index.approximate_size_of()
+            + self.approx_filter.iter().map(|x| x.len()).sum::<usize>()
+            + self.exact_filter.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::tests::{build_from_source, test_items};
+    use crate::builder::ClubcardBuilder;
+    use crate::test_vectors::{
+        generate, generated, rebuild_block_1, TestVectorQuery, BLOCKS, UNIVERSE_BOUND, W,
+    };
+    use serde::de::value::{Error, MapDeserializer, SeqDeserializer};
+    use serde::de::{IntoDeserializer, Visitor};
+    use serde::ser::{self, Impossible, Serializer};
+
+    /// A minimal self-describing serde data model, to check the serde representation of
+    /// clubcards without depending on a serialization format.
+    #[derive(Clone, Debug, PartialEq)]
+    enum Value {
+        Unit,
+        Bool(bool),
+        U64(u64),
+        Str(String),
+        Option(Option<Box<Value>>),
+        Seq(Vec<Value>),
+        Map(Vec<(Value, Value)>),
+    }
+
+    impl Value {
+        /// The field names of a serialized struct.
+        fn keys(&self) -> Vec<&str> {
+            let Value::Map(entries) = self else {
+                panic!("expected a map");
+            };
+            entries
+                .iter()
+                .map(|(key, _)| match key {
+                    Value::Str(key) => key.as_str(),
+                    _ => panic!("expected a field name"),
+                })
+                .collect()
+        }
+    }
+
+    fn to_value<T: Serialize>(x: &T) -> Value {
+        x.serialize(ValueSerializer).unwrap()
+    }
+
+    fn from_value<'de, T: Deserialize<'de>>(x: Value) -> T {
+        T::deserialize(x).unwrap()
+    }
+
+    struct ValueSerializer;
+
+    #[derive(Default)]
+    struct Compound(Vec<Value>, Vec<(Value, Value)>);
+
+    impl Serializer for ValueSerializer {
+        type Ok = Value;
+        type Error = Error;
+        type SerializeSeq = Compound;
+        type SerializeTuple = Compound;
+        type SerializeTupleStruct = Impossible<Value, Error>;
+        type SerializeTupleVariant = Impossible<Value, Error>;
+        type SerializeMap = Compound;
+        type SerializeStruct = Compound;
+        type SerializeStructVariant = Impossible<Value, Error>;
+
+        fn serialize_bool(self, v: bool) -> Result<Value, Error> {
+            Ok(Value::Bool(v))
+        }
+
+        fn serialize_i8(self, v: i8) -> Result<Value, Error> {
+            self.serialize_i64(v.into())
+        }
+
+        fn serialize_i16(self, v: i16) -> Result<Value, Error> {
+            self.serialize_i64(v.into())
+        }
+
+        fn serialize_i32(self, v: i32) -> Result<Value, Error> {
+            self.serialize_i64(v.into())
+        }
+
+        fn serialize_i64(self, v: i64) -> Result<Value, Error> {
+            u64::try_from(v)
+                .map(Value::U64)
+                .map_err(|_| ser::Error::custom("negative integer"))
+        }
+
+        fn serialize_u8(self, v: u8) -> Result<Value, Error> {
+            Ok(Value::U64(v.into()))
+        }
+
+        fn serialize_u16(self, v: u16) -> Result<Value, Error> {
+            Ok(Value::U64(v.into()))
+        }
+
+        fn serialize_u32(self, v: u32) -> Result<Value, Error> {
+            Ok(Value::U64(v.into()))
+        }
+
+        fn serialize_u64(self, v: u64) -> Result<Value, Error> {
+            Ok(Value::U64(v))
+        }
+
+        fn serialize_f32(self, _v: f32) -> Result<Value, Error> {
+            Err(ser::Error::custom("unsupported"))
+        }
+
+        fn serialize_f64(self, _v: f64) -> Result<Value, Error> {
+            Err(ser::Error::custom("unsupported"))
+        }
+
+        fn serialize_char(self, v: char) -> Result<Value, Error> {
+            Ok(Value::Str(v.into()))
+        }
+
+        fn serialize_str(self, v: &str) -> Result<Value, Error> {
+            Ok(Value::Str(v.into()))
+        }
+
+        fn serialize_bytes(self, v: &[u8]) -> Result<Value, Error> {
+            Ok(Value::Seq(
+                v.iter().map(|x| Value::U64((*x).into())).collect(),
+            ))
+        }
+
+        fn serialize_none(self) -> Result<Value, Error> {
+            Ok(Value::Option(None))
+        }
+
+        fn serialize_some<T: ?Sized + Serialize>(self, v: &T) -> Result<Value, Error> {
+            Ok(Value::Option(Some(Box::new(v.serialize(self)?))))
+        }
+
+        fn serialize_unit(self) -> Result<Value, Error> {
+            Ok(Value::Unit)
+        }
+
+        fn serialize_unit_struct(self, _name: &'static str) -> Result<Value, Error> {
+            Ok(Value::Unit)
+        }
+
+        fn serialize_unit_variant(
+            self,
+            _name: &'static str,
+            _index: u32,
+            variant: &'static str,
+        ) -> Result<Value, Error> {
+            Ok(Value::Str(variant.into()))
+        }
+
+        fn serialize_newtype_struct<T: ?Sized + Serialize>(
+            self,
+            _name: &'static str,
+            v: &T,
+        ) -> Result<Value, Error> {
+            v.serialize(self)
+        }
+
+        fn serialize_newtype_variant<T: ?Sized + Serialize>(
+            self,
+            _name: &'static str,
+            _index: u32,
+            _variant: &'static str,
+            _v: &T,
+        ) -> Result<Value, Error> {
+            Err(ser::Error::custom("unsupported"))
+        }
+
+        fn serialize_seq(self, _len: Option<usize>) -> Result<Compound, Error> {
+            Ok(Compound::default())
+        }
+
+        fn serialize_tuple(self, _len: usize) -> Result<Compound, Error> {
+            Ok(Compound::default())
+        }
+
+        fn serialize_tuple_struct(
+            self,
+            _name: &'static str,
+            _len: usize,
+        ) -> Result<Self::SerializeTupleStruct, Error> {
+            Err(ser::Error::custom("unsupported"))
+        }
+
+        fn serialize_tuple_variant(
+            self,
+            _name: &'static str,
+            _index: u32,
+            _variant: &'static str,
+            _len: usize,
+        ) -> Result<Self::SerializeTupleVariant, Error> {
+            Err(ser::Error::custom("unsupported"))
+        }
+
+        fn serialize_map(self, _len: Option<usize>) -> Result<Compound, Error> {
+            Ok(Compound::default())
+        }
+
+        fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Compound, Error> {
+            Ok(Compound::default())
+        }
+
+        fn serialize_struct_variant(
+            self,
+            _name: &'static str,
+            _index: u32,
+            _variant: &'static str,
+            _len: usize,
+        ) -> Result<Self::SerializeStructVariant, Error> {
+            Err(ser::Error::custom("unsupported"))
+        }
+    }
+
+    impl ser::SerializeSeq for Compound {
+        type Ok = Value;
+        type Error = Error;
+
+        fn serialize_element<T: ?Sized + Serialize>(&mut self, v: &T) -> Result<(), Error> {
+            self.0.push(v.serialize(ValueSerializer)?);
+            Ok(())
+        }
+
+        fn end(self) -> Result<Value, Error> {
+            Ok(Value::Seq(self.0))
+        }
+    }
+
+    impl ser::SerializeTuple for Compound {
+        type Ok = Value;
+        type Error = Error;
+
+        fn serialize_element<T: ?Sized + Serialize>(&mut self, v: &T) -> Result<(), Error> {
+            ser::SerializeSeq::serialize_element(self, v)
+        }
+
+        fn end(self) -> Result<Value, Error> {
+            ser::SerializeSeq::end(self)
+        }
+    }
+
+    impl ser::SerializeMap for Compound {
+        type Ok = Value;
+        type Error = Error;
+
+        fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> Result<(), Error> {
+            self.0.push(key.serialize(ValueSerializer)?);
+            Ok(())
+        }
+
+        fn serialize_value<T: ?Sized + Serialize>(&mut self, v: &T) -> Result<(), Error> {
+            let key = self.0.pop().unwrap();
+            self.1.push((key, v.serialize(ValueSerializer)?));
+            Ok(())
+        }
+
+        fn end(self) -> Result<Value, Error> {
+            Ok(Value::Map(self.1))
+        }
+    }
+
+    impl ser::SerializeStruct for Compound {
+        type Ok = Value;
+        type Error = Error;
+
+        fn serialize_field<T: ?Sized + Serialize>(
+            &mut self,
+            key: &'static str,
+            v: &T,
+        ) -> Result<(), Error> {
+            self.1
+                .push((Value::Str(key.into()), v.serialize(ValueSerializer)?));
+            Ok(())
+        }
+
+        fn end(self) -> Result<Value, Error> {
+            Ok(Value::Map(self.1))
+        }
+    }
+
+    impl<'de> serde::Deserializer<'de> for Value {
+        type Error = Error;
+
+        fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+            match self {
+                Value::Unit => visitor.visit_unit(),
+                Value::Bool(v) => visitor.visit_bool(v),
+                Value::U64(v) => visitor.visit_u64(v),
+                Value::Str(v) => visitor.visit_string(v),
+                Value::Option(None) => visitor.visit_none(),
+                Value::Option(Some(v)) => visitor.visit_some(*v),
+                Value::Seq(v) => visitor.visit_seq(SeqDeserializer::new(v.into_iter())),
+                Value::Map(v) => visitor.visit_map(MapDeserializer::new(v.into_iter())),
+            }
+        }
+
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+            option unit unit_struct newtype_struct seq tuple tuple_struct map struct enum
+            identifier ignored_any
+        }
+    }
+
+    impl IntoDeserializer<'_, Error> for Value {
+        type Deserializer = Value;
+
+        fn into_deserializer(self) -> Value {
+            self
+        }
+    }
+
+    #[test]
+    fn test_serde_representation() {
+        // The fields of version 0.3.1. Non-self-describing formats such as bincode read
+        // fields by position, so fields can be neither added nor removed.
+        let entry = ClubcardIndexEntry {
+            approx_filter_m: 1,
+            exact_filter_m: 2,
+            approx_filter_rank: 3,
+            approx_filter_offset: 4,
+            exact_filter_offset: 5,
+            inverted: true,
+            exceptions: vec![vec![6]],
+            input_digest: Some([7; 32]),
+            checksum: Some(8),
+            tombstoned: false,
+        };
+        let value = to_value(&entry);
+        assert!(
+            value.keys()
+                == [
+                    "approx_filter_m",
+                    "exact_filter_m",
+                    "approx_filter_rank",
+                    "approx_filter_offset",
+                    "exact_filter_offset",
+                    "inverted",
+                    "exceptions",
+                ]
+        );
+        let decoded: ClubcardIndexEntry = from_value(value);
+        assert!(
+            decoded
+                == ClubcardIndexEntry {
+                    input_digest: None,
+                    checksum: None,
+                    ..entry.clone()
+                }
+        );
+
+        let clubcard: Clubcard<1, (), ()> = Clubcard {
+            universe: (),
+            partition: (),
+            index: BTreeMap::from([(vec![0], entry)]),
+            approx_filter: vec![vec![1, 2]],
+            exact_filter: vec![3],
+            build_config: BuildConfig {
+                seed: Some(4),
+                ..Default::default()
+            },
+            members: None,
+        };
+        let value = to_value(&clubcard);
+        assert!(
+            value.keys()
+                == [
+                    "universe",
+                    "partition",
+                    "index",
+                    "approx_filter",
+                    "exact_filter"
+                ]
+        );
+        let decoded: Clubcard<1, (), ()> = from_value(value);
+        assert!(decoded.build_config == BuildConfig::default());
+        assert!(decoded.approx_filter == clubcard.approx_filter);
+        assert!(decoded.exact_filter == clubcard.exact_filter);
+        assert!(decoded.index.keys().eq(clubcard.index.keys()));
+    }
+
+    #[test]
+    fn test_serde_drops_tombstones() {
+        let mut clubcard: Clubcard<1, (), ()> = Clubcard::empty((), ());
+        let entry = ClubcardIndexEntry {
+            tombstoned: true,
+            ..Default::default()
+        };
+        clubcard.index.insert(vec![0], entry);
+        let decoded: Clubcard<1, (), ()> = from_value(to_value(&clubcard));
+        assert!(!decoded.index[&vec![0]].tombstoned);
+        assert!(decoded.index[&vec![0]] == ClubcardIndexEntry::default());
+    }
+
+    #[test]
+    fn test_export_blocks() {
+        let (clubcard, queries) = generated(0);
+        for blocks in [
+            vec![],
+            vec![[1u8]],
+            vec![[0], [2]],
+            vec![[3], [0], [1], [2]],
+        ] {
+            let exported = clubcard.export_blocks(blocks.iter().map(|b| &b[..]));
+            assert!(exported.validate().is_ok());
+            for (query, expected) in &queries {
+                if blocks.contains(&query.block) || *expected == Membership::NotInUniverse {
+                    assert_eq!(exported.contains(query), *expected);
+                } else {
+                    assert_eq!(exported.contains(query), Membership::NoData);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_blocks() {
+        let (clubcard, _) = generated(0);
+        let entries: Vec<_> = clubcard.blocks().collect();
+        assert!(entries.len() == BLOCKS.len());
+        let mut exact_bits = vec![];
+        for (i, entry) in entries.iter().enumerate() {
+            assert!(entry.block == [i as u8]);
+            assert!(Some(entry.stats()) == clubcard.block_stats(entry.block));
+            assert!(entry.exact_filter_bits().len() == entry.meta.exact_filter_m);
+            exact_bits.push(entry.exact_filter_bits());
+        }
+        exact_bits.sort_by_key(|bits| bits.start);
+        assert!(exact_bits
+            .windows(2)
+            .all(|pair| pair[0].end <= pair[1].start));
+    }
+
+    #[test]
+    fn test_replace_blocks() {
+        let (clubcard, queries) = generated(0);
+        let (replacement, block_1) = rebuild_block_1(&clubcard, &queries);
+
+        let replaced = clubcard.replace_blocks(replacement).unwrap();
+        assert!(replaced.validate().is_ok());
+        for (query, expected) in &queries {
+            if block_1.contains(query) {
+                assert_eq!(replaced.contains(query), Membership::Member);
+            } else {
+                assert_eq!(replaced.contains(query), *expected);
+            }
+        }
+
+        let (mismatched, _) = generated(1);
+        assert!(clubcard.replace_blocks(mismatched) == Err(ClubcardError::Malformed));
+    }
+
+    #[test]
+    fn test_append_blocks() {
+        let (clubcard, queries) = generated(0);
+        let head = clubcard.export_blocks([&[0u8][..], &[1]]);
+        let tail = clubcard.export_blocks([&[2u8][..], &[3]]);
+        let appended = head.append_blocks(tail).unwrap();
+        assert!(appended.validate().is_ok());
+        for (query, expected) in &queries {
+            assert_eq!(appended.contains(query), *expected);
+        }
+
+        let head = clubcard.export_blocks([&[0u8][..], &[1]]);
+        let overlap = clubcard.export_blocks([&[1u8][..], &[2]]);
+        assert!(head.append_blocks(overlap) == Err(ClubcardError::InvalidBlock(vec![1])));
+    }
+
+    #[test]
+    fn test_remove_blocks() {
+        let (clubcard, queries) = generated(0);
+        let removed = clubcard.remove_blocks([&[0u8][..], &[4]]);
+        assert!(removed.validate().is_ok());
+        assert!(removed.to_bytes().len() < clubcard.to_bytes().len());
+        for (query, expected) in &queries {
+            if query.block == [0] && *expected != Membership::NotInUniverse {
+                assert_eq!(removed.contains(query), Membership::NoData);
+            } else {
+                assert_eq!(removed.contains(query), *expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_header() {
+        let (bytes, _) = generate(0);
+        let clubcard = Clubcard::<W, u32, ()>::from_bytes(&bytes).unwrap();
+        let len = ClubcardHeader::<u32, ()>::encoded_len(&bytes).unwrap();
+        assert!(len < bytes.len());
+        let header = ClubcardHeader::<u32, ()>::from_bytes(&bytes[..len]).unwrap();
+        assert!(header.universe == UNIVERSE_BOUND);
+        assert!(header.build_config == *clubcard.build_config());
+        assert!(header
+            .blocks()
+            .map(|entry| entry.block)
+            .eq(clubcard.blocks().map(|entry| entry.block)));
+        assert!(
+            ClubcardHeader::<u32, ()>::from_bytes(&bytes[..len - 1]).err()
+                == Some(ClubcardError::UnexpectedEof)
+        );
+    }
+
+    #[test]
+    fn test_upgrade_bytes() {
+        let (bytes, queries) = generate(0);
+        let clubcard = Clubcard::<W, u32, ()>::from_bytes(&bytes).unwrap();
+
+        // Index entries in earlier versions of the format have no checksum.
+        let mut unchecked = Clubcard::<W, u32, ()>::from_bytes(&bytes).unwrap();
+        for meta in unchecked.index.values_mut() {
+            meta.checksum = None;
+        }
+        let unchecked_bytes = unchecked.to_bytes();
+
+        // Index entries in the first two versions of the format have no input digest.
+        let mut index = vec![];
+        clubcard.index.len().encode(&mut index);
+        for (block, meta) in &clubcard.index {
+            block.encode(&mut index);
+            meta.approx_filter_m.encode(&mut index);
+            meta.exact_filter_m.encode(&mut index);
+            meta.approx_filter_rank.encode(&mut index);
+            meta.approx_filter_offset.encode(&mut index);
+            meta.exact_filter_offset.encode(&mut index);
+            meta.inverted.encode(&mut index);
+            meta.exceptions.encode(&mut index);
+        }
+
+        // The first version of the format. The partition metadata is (), which is encoded as
+        // nothing.
+        let mut old = vec![];
+        0xffffu16.encode(&mut old);
+        clubcard.universe.encode(&mut old);
+        old.extend_from_slice(&index);
+        clubcard.approx_filter.encode(&mut old);
+        clubcard.exact_filter.encode(&mut old);
+
+        let upgraded = Clubcard::<W, u32, ()>::from_bytes(&old).unwrap();
+        assert!(*upgraded.build_config() == Default::default());
+        for (query, expected) in &queries {
+            assert_eq!(upgraded.contains(query), *expected);
+        }
+        assert!(Clubcard::<W, u32, ()>::upgrade_bytes(&old).unwrap() == upgraded.to_bytes());
+        assert!(Clubcard::<W, u32, ()>::upgrade_bytes(&bytes).unwrap() == bytes);
+
+        // The second version of the format.
+        let mut header = vec![];
+        clubcard.build_config.encode(&mut header);
+        clubcard.universe.encode(&mut header);
+        header.extend_from_slice(&index);
+        let mut old = vec![];
+        0xfffeu16.encode(&mut old);
+        header.len().encode(&mut old);
+        old.extend_from_slice(&header);
+        clubcard.approx_filter.encode(&mut old);
+        clubcard.exact_filter.encode(&mut old);
+
+        let len = ClubcardHeader::<u32, ()>::encoded_len(&old).unwrap();
+        let old_header = ClubcardHeader::<u32, ()>::from_bytes(&old[..len]).unwrap();
+        assert!(old_header.index == unchecked.index);
+        assert!(Clubcard::<W, u32, ()>::from_bytes(&old).unwrap() == unchecked);
+        assert!(Clubcard::<W, u32, ()>::upgrade_bytes(&old).unwrap() == unchecked_bytes);
+
+        // The third and fourth versions of the format, which differ in whether they record the
+        // width.
+        let mut index = vec![];
+        clubcard.index.len().encode(&mut index);
+        for (block, meta) in &clubcard.index {
+            block.encode(&mut index);
+            meta.approx_filter_m.encode(&mut index);
+            meta.exact_filter_m.encode(&mut index);
+            meta.approx_filter_rank.encode(&mut index);
+            meta.approx_filter_offset.encode(&mut index);
+            meta.exact_filter_offset.encode(&mut index);
+            meta.inverted.encode(&mut index);
+            meta.exceptions.encode(&mut index);
+            meta.input_digest.encode(&mut index);
+        }
+        for (version, width) in [(0xfffdu16, None), (0xfffc, Some(W))] {
+            let mut header = vec![];
+            clubcard.build_config.encode(&mut header);
+            if let Some(width) = width {
+                width.encode(&mut header);
+            }
+            clubcard.universe.encode(&mut header);
+            header.extend_from_slice(&index);
+            let mut old = vec![];
+            version.encode(&mut old);
+            header.len().encode(&mut old);
+            old.extend_from_slice(&header);
+            clubcard.approx_filter.encode(&mut old);
+            clubcard.exact_filter.encode(&mut old);
+
+            let len = ClubcardHeader::<u32, ()>::encoded_len(&old).unwrap();
+            let old_header = ClubcardHeader::<u32, ()>::from_bytes(&old[..len]).unwrap();
+            assert!(old_header.width == width);
+            assert!(old_header.index == unchecked.index);
+            assert!(Clubcard::<W, u32, ()>::from_bytes(&old).unwrap() == unchecked);
+            assert!(Clubcard::<W, u32, ()>::upgrade_bytes(&old).unwrap() == unchecked_bytes);
+            assert!(crate::serialized_layout(&old).is_ok());
+        }
+
+        let mut old = bytes.clone();
+        old[..2].copy_from_slice(&0xfffau16.to_le_bytes());
+        assert!(
+            Clubcard::<W, u32, ()>::from_bytes(&old).err()
+                == Some(ClubcardError::UnsupportedVersion(0xfffa))
+        );
+    }
+
+    #[test]
+    fn test_contains_batch() {
+        let (clubcard, queries) = generated(0);
+        let (items, expected): (Vec<_>, Vec<_>) = queries.into_iter().rev().unzip();
+        assert!(clubcard.contains_batch(&items) == expected);
+        assert!(clubcard.contains_batch::<TestVectorQuery>(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_tiny_inputs() {
+        // Every byte string of length at most 2, and every supported version followed by a
+        // truncated or overflowing header length.
+        let mut corpus: Vec<Vec<u8>> = vec![vec![]];
+        for x in 0..=u8::MAX {
+            corpus.push(vec![x]);
+            for y in 0..=u8::MAX {
+                corpus.push(vec![x, y]);
+            }
+        }
+        for version in SUPPORTED_VERSIONS {
+            for tail in [
+                &[0u8; 7][..],
+                &[0xff; 7],
+                &[0xff; 8],
+                &[0xf6, 0xff, 0xff, 0xff],
+            ] {
+                let mut input = version.to_le_bytes().to_vec();
+                input.extend_from_slice(tail);
+                corpus.push(input);
+            }
+        }
+        for input in &corpus {
+            assert!(Clubcard::<W, u32, ()>::from_bytes(input).is_err());
+            assert!(Clubcard::<W, u32, ()>::open_lazy(std::io::Cursor::new(input)).is_err());
+            assert!(ClubcardHeader::<u32, ()>::encoded_len(input).is_err());
+            assert!(ClubcardHeader::<u32, ()>::from_bytes(input).is_err());
+            assert!(crate::parts::header_len(input).is_err());
+            assert!(crate::parts::ClubcardPartsHeader::<u32, ()>::from_bytes(input).is_err());
+            assert!(crate::shard::ShardManifest::from_bytes(input).is_err());
+            assert!(crate::MembersSidecar::from_bytes(input).is_err());
+        }
+
+        // Every strict prefix of a valid serialization.
+        let (bytes, _) = generate(0);
+        let clubcard = Clubcard::<W, u32, ()>::from_bytes(&bytes).unwrap();
+        let manifest = clubcard.to_shards(2).unwrap().0.to_bytes();
+        let sidecar = crate::MembersSidecar::default().to_bytes();
+        for i in 0..bytes.len() {
+            assert!(Clubcard::<W, u32, ()>::from_bytes(&bytes[..i]).is_err());
+            assert!(Clubcard::<W, u32, ()>::open_lazy(std::io::Cursor::new(&bytes[..i])).is_err());
+        }
+        for i in 0..manifest.len() {
+            assert!(crate::shard::ShardManifest::from_bytes(&manifest[..i]).is_err());
+        }
+        for i in 0..sidecar.len() {
+            assert!(crate::MembersSidecar::from_bytes(&sidecar[..i]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_contains_any() {
+        let (clubcard, _) = generated(0);
+        // Every item in block 2 is a member, and no item in block 3 is.
+        let member = TestVectorQuery::new(2, 0);
+        let nonmember = TestVectorQuery::new(3, 0);
+        let no_data = TestVectorQuery::new(0xff, 0);
+        let not_in_universe = TestVectorQuery::new(2, UNIVERSE_BOUND);
+        for (items, expected) in [
+            (vec![], Membership::NotInUniverse),
+            (vec![not_in_universe.clone()], Membership::NotInUniverse),
+            (
+                vec![not_in_universe.clone(), no_data.clone()],
+                Membership::NoData,
+            ),
+            (
+                vec![no_data.clone(), nonmember.clone()],
+                Membership::Nonmember,
+            ),
+            (
+                vec![nonmember, no_data, member, not_in_universe],
+                Membership::Member,
+            ),
+        ] {
+            assert!(clubcard.contains_any(&items) == expected);
+        }
+    }
+
+    #[test]
+    fn test_width_mismatch() {
+        let (bytes, _) = generate(0);
+        let len = ClubcardHeader::<u32, ()>::encoded_len(&bytes).unwrap();
+        assert!(
+            ClubcardHeader::<u32, ()>::from_bytes(&bytes[..len])
+                .unwrap()
+                .width
+                == Some(W)
+        );
+        assert!(
+            Clubcard::<2, u32, ()>::from_bytes(&bytes).err()
+                == Some(ClubcardError::WidthMismatch {
+                    expected: 2,
+                    found: W
+                })
+        );
+        assert!(
+            Clubcard::<2, u32, ()>::open_lazy(std::io::Cursor::new(&bytes)).err()
+                == Some(ClubcardError::WidthMismatch {
+                    expected: 2,
+                    found: W
+                })
+        );
+    }
+
+    #[test]
+    fn test_filter_members() {
+        let (clubcard, queries) = generated(0);
+        for entry in clubcard.blocks() {
+            let handle = clubcard.block_handle(entry.block).unwrap();
+            let items: Vec<_> = queries
+                .iter()
+                .filter(|(query, _)| query.block == entry.block)
+                .collect();
+            let members: Vec<_> = handle.filter_members(items.iter().map(|x| &x.0)).collect();
+            let expected: Vec<_> = items
+                .iter()
+                .filter(|(_, expected)| *expected == Membership::Member)
+                .map(|x| &x.0)
+                .collect();
+            assert!(members == expected);
+        }
+    }
+
+    #[test]
+    fn test_from_bytes_salvage() {
+        let (bytes, queries) = generate(0);
+        let (clubcard, corrupted) = Clubcard::<W, u32, ()>::from_bytes_salvage(&bytes).unwrap();
+        assert!(corrupted.is_empty() && !clubcard.is_tombstoned(&[1]));
+
+        let mut clubcard = Clubcard::<W, u32, ()>::from_bytes(&bytes).unwrap();
+        clubcard
+            .index
+            .get_mut(&vec![1])
+            .unwrap()
+            .exact_filter_offset = usize::MAX / 2;
+        let bytes = clubcard.to_bytes();
+        let (clubcard, corrupted) = Clubcard::<W, u32, ()>::from_bytes_salvage(&bytes).unwrap();
+        assert!(corrupted == vec![vec![1]]);
+        assert!(clubcard.validate().is_ok());
+        for (query, expected) in &queries {
+            let expected = match query.block {
+                [1] => Membership::NotInUniverse,
+                _ => *expected,
+            };
+            assert!(clubcard.contains(query) == expected);
+            assert!(clubcard.contains_uniform_time(query) == expected);
+        }
+
+        // Block 1 is valid on its own, but overlaps block 0 in the exact filter.
+        let (bytes, _) = generate(0);
+        let mut clubcard = Clubcard::<W, u32, ()>::from_bytes(&bytes).unwrap();
+        let offset = clubcard.index[&vec![0]].exact_filter_offset;
+        let meta = clubcard.index.get_mut(&vec![1]).unwrap();
+        meta.exact_filter_offset = offset;
+        meta.checksum = None;
+        let bytes = clubcard.to_bytes();
+        let (clubcard, corrupted) = Clubcard::<W, u32, ()>::from_bytes_salvage(&bytes).unwrap();
+        assert!(corrupted == vec![vec![1]]);
+        assert!(clubcard.validate().is_ok());
+    }
+
+    #[test]
+    fn test_from_bytes_parallel() {
+        let (bytes, queries) = generate(0);
+        let expected = Clubcard::<W, u32, ()>::from_bytes(&bytes).unwrap();
+        for threads in [0, 1, 3, 64] {
+            let clubcard = Clubcard::<W, u32, ()>::from_bytes_parallel(&bytes, threads).unwrap();
+            assert!(clubcard.to_bytes() == bytes);
+            assert!(clubcard.approx_filter == expected.approx_filter);
+            for (query, expected) in &queries {
+                assert!(clubcard.contains(query) == *expected);
+            }
+        }
+        for len in [bytes.len() - 1, bytes.len() - 8, 100] {
+            assert!(
+                Clubcard::<W, u32, ()>::from_bytes_parallel(&bytes[..len], 2).err()
+                    == Clubcard::<W, u32, ()>::from_bytes(&bytes[..len]).err()
+            );
+        }
+        let mut extended = bytes.clone();
+        extended.push(0);
+        assert!(
+            Clubcard::<W, u32, ()>::from_bytes_parallel(&extended, 2).err()
+                == Some(ClubcardError::Malformed)
+        );
+    }
+
+    #[test]
+    fn test_max_query_work() {
+        let (clubcard, _) = generated(0);
+        let work = clubcard.max_query_work();
+        let max_rank = clubcard
+            .blocks()
+            .map(|entry| entry.stats().approx_filter_rank)
+            .max()
+            .unwrap();
+        assert!(work.hashes == 2);
+        assert!(work.filter_bytes == (max_rank + 1) * (W + 1) * 8);
+        assert!(
+            work.exception_bytes
+                == clubcard
+                    .blocks()
+                    .map(|entry| entry.meta.exceptions.iter().map(|x| x.len()).sum())
+                    .max()
+                    .unwrap()
+        );
+        assert!(clubcard.export_blocks([]).max_query_work() == QueryWork::default());
+    }
+
+    #[test]
+    fn test_contains_staged() {
+        use crate::metrics::{QueryMetrics, QueryStage};
+
+        let (clubcard, queries) = generated(0);
+        let metrics = QueryMetrics::new();
+        for (query, expected) in &queries {
+            let (result, stage) = clubcard.contains_staged(query);
+            assert!(result == *expected);
+            assert!(clubcard.contains_measured(query, &metrics) == *expected);
+            match result {
+                Membership::NotInUniverse => assert!(stage == QueryStage::Universe),
+                Membership::NoData => assert!(stage == QueryStage::Index),
+                _ => assert!(stage != QueryStage::Universe && stage != QueryStage::Index),
+            }
+        }
+        assert!(metrics.stage_count(QueryStage::Universe) == 1);
+        assert!(metrics.stage_count(QueryStage::Index) == 1);
+        // Members of an inverted block can stop at the approximate layer.
+        assert!(metrics.stage_count(QueryStage::ApproxFilter) > 0);
+        assert!(metrics.stage_count(QueryStage::ExactFilter) > 0);
+    }
+
+    #[test]
+    fn test_warm() {
+        let (clubcard, _) = generated(0);
+        let touched = clubcard.warm(1);
+        assert!(touched > 0);
+        assert!(clubcard.warm(4) == touched);
+        assert!(clubcard.warm(0) == touched);
+    }
+
+    #[test]
+    fn test_contains_counting() {
+        let (clubcard, queries) = generated(0);
+        for (query, expected) in &queries {
+            let (result, cost) = clubcard.contains_counting(query);
+            assert!(result == *expected);
+            assert!(cost.hashes == cost.layers);
+            if cost.layers == 0 {
+                assert!(cost == Default::default());
+            } else {
+                assert!(cost.columns > 0 && cost.cache_lines >= cost.columns);
+                assert!(cost.cache_lines <= 2 * cost.columns);
+            }
+            if matches!(result, Membership::NotInUniverse | Membership::NoData) {
+                assert!(cost.layers == 0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_single_block() {
+        let items = test_items(128);
+        let mut clubcard_builder = ClubcardBuilder::new();
+        clubcard_builder.set_single_block(true);
+        let clubcard = build_from_source(clubcard_builder, &items);
+        assert!(clubcard.num_blocks() == 1);
+        for item in &items {
+            assert!(clubcard.contains_single_block(item) == clubcard.contains(item));
+        }
+
+        let mut other = clubcard.export_blocks(std::iter::empty());
+        assert!(other.num_blocks() == 0);
+        assert!(other.contains_single_block(&items[0]) == Membership::NoData);
+        other.index.insert(vec![0], ClubcardIndexEntry::default());
+        other.index.insert(vec![1], ClubcardIndexEntry::default());
+        assert!(other.contains_single_block(&items[0]) == Membership::NoData);
+
+        let mut clubcard_builder = ClubcardBuilder::<1, Equation<1>>::new();
+        clubcard_builder.set_single_block(true);
+        let source = BTreeMap::from([(vec![0], items.clone()), (vec![1], items)]);
+        assert!(matches!(
+            clubcard_builder.collect_ribbons_from_source(&source),
+            Err(ClubcardError::InvalidArgument(_))
+        ));
+    }
+    #[test]
+    fn test_concat() {
+        let (clubcard, queries) = generated(0);
+        let parts = [
+            clubcard.export_blocks([&[0u8][..]]),
+            clubcard.export_blocks([&[1u8][..], &[2u8][..], &[3u8][..]]),
+        ];
+        let joined = Clubcard::concat(parts).unwrap();
+        assert!(joined.index.keys().eq(clubcard.index.keys()));
+        for (query, expected) in &queries {
+            assert!(joined.contains(query) == *expected);
+        }
+
+        let part = clubcard.export_blocks([&[0u8][..]]);
+        assert!(
+            Clubcard::concat([part, clubcard.export_blocks([&[0u8][..]])]).err()
+                == Some(ClubcardError::InvalidBlock(vec![0]))
+        );
+        assert!(Clubcard::<W, u32, ()>::concat([]).err() == Some(ClubcardError::Malformed));
     }
 }
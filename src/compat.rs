@@ -0,0 +1,103 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Backward-compatibility checks for archived clubcards.
+//!
+//! Consumers that upgrade this crate can keep serialized clubcards from earlier versions,
+//! together with a sample of queries and the results that the earlier version returned, and
+//! call check in their tests to confirm that the new version reads the clubcards and answers
+//! the queries identically.
+
+use crate::encoding::Decode;
+use crate::{Clubcard, ClubcardError, Membership, Queryable};
+
+/// A query whose result differs from the archived result.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompatMismatch {
+    /// The position of the query in the sample.
+    pub index: usize,
+    pub expected: Membership,
+    pub actual: Membership,
+}
+
+/// The result of check.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct CompatReport {
+    /// The format version that the clubcard was serialized with, if the input is long enough
+    /// to record one.
+    pub version: Option<u16>,
+    /// The error from Clubcard::from_bytes or Clubcard::validate, if the clubcard could not be
+    /// read. No queries are checked in that case.
+    pub error: Option<ClubcardError>,
+    /// The number of queries that were checked.
+    pub checked: usize,
+    pub mismatches: Vec<CompatMismatch>,
+}
+
+impl CompatReport {
+    /// Whether the clubcard was read and every query returned the archived result.
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none() && self.mismatches.is_empty()
+    }
+}
+
+/// Read the serialized clubcard `bytes` and compare the result of each query in `sample` with
+/// the result recorded for it.
+pub fn check<const W: usize, T>(bytes: &[u8], sample: &[(T, Membership)]) -> CompatReport
+where
+    T: Queryable<W>,
+    T::UniverseMetadata: Decode,
+    T::PartitionMetadata: Decode,
+{
+    let mut report = CompatReport {
+        version: u16::decode(&mut &bytes[..]).ok(),
+        ..Default::default()
+    };
+    let clubcard = match Clubcard::<W, T::UniverseMetadata, T::PartitionMetadata>::from_bytes(bytes)
+        .and_then(|clubcard| clubcard.validate().map(|()| clubcard))
+    {
+        Ok(clubcard) => clubcard,
+        Err(e) => {
+            report.error = Some(e);
+            return report;
+        }
+    };
+    for (index, (item, expected)) in sample.iter().enumerate() {
+        let actual = clubcard.contains(item);
+        if actual != *expected {
+            report.mismatches.push(CompatMismatch {
+                index,
+                expected: *expected,
+                actual,
+            });
+        }
+    }
+    report.checked = sample.len();
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_vectors::generate;
+
+    #[test]
+    fn test_compat_check() {
+        let (bytes, mut queries) = generate(0);
+        let report = check(&bytes, &queries);
+        assert!(report.is_ok());
+        assert!(report.version == Some(crate::CLUBCARD_VERSION));
+        assert!(report.checked == queries.len());
+
+        queries[3].1 = Membership::NoData;
+        let report = check(&bytes, &queries);
+        assert!(!report.is_ok());
+        assert!(report.mismatches.len() == 1 && report.mismatches[0].index == 3);
+
+        let report = check(&bytes[..bytes.len() - 1], &queries);
+        assert!(report.error == Some(ClubcardError::UnexpectedEof));
+        assert!(report.checked == 0);
+        assert!(check(&[], &queries).version.is_none());
+    }
+}
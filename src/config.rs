@@ -0,0 +1,115 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use crate::encoding::{Decode, Encode};
+use crate::ClubcardError;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// The default value of BuildConfig::epsilon.
+pub const DEFAULT_EPSILON: f64 = 0.02;
+
+/// The key of the annotation that holds the label set by ClubcardBuilder::set_label.
+pub const LABEL_ANNOTATION: &str = "label";
+
+/// Options that control how a clubcard is built. The configuration is stored in the clubcard
+/// (see Clubcard::build_config) to record how it was built.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BuildConfig {
+    /// The space overhead of each ribbon: a ribbon for n items has (1 + epsilon) * n rows.
+    /// Larger values make insertion failures (exceptions) less likely.
+    pub epsilon: f64,
+    /// Seed for the random choices made while solving. A builder with a seed produces the same
+    /// clubcard every time it is given the same inputs (in the same order).
+    pub seed: Option<u64>,
+    /// Whether block builders should drop items whose discriminant has already been inserted
+    /// into the same block. Only the first such item is kept, and the others are counted (see
+    /// Ribbon::duplicates). Duplicates are not looked for when this is not set.
+    pub dedup: bool,
+    /// The maximum length of an item's discriminant. Exact ribbon builders set aside items with
+    /// longer discriminants, and ClubcardBuilder::collect_exact_ribbons rejects the ribbons with
+    /// an error that lists them. Since every item in the universe passes through an exact
+    /// ribbon builder, the approximate ribbon builders do not check this limit.
+    pub max_discriminant_len: Option<usize>,
+    /// The maximum number of items in a block that may fail insertion into the block's exact
+    /// ribbon. Such items are stored as exceptions in the index, so a block with many of them
+    /// indicates a problem with the input (e.g. a poor hash). Exact ribbons that exceed this
+    /// limit are rejected by ClubcardBuilder::collect_exact_ribbons.
+    pub max_exceptions: Option<usize>,
+    /// Free-form provenance, e.g. the hash function and salt used by the caller's AsQuery
+    /// implementation. Not used by the builder.
+    pub annotations: BTreeMap<String, String>,
+}
+
+impl Default for BuildConfig {
+    fn default() -> Self {
+        BuildConfig {
+            epsilon: DEFAULT_EPSILON,
+            seed: None,
+            dedup: false,
+            max_discriminant_len: None,
+            max_exceptions: None,
+            annotations: BTreeMap::new(),
+        }
+    }
+}
+
+impl BuildConfig {
+    /// A human-readable name for the clubcard, e.g. "production-2024-06-01-full". Stored as
+    /// the annotation LABEL_ANNOTATION.
+    pub fn label(&self) -> Option<&str> {
+        self.annotations.get(LABEL_ANNOTATION).map(String::as_str)
+    }
+}
+
+impl Encode for BuildConfig {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.epsilon.encode(out);
+        self.seed.encode(out);
+        self.dedup.encode(out);
+        self.max_discriminant_len.encode(out);
+        self.max_exceptions.encode(out);
+        self.annotations.encode(out);
+    }
+}
+
+impl Decode for BuildConfig {
+    fn decode(bytes: &mut &[u8]) -> Result<Self, ClubcardError> {
+        Ok(BuildConfig {
+            epsilon: Decode::decode(bytes)?,
+            seed: Decode::decode(bytes)?,
+            dedup: Decode::decode(bytes)?,
+            max_discriminant_len: Decode::decode(bytes)?,
+            max_exceptions: Decode::decode(bytes)?,
+            annotations: Decode::decode(bytes)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_vectors::{generate, W};
+    use crate::Clubcard;
+
+    #[test]
+    fn test_build_config() {
+        let (bytes, _) = generate(0);
+        let clubcard = Clubcard::<W, u32, ()>::from_bytes(&bytes).unwrap();
+        let config = clubcard.build_config();
+        assert!(config.epsilon == DEFAULT_EPSILON);
+        assert!(config.seed.is_some());
+        assert!(clubcard.label().is_none());
+
+        let lazy = Clubcard::<W, u32, ()>::open_lazy(std::io::Cursor::new(&bytes)).unwrap();
+        assert!(lazy.build_config() == config);
+
+        let parts = clubcard.to_parts();
+        let header_len = crate::parts::header_len(&parts).unwrap();
+        let header =
+            crate::parts::ClubcardPartsHeader::<u32, ()>::from_bytes(&parts[..header_len]).unwrap();
+        assert!(header.build_config == *config);
+    }
+}
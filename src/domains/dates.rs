@@ -0,0 +1,200 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Universes defined by date ranges, e.g. the certificates issued (by notBefore) in the
+//! periods that a filter covers.
+
+use crate::encoding::{Decode, Encode};
+use crate::rotation::Coverage;
+use crate::{ApproximateSizeOf, AsQuery, Clubcard, ClubcardError, Membership, UniversePolicy};
+use std::ops::{Range, RangeInclusive};
+
+/// An item with a date in caller-defined units, e.g. seconds since the Unix epoch.
+pub trait Dated {
+    fn date(&self) -> u64;
+}
+
+/// Universe metadata that covers the items whose date lies in one of a set of half-open
+/// ranges. Use it with Clubcard::contains_in.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DateRangeCoverage {
+    /// Sorted, non-empty, and separated by gaps.
+    ranges: Vec<Range<u64>>,
+}
+
+impl DateRangeCoverage {
+    /// The union of `ranges`. Empty ranges are ignored.
+    pub fn new(ranges: impl IntoIterator<Item = Range<u64>>) -> Self {
+        let mut sorted: Vec<Range<u64>> = ranges.into_iter().filter(|r| !r.is_empty()).collect();
+        sorted.sort_unstable_by_key(|range| range.start);
+        let mut ranges: Vec<Range<u64>> = Vec::with_capacity(sorted.len());
+        for range in sorted {
+            match ranges.last_mut() {
+                Some(last) if last.end >= range.start => last.end = last.end.max(range.end),
+                _ => ranges.push(range),
+            }
+        }
+        DateRangeCoverage { ranges }
+    }
+
+    /// The union of the closed ranges `ranges`, for data sources whose upper bounds are
+    /// inclusive. Each range is normalized to the half-open range that covers the same dates,
+    /// except that u64::MAX is never covered.
+    pub fn from_inclusive(ranges: impl IntoIterator<Item = RangeInclusive<u64>>) -> Self {
+        Self::new(
+            ranges
+                .into_iter()
+                .map(|range| *range.start()..range.end().saturating_add(1)),
+        )
+    }
+
+    pub fn ranges(&self) -> &[Range<u64>] {
+        &self.ranges
+    }
+
+    pub fn covers_date(&self, date: u64) -> bool {
+        self.covering_range(date).is_some()
+    }
+
+    /// The range that contains `date`, if any.
+    pub fn covering_range(&self, date: u64) -> Option<&Range<u64>> {
+        let i = self.ranges.partition_point(|range| range.end <= date);
+        self.ranges.get(i).filter(|range| range.contains(&date))
+    }
+
+    /// Whether every date covered by `older` is covered by this coverage.
+    pub fn is_superset_of(&self, older: &DateRangeCoverage) -> bool {
+        older.ranges.iter().all(|range| {
+            self.covering_range(range.start)
+                .is_some_and(|covering| covering.end >= range.end)
+        })
+    }
+}
+
+impl Coverage for DateRangeCoverage {
+    fn is_superset_of(&self, older: &Self) -> bool {
+        DateRangeCoverage::is_superset_of(self, older)
+    }
+}
+
+impl<const W: usize, PartitionMetadata> Clubcard<W, DateRangeCoverage, PartitionMetadata> {
+    /// Same as contains_in, but also returns the range of the universe that covers the item,
+    /// e.g. for logging. The range is None if and only if the result is NotInUniverse because
+    /// the item's date is not covered.
+    pub fn contains_detailed<T>(&self, item: &T) -> (Membership, Option<Range<u64>>)
+    where
+        T: AsQuery<W> + Dated,
+    {
+        let range = self.universe().covering_range(item.date()).cloned();
+        (self.contains_in(item), range)
+    }
+}
+
+impl<T: Dated> UniversePolicy<T> for DateRangeCoverage {
+    fn covers(&self, item: &T) -> bool {
+        self.covers_date(item.date())
+    }
+}
+
+impl Encode for DateRangeCoverage {
+    fn encode(&self, out: &mut Vec<u8>) {
+        let ranges: Vec<([u8; 8], [u8; 8])> = self
+            .ranges
+            .iter()
+            .map(|range| (range.start.to_le_bytes(), range.end.to_le_bytes()))
+            .collect();
+        ranges.encode(out);
+    }
+}
+
+impl Decode for DateRangeCoverage {
+    fn decode(bytes: &mut &[u8]) -> Result<Self, ClubcardError> {
+        let ranges: Vec<([u8; 8], [u8; 8])> = Decode::decode(bytes)?;
+        let ranges: Vec<Range<u64>> = ranges
+            .into_iter()
+            .map(|(start, end)| u64::from_le_bytes(start)..u64::from_le_bytes(end))
+            .collect();
+        if ranges.iter().any(Range::is_empty)
+            || !ranges.windows(2).all(|pair| pair[0].end < pair[1].start)
+        {
+            return Err(ClubcardError::Malformed);
+        }
+        Ok(DateRangeCoverage { ranges })
+    }
+}
+
+impl ApproximateSizeOf for DateRangeCoverage {
+    fn approximate_size_of(&self) -> usize {
+        std::mem::size_of::<DateRangeCoverage>()
+            + self.ranges.len() * std::mem::size_of::<Range<u64>>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_date_range_coverage() {
+        let coverage = DateRangeCoverage::new([20..30, 0..10, 5..12, 30..31, 40..40]);
+        assert!(coverage.ranges() == [0..12, 20..31]);
+        for (date, covered) in [(0, true), (11, true), (12, false), (19, false), (30, true)] {
+            assert!(coverage.covers_date(date) == covered);
+        }
+        assert!(!coverage.covers_date(u64::MAX));
+        assert!(DateRangeCoverage::from_inclusive([20..=30, 0..=9, 5..=11]) == coverage);
+        let full = DateRangeCoverage::from_inclusive([0..=u64::MAX]);
+        assert!(full.covers_date(u64::MAX - 1) && !full.covers_date(u64::MAX));
+
+        assert!(coverage.is_superset_of(&coverage));
+        assert!(coverage.is_superset_of(&DateRangeCoverage::new([1..5, 20..31])));
+        assert!(coverage.is_superset_of(&DateRangeCoverage::default()));
+        assert!(!coverage.is_superset_of(&DateRangeCoverage::new([0..5, 10..13])));
+        assert!(!coverage.is_superset_of(&DateRangeCoverage::new([0..1, 11..21])));
+        assert!(!DateRangeCoverage::default().is_superset_of(&coverage));
+
+        let mut bytes = vec![];
+        coverage.encode(&mut bytes);
+        assert!(DateRangeCoverage::decode(&mut &bytes[..]) == Ok(coverage));
+
+        // Ranges must be separated by gaps.
+        let mut bytes = vec![];
+        vec![
+            (0u64.to_le_bytes(), 10u64.to_le_bytes()),
+            (10u64.to_le_bytes(), 20u64.to_le_bytes()),
+        ]
+        .encode(&mut bytes);
+        assert!(DateRangeCoverage::decode(&mut &bytes[..]) == Err(ClubcardError::Malformed));
+    }
+
+    #[test]
+    fn test_contains_detailed() {
+        struct Item(u64);
+
+        impl AsQuery<1> for Item {
+            fn as_query(&self, _m: usize) -> crate::Equation<1> {
+                crate::Equation::homogeneous(0, [1])
+            }
+
+            fn block(&self) -> &[u8] {
+                &[]
+            }
+
+            fn discriminant(&self) -> &[u8] {
+                &[]
+            }
+        }
+
+        impl Dated for Item {
+            fn date(&self) -> u64 {
+                self.0
+            }
+        }
+
+        let clubcard: Clubcard<1, DateRangeCoverage, ()> =
+            Clubcard::empty(DateRangeCoverage::new([0..10, 20..30]), ());
+        assert!(clubcard.contains_detailed(&Item(25)) == (Membership::NoData, Some(20..30)));
+        assert!(clubcard.contains_detailed(&Item(15)) == (Membership::NotInUniverse, None));
+    }
+}
@@ -0,0 +1,315 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Sets of IP addresses, e.g. blocklists.
+//!
+//! The block of an IPv4 address is its /16 prefix and the block of an IPv6 address is its /32
+//! prefix, each preceded by the IP version (4 or 6). The discriminant is the rest of the
+//! address. An IPv4 block is enumerated in full: the addresses of a covered /16 that were not
+//! inserted are nonmembers. An IPv6 block is too large to enumerate, so its universe is the set
+//! of addresses that were inserted, and queries for other addresses in a covered IPv6 block
+//! have arbitrary results.
+
+use crate::encoding::{Decode, Encode};
+use crate::int::mix;
+use crate::{
+    ApproximateSizeOf, AsQuery, Clubcard, ClubcardError, Equation, Filterable, Membership,
+    Queryable,
+};
+use std::net::IpAddr;
+
+/// The width of the equations of an IpSet.
+const W: usize = 2;
+
+/// The blocks that an IpSet covers, in sorted order. Queries for addresses in other blocks
+/// return NotInUniverse.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IpCoverage {
+    pub blocks: Vec<Vec<u8>>,
+}
+
+impl IpCoverage {
+    pub fn covers(&self, addr: IpAddr) -> bool {
+        self.blocks
+            .binary_search_by(|block| block[..].cmp(IpItem::new(addr, false).block()))
+            .is_ok()
+    }
+}
+
+impl Encode for IpCoverage {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.blocks.encode(out);
+    }
+}
+
+impl Decode for IpCoverage {
+    fn decode(bytes: &mut &[u8]) -> Result<Self, ClubcardError> {
+        let blocks: Vec<Vec<u8>> = Decode::decode(bytes)?;
+        if !blocks.windows(2).all(|pair| pair[0] < pair[1]) {
+            return Err(ClubcardError::Malformed);
+        }
+        Ok(IpCoverage { blocks })
+    }
+}
+
+impl ApproximateSizeOf for IpCoverage {
+    fn approximate_size_of(&self) -> usize {
+        std::mem::size_of::<IpCoverage>() + self.blocks.iter().map(|b| b.len()).sum::<usize>()
+    }
+}
+
+#[derive(Clone)]
+struct IpItem {
+    /// The IP version followed by the address.
+    bytes: [u8; 17],
+    block_len: usize,
+    len: usize,
+    included: bool,
+}
+
+impl IpItem {
+    fn new(addr: IpAddr, included: bool) -> Self {
+        let mut bytes = [0u8; 17];
+        let (block_len, len) = match addr {
+            IpAddr::V4(addr) => {
+                bytes[0] = 4;
+                bytes[1..5].copy_from_slice(&addr.octets());
+                (3, 5)
+            }
+            IpAddr::V6(addr) => {
+                bytes[0] = 6;
+                bytes[1..17].copy_from_slice(&addr.octets());
+                (5, 17)
+            }
+        };
+        IpItem {
+            bytes,
+            block_len,
+            len,
+            included,
+        }
+    }
+}
+
+impl AsQuery<W> for IpItem {
+    fn as_query(&self, m: usize) -> Equation<W> {
+        const GAMMA: u64 = 0x9e3779b97f4a7c15;
+        let mut addr = [0u8; 16];
+        addr[16 - (self.len - 1)..].copy_from_slice(&self.bytes[1..self.len]);
+        let hi = u64::from_le_bytes(addr[..8].try_into().unwrap());
+        let lo = u64::from_le_bytes(addr[8..].try_into().unwrap());
+        let hash = |i: u64| mix(mix(hi ^ GAMMA.wrapping_mul(i)) ^ lo);
+        let s = hash(3) % m.max(1) as u64;
+        Equation::homogeneous(s as usize, [hash(1) | 1, hash(2)])
+    }
+
+    fn block(&self) -> &[u8] {
+        &self.bytes[..self.block_len]
+    }
+
+    fn discriminant(&self) -> &[u8] {
+        &self.bytes[self.block_len..self.len]
+    }
+}
+
+impl Filterable<W> for IpItem {
+    fn included(&self) -> bool {
+        self.included
+    }
+}
+
+impl Queryable<W> for IpItem {
+    type UniverseMetadata = IpCoverage;
+    type PartitionMetadata = ();
+
+    fn in_universe(&self, coverage: &IpCoverage) -> bool {
+        coverage
+            .blocks
+            .binary_search_by(|block| block[..].cmp(self.block()))
+            .is_ok()
+    }
+}
+
+/// A set of IP addresses, built with IpSetBuilder.
+pub struct IpSet {
+    clubcard: Clubcard<W, IpCoverage, ()>,
+}
+
+impl IpSet {
+    /// Whether `addr` is in the set. Returns NotInUniverse if the block of `addr` is not
+    /// covered. See the module documentation for addresses in covered IPv6 blocks.
+    pub fn contains(&self, addr: IpAddr) -> Membership {
+        self.clubcard.contains(&IpItem::new(addr, false))
+    }
+
+    pub fn coverage(&self) -> &IpCoverage {
+        self.clubcard.universe()
+    }
+
+    pub fn clubcard(&self) -> &Clubcard<W, IpCoverage, ()> {
+        &self.clubcard
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.clubcard.to_bytes()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ClubcardError> {
+        Ok(IpSet {
+            clubcard: Clubcard::from_bytes(bytes)?,
+        })
+    }
+}
+
+/// Builds an IpSet from addresses and CIDR ranges.
+#[cfg(feature = "builder")]
+#[derive(Default)]
+pub struct IpSetBuilder {
+    addrs: std::collections::BTreeMap<IpAddr, bool>,
+    seed: Option<u64>,
+}
+
+#[cfg(feature = "builder")]
+impl IpSetBuilder {
+    pub fn new() -> Self {
+        IpSetBuilder::default()
+    }
+
+    /// See ClubcardBuilder::new_with_seed.
+    pub fn new_with_seed(seed: u64) -> Self {
+        IpSetBuilder {
+            seed: Some(seed),
+            ..Default::default()
+        }
+    }
+
+    /// Add `addr` to the universe, and cover its block. If `addr` was inserted before, the
+    /// last call decides whether it is a member.
+    pub fn insert(&mut self, addr: IpAddr, member: bool) {
+        self.addrs.insert(addr, member);
+    }
+
+    /// Insert every address of the CIDR range `addr`/`prefix_len`. The range must lie within
+    /// one block: `prefix_len` must be at least 16 for IPv4, and at least 112 for IPv6, so
+    /// that the range has at most 2^16 addresses. Fails with ClubcardError::InvalidArgument
+    /// otherwise, or if `prefix_len` exceeds the length of the address, without inserting
+    /// anything.
+    pub fn insert_cidr(
+        &mut self,
+        addr: IpAddr,
+        prefix_len: u8,
+        member: bool,
+    ) -> Result<(), ClubcardError> {
+        match addr {
+            IpAddr::V4(addr) => {
+                if !(16..=32).contains(&prefix_len) {
+                    return Err(ClubcardError::InvalidArgument(
+                        "IPv4 prefix length must be between 16 and 32",
+                    ));
+                }
+                let host_bits = 32 - prefix_len as u32;
+                let base = u32::from(addr) & !((1u64 << host_bits) - 1) as u32;
+                for i in 0..1u32 << host_bits {
+                    self.insert(IpAddr::V4((base | i).into()), member);
+                }
+            }
+            IpAddr::V6(addr) => {
+                if !(112..=128).contains(&prefix_len) {
+                    return Err(ClubcardError::InvalidArgument(
+                        "IPv6 prefix length must be between 112 and 128",
+                    ));
+                }
+                let host_bits = 128 - prefix_len as u32;
+                let base = u128::from(addr) & !((1u128 << host_bits) - 1);
+                for i in 0..1u128 << host_bits {
+                    self.insert(IpAddr::V6((base | i).into()), member);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Build the set. Fails as ClubcardBuilder::collect_ribbons_from_source.
+    pub fn build(self) -> Result<IpSet, ClubcardError> {
+        use crate::builder::{BuildConfig, ClubcardBuilder};
+        use std::collections::BTreeMap;
+        use std::net::Ipv4Addr;
+
+        let mut source: BTreeMap<Vec<u8>, Vec<IpItem>> = BTreeMap::new();
+        for (&addr, &member) in &self.addrs {
+            let item = IpItem::new(addr, member);
+            let items = source.entry(item.block().to_vec()).or_default();
+            // The IPv4 blocks are filled in below.
+            if addr.is_ipv6() {
+                items.push(item);
+            }
+        }
+        for (block, items) in source.iter_mut().filter(|(block, _)| block[0] == 4) {
+            for i in 0..=u16::MAX {
+                let [c, d] = i.to_be_bytes();
+                let addr = IpAddr::V4(Ipv4Addr::new(block[1], block[2], c, d));
+                let member = self.addrs.get(&addr).copied().unwrap_or(false);
+                items.push(IpItem::new(addr, member));
+            }
+        }
+
+        let coverage = IpCoverage {
+            blocks: source.keys().cloned().collect(),
+        };
+        let mut builder = ClubcardBuilder::new_with_config(BuildConfig {
+            seed: self.seed,
+            ..Default::default()
+        });
+        builder.collect_ribbons_from_source(&source)?;
+        Ok(IpSet {
+            clubcard: builder.build::<IpItem>(coverage, ()),
+        })
+    }
+}
+
+#[cfg(all(test, feature = "builder"))]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    #[test]
+    fn test_ipset() {
+        let v4 = |a, b, c, d| IpAddr::V4(Ipv4Addr::new(a, b, c, d));
+        let v6 = |x: u128| IpAddr::V6(Ipv6Addr::from(x));
+
+        let mut builder = IpSetBuilder::new_with_seed(0);
+        builder.insert_cidr(v4(10, 1, 2, 0), 24, true).unwrap();
+        builder.insert(v4(10, 1, 2, 7), false);
+        builder.insert(v4(192, 168, 0, 1), true);
+        builder
+            .insert_cidr(v6(0x2001_0db8 << 96), 120, true)
+            .unwrap();
+        builder.insert(v6((0x2001_0db8 << 96) | 0x1_0000), false);
+        for (addr, prefix_len) in [(v4(10, 0, 0, 0), 8), (v4(10, 0, 0, 0), 33), (v6(0), 64)] {
+            assert!(matches!(
+                builder.insert_cidr(addr, prefix_len, true),
+                Err(ClubcardError::InvalidArgument(_))
+            ));
+        }
+        let set = builder.build().unwrap();
+
+        assert!(set.coverage().blocks.len() == 3);
+        assert!(set.contains(v4(10, 1, 2, 0)) == Membership::Member);
+        assert!(set.contains(v4(10, 1, 2, 255)) == Membership::Member);
+        assert!(set.contains(v4(10, 1, 2, 7)) == Membership::Nonmember);
+        assert!(set.contains(v4(10, 1, 3, 0)) == Membership::Nonmember);
+        assert!(set.contains(v4(192, 168, 0, 1)) == Membership::Member);
+        assert!(set.contains(v4(192, 168, 0, 2)) == Membership::Nonmember);
+        assert!(set.contains(v4(10, 2, 0, 0)) == Membership::NotInUniverse);
+        assert!(set.contains(v6((0x2001_0db8 << 96) | 0xff)) == Membership::Member);
+        assert!(set.contains(v6((0x2001_0db8 << 96) | 0x1_0000)) == Membership::Nonmember);
+        assert!(set.contains(v6(1)) == Membership::NotInUniverse);
+        assert!(!set.coverage().covers(v4(10, 2, 0, 0)));
+        assert!(set.coverage().covers(v6((0x2001_0db8 << 96) | 0xffff_ffff)));
+
+        let decoded = IpSet::from_bytes(&set.to_bytes()).unwrap();
+        assert!(decoded.coverage() == set.coverage());
+        assert!(decoded.contains(v4(10, 1, 2, 1)) == Membership::Member);
+    }
+}
@@ -0,0 +1,132 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use crate::{Clubcard, Membership, Queryable};
+use std::any::Any;
+use std::marker::PhantomData;
+
+/// An object-safe query interface, so that clubcards with different widths, metadata, and item
+/// types can be used through one `Box<dyn DynClubcard>`. See Clubcard::into_dyn.
+pub trait DynClubcard {
+    /// Query the item with the given block and discriminant. `aux` carries whatever else the
+    /// clubcard's item type needs (e.g. timestamps for the universe check). Returns
+    /// Membership::NotInUniverse if the item type cannot be constructed from the arguments.
+    fn contains_bytes(&self, block: &[u8], discriminant: &[u8], aux: &dyn Any) -> Membership;
+}
+
+/// An item type that can be constructed from the arguments of DynClubcard::contains_bytes.
+pub trait QueryFromBytes<const W: usize>: Queryable<W> + Sized {
+    /// Returns None if the arguments do not describe an item, e.g. if `aux` has the wrong type.
+    fn from_bytes(block: &[u8], discriminant: &[u8], aux: &dyn Any) -> Option<Self>;
+}
+
+/// A clubcard whose items are of type T. See Clubcard::into_dyn.
+pub struct DynAdapter<const W: usize, UniverseMetadata, PartitionMetadata, T> {
+    clubcard: Clubcard<W, UniverseMetadata, PartitionMetadata>,
+    phantom: PhantomData<fn(&T)>,
+}
+
+impl<const W: usize, UniverseMetadata, PartitionMetadata, T>
+    DynAdapter<W, UniverseMetadata, PartitionMetadata, T>
+{
+    pub fn clubcard(&self) -> &Clubcard<W, UniverseMetadata, PartitionMetadata> {
+        &self.clubcard
+    }
+
+    pub fn into_clubcard(self) -> Clubcard<W, UniverseMetadata, PartitionMetadata> {
+        self.clubcard
+    }
+}
+
+impl<const W: usize, UniverseMetadata, PartitionMetadata, T> DynClubcard
+    for DynAdapter<W, UniverseMetadata, PartitionMetadata, T>
+where
+    T: QueryFromBytes<
+        W,
+        UniverseMetadata = UniverseMetadata,
+        PartitionMetadata = PartitionMetadata,
+    >,
+{
+    fn contains_bytes(&self, block: &[u8], discriminant: &[u8], aux: &dyn Any) -> Membership {
+        match T::from_bytes(block, discriminant, aux) {
+            Some(item) => self.clubcard.contains(&item),
+            None => Membership::NotInUniverse,
+        }
+    }
+}
+
+impl<const W: usize, UniverseMetadata, PartitionMetadata>
+    Clubcard<W, UniverseMetadata, PartitionMetadata>
+{
+    /// Wrap this clubcard in a DynClubcard that queries it with items of type T.
+    pub fn into_dyn<T>(self) -> DynAdapter<W, UniverseMetadata, PartitionMetadata, T>
+    where
+        T: QueryFromBytes<
+            W,
+            UniverseMetadata = UniverseMetadata,
+            PartitionMetadata = PartitionMetadata,
+        >,
+    {
+        DynAdapter {
+            clubcard: self,
+            phantom: PhantomData,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AsQuery, Equation};
+
+    struct Query {
+        discriminant: [u8; 1],
+    }
+
+    impl AsQuery<1> for Query {
+        fn as_query(&self, _m: usize) -> Equation<1> {
+            Equation::homogeneous(0, [1])
+        }
+
+        fn block(&self) -> &[u8] {
+            &[]
+        }
+
+        fn discriminant(&self) -> &[u8] {
+            &self.discriminant
+        }
+    }
+
+    impl Queryable<1> for Query {
+        type UniverseMetadata = u8;
+        type PartitionMetadata = ();
+
+        fn in_universe(&self, bound: &u8) -> bool {
+            self.discriminant[0] < *bound
+        }
+    }
+
+    impl QueryFromBytes<1> for Query {
+        fn from_bytes(block: &[u8], discriminant: &[u8], aux: &dyn Any) -> Option<Self> {
+            aux.downcast_ref::<()>()?;
+            if !block.is_empty() {
+                return None;
+            }
+            Some(Query {
+                discriminant: discriminant.try_into().ok()?,
+            })
+        }
+    }
+
+    #[test]
+    fn test_dyn_clubcard() {
+        let clubcard: Clubcard<1, u8, ()> = Clubcard::empty(4, ());
+        let clubcard: Box<dyn DynClubcard> = Box::new(clubcard.into_dyn::<Query>());
+        assert!(clubcard.contains_bytes(&[], &[0], &()) == Membership::NoData);
+        assert!(clubcard.contains_bytes(&[], &[4], &()) == Membership::NotInUniverse);
+        assert!(clubcard.contains_bytes(&[], &[0, 0], &()) == Membership::NotInUniverse);
+        assert!(clubcard.contains_bytes(&[0], &[0], &()) == Membership::NotInUniverse);
+        assert!(clubcard.contains_bytes(&[], &[0], &0u8) == Membership::NotInUniverse);
+    }
+}
@@ -0,0 +1,287 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! A simple, explicit binary encoding for clubcards.
+//!
+//! All integers are little-endian. A `usize` is written as a u64. Variable length sequences are
+//! written as a u64 length followed by the encoding of each element.
+
+use crate::error::ClubcardError;
+use std::collections::BTreeMap;
+
+/// A type that can be written in the clubcard binary format.
+pub trait Encode {
+    fn encode(&self, out: &mut Vec<u8>);
+}
+
+/// A type that can be read from the clubcard binary format. On success, `bytes` is advanced past
+/// the decoded value.
+pub trait Decode: Sized {
+    fn decode(bytes: &mut &[u8]) -> Result<Self, ClubcardError>;
+}
+
+pub(crate) fn take<'a>(bytes: &mut &'a [u8], n: usize) -> Result<&'a [u8], ClubcardError> {
+    if bytes.len() < n {
+        return Err(ClubcardError::UnexpectedEof);
+    }
+    let (head, tail) = bytes.split_at(n);
+    *bytes = tail;
+    Ok(head)
+}
+
+macro_rules! impl_int {
+    ($($t:ty),*) => {
+        $(
+            impl Encode for $t {
+                fn encode(&self, out: &mut Vec<u8>) {
+                    out.extend_from_slice(&self.to_le_bytes());
+                }
+            }
+
+            impl Decode for $t {
+                fn decode(bytes: &mut &[u8]) -> Result<Self, ClubcardError> {
+                    let buf = take(bytes, std::mem::size_of::<$t>())?;
+                    Ok(<$t>::from_le_bytes(buf.try_into().unwrap()))
+                }
+            }
+        )*
+    };
+}
+
+impl_int!(u8, u16, u32, u64);
+
+impl Encode for usize {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (*self as u64).encode(out)
+    }
+}
+
+impl Decode for usize {
+    fn decode(bytes: &mut &[u8]) -> Result<Self, ClubcardError> {
+        usize::try_from(u64::decode(bytes)?).map_err(|_| ClubcardError::Malformed)
+    }
+}
+
+impl Encode for bool {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (*self as u8).encode(out)
+    }
+}
+
+impl Decode for bool {
+    fn decode(bytes: &mut &[u8]) -> Result<Self, ClubcardError> {
+        match u8::decode(bytes)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(ClubcardError::Malformed),
+        }
+    }
+}
+
+impl Encode for f64 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.to_bits().encode(out)
+    }
+}
+
+impl Decode for f64 {
+    fn decode(bytes: &mut &[u8]) -> Result<Self, ClubcardError> {
+        Ok(f64::from_bits(u64::decode(bytes)?))
+    }
+}
+
+impl Encode for () {
+    fn encode(&self, _out: &mut Vec<u8>) {}
+}
+
+impl Decode for () {
+    fn decode(_bytes: &mut &[u8]) -> Result<Self, ClubcardError> {
+        Ok(())
+    }
+}
+
+impl<const N: usize> Encode for [u8; N] {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self);
+    }
+}
+
+impl<const N: usize> Decode for [u8; N] {
+    fn decode(bytes: &mut &[u8]) -> Result<Self, ClubcardError> {
+        Ok(take(bytes, N)?.try_into().unwrap())
+    }
+}
+
+impl<T: Encode> Encode for [T] {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.len().encode(out);
+        for x in self {
+            x.encode(out);
+        }
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.as_slice().encode(out);
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode(bytes: &mut &[u8]) -> Result<Self, ClubcardError> {
+        let len = usize::decode(bytes)?;
+        // Every element occupies at least one byte, except for zero-sized types, so we cap the
+        // preallocation to avoid trusting the length prefix.
+        let mut out = Vec::with_capacity(std::cmp::min(len, bytes.len()));
+        for _ in 0..len {
+            out.push(T::decode(bytes)?);
+        }
+        Ok(out)
+    }
+}
+
+impl Encode for String {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.len().encode(out);
+        out.extend_from_slice(self.as_bytes());
+    }
+}
+
+impl Decode for String {
+    fn decode(bytes: &mut &[u8]) -> Result<Self, ClubcardError> {
+        let len = usize::decode(bytes)?;
+        String::from_utf8(take(bytes, len)?.to_vec()).map_err(|_| ClubcardError::Malformed)
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.is_some().encode(out);
+        if let Some(x) = self {
+            x.encode(out);
+        }
+    }
+}
+
+impl<T: Decode> Decode for Option<T> {
+    fn decode(bytes: &mut &[u8]) -> Result<Self, ClubcardError> {
+        match bool::decode(bytes)? {
+            true => Ok(Some(T::decode(bytes)?)),
+            false => Ok(None),
+        }
+    }
+}
+
+impl<A: Encode, B: Encode> Encode for (A, B) {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.0.encode(out);
+        self.1.encode(out);
+    }
+}
+
+impl<A: Decode, B: Decode> Decode for (A, B) {
+    fn decode(bytes: &mut &[u8]) -> Result<Self, ClubcardError> {
+        Ok((A::decode(bytes)?, B::decode(bytes)?))
+    }
+}
+
+impl<K: Encode, V: Encode> Encode for BTreeMap<K, V> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.len().encode(out);
+        for (k, v) in self {
+            k.encode(out);
+            v.encode(out);
+        }
+    }
+}
+
+impl<K: Decode + Ord, V: Decode> Decode for BTreeMap<K, V> {
+    fn decode(bytes: &mut &[u8]) -> Result<Self, ClubcardError> {
+        let len = usize::decode(bytes)?;
+        let mut out = BTreeMap::new();
+        for _ in 0..len {
+            let k = K::decode(bytes)?;
+            let v = V::decode(bytes)?;
+            out.insert(k, v);
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip<T: Encode + Decode + PartialEq + std::fmt::Debug>(x: T) {
+        let mut bytes = vec![];
+        x.encode(&mut bytes);
+        let mut cursor = &bytes[..];
+        assert_eq!(T::decode(&mut cursor), Ok(x));
+        assert!(cursor.is_empty());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        round_trip(0x0102u16);
+        round_trip(u64::MAX);
+        round_trip(true);
+        round_trip(vec![vec![1u8, 2, 3], vec![]]);
+        round_trip([7u8; 32]);
+        round_trip(String::from("clubcard"));
+        round_trip(Some(0.5f64));
+        round_trip(None::<u64>);
+        round_trip(BTreeMap::from([(vec![0u8], (1u64, false))]));
+        round_trip(vec![
+            crate::Membership::Member,
+            crate::Membership::Unavailable,
+        ]);
+        assert_eq!(
+            crate::Membership::decode(&mut &[5u8][..]),
+            Err(ClubcardError::Malformed)
+        );
+        for x in 0..=u8::MAX {
+            let raw = crate::RawMembership(x);
+            match crate::Membership::try_from(raw) {
+                Ok(m) => assert!(x <= 4 && m as u8 == x && crate::RawMembership::from(m) == raw),
+                Err(_) => assert!(x > 4),
+            }
+        }
+    }
+
+    #[test]
+    fn test_truncated() {
+        let mut bytes = vec![];
+        vec![1u64, 2, 3].encode(&mut bytes);
+        for i in 0..bytes.len() {
+            assert_eq!(
+                Vec::<u64>::decode(&mut &bytes[..i]),
+                Err(ClubcardError::UnexpectedEof)
+            );
+        }
+    }
+
+    #[test]
+    fn test_byte_order() {
+        // The encoding does not depend on the byte order or the pointer width of the host, so
+        // these bytes are the same on every target.
+        let mut bytes = vec![];
+        0x0102u16.encode(&mut bytes);
+        0x01020304u32.encode(&mut bytes);
+        0x0102030405060708u64.encode(&mut bytes);
+        0x0102usize.encode(&mut bytes);
+        1.0f64.encode(&mut bytes);
+        assert_eq!(
+            bytes,
+            [
+                2, 1, 4, 3, 2, 1, 8, 7, 6, 5, 4, 3, 2, 1, 2, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+                0xf0, 0x3f
+            ]
+        );
+        // A length that does not fit in a usize is rejected rather than truncated.
+        assert_eq!(
+            usize::decode(&mut &[0, 0, 0, 0, 1, 0, 0, 0][..]).ok(),
+            usize::try_from(1u64 << 32).ok()
+        );
+    }
+}
@@ -0,0 +1,113 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use crate::encoding::{Decode, Encode};
+use crate::{Clubcard, ClubcardError};
+
+/// The version of the EnrolledBlocks encoding. This is independent of CLUBCARD_VERSION, since
+/// the list is published and fetched on its own.
+pub const ENROLLMENT_VERSION: u16 = 0xffff;
+
+/// The sorted identifiers of the blocks of a clubcard, e.g. the issuers that are enrolled in a
+/// revocation filter. A client can fetch this list on its own to decide whether an item is
+/// covered before it fetches the clubcard or constructs a full query. Produced by
+/// Clubcard::enrolled_blocks, so it always matches the clubcard's block set.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EnrolledBlocks {
+    blocks: Vec<Vec<u8>>,
+}
+
+impl EnrolledBlocks {
+    pub fn blocks(&self) -> impl Iterator<Item = &[u8]> {
+        self.blocks.iter().map(|block| &block[..])
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    pub fn contains(&self, block: &[u8]) -> bool {
+        self.blocks.binary_search_by(|x| x[..].cmp(block)).is_ok()
+    }
+
+    /// Whether this list has exactly the blocks of `clubcard`.
+    pub fn matches<const W: usize, UniverseMetadata, PartitionMetadata>(
+        &self,
+        clubcard: &Clubcard<W, UniverseMetadata, PartitionMetadata>,
+    ) -> bool {
+        self.blocks.iter().eq(clubcard.index.keys())
+    }
+
+    /// Serialize this list. The output starts with a two byte little-endian version
+    /// (ENROLLMENT_VERSION) followed by the list of blocks.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = vec![];
+        ENROLLMENT_VERSION.encode(&mut out);
+        self.blocks.encode(&mut out);
+        out
+    }
+
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<Self, ClubcardError> {
+        let bytes = &mut bytes;
+        let version = u16::decode(bytes)?;
+        if version != ENROLLMENT_VERSION {
+            return Err(ClubcardError::UnsupportedVersion(version));
+        }
+        let blocks: Vec<Vec<u8>> = Decode::decode(bytes)?;
+        if !bytes.is_empty() || !blocks.windows(2).all(|pair| pair[0] < pair[1]) {
+            return Err(ClubcardError::Malformed);
+        }
+        Ok(EnrolledBlocks { blocks })
+    }
+}
+
+impl<const W: usize, UniverseMetadata, PartitionMetadata>
+    Clubcard<W, UniverseMetadata, PartitionMetadata>
+{
+    /// The list of this clubcard's blocks, to be published alongside it.
+    pub fn enrolled_blocks(&self) -> EnrolledBlocks {
+        EnrolledBlocks {
+            blocks: self.index.keys().cloned().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_vectors::{generated, BLOCKS};
+
+    #[test]
+    fn test_enrolled_blocks() {
+        let (clubcard, _) = generated(0);
+        let enrolled = clubcard.enrolled_blocks();
+        assert!(enrolled.len() == BLOCKS.len());
+        assert!(enrolled.matches(&clubcard));
+        assert!((0..BLOCKS.len() as u8).all(|block| enrolled.contains(&[block])));
+        assert!(!enrolled.contains(&[0xff]));
+
+        let decoded = EnrolledBlocks::from_bytes(&enrolled.to_bytes()).unwrap();
+        assert!(decoded == enrolled);
+        let part = clubcard.export_blocks([&[0u8][..]]);
+        assert!(!decoded.matches(&part));
+        assert!(part.enrolled_blocks().matches(&part));
+
+        // Blocks must be sorted.
+        let mut unsorted = vec![];
+        ENROLLMENT_VERSION.encode(&mut unsorted);
+        vec![vec![1u8], vec![0u8]].encode(&mut unsorted);
+        assert!(EnrolledBlocks::from_bytes(&unsorted) == Err(ClubcardError::Malformed));
+
+        let mut other_version = enrolled.to_bytes();
+        other_version[..2].copy_from_slice(&crate::CLUBCARD_VERSION.to_le_bytes());
+        assert!(
+            EnrolledBlocks::from_bytes(&other_version)
+                == Err(ClubcardError::UnsupportedVersion(crate::CLUBCARD_VERSION))
+        );
+    }
+}
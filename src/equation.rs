@@ -2,7 +2,33 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
-use std::cmp::min;
+use std::cmp::{max, min};
+
+/// The largest supported value of W, i.e. equations have at most 64 * MAX_WIDTH coefficients.
+pub const MAX_WIDTH: usize = 8;
+
+/// A marker type for the width parameter W. See ValidWidth.
+pub struct Width<const W: usize>;
+
+mod private {
+    pub trait Sealed {}
+}
+
+/// Implemented for Width\<W\> when 1 <= W <= MAX_WIDTH. Code that is generic over W can require
+/// `Width<W>: ValidWidth` to reject unsupported widths at the call site. Equation\<W\> checks the
+/// same condition at compile time when it is used with a particular W.
+pub trait ValidWidth: private::Sealed {}
+
+macro_rules! impl_valid_width {
+    ($($w:literal),*) => {
+        $(
+            impl private::Sealed for Width<$w> {}
+            impl ValidWidth for Width<$w> {}
+        )*
+    };
+}
+
+impl_valid_width!(1, 2, 3, 4, 5, 6, 7, 8);
 
 /// An Equation\<W\> is a representation of a GF(2) linear functional
 ///     a(x) = b + sum_i a_i x_i
@@ -17,6 +43,17 @@ pub struct Equation<const W: usize> {
 }
 
 impl<const W: usize> Equation<W> {
+    /// Evaluating this fails to compile unless 1 <= W <= MAX_WIDTH.
+    const VALID_WIDTH: () = assert!(
+        W >= 1 && W <= MAX_WIDTH,
+        "Equation<W> requires 1 <= W <= MAX_WIDTH"
+    );
+
+    /// Evaluating this fails to compile unless a SHA-512 digest is long enough for
+    /// Equation::from_digest.
+    #[cfg(feature = "sha2")]
+    const HASHABLE_WIDTH: () = assert!(W <= 7, "Equation::from_hashable requires W <= 7");
+
     /// Construct the equation a(x) = sum_{i=s}^{s+64*W} a_i x^i.
     /// The result is aligned.
     pub fn homogeneous(s: usize, a: [u64; W]) -> Equation<W> {
@@ -32,8 +69,39 @@ impl<const W: usize> Equation<W> {
         eq
     }
 
+    /// Construct an aligned homogeneous equation from a uniformly random digest, as required by
+    /// AsQuery::as_query. The coefficients are read from the first 8*W bytes of `digest` as
+    /// little-endian u64s, with the low bit of the first coefficient set. The starting position
+    /// is the little-endian u64 in the next 8 bytes, reduced modulo max(1, m).
+    ///
+    /// Returns None if the digest is shorter than 8*W + 8 bytes, so that the starting position
+    /// never depends on the coefficients. A SHA-256 digest is too short for W >= 4.
+    pub fn from_digest(digest: &[u8], m: usize) -> Option<Equation<W>> {
+        if digest.len() < 8 * W + 8 {
+            return None;
+        }
+        let mut a = [0u64; W];
+        for (a_i, x) in a.iter_mut().zip(digest.chunks_exact(8)) {
+            *a_i = u64::from_le_bytes(x.try_into().unwrap());
+        }
+        a[0] |= 1;
+        let tail = &digest[8 * W..8 * W + 8];
+        let s = (u64::from_le_bytes(tail.try_into().unwrap()) as usize) % max(1, m);
+        Some(Equation::homogeneous(s, a))
+    }
+
+    /// Construct an aligned homogeneous equation from the SHA-512 digest of `bytes`. See
+    /// Equation::from_digest. Fails to compile unless W <= 7.
+    #[cfg(feature = "sha2")]
+    pub fn from_hashable(bytes: &[u8], m: usize) -> Equation<W> {
+        use sha2::{Digest, Sha512};
+        let () = Self::HASHABLE_WIDTH;
+        Equation::from_digest(&Sha512::digest(bytes), m).unwrap()
+    }
+
     /// Construct the equation a(x) = 0.
     pub fn zero() -> Self {
+        let () = Self::VALID_WIDTH;
         Equation {
             s: 0,
             a: [0u64; W],
@@ -47,22 +115,63 @@ impl<const W: usize> Equation<W> {
         self.a == [0u64; W]
     }
 
+    /// Is b = 0? AsQuery::as_query must return homogeneous equations: the builders set b
+    /// from Filterable::included, and queries ignore it.
+    pub fn is_homogeneous(&self) -> bool {
+        self.b == 0
+    }
+
+    /// Is a_s = 1? The zero equation is not aligned.
+    pub fn is_aligned(&self) -> bool {
+        self.a[0] & 1 == 1
+    }
+
+    /// The index of the first non-zero coefficient, i.e. the smallest i with a_i = 1. Returns
+    /// None if this equation is zero. For an aligned equation, this is s.
+    pub fn leading_coefficient(&self) -> Option<usize> {
+        let limb = self.a.iter().position(|&a_i| a_i != 0)?;
+        Some(self.s + 64 * limb + self.a[limb].trailing_zeros() as usize)
+    }
+
+    /// The coefficient a_i.
+    pub fn coefficient(&self, i: usize) -> u8 {
+        match i.checked_sub(self.s) {
+            Some(k) if k < 64 * W => ((self.a[k / 64] >> (k % 64)) & 1) as u8,
+            _ => 0,
+        }
+    }
+
     /// Adds `other` into `self`, i.e. sets self.a ^= other.a and self.b ^= other.b and then aligns
     /// the result.
     pub fn add(&mut self, other: &Equation<W>) {
+        self.xor(other);
+        self.align();
+    }
+
+    /// Sets self.a ^= other.a and self.b ^= other.b without aligning the result. The equations
+    /// must have the same starting position.
+    pub fn xor(&mut self, other: &Equation<W>) {
+        let () = Self::VALID_WIDTH;
         assert!(self.s == other.s);
         // Add the equations in GF(2)
         for i in 0..W {
             self.a[i] ^= other.a[i];
         }
         self.b ^= other.b;
-        // Exit early if this equation is now zero.
+    }
+
+    /// Shift the coefficients so that the first non-zero coefficient is at position s, i.e. so
+    /// that s becomes leading_coefficient(). The functional that this equation represents does
+    /// not change. Does nothing if this equation is zero.
+    pub fn align(&mut self) {
+        // Exit early if this equation is zero.
         if self.is_zero() {
             return;
         }
         // Shift until there is a non-zero bit in the lowest limb.
         while self.a[0] == 0 {
             self.a.rotate_left(1);
+            self.s += 64;
         }
         // Shift first non-zero bit to position 0.
         let k = self.a[0].trailing_zeros();
@@ -80,6 +189,7 @@ impl<const W: usize> Equation<W> {
 
     /// Computes a(z) = sum a_i z_i.
     pub fn eval(&self, z: &[u64]) -> u8 {
+        let () = Self::VALID_WIDTH;
         // Compute a(z), noting that this only depends
         // on 64*W bits of z starting from position s.
         let limb = self.s / 64;
@@ -98,7 +208,19 @@ impl<const W: usize> Equation<W> {
 
 #[cfg(test)]
 mod tests {
-    use crate::Equation;
+    use crate::{Equation, ValidWidth, Width, MAX_WIDTH};
+
+    #[test]
+    fn test_valid_width() {
+        fn width<const W: usize>() -> usize
+        where
+            Width<W>: ValidWidth,
+        {
+            Equation::<W>::zero().a.len()
+        }
+        assert!(width::<1>() == 1);
+        assert!(width::<MAX_WIDTH>() == MAX_WIDTH);
+    }
 
     #[test]
     fn test_equation_add() {
@@ -117,6 +239,7 @@ mod tests {
         assert!(e1.s == 128);
         assert!(e1.a[0] == 0b1);
         assert!(e1.b == 0);
+        assert!(e1.is_homogeneous() && !e2.is_homogeneous());
 
         let mut e1 = Equation {
             s: 127,
@@ -139,6 +262,57 @@ mod tests {
         assert!(e1.b == 0);
     }
 
+    #[test]
+    fn test_equation_align() {
+        let mut e1 = Equation {
+            s: 10,
+            a: [0b1, 0b10],
+            b: 1,
+        };
+        let e2 = Equation {
+            s: 10,
+            a: [0b1, 0b0],
+            b: 0,
+        };
+        assert!(e1.is_aligned());
+        assert!(e1.leading_coefficient() == Some(10));
+        assert!(e1.coefficient(10) == 1 && e1.coefficient(75) == 1);
+        assert!(e1.coefficient(9) == 0 && e1.coefficient(74) == 0);
+
+        e1.xor(&e2);
+        assert!(!e1.is_aligned());
+        assert!(e1.leading_coefficient() == Some(75));
+        assert!(e1.b == 1);
+
+        // The first limb is zero, so aligning moves the second limb down.
+        let z = [0, 1 << 11];
+        let before = e1.eval(&z);
+        e1.align();
+        assert!(e1.is_aligned());
+        assert!(e1.s == 75);
+        assert!(e1.a == [1, 0]);
+        assert!(e1.eval(&z) == before && before == 1);
+
+        assert!(Equation::<2>::zero().leading_coefficient().is_none());
+    }
+
+    #[test]
+    fn test_equation_from_digest() {
+        let digest: Vec<u8> = (0u8..40).collect();
+        let eq = Equation::<4>::from_digest(&digest, 1000).unwrap();
+        assert!(eq.a[0] & 1 == 1);
+        assert!(eq.a[1] == u64::from_le_bytes([8, 9, 10, 11, 12, 13, 14, 15]));
+        assert!(eq.s == (u64::from_le_bytes([32, 33, 34, 35, 36, 37, 38, 39]) as usize) % 1000);
+        assert!(eq.b == 0);
+
+        let eq = Equation::<1>::from_digest(&digest, 0).unwrap();
+        assert!(eq.s == 0);
+        assert!(eq.a[0] == u64::from_le_bytes([1, 1, 2, 3, 4, 5, 6, 7]));
+
+        assert!(Equation::<4>::from_digest(&digest[..32], 1000).is_none());
+        assert!(Equation::<1>::from_digest(&[], 1000).is_none());
+    }
+
     #[test]
     fn test_equation_eval() {
         for s in 0..64 {
@@ -0,0 +1,112 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::fmt;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ClubcardError {
+    /// The input ended before a complete clubcard could be read.
+    UnexpectedEof,
+    /// The input contains a value that is not valid in its position.
+    Malformed,
+    /// The input was produced by an unsupported version of this crate.
+    UnsupportedVersion(u16),
+    /// The exact ribbons for these blocks did not see the number of items that was declared as
+    /// the universe size for the corresponding approximate ribbons.
+    UniverseSizeMismatch(Vec<Vec<u8>>),
+    /// These (block, discriminant) pairs exceed the configured maximum discriminant length.
+    DiscriminantTooLong(Vec<(Vec<u8>, Vec<u8>)>),
+    /// These (block, discriminant) pairs are included items that are not in the universe.
+    UncoveredMembers(Vec<(Vec<u8>, Vec<u8>)>),
+    /// These blocks have more exceptions than the configured maximum.
+    TooManyExceptions(Vec<Vec<u8>>),
+    /// These blocks have more members than the configured maximum.
+    TooManyMembers(Vec<Vec<u8>>),
+    /// The index entry for this block is inconsistent with the filters, or the sizes given for
+    /// this block are inconsistent with its items.
+    InvalidBlock(Vec<u8>),
+    /// An I/O error occurred while reading a clubcard.
+    Io(std::io::ErrorKind),
+    /// A clubcard was offered as a replacement for one with the same or a later sequence number.
+    NonMonotonicSequence { current: u64, proposed: u64 },
+    /// A clubcard was serialized with width `found` and read as a clubcard of width `expected`.
+    WidthMismatch { expected: usize, found: usize },
+    /// The input exceeds MAX_SERIALIZED_LEN or has more than MAX_BLOCKS blocks.
+    LimitExceeded,
+    /// A query result in this block disagrees with a VerificationLayer.
+    VerificationFailed(Vec<u8>),
+    /// A clubcard covers less of the universe than the clubcard it was to replace.
+    CoverageRegressed,
+    /// The filter data of this block does not match the checksum in its index entry.
+    CorruptBlock(Vec<u8>),
+    /// These blocks are not valid identifiers under the convention given to
+    /// ClubcardBuilder::set_block_id, or this block is not a valid BlockId.
+    MalformedBlockId(Vec<Vec<u8>>),
+    /// An argument is outside of the range that the function accepts, as described.
+    InvalidArgument(&'static str),
+}
+
+impl fmt::Display for ClubcardError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClubcardError::UnexpectedEof => write!(f, "unexpected end of input"),
+            ClubcardError::Malformed => write!(f, "malformed input"),
+            ClubcardError::UnsupportedVersion(v) => write!(f, "unsupported version {:#06x}", v),
+            ClubcardError::UniverseSizeMismatch(blocks) => {
+                write!(f, "universe size mismatch in blocks {:?}", blocks)
+            }
+            ClubcardError::DiscriminantTooLong(items) => {
+                write!(f, "{} discriminants exceed the maximum length", items.len())
+            }
+            ClubcardError::UncoveredMembers(items) => {
+                write!(f, "{} members are not in the universe", items.len())
+            }
+            ClubcardError::TooManyExceptions(blocks) => {
+                write!(f, "too many exceptions in blocks {:?}", blocks)
+            }
+            ClubcardError::TooManyMembers(blocks) => {
+                write!(f, "too many members in blocks {:?}", blocks)
+            }
+            ClubcardError::InvalidBlock(block) => {
+                write!(f, "invalid index entry for block {:?}", block)
+            }
+            ClubcardError::Io(kind) => write!(f, "I/O error: {}", kind),
+            ClubcardError::NonMonotonicSequence { current, proposed } => write!(
+                f,
+                "sequence number {} does not follow current sequence number {}",
+                proposed, current
+            ),
+            ClubcardError::WidthMismatch { expected, found } => write!(
+                f,
+                "clubcard has width {} but was read with width {}",
+                found, expected
+            ),
+            ClubcardError::LimitExceeded => write!(f, "input exceeds the format limits"),
+            ClubcardError::VerificationFailed(block) => {
+                write!(f, "verification failed in block {:?}", block)
+            }
+            ClubcardError::CoverageRegressed => {
+                write!(f, "coverage does not include the previous coverage")
+            }
+            ClubcardError::CorruptBlock(block) => {
+                write!(f, "checksum mismatch in block {:?}", block)
+            }
+            ClubcardError::MalformedBlockId(blocks) => {
+                write!(f, "malformed block identifiers {:?}", blocks)
+            }
+            ClubcardError::InvalidArgument(reason) => write!(f, "invalid argument: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ClubcardError {}
+
+impl From<std::io::Error> for ClubcardError {
+    fn from(e: std::io::Error) -> Self {
+        match e.kind() {
+            std::io::ErrorKind::UnexpectedEof => ClubcardError::UnexpectedEof,
+            kind => ClubcardError::Io(kind),
+        }
+    }
+}
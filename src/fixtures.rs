@@ -0,0 +1,46 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Small pre-built clubcards and their expected query answers, for integration tests that
+//! should not need the builder. Each fixture is the output of test_vectors::generate for a fixed
+//! seed, and is checked against it by this crate's tests. See the test_vectors module for the
+//! query format.
+
+use crate::encoding::Decode;
+use crate::test_vectors::{TestVectorQuery, W};
+use crate::{Clubcard, Membership};
+
+/// A serialized clubcard and the encoded list of queries with their expected results.
+#[derive(Clone, Copy, Debug)]
+pub struct Fixture {
+    pub seed: u64,
+    pub bytes: &'static [u8],
+    pub answers: &'static [u8],
+}
+
+impl Fixture {
+    pub fn clubcard(&self) -> Clubcard<W, u32, ()> {
+        Clubcard::from_bytes(self.bytes).expect("fixture should decode")
+    }
+
+    pub fn answers(&self) -> Vec<(TestVectorQuery, Membership)> {
+        Decode::decode(&mut &self.answers[..]).expect("fixture answers should decode")
+    }
+}
+
+pub const SEED_0: Fixture = Fixture {
+    seed: 0,
+    bytes: include_bytes!("../testdata/test_vectors_seed_0.bin"),
+    answers: include_bytes!("../testdata/test_vectors_seed_0_answers.bin"),
+};
+
+pub const SEED_1: Fixture = Fixture {
+    seed: 1,
+    bytes: include_bytes!("../testdata/test_vectors_seed_1.bin"),
+    answers: include_bytes!("../testdata/test_vectors_seed_1_answers.bin"),
+};
+
+pub fn all() -> [Fixture; 2] {
+    [SEED_0, SEED_1]
+}
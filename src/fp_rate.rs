@@ -0,0 +1,139 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use crate::{AsQuery, BlockStats, Clubcard, Equation};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// The z-score of a two-sided 95% confidence interval.
+const Z: f64 = 1.96;
+
+/// The false positive rate of the approximate layer of a block, measured by
+/// Clubcard::estimate_fp_rate.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FpRateEstimate {
+    /// The number of random queries.
+    pub samples: usize,
+    /// The number of random queries that passed the approximate layer.
+    pub false_positives: usize,
+    /// The design rate. See BlockStats::design_fp_rate.
+    pub expected: f64,
+    /// The bounds of the 95% Wilson score interval around the observed rate.
+    pub lower: f64,
+    pub upper: f64,
+}
+
+impl FpRateEstimate {
+    fn new(samples: usize, false_positives: usize, expected: f64) -> Self {
+        if samples == 0 {
+            // Without samples, any rate is possible.
+            return FpRateEstimate {
+                samples,
+                false_positives,
+                expected,
+                lower: 0.0,
+                upper: 1.0,
+            };
+        }
+        let n = samples as f64;
+        let p = false_positives as f64 / n;
+        let denominator = 1.0 + Z * Z / n;
+        let center = (p + Z * Z / (2.0 * n)) / denominator;
+        let half_width = Z * (p * (1.0 - p) / n + Z * Z / (4.0 * n * n)).sqrt() / denominator;
+        FpRateEstimate {
+            samples,
+            false_positives,
+            expected,
+            lower: (center - half_width).max(0.0),
+            upper: (center + half_width).min(1.0),
+        }
+    }
+
+    /// The observed rate, or 0 if there were no samples.
+    pub fn rate(&self) -> f64 {
+        if self.samples == 0 {
+            return 0.0;
+        }
+        self.false_positives as f64 / self.samples as f64
+    }
+
+    /// Whether the design rate is within the confidence interval.
+    pub fn is_consistent(&self) -> bool {
+        self.lower <= self.expected && self.expected <= self.upper
+    }
+}
+
+/// A query with a random hash, standing in for an item that was not inserted into the block.
+struct RandomQuery<const W: usize> {
+    s: u64,
+    a: [u64; W],
+}
+
+impl<const W: usize> AsQuery<W> for RandomQuery<W> {
+    fn as_query(&self, m: usize) -> Equation<W> {
+        Equation::homogeneous((self.s % m as u64) as usize, self.a)
+    }
+
+    fn block(&self) -> &[u8] {
+        &[]
+    }
+
+    fn discriminant(&self) -> &[u8] {
+        &[]
+    }
+}
+
+impl<const W: usize, UniverseMetadata, PartitionMetadata>
+    Clubcard<W, UniverseMetadata, PartitionMetadata>
+{
+    /// Estimate the false positive rate of the approximate layer of `block` by querying it with
+    /// `samples` random hashes, drawn from a generator seeded with `seed`. Since the hashes of
+    /// the items in the block's universe are uniform, this is the rate at which items outside
+    /// the encoded subset pass the approximate layer. Returns None if the block is not in the
+    /// index. With no samples, the estimate is empty: its interval is [0, 1].
+    pub fn estimate_fp_rate(
+        &self,
+        block: &[u8],
+        seed: u64,
+        samples: usize,
+    ) -> Option<FpRateEstimate> {
+        let handle = self.block_handle(block)?;
+        let meta = handle.meta();
+        let expected = BlockStats::from(meta).design_fp_rate();
+        let mut rng = StdRng::seed_from_u64(seed);
+        let false_positives = (0..samples)
+            .filter(|_| {
+                let mut a = [0u64; W];
+                rng.fill(&mut a[..]);
+                a[0] |= 1;
+                handle.approx_contains_any(&RandomQuery { s: rng.gen(), a })
+            })
+            .count();
+        Some(FpRateEstimate::new(samples, false_positives, expected))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_vectors::generated;
+
+    #[test]
+    fn test_estimate_fp_rate() {
+        let (clubcard, _) = generated(0);
+        for entry in clubcard.blocks() {
+            let estimate = clubcard.estimate_fp_rate(entry.block, 0, 4096).unwrap();
+            assert!(estimate == clubcard.estimate_fp_rate(entry.block, 0, 4096).unwrap());
+            assert!(estimate.lower <= estimate.rate() && estimate.rate() <= estimate.upper);
+            assert!((estimate.rate() - estimate.expected).abs() < 0.05);
+            assert!(estimate.expected == entry.stats().design_fp_rate());
+        }
+        assert!(clubcard.estimate_fp_rate(&[0xff], 0, 1).is_none());
+
+        let empty = clubcard.estimate_fp_rate(&[0], 0, 0).unwrap();
+        assert!(empty.samples == 0 && empty.false_positives == 0);
+        assert!(empty.rate() == 0.0);
+        assert!(empty.lower == 0.0 && empty.upper == 1.0);
+        assert!(empty.is_consistent());
+    }
+}
@@ -0,0 +1,93 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use crate::{Clubcard, ClubcardError};
+use std::sync::{Arc, RwLock};
+
+/// A shared, replaceable Clubcard for long running services.
+///
+/// Each clubcard installed in the handle is tagged with a sequence number, and the sequence
+/// number must increase with every swap. Query threads call `load()` to get an Arc of the
+/// current clubcard. The lock is only held long enough to clone the Arc, so a swap never waits
+/// on in-flight queries, and queries against the old clubcard complete normally.
+pub struct ClubcardHandle<const W: usize, UniverseMetadata, PartitionMetadata> {
+    current: RwLock<(u64, Arc<Clubcard<W, UniverseMetadata, PartitionMetadata>>)>,
+}
+
+impl<const W: usize, UniverseMetadata, PartitionMetadata>
+    ClubcardHandle<W, UniverseMetadata, PartitionMetadata>
+{
+    pub fn new(sequence: u64, clubcard: Clubcard<W, UniverseMetadata, PartitionMetadata>) -> Self {
+        ClubcardHandle {
+            current: RwLock::new((sequence, Arc::new(clubcard))),
+        }
+    }
+
+    /// The current clubcard.
+    pub fn load(&self) -> Arc<Clubcard<W, UniverseMetadata, PartitionMetadata>> {
+        self.current.read().unwrap().1.clone()
+    }
+
+    /// The sequence number of the current clubcard.
+    pub fn sequence(&self) -> u64 {
+        self.current.read().unwrap().0
+    }
+
+    /// The current clubcard and its sequence number, read atomically.
+    pub fn load_with_sequence(
+        &self,
+    ) -> (u64, Arc<Clubcard<W, UniverseMetadata, PartitionMetadata>>) {
+        let current = self.current.read().unwrap();
+        (current.0, current.1.clone())
+    }
+
+    /// Replace the current clubcard, returning the old one. Fails with
+    /// ClubcardError::NonMonotonicSequence if `sequence` is not greater than the sequence number
+    /// of the current clubcard.
+    pub fn swap(
+        &self,
+        sequence: u64,
+        clubcard: Clubcard<W, UniverseMetadata, PartitionMetadata>,
+    ) -> Result<Arc<Clubcard<W, UniverseMetadata, PartitionMetadata>>, ClubcardError> {
+        let mut current = self.current.write().unwrap();
+        if sequence <= current.0 {
+            return Err(ClubcardError::NonMonotonicSequence {
+                current: current.0,
+                proposed: sequence,
+            });
+        }
+        let (_, old) = std::mem::replace(&mut *current, (sequence, Arc::new(clubcard)));
+        Ok(old)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn empty_clubcard(universe: u32) -> Clubcard<1, u32, ()> {
+        Clubcard::empty(universe, ())
+    }
+
+    #[test]
+    fn test_swap() {
+        let handle = ClubcardHandle::new(1, empty_clubcard(1));
+        let reader = handle.load();
+
+        let old = handle.swap(2, empty_clubcard(2)).unwrap();
+        assert!(Arc::ptr_eq(&old, &reader));
+        assert!(*reader.universe() == 1);
+        assert!(*handle.load().universe() == 2);
+        assert!(handle.sequence() == 2);
+
+        assert!(
+            handle.swap(2, empty_clubcard(3)).err()
+                == Some(ClubcardError::NonMonotonicSequence {
+                    current: 2,
+                    proposed: 2
+                })
+        );
+        assert!(*handle.load().universe() == 2);
+    }
+}
@@ -0,0 +1,231 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use crate::encoding::{Decode, Encode};
+use crate::{
+    ApproximateSizeOf, AsQuery, Clubcard, ClubcardError, Equation, Filterable, Membership,
+    Queryable,
+};
+
+/// The width of the equations of an IntClubcard.
+const W: usize = 2;
+
+/// The finalizer of the SplitMix64 generator.
+pub(crate) fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// The inclusive range of keys that were passed to an IntClubcardBuilder. Empty if min > max.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IntUniverse {
+    pub min: u64,
+    pub max: u64,
+}
+
+impl Encode for IntUniverse {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.min.to_le_bytes().encode(out);
+        self.max.to_le_bytes().encode(out);
+    }
+}
+
+impl Decode for IntUniverse {
+    fn decode(bytes: &mut &[u8]) -> Result<Self, ClubcardError> {
+        Ok(IntUniverse {
+            min: u64::from_le_bytes(Decode::decode(bytes)?),
+            max: u64::from_le_bytes(Decode::decode(bytes)?),
+        })
+    }
+}
+
+impl ApproximateSizeOf for IntUniverse {}
+
+struct IntItem {
+    key: [u8; 8],
+    included: bool,
+}
+
+impl IntItem {
+    fn new(key: u64, included: bool) -> Self {
+        IntItem {
+            key: key.to_le_bytes(),
+            included,
+        }
+    }
+}
+
+impl AsQuery<W> for IntItem {
+    fn as_query(&self, m: usize) -> Equation<W> {
+        // Three outputs of SplitMix64 seeded with the key.
+        const GAMMA: u64 = 0x9e3779b97f4a7c15;
+        let key = u64::from_le_bytes(self.key);
+        let a = [
+            mix(key.wrapping_add(GAMMA)) | 1,
+            mix(key.wrapping_add(GAMMA.wrapping_mul(2))),
+        ];
+        let s = mix(key.wrapping_add(GAMMA.wrapping_mul(3))) % m.max(1) as u64;
+        Equation::homogeneous(s as usize, a)
+    }
+
+    fn block(&self) -> &[u8] {
+        &[]
+    }
+
+    fn discriminant(&self) -> &[u8] {
+        &self.key
+    }
+}
+
+impl Filterable<W> for IntItem {
+    fn included(&self) -> bool {
+        self.included
+    }
+}
+
+impl Queryable<W> for IntItem {
+    type UniverseMetadata = IntUniverse;
+    type PartitionMetadata = ();
+
+    fn in_universe(&self, universe: &IntUniverse) -> bool {
+        (universe.min..=universe.max).contains(&u64::from_le_bytes(self.key))
+    }
+}
+
+/// A clubcard over a set of u64 keys, built with IntClubcardBuilder. Keys are hashed with
+/// SplitMix64 rather than SHA-256, since they need no domain separation.
+pub struct IntClubcard {
+    clubcard: Clubcard<W, IntUniverse, ()>,
+}
+
+impl IntClubcard {
+    /// Whether `key` is a member. Returns NotInUniverse for keys outside of the range of keys
+    /// that were passed to the builder. The result for other keys that were not passed to the
+    /// builder is arbitrary.
+    pub fn contains(&self, key: u64) -> Membership {
+        self.clubcard.contains(&IntItem::new(key, false))
+    }
+
+    pub fn universe(&self) -> &IntUniverse {
+        self.clubcard.universe()
+    }
+
+    pub fn clubcard(&self) -> &Clubcard<W, IntUniverse, ()> {
+        &self.clubcard
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.clubcard.to_bytes()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ClubcardError> {
+        Ok(IntClubcard {
+            clubcard: Clubcard::from_bytes(bytes)?,
+        })
+    }
+}
+
+/// Builds an IntClubcard from (key, member) pairs.
+#[cfg(feature = "builder")]
+#[derive(Default)]
+pub struct IntClubcardBuilder {
+    keys: std::collections::BTreeMap<u64, bool>,
+    seed: Option<u64>,
+}
+
+#[cfg(feature = "builder")]
+impl IntClubcardBuilder {
+    pub fn new() -> Self {
+        IntClubcardBuilder::default()
+    }
+
+    /// See ClubcardBuilder::new_with_seed.
+    pub fn new_with_seed(seed: u64) -> Self {
+        IntClubcardBuilder {
+            seed: Some(seed),
+            ..Default::default()
+        }
+    }
+
+    /// Add `key` to the universe. If `key` was inserted before, the last call decides whether
+    /// it is a member.
+    pub fn insert(&mut self, key: u64, member: bool) {
+        self.keys.insert(key, member);
+    }
+
+    /// The number of distinct keys.
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn build(self) -> IntClubcard {
+        use crate::builder::{ApproximateRibbon, BuildConfig, ClubcardBuilder, ExactRibbon};
+
+        let universe = match (self.keys.first_key_value(), self.keys.last_key_value()) {
+            (Some((&min, _)), Some((&max, _))) => IntUniverse { min, max },
+            _ => IntUniverse { min: 1, max: 0 },
+        };
+        let mut builder = ClubcardBuilder::new_with_config(BuildConfig {
+            seed: self.seed,
+            ..Default::default()
+        });
+        let mut approx_builder = builder.new_approx_builder(&[]);
+        for (&key, _) in self.keys.iter().filter(|(_, member)| **member) {
+            approx_builder.insert(IntItem::new(key, true));
+        }
+        approx_builder.set_universe_size(self.keys.len());
+        builder.collect_approx_ribbons(vec![ApproximateRibbon::from(approx_builder)]);
+
+        let mut exact_builder = builder.new_exact_builder(&[]);
+        for (&key, &member) in &self.keys {
+            exact_builder.insert(IntItem::new(key, member));
+        }
+        builder
+            .collect_exact_ribbons(vec![ExactRibbon::from(exact_builder)])
+            .unwrap();
+        IntClubcard {
+            clubcard: builder.build::<IntItem>(universe, ()),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "builder"))]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_int_clubcard() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let keys: Vec<(u64, bool)> = (0..4096)
+            .map(|_| (rng.gen_range(1000..u64::MAX / 2), rng.gen_bool(0.1)))
+            .collect();
+        let mut builder = IntClubcardBuilder::new_with_seed(0);
+        for &(key, member) in &keys {
+            builder.insert(key, !member);
+            builder.insert(key, member);
+        }
+        let clubcard = builder.build();
+        for &(key, member) in &keys {
+            assert!(clubcard.contains(key) == member.into());
+        }
+        assert!(clubcard.contains(0) == Membership::NotInUniverse);
+        assert!(clubcard.contains(u64::MAX) == Membership::NotInUniverse);
+
+        let decoded = IntClubcard::from_bytes(&clubcard.to_bytes()).unwrap();
+        assert!(decoded.universe() == clubcard.universe());
+        assert!(keys
+            .iter()
+            .all(|&(key, member)| decoded.contains(key) == member.into()));
+
+        let empty = IntClubcardBuilder::new().build();
+        assert!(empty.contains(0) == Membership::NotInUniverse);
+    }
+}
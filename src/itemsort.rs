@@ -0,0 +1,244 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! External-memory sorting of builder items by block, for use with
+//! ClubcardBuilder::collect_approx_ribbons_from_sorted.
+//!
+//! Items are buffered in memory until the buffer holds `max_items` items. The buffer is then
+//! sorted by block and written to a temporary file (a "run") in the clubcard binary encoding. The
+//! runs are merged when the items are read back. Items with the same block are returned in the
+//! order in which they were pushed.
+
+use crate::encoding::{Decode, Encode};
+use crate::Filterable;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static RUN_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+pub struct ItemSorter<const W: usize, T> {
+    dir: PathBuf,
+    max_items: usize,
+    buffer: Vec<T>,
+    runs: Vec<PathBuf>,
+}
+
+impl<const W: usize, T> ItemSorter<W, T>
+where
+    T: Filterable<W> + Encode + Decode,
+{
+    /// Construct a sorter that keeps at most `max_items` items (0 is treated as 1) in memory and
+    /// spills the rest to temporary files in `dir`.
+    pub fn new(dir: impl AsRef<Path>, max_items: usize) -> Self {
+        let max_items = max_items.max(1);
+        ItemSorter {
+            dir: dir.as_ref().to_path_buf(),
+            max_items,
+            buffer: Vec::with_capacity(max_items),
+            runs: vec![],
+        }
+    }
+
+    pub fn push(&mut self, item: T) -> io::Result<()> {
+        self.buffer.push(item);
+        if self.buffer.len() >= self.max_items {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /// Sort the buffer and write it to a new run file.
+    fn spill(&mut self) -> io::Result<()> {
+        self.buffer.sort_by(|a, b| a.block().cmp(b.block()));
+        let path = self.dir.join(format!(
+            "clubcard-itemsort-{}-{}.run",
+            std::process::id(),
+            RUN_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        // Register the run before writing it, so that it is removed on drop even if writing
+        // fails.
+        self.runs.push(path.clone());
+        let mut writer = BufWriter::new(File::create(&path)?);
+        let mut bytes = vec![];
+        for item in self.buffer.drain(..) {
+            bytes.clear();
+            item.encode(&mut bytes);
+            writer.write_all(&(bytes.len() as u64).to_le_bytes())?;
+            writer.write_all(&bytes)?;
+        }
+        writer.flush()
+    }
+
+    /// Finish pushing items and return an iterator over the items sorted by block.
+    pub fn finish(mut self) -> io::Result<SortedItems<W, T>> {
+        if self.runs.is_empty() {
+            // Everything fit in memory.
+            self.buffer.sort_by(|a, b| a.block().cmp(b.block()));
+            return Ok(SortedItems {
+                memory: std::mem::take(&mut self.buffer).into_iter(),
+                readers: vec![],
+                heads: vec![],
+                runs: vec![],
+            });
+        }
+        if !self.buffer.is_empty() {
+            self.spill()?;
+        }
+        let runs = std::mem::take(&mut self.runs);
+        let mut sorted = SortedItems {
+            memory: vec![].into_iter(),
+            readers: vec![],
+            heads: vec![],
+            runs,
+        };
+        for path in &sorted.runs {
+            let mut reader = BufReader::new(File::open(path)?);
+            sorted.heads.push(read_item(&mut reader)?);
+            sorted.readers.push(reader);
+        }
+        Ok(sorted)
+    }
+}
+
+impl<const W: usize, T> Drop for ItemSorter<W, T> {
+    fn drop(&mut self) {
+        for path in &self.runs {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+fn read_item<T: Decode>(reader: &mut impl Read) -> io::Result<Option<T>> {
+    let mut len = [0u8; 8];
+    match reader.read_exact(&mut len) {
+        Ok(()) => (),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let mut bytes = vec![0u8; u64::from_le_bytes(len) as usize];
+    reader.read_exact(&mut bytes)?;
+    let item =
+        T::decode(&mut &bytes[..]).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(Some(item))
+}
+
+/// Items in block order. See ItemSorter::finish.
+pub struct SortedItems<const W: usize, T> {
+    memory: std::vec::IntoIter<T>,
+    readers: Vec<BufReader<File>>,
+    /// The next item from each run.
+    heads: Vec<Option<T>>,
+    runs: Vec<PathBuf>,
+}
+
+impl<const W: usize, T> Iterator for SortedItems<W, T>
+where
+    T: Filterable<W> + Decode,
+{
+    type Item = io::Result<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.runs.is_empty() {
+            return self.memory.next().map(Ok);
+        }
+        // Take the smallest head. Ties go to the earliest run, which preserves insertion order.
+        let mut min: Option<usize> = None;
+        for (i, head) in self.heads.iter().enumerate() {
+            if let Some(item) = head {
+                if min.map_or(true, |j| {
+                    item.block() < self.heads[j].as_ref().unwrap().block()
+                }) {
+                    min = Some(i);
+                }
+            }
+        }
+        let i = min?;
+        let next = match read_item(&mut self.readers[i]) {
+            Ok(next) => next,
+            Err(e) => return Some(Err(e)),
+        };
+        std::mem::replace(&mut self.heads[i], next).map(Ok)
+    }
+}
+
+impl<const W: usize, T> Drop for SortedItems<W, T> {
+    fn drop(&mut self) {
+        for path in &self.runs {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AsQuery, ClubcardError, Equation};
+
+    #[derive(Debug, PartialEq)]
+    struct Item {
+        block: [u8; 1],
+        discriminant: [u8; 2],
+    }
+
+    impl AsQuery<1> for Item {
+        fn as_query(&self, m: usize) -> Equation<1> {
+            Equation::homogeneous(m / 2, [1])
+        }
+
+        fn block(&self) -> &[u8] {
+            &self.block
+        }
+
+        fn discriminant(&self) -> &[u8] {
+            &self.discriminant
+        }
+    }
+
+    impl Filterable<1> for Item {}
+
+    impl Encode for Item {
+        fn encode(&self, out: &mut Vec<u8>) {
+            self.block.encode(out);
+            self.discriminant.encode(out);
+        }
+    }
+
+    impl Decode for Item {
+        fn decode(bytes: &mut &[u8]) -> Result<Self, ClubcardError> {
+            Ok(Item {
+                block: Decode::decode(bytes)?,
+                discriminant: Decode::decode(bytes)?,
+            })
+        }
+    }
+
+    fn items() -> Vec<Item> {
+        (0u16..100)
+            .map(|i| Item {
+                block: [(i * 37 % 7) as u8],
+                discriminant: i.to_le_bytes(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_itemsort() {
+        let mut expected = items();
+        expected.sort_by_key(|item| item.block);
+
+        for max_items in [0, 1, 7, 100, 1000] {
+            let mut sorter = ItemSorter::new(std::env::temp_dir(), max_items);
+            for item in items() {
+                sorter.push(item).unwrap();
+            }
+            let sorted = sorter.finish().unwrap();
+            let runs = sorted.runs.clone();
+            let sorted: Vec<Item> = sorted.map(Result::unwrap).collect();
+            assert_eq!(sorted, expected);
+            assert!(runs.iter().all(|path| !path.exists()));
+        }
+    }
+}
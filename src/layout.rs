@@ -0,0 +1,150 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use crate::clubcard::records_width;
+use crate::encoding::{take, Decode, Encode};
+use crate::{BuildConfig, Clubcard, ClubcardError, ClubcardHeader};
+use std::fmt;
+
+/// A contiguous section of a serialized clubcard. See serialized_layout.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SectionInfo {
+    pub name: String,
+    pub offset: usize,
+    pub length: usize,
+    /// The 64-bit FNV-1a hash of the section. This is not a cryptographic hash; it is meant for
+    /// finding sections that are likely to be unchanged between two clubcards.
+    pub checksum: u64,
+}
+
+impl fmt::Display for SectionInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:>10} {:>10} {:016x} {}",
+            self.offset, self.length, self.checksum, self.name
+        )
+    }
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// The sections of the serialized clubcard `bytes`, in order: "version", "header_length",
+/// "build_config", "width" (if the format records it), "metadata" (the universe metadata, the
+/// partition metadata, and the index), "approx_filter.columns", one "approx_filter[i]" for each
+/// column of X, and "exact_filter". Each filter section includes its length prefix. The
+/// sections cover `bytes` exactly. The metadata types are not needed, but clubcards in the
+/// oldest format (which has no header length) are not supported.
+pub fn serialized_layout(bytes: &[u8]) -> Result<Vec<SectionInfo>, ClubcardError> {
+    let header_len = ClubcardHeader::<(), ()>::encoded_len(bytes)?;
+    let version = u16::decode(&mut &bytes[..])?;
+    let mut rest = bytes;
+    let mut sections = vec![];
+    let mut push = |name: String, rest: &mut &[u8], len: usize| -> Result<(), ClubcardError> {
+        let offset = bytes.len() - rest.len();
+        let section = take(rest, len)?;
+        sections.push(SectionInfo {
+            name,
+            offset,
+            length: len,
+            checksum: fnv1a(section),
+        });
+        Ok(())
+    };
+    push("version".into(), &mut rest, 2)?;
+    push("header_length".into(), &mut rest, 8)?;
+    let mut header = bytes
+        .get(ClubcardHeader::<(), ()>::PREFIX_LEN..header_len)
+        .ok_or(ClubcardError::UnexpectedEof)?;
+    let start = header.len();
+    BuildConfig::decode(&mut header)?;
+    push("build_config".into(), &mut rest, start - header.len())?;
+    if records_width(version) {
+        usize::decode(&mut header)?;
+        push("width".into(), &mut rest, 8)?;
+    }
+    push("metadata".into(), &mut rest, header.len())?;
+
+    let columns = usize::decode(&mut &rest[..])?;
+    push("approx_filter.columns".into(), &mut rest, 8)?;
+    for i in 0..=columns {
+        let len = usize::decode(&mut &rest[..])?
+            .checked_mul(8)
+            .and_then(|len| len.checked_add(8))
+            .ok_or(ClubcardError::Malformed)?;
+        let name = match i < columns {
+            true => format!("approx_filter[{i}]"),
+            false => "exact_filter".into(),
+        };
+        push(name, &mut rest, len)?;
+    }
+    if !rest.is_empty() {
+        return Err(ClubcardError::Malformed);
+    }
+    Ok(sections)
+}
+
+impl<const W: usize, UniverseMetadata, PartitionMetadata>
+    Clubcard<W, UniverseMetadata, PartitionMetadata>
+where
+    UniverseMetadata: Encode,
+    PartitionMetadata: Encode,
+{
+    /// The sections of the output of to_bytes. See serialized_layout.
+    pub fn layout(&self) -> Vec<SectionInfo> {
+        serialized_layout(&self.to_bytes()).expect("to_bytes output should be well formed")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_vectors::{generate, W};
+
+    #[test]
+    fn test_layout() {
+        let (bytes, _) = generate(0);
+        let clubcard = Clubcard::<W, u32, ()>::from_bytes(&bytes).unwrap();
+        let layout = clubcard.layout();
+        assert!(serialized_layout(&bytes).unwrap() == layout);
+        let mut offset = 0;
+        for section in &layout {
+            assert!(section.offset == offset);
+            offset += section.length;
+        }
+        assert!(offset == bytes.len());
+        let names: Vec<_> = layout.iter().map(|x| x.name.as_str()).collect();
+        assert!(
+            names[..6]
+                == [
+                    "version",
+                    "header_length",
+                    "build_config",
+                    "width",
+                    "metadata",
+                    "approx_filter.columns"
+                ]
+        );
+        assert!(names.last() == Some(&"exact_filter"));
+        assert!(format!("{}", layout[0]).ends_with(" version"));
+
+        // Only the metadata and its length change when a block is dropped from the index.
+        let mut other = Clubcard::<W, u32, ()>::from_bytes(&bytes).unwrap();
+        other.index.remove(&vec![3]);
+        let other_layout = other.layout();
+        for (a, b) in layout.iter().zip(&other_layout) {
+            let changed = ["header_length", "metadata"].contains(&a.name.as_str());
+            assert!((a.checksum == b.checksum) != changed);
+        }
+
+        assert!(serialized_layout(&bytes[..bytes.len() - 1]).is_err());
+        assert!(
+            serialized_layout(&[bytes.clone(), vec![0]].concat()) == Err(ClubcardError::Malformed)
+        );
+    }
+}
@@ -0,0 +1,423 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use crate::cipher::{
+    authenticate, nonce, BlockSpans, FilterCipher, FilterColumn, NONCE_ANNOTATION,
+};
+use crate::clubcard::{block_span, ClubcardIndex};
+use crate::config::BuildConfig;
+use crate::encoding::Decode;
+use crate::{
+    Clubcard, ClubcardError, ClubcardHeader, ClubcardIndexEntry, Membership, Queryable,
+    TombstonePatch, MAX_SERIALIZED_LEN,
+};
+use std::collections::HashMap;
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::{Arc, Mutex};
+
+/// The position of a column of X or Y in the serialized clubcard.
+struct Column {
+    /// Byte offset of the first word.
+    start: u64,
+    /// Length in words.
+    len: usize,
+}
+
+/// What is needed to decrypt the blocks of a clubcard that was serialized with
+/// Clubcard::to_bytes_encrypted.
+struct Decryption {
+    cipher: Box<dyn FilterCipher>,
+    nonce: u64,
+    spans: BlockSpans,
+}
+
+/// A single block of a clubcard, stored as a clubcard with one block.
+type LoadedBlock<const W: usize> = Arc<Clubcard<W, (), ()>>;
+
+/// A clubcard whose index has been read, but whose blocks are read from the source on first
+/// use. See Clubcard::open_lazy.
+pub struct LazyClubcard<const W: usize, UniverseMetadata, PartitionMetadata, R> {
+    universe: UniverseMetadata,
+    partition: PartitionMetadata,
+    build_config: BuildConfig,
+    index: ClubcardIndex,
+    approx_columns: Vec<Column>,
+    exact_column: Column,
+    source: Mutex<R>,
+    /// Decrypts the filters, if they were serialized with Clubcard::to_bytes_encrypted.
+    decryption: Option<Decryption>,
+    /// Blocks that have been read from the source.
+    loaded: Mutex<HashMap<Vec<u8>, LoadedBlock<W>>>,
+}
+
+impl<const W: usize, UniverseMetadata, PartitionMetadata>
+    Clubcard<W, UniverseMetadata, PartitionMetadata>
+where
+    UniverseMetadata: Decode,
+    PartitionMetadata: Decode,
+{
+    /// Read the header and the index of a clubcard that was serialized with Clubcard::to_bytes,
+    /// and leave the filters in `source` until they are needed.
+    pub fn open_lazy<R: Read + Seek>(
+        source: R,
+    ) -> Result<LazyClubcard<W, UniverseMetadata, PartitionMetadata, R>, ClubcardError> {
+        Self::open_lazy_inner(source, None)
+    }
+
+    /// Same as open_lazy, for a clubcard that was serialized with Clubcard::to_bytes_encrypted.
+    /// Each block is decrypted with `cipher` and checked against its tag when it is read. Since
+    /// queries against a block read the first bits of the blocks after it, those bits are
+    /// decrypted with the keystreams of the blocks that they belong to.
+    pub fn open_lazy_encrypted<R: Read + Seek>(
+        source: R,
+        cipher: Box<dyn FilterCipher>,
+    ) -> Result<LazyClubcard<W, UniverseMetadata, PartitionMetadata, R>, ClubcardError> {
+        Self::open_lazy_inner(source, Some(cipher))
+    }
+
+    fn open_lazy_inner<R: Read + Seek>(
+        mut source: R,
+        cipher: Option<Box<dyn FilterCipher>>,
+    ) -> Result<LazyClubcard<W, UniverseMetadata, PartitionMetadata, R>, ClubcardError> {
+        let start = source.stream_position()?;
+        let end = source.seek(SeekFrom::End(0))?;
+        source.seek(SeekFrom::Start(start))?;
+        if end.saturating_sub(start) > MAX_SERIALIZED_LEN {
+            return Err(ClubcardError::LimitExceeded);
+        }
+        let mut buf = vec![0u8; ClubcardHeader::<(), ()>::PREFIX_LEN];
+        source.read_exact(&mut buf)?;
+        let len = ClubcardHeader::<(), ()>::encoded_len(&buf)?;
+        // Check the length against the source before allocating a buffer for the header.
+        let position = source.stream_position()?;
+        if position.saturating_add((len - buf.len()) as u64) > source.seek(SeekFrom::End(0))? {
+            return Err(ClubcardError::UnexpectedEof);
+        }
+        source.seek(SeekFrom::Start(position))?;
+        buf.resize(len, 0);
+        source.read_exact(&mut buf[ClubcardHeader::<(), ()>::PREFIX_LEN..])?;
+        let mut header = ClubcardHeader::from_bytes(&buf)?;
+        header.check_width::<W>()?;
+        let decryption = match cipher {
+            Some(cipher) => {
+                let nonce = nonce(&header.build_config)?;
+                header.build_config.annotations.remove(NONCE_ANNOTATION);
+                let spans = BlockSpans::new(&header.index);
+                Some(Decryption {
+                    cipher,
+                    nonce,
+                    spans,
+                })
+            }
+            None => None,
+        };
+
+        let mut approx_columns = vec![];
+        for _ in 0..read_len(&mut source)? {
+            let column = skip_column(&mut source)?;
+            approx_columns.push(column);
+        }
+        let exact_column = skip_column(&mut source)?;
+
+        Ok(LazyClubcard {
+            universe: header.universe,
+            partition: header.partition,
+            build_config: header.build_config,
+            index: header.index,
+            approx_columns,
+            exact_column,
+            source: Mutex::new(source),
+            decryption,
+            loaded: Mutex::new(HashMap::new()),
+        })
+    }
+}
+
+fn read_len(source: &mut impl Read) -> Result<usize, ClubcardError> {
+    let mut len = [0u8; 8];
+    source.read_exact(&mut len)?;
+    usize::decode(&mut &len[..])
+}
+
+/// Record the position of the column at the current position of `source` and seek past it.
+fn skip_column<R: Read + Seek>(source: &mut R) -> Result<Column, ClubcardError> {
+    let len = read_len(source)?;
+    let bytes = u64::try_from(len)
+        .ok()
+        .and_then(|len| len.checked_mul(8))
+        .ok_or(ClubcardError::Malformed)?;
+    let start = source.stream_position()?;
+    let end = source.seek(SeekFrom::Current(
+        i64::try_from(bytes).map_err(|_| ClubcardError::Malformed)?,
+    ))?;
+    if end > source.seek(SeekFrom::End(0))? {
+        return Err(ClubcardError::UnexpectedEof);
+    }
+    source.seek(SeekFrom::Start(end))?;
+    Ok(Column { start, len })
+}
+
+/// Read the words of `column` that cover bits `offset..offset + span`, decrypting them with
+/// `decryption` if there is one. Returns the words and the position of bit `offset` in them.
+fn read_bits<R: Read + Seek>(
+    source: &mut R,
+    column: &Column,
+    decryption: Option<(&Decryption, FilterColumn)>,
+    offset: usize,
+    span: usize,
+) -> Result<(Vec<u64>, usize), ClubcardError> {
+    let first = (offset / 64).min(column.len);
+    let last = offset
+        .checked_add(span)
+        .ok_or(ClubcardError::Malformed)?
+        .div_ceil(64)
+        .min(column.len);
+    let mut bytes = vec![0u8; 8 * (last - first)];
+    source.seek(SeekFrom::Start(column.start + 8 * first as u64))?;
+    source.read_exact(&mut bytes)?;
+    let mut words: Vec<u64> = bytes
+        .chunks_exact(8)
+        .map(|word| u64::from_le_bytes(word.try_into().unwrap()))
+        .collect();
+    if let Some((decryption, id)) = decryption {
+        let cipher = &*decryption.cipher;
+        decryption
+            .spans
+            .apply(cipher, decryption.nonce, id, 64 * first, &mut words);
+    }
+    Ok((words, offset - 64 * first))
+}
+
+impl<const W: usize, UniverseMetadata, PartitionMetadata, R>
+    LazyClubcard<W, UniverseMetadata, PartitionMetadata, R>
+where
+    R: Read + Seek,
+{
+    pub fn universe(&self) -> &UniverseMetadata {
+        &self.universe
+    }
+
+    pub fn partition(&self) -> &PartitionMetadata {
+        &self.partition
+    }
+
+    pub fn build_config(&self) -> &BuildConfig {
+        &self.build_config
+    }
+
+    pub fn index(&self) -> &ClubcardIndex {
+        &self.index
+    }
+
+    /// Same as Clubcard::apply_tombstones.
+    pub fn apply_tombstones(&mut self, patch: &TombstonePatch) -> Result<(), ClubcardError> {
+        patch.apply(&mut self.index)
+    }
+
+    /// Whether the data for `block` has been read from the source.
+    pub fn is_loaded(&self, block: &[u8]) -> bool {
+        self.loaded.lock().unwrap().contains_key(block)
+    }
+
+    /// Read the data for `block` from the source, if it has not been read already. Returns
+    /// None if the block is not in the index.
+    fn load(&self, block: &[u8]) -> Result<Option<LoadedBlock<W>>, ClubcardError> {
+        let Some((block, meta)) = self.index.get_key_value(block) else {
+            return Ok(None);
+        };
+        if let Some(loaded) = self.loaded.lock().unwrap().get(block) {
+            return Ok(Some(loaded.clone()));
+        }
+
+        let mut source = self.source.lock().unwrap();
+        let columns = self
+            .approx_columns
+            .get(..meta.approx_filter_rank)
+            .ok_or(ClubcardError::InvalidBlock(block.clone()))?;
+        let mut approx_filter = vec![];
+        let mut approx_filter_offset = 0;
+        for (i, column) in columns.iter().enumerate() {
+            let (words, offset) = read_bits(
+                &mut *source,
+                column,
+                self.decryption
+                    .as_ref()
+                    .map(|decryption| (decryption, FilterColumn::Approx(i))),
+                meta.approx_filter_offset,
+                block_span::<W>(meta.approx_filter_m),
+            )?;
+            approx_filter.push(words);
+            approx_filter_offset = offset;
+        }
+        let (exact_filter, exact_filter_offset) = read_bits(
+            &mut *source,
+            &self.exact_column,
+            self.decryption
+                .as_ref()
+                .map(|decryption| (decryption, FilterColumn::Exact)),
+            meta.exact_filter_offset,
+            block_span::<W>(meta.exact_filter_m),
+        )?;
+        drop(source);
+
+        let entry = ClubcardIndexEntry {
+            approx_filter_m: meta.approx_filter_m,
+            exact_filter_m: meta.exact_filter_m,
+            approx_filter_rank: meta.approx_filter_rank,
+            approx_filter_offset,
+            exact_filter_offset,
+            inverted: meta.inverted,
+            exceptions: meta.exceptions.clone(),
+            input_digest: meta.input_digest,
+            checksum: meta.checksum,
+            tombstoned: false,
+        };
+        let mut loaded = Clubcard {
+            universe: (),
+            partition: (),
+            index: ClubcardIndex::from([(block.clone(), entry)]),
+            approx_filter,
+            exact_filter,
+            build_config: BuildConfig::default(),
+            members: None,
+        };
+        if let Some(decryption) = &self.decryption {
+            authenticate(&*decryption.cipher, decryption.nonce, &mut loaded)?;
+        }
+        loaded.validate_block(block)?;
+        let loaded = Arc::new(loaded);
+        self.loaded
+            .lock()
+            .unwrap()
+            .insert(block.clone(), loaded.clone());
+        Ok(Some(loaded))
+    }
+
+    /// Read every block from the source. Fails with ClubcardError::InvalidBlock or
+    /// ClubcardError::CorruptBlock for the first block that does not pass
+    /// Clubcard::validate_block. Blocks are validated as they are
+    /// read, so this is only needed to detect corruption before the blocks are queried.
+    pub fn validate_all(&self) -> Result<(), ClubcardError> {
+        for block in self.index.keys() {
+            self.load(block)?;
+        }
+        Ok(())
+    }
+
+    /// Same as Clubcard::contains, reading the item's block from the source if necessary. Fails
+    /// with ClubcardError::InvalidBlock if the block does not pass Clubcard::validate_block, or
+    /// with ClubcardError::CorruptBlock if its data does not match its checksum. A block that
+    /// fails is not cached, so it is read again by the next query.
+    pub fn contains<T>(&self, item: &T) -> Result<Membership, ClubcardError>
+    where
+        T: Queryable<W, UniverseMetadata = UniverseMetadata, PartitionMetadata = PartitionMetadata>,
+    {
+        if !item.in_universe(&self.universe)
+            || self
+                .index
+                .get(item.block())
+                .is_some_and(|meta| meta.tombstoned)
+        {
+            return Ok(Membership::NotInUniverse);
+        }
+        let Some(loaded) = self.load(item.block())? else {
+            return Ok(Membership::NoData);
+        };
+        let handle = loaded.block_handle(item.block()).unwrap();
+        Ok(handle.unchecked_contains_any(item).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_vectors::{generate, TestVectorQuery, UNIVERSE_BOUND, W};
+
+    #[test]
+    fn test_open_lazy() {
+        let (bytes, queries) = generate(0);
+        let lazy = Clubcard::<W, u32, ()>::open_lazy(std::io::Cursor::new(&bytes)).unwrap();
+        assert!(*lazy.universe() == UNIVERSE_BOUND);
+        assert!(!lazy.is_loaded(&[1]));
+        for (query, expected) in &queries {
+            assert_eq!(lazy.contains(query).unwrap(), *expected);
+        }
+        assert!(lazy.is_loaded(&[1]));
+
+        assert!(lazy.validate_all().is_ok());
+
+        let mut corrupted = Clubcard::<W, u32, ()>::from_bytes(&bytes).unwrap();
+        corrupted
+            .index
+            .get_mut(&vec![1])
+            .unwrap()
+            .exact_filter_offset = usize::MAX / 2;
+        let corrupted = corrupted.to_bytes();
+        let lazy = Clubcard::<W, u32, ()>::open_lazy(std::io::Cursor::new(&corrupted)).unwrap();
+        assert!(lazy.contains(&TestVectorQuery::new(0, 0)).is_ok());
+        assert!(
+            lazy.contains(&TestVectorQuery::new(1, 0)) == Err(ClubcardError::InvalidBlock(vec![1]))
+        );
+        assert!(lazy.validate_all() == Err(ClubcardError::InvalidBlock(vec![1])));
+
+        // A flipped bit in the data of block 1 is caught by its checksum.
+        let mut damaged = Clubcard::<W, u32, ()>::from_bytes(&bytes).unwrap();
+        let meta = &damaged.index[&vec![1]];
+        assert!(meta.checksum.is_some() && meta.exact_filter_m > 0);
+        let bit = meta.exact_filter_offset;
+        damaged.exact_filter[bit / 64] ^= 1 << (bit % 64);
+        let damaged = damaged.to_bytes();
+        let lazy = Clubcard::<W, u32, ()>::open_lazy(std::io::Cursor::new(&damaged)).unwrap();
+        assert!(lazy.contains(&TestVectorQuery::new(0, 0)).is_ok());
+        assert!(
+            lazy.contains(&TestVectorQuery::new(1, 0)) == Err(ClubcardError::CorruptBlock(vec![1]))
+        );
+        assert!(!lazy.is_loaded(&[1]));
+
+        let mut lazy = Clubcard::<W, u32, ()>::open_lazy(std::io::Cursor::new(&bytes)).unwrap();
+        lazy.apply_tombstones(&TombstonePatch::new([vec![3u8]]))
+            .unwrap();
+        let query = TestVectorQuery::new(3, 0);
+        assert!(lazy.contains(&query).unwrap() == Membership::NotInUniverse);
+        assert!(!lazy.is_loaded(&[3]));
+
+        assert!(
+            Clubcard::<W, u32, ()>::open_lazy(std::io::Cursor::new(&bytes[..bytes.len() - 1]))
+                .err()
+                == Some(ClubcardError::UnexpectedEof)
+        );
+    }
+
+    #[test]
+    fn test_open_lazy_limit() {
+        /// A source of zeros that is one byte longer than MAX_SERIALIZED_LEN.
+        struct Oversized(u64);
+        impl std::io::Read for Oversized {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                buf.fill(0);
+                Ok(buf.len())
+            }
+        }
+        impl std::io::Seek for Oversized {
+            fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+                self.0 = match pos {
+                    std::io::SeekFrom::Start(x) => x,
+                    std::io::SeekFrom::End(x) => {
+                        (MAX_SERIALIZED_LEN + 1).checked_add_signed(x).unwrap()
+                    }
+                    std::io::SeekFrom::Current(x) => self.0.checked_add_signed(x).unwrap(),
+                };
+                Ok(self.0)
+            }
+        }
+        assert!(
+            Clubcard::<W, u32, ()>::open_lazy(Oversized(0)).err()
+                == Some(ClubcardError::LimitExceeded)
+        );
+        assert!(
+            Clubcard::<W, u32, ()>::open_lazy(Oversized(1)).err()
+                != Some(ClubcardError::LimitExceeded)
+        );
+    }
+}
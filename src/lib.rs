@@ -38,16 +38,147 @@
 //! seconds (on a Ryzen 3975WX with 64GB of RAM). This Clubcard implementation produces an 8.5MB
 //! filter in 200 seconds.
 //!
+//! This crate contains no unsafe code.
+//!
 //#![warn(missing_docs)]
+// Only the unit tests use unsafe code, to view test equations as discriminants.
+#![cfg_attr(not(test), forbid(unsafe_code))]
+
+#[cfg(feature = "builder")]
+mod analysis;
+#[cfg(feature = "builder")]
+pub use analysis::{
+    analyze_inputs, estimated_block_bits, recommend_coalescing, BlockCounts, BlockInputStats,
+    CoalescingReport, InputReport,
+};
+
+mod anomaly;
+pub use anomaly::{AnomalyMonitor, AnomalyReport};
+
+#[cfg(feature = "sha2")]
+mod audit;
+#[cfg(feature = "sha2")]
+pub use audit::input_digest;
+
+pub mod bench;
+
+mod block_id;
+pub use block_id::{BlockId, FixedBlockId};
+
+pub mod bounds;
 
 #[cfg(feature = "builder")]
 pub mod builder;
 
+mod cache;
+pub use cache::CachedClubcard;
+
+mod cipher;
+pub use cipher::{FilterCipher, FilterColumn, NONCE_ANNOTATION};
+
 mod clubcard;
-pub use clubcard::{ApproximateSizeOf, Clubcard, ClubcardIndexEntry, Membership};
+pub use clubcard::{
+    ApproximateSizeOf, BlockEntry, BlockHandle, BlockStats, Clubcard, ClubcardHeader,
+    ClubcardIndexEntry, Membership, QueryCost, QueryWork, RawMembership, VerifyReport,
+    CLUBCARD_VERSION, MAX_BLOCKS, MAX_SERIALIZED_LEN, SUPPORTED_VERSIONS,
+};
+
+pub mod compat;
+
+mod config;
+pub use config::{BuildConfig, DEFAULT_EPSILON, LABEL_ANNOTATION};
+
+pub mod domains;
+
+mod dynamic;
+pub use dynamic::{DynAdapter, DynClubcard, QueryFromBytes};
+
+pub mod encoding;
+
+mod enrollment;
+pub use enrollment::{EnrolledBlocks, ENROLLMENT_VERSION};
 
 mod equation;
-pub use equation::Equation;
+pub use equation::{Equation, ValidWidth, Width, MAX_WIDTH};
+
+mod error;
+pub use error::ClubcardError;
+
+#[cfg(feature = "fixtures")]
+pub mod fixtures;
+
+#[cfg(feature = "builder")]
+mod fp_rate;
+#[cfg(feature = "builder")]
+pub use fp_rate::FpRateEstimate;
+
+mod handle;
+pub use handle::ClubcardHandle;
+
+mod int;
+#[cfg(feature = "builder")]
+pub use int::IntClubcardBuilder;
+pub use int::{IntClubcard, IntUniverse};
+
+#[cfg(feature = "builder")]
+pub mod itemsort;
+
+mod layout;
+pub use layout::{serialized_layout, SectionInfo};
+
+mod lazy;
+pub use lazy::LazyClubcard;
+
+pub mod metrics;
+
+mod namespace;
+pub use namespace::{namespaced_block, split_namespaced_block, Namespaced};
+
+pub mod parts;
+
+pub mod prelude;
+
+mod policy;
+pub use policy::{
+    Action, ActionPolicy, AsyncResolver, FreshnessPolicy, PolicyFilter, Resolver, UniversePolicy,
+};
+
+#[cfg(feature = "sha2")]
+mod pseudonym;
+#[cfg(feature = "sha2")]
+pub use pseudonym::{BlockHasher, HashedBlock};
+#[cfg(all(feature = "sha2", feature = "builder"))]
+pub use pseudonym::HashedSource;
 
 mod query;
 pub use query::{AsQuery, Filterable, Queryable};
+
+pub mod rotation;
+
+pub mod shard;
+
+mod sidecar;
+pub use sidecar::{MembersSidecar, SIDECAR_VERSION};
+
+mod snapshot;
+pub use snapshot::BuilderSnapshot;
+
+mod storage;
+pub use storage::StoredClubcard;
+
+#[cfg(feature = "builder")]
+pub mod testing;
+
+#[cfg(feature = "fixtures")]
+pub mod test_vectors;
+
+mod tombstone;
+pub use tombstone::{TombstonePatch, TOMBSTONE_VERSION};
+
+#[cfg(feature = "sha2")]
+mod verification;
+#[cfg(feature = "sha2")]
+pub use verification::{VerificationLayer, VERIFICATION_VERSION};
+
+mod view;
+pub use view::ClubcardView;
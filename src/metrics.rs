@@ -0,0 +1,280 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Query counters and latency histograms for servers that export metrics, with fixed names so
+//! that dashboards work across deployments. QueryMetrics::render writes them in the Prometheus
+//! text format.
+
+use crate::{Clubcard, ClubcardError, LazyClubcard, Membership, Queryable};
+use std::fmt::Write;
+use std::io::{Read, Seek};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Counter of queries, labeled by outcome.
+pub const QUERIES_TOTAL: &str = "clubcard_queries_total";
+/// Histogram of query latency, in seconds.
+pub const QUERY_LATENCY_SECONDS: &str = "clubcard_query_latency_seconds";
+/// Counter of queries that failed because a block could not be loaded.
+pub const LOAD_FAILURES_TOTAL: &str = "clubcard_load_failures_total";
+/// Counter of queries, labeled by the last stage that they reached.
+pub const QUERY_STAGES_TOTAL: &str = "clubcard_query_stages_total";
+
+/// The last stage of Clubcard::contains that a query reached. Many queries that stop at the
+/// universe check suggest that the universe metadata is too narrow, or that the caller derives
+/// items incorrectly.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueryStage {
+    /// Queryable::in_universe returned false.
+    Universe,
+    /// The block is not in the index, or is tombstoned.
+    Index,
+    /// The approximate layer ruled the item out.
+    ApproxFilter,
+    /// The exact layer and the exceptions were consulted.
+    ExactFilter,
+}
+
+const STAGES: [QueryStage; 4] = [
+    QueryStage::Universe,
+    QueryStage::Index,
+    QueryStage::ApproxFilter,
+    QueryStage::ExactFilter,
+];
+
+fn stage_label(stage: QueryStage) -> &'static str {
+    match stage {
+        QueryStage::Universe => "universe",
+        QueryStage::Index => "index",
+        QueryStage::ApproxFilter => "approx_filter",
+        QueryStage::ExactFilter => "exact_filter",
+    }
+}
+
+/// Upper bounds of the finite buckets of the latency histogram.
+pub const LATENCY_BUCKETS: [Duration; 5] = [
+    Duration::from_micros(1),
+    Duration::from_micros(10),
+    Duration::from_micros(100),
+    Duration::from_millis(1),
+    Duration::from_millis(10),
+];
+
+const OUTCOMES: [Membership; 5] = [
+    Membership::Member,
+    Membership::Nonmember,
+    Membership::NotInUniverse,
+    Membership::NoData,
+    Membership::Unavailable,
+];
+
+fn outcome_label(outcome: Membership) -> &'static str {
+    match outcome {
+        Membership::Member => "member",
+        Membership::Nonmember => "nonmember",
+        Membership::NotInUniverse => "not_in_universe",
+        Membership::NoData => "no_data",
+        Membership::Unavailable => "unavailable",
+    }
+}
+
+/// Metrics for the queries made through Clubcard::contains_measured and
+/// LazyClubcard::contains_measured. All methods take &self, so one QueryMetrics can be shared
+/// by many threads.
+#[derive(Debug, Default)]
+pub struct QueryMetrics {
+    /// Indexed like OUTCOMES.
+    outcomes: [AtomicU64; 5],
+    /// Non-cumulative counts, indexed like LATENCY_BUCKETS, with a final bucket for the rest.
+    latency_buckets: [AtomicU64; 6],
+    latency_sum_nanos: AtomicU64,
+    load_failures: AtomicU64,
+    /// Indexed like STAGES.
+    stages: [AtomicU64; 4],
+}
+
+impl QueryMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a query that returned `outcome` after `elapsed`.
+    pub fn record(&self, outcome: Membership, elapsed: Duration) {
+        let i = OUTCOMES.iter().position(|x| *x == outcome).unwrap();
+        self.outcomes[i].fetch_add(1, Ordering::Relaxed);
+        let bucket = LATENCY_BUCKETS.partition_point(|bound| *bound < elapsed);
+        self.latency_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        let nanos = u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX);
+        self.latency_sum_nanos.fetch_add(nanos, Ordering::Relaxed);
+    }
+
+    /// Record a query that stopped at `stage`.
+    pub fn record_stage(&self, stage: QueryStage) {
+        let i = STAGES.iter().position(|x| *x == stage).unwrap();
+        self.stages[i].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The number of queries that stopped at `stage`. Only Clubcard::contains_measured records
+    /// stages.
+    pub fn stage_count(&self, stage: QueryStage) -> u64 {
+        let i = STAGES.iter().position(|x| *x == stage).unwrap();
+        self.stages[i].load(Ordering::Relaxed)
+    }
+
+    /// Record a query that failed because its block could not be loaded.
+    pub fn record_load_failure(&self) {
+        self.load_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The number of queries that returned `outcome`.
+    pub fn queries(&self, outcome: Membership) -> u64 {
+        let i = OUTCOMES.iter().position(|x| *x == outcome).unwrap();
+        self.outcomes[i].load(Ordering::Relaxed)
+    }
+
+    pub fn load_failures(&self) -> u64 {
+        self.load_failures.load(Ordering::Relaxed)
+    }
+
+    /// The cumulative number of queries that completed within each bound of LATENCY_BUCKETS.
+    pub fn latency_histogram(&self) -> [u64; 5] {
+        let mut total = 0;
+        let mut out = [0; 5];
+        for (count, bucket) in out.iter_mut().zip(&self.latency_buckets) {
+            total += bucket.load(Ordering::Relaxed);
+            *count = total;
+        }
+        out
+    }
+
+    /// Write the metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "# TYPE {QUERIES_TOTAL} counter").unwrap();
+        for outcome in OUTCOMES {
+            let label = outcome_label(outcome);
+            let count = self.queries(outcome);
+            writeln!(out, "{QUERIES_TOTAL}{{outcome=\"{label}\"}} {count}").unwrap();
+        }
+
+        writeln!(out, "# TYPE {QUERY_LATENCY_SECONDS} histogram").unwrap();
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(self.latency_histogram()) {
+            let le = bound.as_secs_f64();
+            writeln!(out, "{QUERY_LATENCY_SECONDS}_bucket{{le=\"{le}\"}} {count}").unwrap();
+        }
+        let count: u64 = OUTCOMES.into_iter().map(|x| self.queries(x)).sum();
+        writeln!(out, "{QUERY_LATENCY_SECONDS}_bucket{{le=\"+Inf\"}} {count}").unwrap();
+        let sum = Duration::from_nanos(self.latency_sum_nanos.load(Ordering::Relaxed));
+        writeln!(out, "{QUERY_LATENCY_SECONDS}_sum {}", sum.as_secs_f64()).unwrap();
+        writeln!(out, "{QUERY_LATENCY_SECONDS}_count {count}").unwrap();
+
+        writeln!(out, "# TYPE {LOAD_FAILURES_TOTAL} counter").unwrap();
+        writeln!(out, "{LOAD_FAILURES_TOTAL} {}", self.load_failures()).unwrap();
+
+        writeln!(out, "# TYPE {QUERY_STAGES_TOTAL} counter").unwrap();
+        for stage in STAGES {
+            let label = stage_label(stage);
+            let count = self.stage_count(stage);
+            writeln!(out, "{QUERY_STAGES_TOTAL}{{stage=\"{label}\"}} {count}").unwrap();
+        }
+        out
+    }
+}
+
+impl<const W: usize, UniverseMetadata, PartitionMetadata>
+    Clubcard<W, UniverseMetadata, PartitionMetadata>
+{
+    /// Same as contains, but records the result, the latency, and the last stage that the query
+    /// reached in `metrics`.
+    pub fn contains_measured<T>(&self, item: &T, metrics: &QueryMetrics) -> Membership
+    where
+        T: Queryable<W, UniverseMetadata = UniverseMetadata, PartitionMetadata = PartitionMetadata>,
+    {
+        let start = Instant::now();
+        let (result, stage) = self.contains_staged(item);
+        metrics.record(result, start.elapsed());
+        metrics.record_stage(stage);
+        result
+    }
+
+    /// Same as contains, and the last stage that the query reached.
+    pub fn contains_staged<T>(&self, item: &T) -> (Membership, QueryStage)
+    where
+        T: Queryable<W, UniverseMetadata = UniverseMetadata, PartitionMetadata = PartitionMetadata>,
+    {
+        if !item.in_universe(self.universe()) {
+            return (Membership::NotInUniverse, QueryStage::Universe);
+        }
+        match self.block_handle(item.block()) {
+            None => (Membership::NoData, QueryStage::Index),
+            Some(handle) if handle.meta().tombstoned => {
+                (Membership::NotInUniverse, QueryStage::Index)
+            }
+            Some(handle) => match handle.unchecked_contains_staged(item) {
+                (result, false) => (result.into(), QueryStage::ApproxFilter),
+                (result, true) => (result.into(), QueryStage::ExactFilter),
+            },
+        }
+    }
+}
+
+impl<const W: usize, UniverseMetadata, PartitionMetadata, R>
+    LazyClubcard<W, UniverseMetadata, PartitionMetadata, R>
+where
+    R: Read + Seek,
+{
+    /// Same as contains, but records the result and the latency, or the load failure, in
+    /// `metrics`.
+    pub fn contains_measured<T>(
+        &self,
+        item: &T,
+        metrics: &QueryMetrics,
+    ) -> Result<Membership, ClubcardError>
+    where
+        T: Queryable<W, UniverseMetadata = UniverseMetadata, PartitionMetadata = PartitionMetadata>,
+    {
+        let start = Instant::now();
+        match self.contains(item) {
+            Ok(result) => {
+                metrics.record(result, start.elapsed());
+                Ok(result)
+            }
+            Err(e) => {
+                metrics.record_load_failure();
+                Err(e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_metrics() {
+        let metrics = QueryMetrics::new();
+        metrics.record(Membership::Member, Duration::from_nanos(500));
+        metrics.record(Membership::Member, Duration::from_micros(10));
+        metrics.record(Membership::NoData, Duration::from_secs(1));
+        metrics.record_load_failure();
+        assert!(metrics.queries(Membership::Member) == 2);
+        assert!(metrics.queries(Membership::Nonmember) == 0);
+        assert!(metrics.queries(Membership::NoData) == 1);
+        assert!(metrics.load_failures() == 1);
+        assert!(metrics.latency_histogram() == [1, 2, 2, 2, 2]);
+
+        let rendered = metrics.render();
+        for line in [
+            "clubcard_queries_total{outcome=\"member\"} 2",
+            "clubcard_queries_total{outcome=\"no_data\"} 1",
+            "clubcard_query_latency_seconds_bucket{le=\"0.00001\"} 2",
+            "clubcard_query_latency_seconds_bucket{le=\"+Inf\"} 3",
+            "clubcard_query_latency_seconds_count 3",
+            "clubcard_load_failures_total 1",
+        ] {
+            assert!(rendered.lines().any(|x| x == line), "{line}");
+        }
+    }
+}
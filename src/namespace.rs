@@ -0,0 +1,157 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use crate::{AsQuery, ClubcardError, ClubcardIndexEntry, Equation, Filterable, Queryable};
+
+/// The block identifier of `block` in `namespace`: the length of the namespace as one byte,
+/// followed by the namespace and the block. Distinct (namespace, block) pairs give distinct
+/// identifiers, so several datasets can be packed into one clubcard without collisions. Fails
+/// with ClubcardError::InvalidArgument if the namespace is longer than 255 bytes.
+pub fn namespaced_block(namespace: &[u8], block: &[u8]) -> Result<Vec<u8>, ClubcardError> {
+    if namespace.len() > u8::MAX as usize {
+        return Err(ClubcardError::InvalidArgument(
+            "namespaces must have at most 255 bytes",
+        ));
+    }
+    let mut out = Vec::with_capacity(1 + namespace.len() + block.len());
+    out.push(namespace.len() as u8);
+    out.extend_from_slice(namespace);
+    out.extend_from_slice(block);
+    Ok(out)
+}
+
+/// The inverse of namespaced_block. Returns None if `block` is too short.
+pub fn split_namespaced_block(block: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (len, rest) = block.split_first()?;
+    (*len as usize <= rest.len()).then(|| rest.split_at(*len as usize))
+}
+
+/// An item whose block is in a namespace. Build and query with the same namespace; see
+/// AsQuery::with_namespace.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Namespaced<T> {
+    block: Vec<u8>,
+    item: T,
+}
+
+impl<T> Namespaced<T> {
+    /// Fails as described in namespaced_block.
+    pub fn new<const W: usize>(namespace: &[u8], item: T) -> Result<Self, ClubcardError>
+    where
+        T: AsQuery<W>,
+    {
+        Ok(Namespaced {
+            block: namespaced_block(namespace, item.block())?,
+            item,
+        })
+    }
+
+    pub fn namespace(&self) -> &[u8] {
+        split_namespaced_block(&self.block).unwrap().0
+    }
+
+    pub fn item(&self) -> &T {
+        &self.item
+    }
+
+    pub fn into_item(self) -> T {
+        self.item
+    }
+}
+
+impl<const W: usize, T: AsQuery<W>> AsQuery<W> for Namespaced<T> {
+    fn as_query(&self, m: usize) -> Equation<W> {
+        self.item.as_query(m)
+    }
+
+    fn block(&self) -> &[u8] {
+        &self.block
+    }
+
+    fn discriminant(&self) -> &[u8] {
+        self.item.discriminant()
+    }
+
+    fn as_approx_query(&self, meta: &ClubcardIndexEntry) -> Equation<W> {
+        self.item.as_approx_query(meta)
+    }
+
+    fn as_exact_query(&self, meta: &ClubcardIndexEntry) -> Equation<W> {
+        self.item.as_exact_query(meta)
+    }
+}
+
+impl<const W: usize, T: Filterable<W>> Filterable<W> for Namespaced<T> {
+    fn included(&self) -> bool {
+        self.item.included()
+    }
+}
+
+impl<const W: usize, T: Queryable<W>> Queryable<W> for Namespaced<T> {
+    type UniverseMetadata = T::UniverseMetadata;
+    type PartitionMetadata = T::PartitionMetadata;
+
+    fn in_universe(&self, meta: &Self::UniverseMetadata) -> bool {
+        self.item.in_universe(meta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::tests::test_items;
+    use crate::builder::ClubcardBuilder;
+    use crate::Membership;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_namespaces() {
+        let items = test_items(512);
+        // The same items, with opposite memberships in the two namespaces.
+        let namespaced = |namespace: &[u8], flip: u8| {
+            items
+                .iter()
+                .map(|item| {
+                    let mut item = item.clone();
+                    item.b ^= flip;
+                    item.with_namespace(namespace).unwrap()
+                })
+                .collect::<Vec<_>>()
+        };
+        let a = namespaced(b"a", 0);
+        let b = namespaced(b"b", 1);
+        assert!(a[0].block() == namespaced_block(b"a", &[]).unwrap());
+        assert!(a[0].namespace() == b"a");
+        let source = BTreeMap::from([
+            (a[0].block().to_vec(), a.clone()),
+            (b[0].block().to_vec(), b.clone()),
+        ]);
+
+        let mut clubcard_builder = ClubcardBuilder::new();
+        clubcard_builder
+            .collect_ribbons_from_source(&source)
+            .unwrap();
+        let clubcard = clubcard_builder.build::<Namespaced<Equation<1>>>((), ());
+        assert!(clubcard.verify(a.iter().cloned()).is_ok());
+        assert!(clubcard.verify(b.iter().cloned()).is_ok());
+        let other = items[0].clone().with_namespace(b"c").unwrap();
+        assert!(clubcard.contains(&other) == Membership::NoData);
+
+        let blocks: Vec<_> = clubcard
+            .blocks()
+            .map(|entry| split_namespaced_block(entry.block).unwrap())
+            .collect();
+        assert!(blocks == [(&b"a"[..], &[][..]), (&b"b"[..], &[][..])]);
+        assert!(split_namespaced_block(&[]).is_none());
+        assert!(split_namespaced_block(&[2, 0]).is_none());
+
+        let long = [0u8; 256];
+        assert!(namespaced_block(&long[..255], &[]).is_ok());
+        assert!(matches!(
+            namespaced_block(&long, &[]),
+            Err(ClubcardError::InvalidArgument(_))
+        ));
+        assert!(items[0].clone().with_namespace(&long).is_err());
+    }
+}
@@ -0,0 +1,451 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! A layout for clubcards that lets a client fetch individual blocks, e.g. with HTTP range
+//! requests.
+//!
+//! The layout is a two byte little-endian version (PARTS_VERSION), the length of the header
+//! as a u64, the header, and then the data for each block. The header holds the universe
+//! metadata, the partition metadata, and a map from block identifiers to the byte range of the
+//! block's data, relative to the end of the header. The header also holds the build
+//! configuration. ClubcardPartsHeader::range gives ranges
+//! relative to the start of the layout. A client reads the first HEADER_PREFIX_LEN bytes,
+//! passes them to header_len, reads the rest of the header, and then fetches the blocks that it
+//! needs and passes them to Clubcard::assemble_from_parts.
+
+use crate::clubcard::ClubcardIndex;
+use crate::config::BuildConfig;
+use crate::encoding::{Decode, Encode};
+use crate::{Clubcard, ClubcardError, ClubcardIndexEntry, Membership, Queryable};
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+/// The version of the layout. This is independent of CLUBCARD_VERSION, and changes whenever
+/// the layout or the encoding of the index entries and filters in it changes.
+pub const PARTS_VERSION: u16 = 0xffff;
+
+/// The number of bytes that header_len needs.
+pub const HEADER_PREFIX_LEN: usize = 10;
+
+/// The length of the header, including the version and the length itself, given the first
+/// HEADER_PREFIX_LEN bytes of the layout.
+pub fn header_len(mut prefix: &[u8]) -> Result<usize, ClubcardError> {
+    let bytes = &mut prefix;
+    let version = u16::decode(bytes)?;
+    if version != PARTS_VERSION {
+        return Err(ClubcardError::UnsupportedVersion(version));
+    }
+    usize::decode(bytes)?
+        .checked_add(HEADER_PREFIX_LEN)
+        .ok_or(ClubcardError::Malformed)
+}
+
+pub struct ClubcardPartsHeader<UniverseMetadata, PartitionMetadata> {
+    pub universe: UniverseMetadata,
+    pub partition: PartitionMetadata,
+    pub build_config: BuildConfig,
+    /// The length of the header.
+    len: u64,
+    /// (start, end) byte offsets of the data for each block, relative to the end of the
+    /// header.
+    ranges: BTreeMap<Vec<u8>, (u64, u64)>,
+}
+
+impl<UniverseMetadata, PartitionMetadata> ClubcardPartsHeader<UniverseMetadata, PartitionMetadata> {
+    /// The byte range of the data for `block`. Returns None if the block is not in the
+    /// clubcard.
+    pub fn range(&self, block: &[u8]) -> Option<Range<u64>> {
+        self.ranges
+            .get(block)
+            .map(|&(start, end)| self.len + start..self.len + end)
+    }
+
+    pub fn blocks(&self) -> impl Iterator<Item = &[u8]> {
+        self.ranges.keys().map(|block| &block[..])
+    }
+}
+
+impl<UniverseMetadata, PartitionMetadata> ClubcardPartsHeader<UniverseMetadata, PartitionMetadata>
+where
+    UniverseMetadata: Decode,
+    PartitionMetadata: Decode,
+{
+    /// Parse the first header_len bytes of the layout.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ClubcardError> {
+        let len = header_len(bytes)?;
+        if bytes.len() != len {
+            return Err(ClubcardError::Malformed);
+        }
+        let bytes = &mut &bytes[HEADER_PREFIX_LEN..];
+        let header = ClubcardPartsHeader {
+            universe: Decode::decode(bytes)?,
+            partition: Decode::decode(bytes)?,
+            build_config: Decode::decode(bytes)?,
+            len: len as u64,
+            ranges: Decode::decode(bytes)?,
+        };
+        if !bytes.is_empty() {
+            return Err(ClubcardError::Malformed);
+        }
+        Ok(header)
+    }
+}
+
+impl<const W: usize, UniverseMetadata, PartitionMetadata>
+    Clubcard<W, UniverseMetadata, PartitionMetadata>
+where
+    UniverseMetadata: Clone + Encode,
+    PartitionMetadata: Clone + Encode,
+{
+    /// Serialize this clubcard in the layout described in the parts module.
+    pub fn to_parts(&self) -> Vec<u8> {
+        let mut blocks = vec![];
+        let mut sizes = vec![];
+        for block in self.index.keys() {
+            let part = self.export_blocks([&block[..]]);
+            let start = blocks.len();
+            part.index[block].encode(&mut blocks);
+            part.approx_filter.encode(&mut blocks);
+            part.exact_filter.encode(&mut blocks);
+            sizes.push((block.clone(), start, blocks.len()));
+        }
+
+        let ranges: BTreeMap<Vec<u8>, (u64, u64)> = sizes
+            .into_iter()
+            .map(|(block, start, end)| (block, (start as u64, end as u64)))
+            .collect();
+        let mut header = vec![];
+        self.universe.encode(&mut header);
+        self.partition.encode(&mut header);
+        self.build_config.encode(&mut header);
+        ranges.encode(&mut header);
+
+        let mut out = vec![];
+        PARTS_VERSION.encode(&mut out);
+        header.len().encode(&mut out);
+        out.extend_from_slice(&header);
+        out.extend_from_slice(&blocks);
+        out
+    }
+}
+
+impl<const W: usize, UniverseMetadata, PartitionMetadata>
+    Clubcard<W, UniverseMetadata, PartitionMetadata>
+where
+    UniverseMetadata: Clone + PartialEq,
+    PartitionMetadata: Clone + PartialEq,
+{
+    /// Construct a clubcard from a header and the data of some of its blocks, given as pairs
+    /// of (block identifier, bytes in the block's range). Queries for items in other blocks
+    /// return NoData. Fails with ClubcardError::InvalidBlock if a block is not in the header,
+    /// or if its data does not have the length given in the header or does not pass
+    /// Clubcard::validate_block, and with ClubcardError::CorruptBlock if its data does not match
+    /// its checksum.
+    pub fn assemble_from_parts<'a>(
+        header: &ClubcardPartsHeader<UniverseMetadata, PartitionMetadata>,
+        blocks: impl IntoIterator<Item = (&'a [u8], &'a [u8])>,
+    ) -> Result<Self, ClubcardError> {
+        let mut parts = vec![Clubcard {
+            build_config: header.build_config.clone(),
+            ..Clubcard::empty(header.universe.clone(), header.partition.clone())
+        }];
+        for (block, mut bytes) in blocks {
+            let invalid = || ClubcardError::InvalidBlock(block.to_vec());
+            let range = header.range(block).ok_or_else(invalid)?;
+            if range.end.checked_sub(range.start) != Some(bytes.len() as u64) {
+                return Err(invalid());
+            }
+            let bytes = &mut bytes;
+            let meta = ClubcardIndexEntry::decode(bytes)?;
+            let part = Clubcard {
+                universe: header.universe.clone(),
+                partition: header.partition.clone(),
+                index: ClubcardIndex::from([(block.to_vec(), meta)]),
+                approx_filter: Decode::decode(bytes)?,
+                exact_filter: Decode::decode(bytes)?,
+                build_config: header.build_config.clone(),
+                members: None,
+            };
+            if !bytes.is_empty() {
+                return Err(invalid());
+            }
+            part.validate_block(block)?;
+            parts.push(part);
+        }
+        Self::concat(parts)
+    }
+}
+
+/// A clubcard for which only some of the blocks have been fetched. Unlike a clubcard from
+/// assemble_from_parts, this distinguishes items in blocks that have not been fetched
+/// (Membership::Unavailable) from items in blocks that are not in the clubcard at all
+/// (Membership::NoData).
+pub struct PartialClubcard<const W: usize, UniverseMetadata, PartitionMetadata> {
+    header: ClubcardPartsHeader<UniverseMetadata, PartitionMetadata>,
+    clubcard: Clubcard<W, UniverseMetadata, PartitionMetadata>,
+}
+
+impl<const W: usize, UniverseMetadata, PartitionMetadata>
+    PartialClubcard<W, UniverseMetadata, PartitionMetadata>
+where
+    UniverseMetadata: Clone + PartialEq,
+    PartitionMetadata: Clone + PartialEq,
+{
+    /// A partial clubcard with no blocks.
+    pub fn new(header: ClubcardPartsHeader<UniverseMetadata, PartitionMetadata>) -> Self {
+        let clubcard = Clubcard::assemble_from_parts(&header, []).unwrap();
+        PartialClubcard { header, clubcard }
+    }
+
+    pub fn header(&self) -> &ClubcardPartsHeader<UniverseMetadata, PartitionMetadata> {
+        &self.header
+    }
+
+    /// Add fetched blocks. See Clubcard::assemble_from_parts. Blocks that have already been
+    /// added are ignored.
+    pub fn add_blocks<'a>(
+        &mut self,
+        blocks: impl IntoIterator<Item = (&'a [u8], &'a [u8])>,
+    ) -> Result<(), ClubcardError> {
+        let blocks: Vec<_> = blocks
+            .into_iter()
+            .filter(|(block, _)| !self.is_available(block))
+            .collect();
+        let fetched = Clubcard::assemble_from_parts(&self.header, blocks)?;
+        let empty = Clubcard::assemble_from_parts(&self.header, [])?;
+        let clubcard = std::mem::replace(&mut self.clubcard, empty);
+        self.clubcard = Clubcard::concat([clubcard, fetched])?;
+        Ok(())
+    }
+
+    /// Whether `block` has been fetched.
+    pub fn is_available(&self, block: &[u8]) -> bool {
+        self.clubcard.index.contains_key(block)
+    }
+
+    /// The blocks that are in the clubcard but have not been fetched.
+    pub fn missing_blocks(&self) -> impl Iterator<Item = &[u8]> {
+        self.header
+            .blocks()
+            .filter(|block| !self.is_available(block))
+    }
+
+    /// Same as Clubcard::contains, except that items in blocks that have not been fetched are
+    /// reported as Membership::Unavailable.
+    pub fn contains<T>(&self, item: &T) -> Membership
+    where
+        T: Queryable<W, UniverseMetadata = UniverseMetadata, PartitionMetadata = PartitionMetadata>,
+    {
+        match self.clubcard.contains(item) {
+            Membership::NoData if self.header.range(item.block()).is_some() => {
+                Membership::Unavailable
+            }
+            result => result,
+        }
+    }
+}
+
+/// A clubcard decomposed into its metadata and the solution data of each block, e.g. for
+/// storage in a key-value store. See Clubcard::into_parts. The invariants are:
+///  - `index` and `blocks` have the same keys.
+///  - The data of a block is the encoding of its columns of X (a `Vec<Vec<u64>>`) followed by
+///    the encoding of its column of Y (a `Vec<u64>`). Only the bits that queries against the
+///    block can depend on are stored.
+///  - The offsets in the index entry of a block are relative to the start of the block's
+///    columns, and are 0 for the output of into_parts.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClubcardParts<UniverseMetadata, PartitionMetadata> {
+    pub universe: UniverseMetadata,
+    pub partition: PartitionMetadata,
+    pub build_config: BuildConfig,
+    pub index: ClubcardIndex,
+    pub blocks: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl<const W: usize, UniverseMetadata, PartitionMetadata>
+    Clubcard<W, UniverseMetadata, PartitionMetadata>
+where
+    UniverseMetadata: Clone + PartialEq,
+    PartitionMetadata: Clone + PartialEq,
+{
+    /// Decompose this clubcard into its metadata and the data of each block.
+    pub fn into_parts(self) -> ClubcardParts<UniverseMetadata, PartitionMetadata> {
+        let mut index = ClubcardIndex::new();
+        let mut blocks = BTreeMap::new();
+        for block in self.index.keys() {
+            let mut part = self.export_blocks([&block[..]]);
+            let mut data = vec![];
+            part.approx_filter.encode(&mut data);
+            part.exact_filter.encode(&mut data);
+            index.insert(block.clone(), part.index.remove(block).unwrap());
+            blocks.insert(block.clone(), data);
+        }
+        ClubcardParts {
+            universe: self.universe,
+            partition: self.partition,
+            build_config: self.build_config,
+            index,
+            blocks,
+        }
+    }
+
+    /// Reassemble a clubcard from the output of into_parts. Fails with
+    /// ClubcardError::InvalidBlock if the parts violate the invariants listed in
+    /// ClubcardParts for some block.
+    pub fn from_parts(
+        parts: ClubcardParts<UniverseMetadata, PartitionMetadata>,
+    ) -> Result<Self, ClubcardError> {
+        let mut blocks = parts.blocks;
+        let mut out = vec![Clubcard {
+            build_config: parts.build_config.clone(),
+            ..Clubcard::empty(parts.universe.clone(), parts.partition.clone())
+        }];
+        for (block, meta) in parts.index {
+            let invalid = || ClubcardError::InvalidBlock(block.clone());
+            let data = blocks.remove(&block).ok_or_else(invalid)?;
+            let bytes = &mut &data[..];
+            let part = Clubcard {
+                universe: parts.universe.clone(),
+                partition: parts.partition.clone(),
+                index: ClubcardIndex::from([(block.clone(), meta)]),
+                approx_filter: Decode::decode(bytes).map_err(|_| invalid())?,
+                exact_filter: Decode::decode(bytes).map_err(|_| invalid())?,
+                build_config: parts.build_config.clone(),
+                members: None,
+            };
+            if !bytes.is_empty() || part.validate().is_err() {
+                return Err(invalid());
+            }
+            out.push(part);
+        }
+        if let Some(block) = blocks.into_keys().next() {
+            return Err(ClubcardError::InvalidBlock(block));
+        }
+        Self::concat(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_vectors::{generated, BLOCKS, W};
+
+    #[test]
+    fn test_assemble_from_parts() {
+        let (clubcard, queries) = generated(0);
+        let layout = clubcard.to_parts();
+
+        let len = header_len(&layout[..HEADER_PREFIX_LEN]).unwrap();
+        let header = ClubcardPartsHeader::<u32, ()>::from_bytes(&layout[..len]).unwrap();
+        assert!(header.blocks().count() == BLOCKS.len());
+
+        let mut prefix = layout[..HEADER_PREFIX_LEN].to_vec();
+        prefix[..2].copy_from_slice(&crate::CLUBCARD_VERSION.to_le_bytes());
+        assert!(
+            header_len(&prefix) == Err(ClubcardError::UnsupportedVersion(crate::CLUBCARD_VERSION))
+        );
+        let fetch = |block: &'static [u8]| {
+            let range = header.range(block).unwrap();
+            (block, &layout[range.start as usize..range.end as usize])
+        };
+
+        let partial =
+            Clubcard::<W, u32, ()>::assemble_from_parts(&header, [fetch(&[0]), fetch(&[2])])
+                .unwrap();
+        assert!(partial.validate().is_ok());
+        for (query, expected) in &queries {
+            if [[0], [2]].contains(&query.block) || *expected == Membership::NotInUniverse {
+                assert_eq!(partial.contains(query), *expected);
+            } else {
+                assert_eq!(partial.contains(query), Membership::NoData);
+            }
+        }
+
+        let (block, data) = fetch(&[1]);
+        assert!(
+            Clubcard::<W, u32, ()>::assemble_from_parts(&header, [(block, &data[1..])]).err()
+                == Some(ClubcardError::InvalidBlock(vec![1]))
+        );
+    }
+
+    #[test]
+    fn test_partial_clubcard() {
+        let (clubcard, queries) = generated(1);
+        let layout = clubcard.to_parts();
+        let len = header_len(&layout[..HEADER_PREFIX_LEN]).unwrap();
+        let header = ClubcardPartsHeader::<u32, ()>::from_bytes(&layout[..len]).unwrap();
+        let mut partial = PartialClubcard::<W, u32, ()>::new(header);
+
+        let fetch = |partial: &PartialClubcard<W, u32, ()>, block: &'static [u8]| {
+            let range = partial.header().range(block).unwrap();
+            (block, &layout[range.start as usize..range.end as usize])
+        };
+        let block = fetch(&partial, &[3]);
+        partial.add_blocks([block]).unwrap();
+        partial.add_blocks([block]).unwrap();
+        assert!(partial.missing_blocks().count() == BLOCKS.len() - 1);
+
+        for (query, expected) in &queries {
+            let result = partial.contains(query);
+            if query.block == [3]
+                || matches!(expected, Membership::NotInUniverse | Membership::NoData)
+            {
+                assert_eq!(result, *expected);
+            } else {
+                assert_eq!(result, Membership::Unavailable);
+            }
+        }
+
+        let blocks: Vec<_> = [&[0u8][..], &[1], &[2]]
+            .into_iter()
+            .map(|block| fetch(&partial, block))
+            .collect();
+        partial.add_blocks(blocks).unwrap();
+        assert!(partial.missing_blocks().count() == 0);
+        for (query, expected) in &queries {
+            assert_eq!(partial.contains(query), *expected);
+        }
+    }
+
+    #[test]
+    fn test_into_parts() {
+        let (clubcard, queries) = generated(0);
+        let parts = generated(0).0.into_parts();
+        assert!(parts.blocks.len() == BLOCKS.len());
+        assert!(parts
+            .index
+            .values()
+            .all(|meta| meta.approx_filter_offset == 0));
+
+        let copy = Clubcard::<W, u32, ()>::from_parts(parts.clone()).unwrap();
+        assert!(copy.validate().is_ok());
+        for (query, expected) in &queries {
+            assert_eq!(copy.contains(query), *expected);
+        }
+        assert!(copy.into_parts() == parts);
+        assert!(clubcard.equivalent(
+            &Clubcard::from_parts(parts.clone()).unwrap(),
+            queries.iter().map(|(query, _)| query.clone())
+        ));
+
+        let mut missing = parts.clone();
+        missing.blocks.remove(&vec![1]);
+        assert!(
+            Clubcard::<W, u32, ()>::from_parts(missing).err()
+                == Some(ClubcardError::InvalidBlock(vec![1]))
+        );
+        let mut truncated = parts.clone();
+        truncated.blocks.get_mut(&vec![2]).unwrap().pop();
+        assert!(
+            Clubcard::<W, u32, ()>::from_parts(truncated).err()
+                == Some(ClubcardError::InvalidBlock(vec![2]))
+        );
+        let mut extra = parts;
+        extra.blocks.insert(vec![9], vec![]);
+        assert!(
+            Clubcard::<W, u32, ()>::from_parts(extra).err()
+                == Some(ClubcardError::InvalidBlock(vec![9]))
+        );
+    }
+}
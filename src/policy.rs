@@ -0,0 +1,365 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use crate::{AsQuery, Clubcard, Membership, Queryable};
+use std::future::Future;
+
+/// A bound on the age of a clubcard. Times are in caller-defined units (e.g. seconds since the
+/// Unix epoch), and must use the same units as the `now` argument of Clubcard::contains_at.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FreshnessPolicy {
+    /// The last time at which the clubcard may be used.
+    pub not_after: u64,
+}
+
+impl FreshnessPolicy {
+    /// A policy for a clubcard that was produced at `issued_at` and may be used for `max_age`.
+    pub fn from_max_age(issued_at: u64, max_age: u64) -> Self {
+        FreshnessPolicy {
+            not_after: issued_at.saturating_add(max_age),
+        }
+    }
+
+    pub fn is_fresh(&self, now: u64) -> bool {
+        now <= self.not_after
+    }
+}
+
+impl<const W: usize, UniverseMetadata, PartitionMetadata>
+    Clubcard<W, UniverseMetadata, PartitionMetadata>
+{
+    /// Same as contains, but returns Membership::NotInUniverse for every item once `now` is
+    /// past the end of the given freshness policy. A stale clubcard may be missing recent
+    /// changes, so the caller should treat it as though it covered nothing.
+    pub fn contains_at<T>(&self, item: &T, now: u64, policy: &FreshnessPolicy) -> Membership
+    where
+        T: Queryable<W, UniverseMetadata = UniverseMetadata, PartitionMetadata = PartitionMetadata>,
+    {
+        if !policy.is_fresh(now) {
+            return Membership::NotInUniverse;
+        }
+        self.contains(item)
+    }
+}
+
+/// A universe check that is implemented by the universe metadata rather than by the query
+/// type, so that one query type can be used with clubcards whose universes are described in
+/// different ways (e.g. by log coverage or by issuance date). See Clubcard::contains_in.
+pub trait UniversePolicy<T: ?Sized> {
+    fn covers(&self, item: &T) -> bool;
+}
+
+/// The unit type covers every item, e.g. for testing.
+impl<T: ?Sized> UniversePolicy<T> for () {
+    fn covers(&self, _item: &T) -> bool {
+        true
+    }
+}
+
+impl<const W: usize, UniverseMetadata, PartitionMetadata>
+    Clubcard<W, UniverseMetadata, PartitionMetadata>
+{
+    /// Same as contains, but the universe check is UniversePolicy::covers of the universe
+    /// metadata, and the item only needs to implement AsQuery.
+    pub fn contains_in<T>(&self, item: &T) -> Membership
+    where
+        T: AsQuery<W>,
+        UniverseMetadata: UniversePolicy<T>,
+    {
+        if !self.universe.covers(item) {
+            return Membership::NotInUniverse;
+        }
+        match self.block_handle(item.block()) {
+            None => Membership::NoData,
+            Some(handle) if handle.meta().tombstoned => Membership::NotInUniverse,
+            Some(handle) => handle.unchecked_contains_any(item).into(),
+        }
+    }
+}
+
+/// What the caller should do with an item. See ActionPolicy.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Treat the item as valid.
+    Accept,
+    /// Treat the item as revoked.
+    Reject,
+    /// Decide by other means, e.g. an online check.
+    Defer,
+}
+
+/// The action to take for each result of a query. Matching on Membership in one place, rather
+/// than in every integration, ensures that no result is left unhandled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ActionPolicy {
+    pub member: Action,
+    pub nonmember: Action,
+    pub not_in_universe: Action,
+    pub no_data: Action,
+    pub unavailable: Action,
+}
+
+impl ActionPolicy {
+    /// Reject members, and accept every item that the clubcard cannot answer for.
+    pub const FAIL_OPEN: ActionPolicy = ActionPolicy {
+        member: Action::Reject,
+        nonmember: Action::Accept,
+        not_in_universe: Action::Accept,
+        no_data: Action::Accept,
+        unavailable: Action::Accept,
+    };
+
+    /// Reject members, and every item that the clubcard cannot answer for.
+    pub const FAIL_CLOSED: ActionPolicy = ActionPolicy {
+        member: Action::Reject,
+        nonmember: Action::Accept,
+        not_in_universe: Action::Reject,
+        no_data: Action::Reject,
+        unavailable: Action::Reject,
+    };
+
+    /// Reject members, and defer every item that the clubcard cannot answer for.
+    pub const DEFER: ActionPolicy = ActionPolicy {
+        member: Action::Reject,
+        nonmember: Action::Accept,
+        not_in_universe: Action::Defer,
+        no_data: Action::Defer,
+        unavailable: Action::Defer,
+    };
+
+    pub fn action(&self, result: Membership) -> Action {
+        match result {
+            Membership::Member => self.member,
+            Membership::Nonmember => self.nonmember,
+            Membership::NotInUniverse => self.not_in_universe,
+            Membership::NoData => self.no_data,
+            Membership::Unavailable => self.unavailable,
+        }
+    }
+}
+
+/// Decides the items that an ActionPolicy defers, e.g. with an OCSP request or a CRL lookup.
+/// See PolicyFilter::check_with.
+pub trait Resolver<T: ?Sized> {
+    /// The final action for `item`, for which the clubcard returned `result`. This should not
+    /// be Action::Defer.
+    fn resolve(&self, item: &T, result: Membership) -> Action;
+
+    /// Called with the query result and the final action for every item checked with
+    /// PolicyFilter::check_with, e.g. to count how often the fallback is taken.
+    fn record(&self, _item: &T, _result: Membership, _action: Action) {}
+}
+
+impl<T: ?Sized, F: Fn(&T, Membership) -> Action> Resolver<T> for F {
+    fn resolve(&self, item: &T, result: Membership) -> Action {
+        self(item, result)
+    }
+}
+
+/// Same as Resolver, for fallbacks that perform I/O. See PolicyFilter::check_with_async.
+pub trait AsyncResolver<T: ?Sized> {
+    fn resolve(&self, item: &T, result: Membership) -> impl Future<Output = Action> + Send;
+
+    fn record(&self, _item: &T, _result: Membership, _action: Action) {}
+}
+
+/// A clubcard together with the policy that turns its query results into actions.
+pub struct PolicyFilter<const W: usize, UniverseMetadata, PartitionMetadata> {
+    clubcard: Clubcard<W, UniverseMetadata, PartitionMetadata>,
+    policy: ActionPolicy,
+}
+
+impl<const W: usize, UniverseMetadata, PartitionMetadata>
+    PolicyFilter<W, UniverseMetadata, PartitionMetadata>
+{
+    pub fn new(
+        clubcard: Clubcard<W, UniverseMetadata, PartitionMetadata>,
+        policy: ActionPolicy,
+    ) -> Self {
+        PolicyFilter { clubcard, policy }
+    }
+
+    pub fn clubcard(&self) -> &Clubcard<W, UniverseMetadata, PartitionMetadata> {
+        &self.clubcard
+    }
+
+    pub fn policy(&self) -> &ActionPolicy {
+        &self.policy
+    }
+
+    /// The action for the result of Clubcard::contains.
+    pub fn check<T>(&self, item: &T) -> Action
+    where
+        T: Queryable<W, UniverseMetadata = UniverseMetadata, PartitionMetadata = PartitionMetadata>,
+    {
+        self.policy.action(self.clubcard.contains(item))
+    }
+
+    /// Same as check, but the items that the policy defers are decided by `resolver`.
+    pub fn check_with<T, R>(&self, item: &T, resolver: &R) -> Action
+    where
+        T: Queryable<W, UniverseMetadata = UniverseMetadata, PartitionMetadata = PartitionMetadata>,
+        R: Resolver<T> + ?Sized,
+    {
+        let result = self.clubcard.contains(item);
+        let action = match self.policy.action(result) {
+            Action::Defer => resolver.resolve(item, result),
+            action => action,
+        };
+        resolver.record(item, result, action);
+        action
+    }
+
+    /// Same as check_with, with an AsyncResolver.
+    pub async fn check_with_async<T, R>(&self, item: &T, resolver: &R) -> Action
+    where
+        T: Queryable<W, UniverseMetadata = UniverseMetadata, PartitionMetadata = PartitionMetadata>,
+        R: AsyncResolver<T> + ?Sized,
+    {
+        let result = self.clubcard.contains(item);
+        let action = match self.policy.action(result) {
+            Action::Defer => resolver.resolve(item, result).await,
+            action => action,
+        };
+        resolver.record(item, result, action);
+        action
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AsQuery, Equation};
+
+    struct Query;
+
+    impl AsQuery<1> for Query {
+        fn as_query(&self, _m: usize) -> Equation<1> {
+            Equation::homogeneous(0, [1])
+        }
+
+        fn block(&self) -> &[u8] {
+            &[]
+        }
+
+        fn discriminant(&self) -> &[u8] {
+            &[]
+        }
+    }
+
+    impl Queryable<1> for Query {
+        type UniverseMetadata = ();
+        type PartitionMetadata = ();
+
+        fn in_universe(&self, _meta: &()) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn test_contains_at() {
+        let clubcard: Clubcard<1, (), ()> = Clubcard::empty((), ());
+        let policy = FreshnessPolicy::from_max_age(100, 10);
+        assert!(clubcard.contains_at(&Query, 110, &policy) == Membership::NoData);
+        assert!(clubcard.contains_at(&Query, 111, &policy) == Membership::NotInUniverse);
+        assert!(FreshnessPolicy::from_max_age(u64::MAX, 1).is_fresh(u64::MAX));
+    }
+
+    #[test]
+    fn test_contains_in() {
+        struct CoversNothing;
+
+        impl UniversePolicy<Query> for CoversNothing {
+            fn covers(&self, _item: &Query) -> bool {
+                false
+            }
+        }
+
+        let clubcard: Clubcard<1, (), ()> = Clubcard::empty((), ());
+        assert!(clubcard.contains_in(&Query) == Membership::NoData);
+        let clubcard: Clubcard<1, CoversNothing, ()> = Clubcard::empty(CoversNothing, ());
+        assert!(clubcard.contains_in(&Query) == Membership::NotInUniverse);
+    }
+
+    #[test]
+    fn test_policy_filter() {
+        let clubcard: Clubcard<1, (), ()> = Clubcard::empty((), ());
+        let filter = PolicyFilter::new(clubcard, ActionPolicy::FAIL_OPEN);
+        assert!(filter.check(&Query) == Action::Accept);
+        let filter = PolicyFilter::new(filter.clubcard, ActionPolicy::FAIL_CLOSED);
+        assert!(filter.check(&Query) == Action::Reject);
+        let filter = PolicyFilter::new(filter.clubcard, ActionPolicy::DEFER);
+        assert!(filter.check(&Query) == Action::Defer);
+
+        for policy in [
+            ActionPolicy::FAIL_OPEN,
+            ActionPolicy::FAIL_CLOSED,
+            ActionPolicy::DEFER,
+        ] {
+            assert!(policy.action(Membership::Member) == Action::Reject);
+            assert!(policy.action(Membership::Nonmember) == Action::Accept);
+        }
+    }
+
+    #[test]
+    fn test_check_with() {
+        use std::cell::RefCell;
+        use std::future::ready;
+        use std::pin::pin;
+        use std::sync::Arc;
+        use std::task::{Context, Poll, Wake, Waker};
+
+        let clubcard: Clubcard<1, (), ()> = Clubcard::empty((), ());
+        let filter = PolicyFilter::new(clubcard, ActionPolicy::DEFER);
+        let revoked = |_: &Query, result| {
+            assert!(result == Membership::NoData);
+            Action::Reject
+        };
+        assert!(filter.check_with(&Query, &revoked) == Action::Reject);
+
+        let filter = PolicyFilter::new(filter.clubcard, ActionPolicy::FAIL_OPEN);
+        let unreachable = |_: &Query, _| -> Action { panic!("resolver called") };
+        assert!(filter.check_with(&Query, &unreachable) == Action::Accept);
+
+        struct Online(RefCell<Vec<(Membership, Action)>>);
+
+        impl AsyncResolver<Query> for Online {
+            fn resolve(
+                &self,
+                _item: &Query,
+                _result: Membership,
+            ) -> impl Future<Output = Action> + Send {
+                ready(Action::Accept)
+            }
+
+            fn record(&self, _item: &Query, result: Membership, action: Action) {
+                self.0.borrow_mut().push((result, action));
+            }
+        }
+
+        // Waker::noop needs Rust 1.85.
+        struct NoopWaker;
+
+        impl Wake for NoopWaker {
+            fn wake(self: Arc<Self>) {}
+        }
+
+        let waker = Waker::from(Arc::new(NoopWaker));
+        let online = Online(RefCell::new(vec![]));
+        for policy in [ActionPolicy::DEFER, ActionPolicy::FAIL_CLOSED] {
+            let filter = PolicyFilter::new(filter.clubcard.export_blocks([]), policy);
+            let mut future = pin!(filter.check_with_async(&Query, &online));
+            let Poll::Ready(_) = future.as_mut().poll(&mut Context::from_waker(&waker)) else {
+                panic!("expected a ready future");
+            };
+        }
+        assert!(
+            online.0.into_inner()
+                == vec![
+                    (Membership::NoData, Action::Accept),
+                    (Membership::NoData, Action::Reject)
+                ]
+        );
+    }
+}
@@ -0,0 +1,21 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! The traits and types that most integrations need, for use with a glob import:
+//!
+//! ```
+//! use clubcard::prelude::*;
+//! ```
+
+pub use crate::encoding::{Decode, Encode};
+pub use crate::{
+    AsQuery, Clubcard, ClubcardError, ClubcardHandle, ClubcardIndexEntry, Equation, Filterable,
+    Membership, Queryable,
+};
+
+#[cfg(feature = "builder")]
+pub use crate::builder::{
+    ApproximateRibbon, BuildConfig, BuildReport, ClubcardBuilder, ExactRibbon, ItemSource,
+    RibbonBuilder,
+};
@@ -0,0 +1,288 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+#[cfg(feature = "builder")]
+use crate::builder::ItemSource;
+use crate::{AsQuery, ClubcardError, ClubcardIndexEntry, Equation, Filterable, Queryable};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+/// Replaces block identifiers with truncated HMAC-SHA256 tags under a secret key. This shrinks
+/// the index when block identifiers are long (e.g. 32 byte issuer hashes), and it hides the
+/// identifiers from anyone who does not hold the key. Build from a source wrapped with
+/// hash_source, which checks for collisions, and query with items wrapped by the same hasher.
+#[derive(Clone, Debug)]
+pub struct BlockHasher {
+    key: [u8; 64],
+    len: usize,
+}
+
+impl BlockHasher {
+    /// A hasher that produces `len` byte identifiers. Fails with ClubcardError::InvalidArgument
+    /// unless 1 <= len <= 32.
+    pub fn new(key: &[u8], len: usize) -> Result<Self, ClubcardError> {
+        if !(1..=32).contains(&len) {
+            return Err(ClubcardError::InvalidArgument(
+                "hashed block identifiers must have between 1 and 32 bytes",
+            ));
+        }
+        let mut padded = [0u8; 64];
+        if key.len() > 64 {
+            padded[..32].copy_from_slice(&Sha256::digest(key));
+        } else {
+            padded[..key.len()].copy_from_slice(key);
+        }
+        Ok(BlockHasher { key: padded, len })
+    }
+
+    pub fn hash(&self, block: &[u8]) -> Vec<u8> {
+        let pad = |byte: u8| self.key.map(|k| k ^ byte);
+        let inner = Sha256::new()
+            .chain_update(pad(0x36))
+            .chain_update(block)
+            .finalize();
+        let outer = Sha256::new()
+            .chain_update(pad(0x5c))
+            .chain_update(inner)
+            .finalize();
+        outer[..self.len].to_vec()
+    }
+
+    /// Fails with ClubcardError::InvalidBlock, naming one of the blocks, if two distinct blocks
+    /// of `blocks` have the same hash. Such blocks would be merged into one.
+    pub fn check_collisions<'a>(
+        &self,
+        blocks: impl IntoIterator<Item = &'a [u8]>,
+    ) -> Result<(), ClubcardError> {
+        self.hash_blocks(blocks).map(|_| ())
+    }
+
+    /// A map from the hash of each block to the block. Fails as described in check_collisions.
+    fn hash_blocks<'a>(
+        &self,
+        blocks: impl IntoIterator<Item = &'a [u8]>,
+    ) -> Result<BTreeMap<Vec<u8>, Vec<u8>>, ClubcardError> {
+        let mut hashed: BTreeMap<Vec<u8>, Vec<u8>> = BTreeMap::new();
+        for block in blocks {
+            match hashed.insert(self.hash(block), block.to_vec()) {
+                Some(other) if other != block => {
+                    return Err(ClubcardError::InvalidBlock(block.to_vec()))
+                }
+                _ => (),
+            }
+        }
+        Ok(hashed)
+    }
+
+    /// Wrap `source` so that its blocks are replaced by their hashes, for
+    /// ClubcardBuilder::collect_ribbons_from_source. Fails as described in check_collisions if
+    /// two blocks of the source have the same hash.
+    #[cfg(feature = "builder")]
+    pub fn hash_source<const W: usize, S: ItemSource<W>>(
+        &self,
+        source: S,
+    ) -> Result<HashedSource<S>, ClubcardError> {
+        let blocks = source.blocks();
+        let blocks = self.hash_blocks(blocks.iter().map(|block| &block[..]))?;
+        Ok(HashedSource { blocks, source })
+    }
+
+    pub fn wrap<const W: usize, T: AsQuery<W>>(&self, item: T) -> HashedBlock<T> {
+        HashedBlock {
+            block: self.hash(item.block()),
+            item,
+        }
+    }
+}
+
+/// A source whose blocks are replaced by their hashes under a BlockHasher. See
+/// BlockHasher::hash_source.
+#[cfg(feature = "builder")]
+#[derive(Clone, Debug)]
+pub struct HashedSource<S> {
+    /// The original identifier of each hashed block.
+    blocks: BTreeMap<Vec<u8>, Vec<u8>>,
+    source: S,
+}
+
+#[cfg(feature = "builder")]
+impl<const W: usize, S: ItemSource<W>> ItemSource<W> for HashedSource<S> {
+    type Item = HashedBlock<S::Item>;
+
+    fn blocks(&self) -> Vec<Vec<u8>> {
+        self.blocks.keys().cloned().collect()
+    }
+
+    fn items_for_block(
+        &self,
+        block: &[u8],
+    ) -> impl Iterator<Item = Result<Self::Item, ClubcardError>> + '_ {
+        self.blocks
+            .get_key_value(block)
+            .into_iter()
+            .flat_map(move |(hashed, original)| {
+                self.source.items_for_block(original).map(move |item| {
+                    item.map(|item| HashedBlock {
+                        block: hashed.clone(),
+                        item,
+                    })
+                })
+            })
+    }
+}
+
+/// An item whose block is replaced by its hash under a BlockHasher. See BlockHasher::wrap.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HashedBlock<T> {
+    block: Vec<u8>,
+    item: T,
+}
+
+impl<T> HashedBlock<T> {
+    pub fn item(&self) -> &T {
+        &self.item
+    }
+
+    pub fn into_item(self) -> T {
+        self.item
+    }
+}
+
+impl<const W: usize, T: AsQuery<W>> AsQuery<W> for HashedBlock<T> {
+    fn as_query(&self, m: usize) -> Equation<W> {
+        self.item.as_query(m)
+    }
+
+    fn block(&self) -> &[u8] {
+        &self.block
+    }
+
+    fn discriminant(&self) -> &[u8] {
+        self.item.discriminant()
+    }
+
+    fn as_approx_query(&self, meta: &ClubcardIndexEntry) -> Equation<W> {
+        self.item.as_approx_query(meta)
+    }
+
+    fn as_exact_query(&self, meta: &ClubcardIndexEntry) -> Equation<W> {
+        self.item.as_exact_query(meta)
+    }
+}
+
+impl<const W: usize, T: Filterable<W>> Filterable<W> for HashedBlock<T> {
+    fn included(&self) -> bool {
+        self.item.included()
+    }
+}
+
+impl<const W: usize, T: Queryable<W>> Queryable<W> for HashedBlock<T> {
+    type UniverseMetadata = T::UniverseMetadata;
+    type PartitionMetadata = T::PartitionMetadata;
+
+    fn in_universe(&self, meta: &Self::UniverseMetadata) -> bool {
+        self.item.in_universe(meta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::tests::test_items;
+    use crate::builder::ClubcardBuilder;
+    use crate::Membership;
+
+    struct Item;
+
+    impl AsQuery<1> for Item {
+        fn as_query(&self, _m: usize) -> Equation<1> {
+            Equation::homogeneous(0, [1])
+        }
+
+        fn block(&self) -> &[u8] {
+            b"issuer"
+        }
+
+        fn discriminant(&self) -> &[u8] {
+            b"serial"
+        }
+    }
+
+    #[test]
+    fn test_block_hasher() {
+        // RFC 4231, test case 2.
+        let hasher = BlockHasher::new(b"Jefe", 32).unwrap();
+        assert!(
+            hasher.hash(b"what do ya want for nothing?")
+                == [
+                    0x5b, 0xdc, 0xc1, 0x46, 0xbf, 0x60, 0x75, 0x4e, 0x6a, 0x04, 0x24, 0x26, 0x08,
+                    0x95, 0x75, 0xc7, 0x5a, 0x00, 0x3f, 0x08, 0x9d, 0x27, 0x39, 0x83, 0x9d, 0xec,
+                    0x58, 0xb9, 0x64, 0xec, 0x38, 0x43,
+                ]
+        );
+
+        for len in [0, 33] {
+            assert!(matches!(
+                BlockHasher::new(b"key", len),
+                Err(ClubcardError::InvalidArgument(_))
+            ));
+        }
+        let hasher = BlockHasher::new(b"key", 1).unwrap();
+        assert!(hasher.hash(b"block").len() == 1);
+        let other = BlockHasher::new(b"other key", 1).unwrap();
+        assert!(hasher.hash(b"block") != other.hash(b"block"));
+        let blocks: Vec<[u8; 2]> = (0..=u8::MAX).map(|i| [i, 0]).collect();
+        let mut blocks: Vec<&[u8]> = blocks.iter().map(|block| &block[..]).collect();
+        let collisions = hasher.check_collisions(blocks.iter().copied());
+        if collisions.is_ok() {
+            // 256 distinct one-byte hashes, so one more block must collide.
+            blocks.push(b"one more");
+        }
+        assert!(matches!(
+            hasher.check_collisions(blocks.iter().copied()),
+            Err(ClubcardError::InvalidBlock(_))
+        ));
+        assert!(hasher.check_collisions([&b"a"[..], b"a"]).is_ok());
+
+        let item = hasher.wrap(Item);
+        assert!(item.block() == hasher.hash(b"issuer"));
+        assert!(item.discriminant() == b"serial");
+    }
+    #[test]
+    fn test_hash_source() {
+        let items = test_items(64);
+        let hasher = BlockHasher::new(b"key", 8).unwrap();
+        let source = BTreeMap::from([(vec![0], items.clone()), (vec![1], items.clone())]);
+        let source = hasher.hash_source(source).unwrap();
+        let mut clubcard_builder = ClubcardBuilder::new();
+        clubcard_builder
+            .collect_ribbons_from_source(&source)
+            .unwrap();
+        let clubcard = clubcard_builder.build::<HashedBlock<Equation<1>>>((), ());
+        let mut hashed = [hasher.hash(&[0]), hasher.hash(&[1])];
+        hashed.sort();
+        assert!(clubcard.index.keys().eq(hashed.iter()));
+        for block in source.blocks() {
+            for item in source.items_for_block(&block) {
+                let item = item.unwrap();
+                assert!(item.block() == block);
+                let expected = match item.included() {
+                    true => Membership::Member,
+                    false => Membership::Nonmember,
+                };
+                assert!(clubcard.contains(&item) == expected);
+            }
+        }
+
+        // With one byte hashes, 257 blocks must collide.
+        let hasher = BlockHasher::new(b"key", 1).unwrap();
+        let source: BTreeMap<Vec<u8>, Vec<Equation<1>>> = (0..=256u16)
+            .map(|i| (i.to_le_bytes().to_vec(), vec![]))
+            .collect();
+        assert!(matches!(
+            hasher.hash_source(source),
+            Err(ClubcardError::InvalidBlock(_))
+        ));
+    }
+}
@@ -4,6 +4,8 @@
 
 use crate::clubcard::ClubcardIndexEntry;
 use crate::equation::Equation;
+use crate::error::ClubcardError;
+use crate::namespace::Namespaced;
 
 pub trait AsQuery<const W: usize> {
     /// Hash this item to a homogeneous equation (s, a) such that
@@ -19,6 +21,14 @@ pub trait AsQuery<const W: usize> {
     /// `discriminant()`.
     fn discriminant(&self) -> &[u8];
 
+    /// This item, with its block in `namespace`. Fails as described in namespaced_block.
+    fn with_namespace(self, namespace: &[u8]) -> Result<Namespaced<Self>, ClubcardError>
+    where
+        Self: Sized,
+    {
+        Namespaced::new(namespace, self)
+    }
+
     #[doc(hidden)]
     fn as_approx_query(&self, meta: &ClubcardIndexEntry) -> Equation<W> {
         let mut approx_eq = self.as_query(meta.approx_filter_m);
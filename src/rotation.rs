@@ -0,0 +1,235 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Bookkeeping for publishing a full clubcard followed by a series of deltas.
+//!
+//! A delta is typically a clubcard that holds only the blocks that changed since the previous
+//! artifact, which clients apply with Clubcard::replace_blocks or Clubcard::append_blocks. Deltas
+//! are cheap to produce and to download, but clients have to fetch every delta since the last full
+//! clubcard, so a Rotation tracks the deltas and reports when a new full clubcard is warranted.
+
+use crate::encoding::Decode;
+use crate::{ClubcardError, ClubcardHeader};
+
+/// Universe metadata that can be compared with the universe metadata of an earlier artifact.
+/// See Rotation::push_full_checked.
+pub trait Coverage {
+    /// Whether every item in the universe described by `older` is in this universe.
+    fn is_superset_of(&self, older: &Self) -> bool;
+}
+
+impl Coverage for () {
+    fn is_superset_of(&self, _older: &Self) -> bool {
+        true
+    }
+}
+
+/// Thresholds at which a Rotation asks for a full rebuild.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RotationPolicy {
+    /// The maximum number of deltas that may follow a full clubcard.
+    pub max_deltas: usize,
+    /// The maximum total size of the deltas that follow a full clubcard, as a fraction of the
+    /// size of the full clubcard.
+    pub max_delta_ratio: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArtifactKind {
+    Full,
+    Delta,
+}
+
+/// A serialized clubcard to publish.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Artifact {
+    /// Artifacts are numbered consecutively, starting from 0. The sequence numbers are suitable
+    /// for ClubcardHandle::swap.
+    pub sequence: u64,
+    pub kind: ArtifactKind,
+    pub bytes: Vec<u8>,
+}
+
+/// A full clubcard and the deltas that have been published since. See the module
+/// documentation.
+pub struct Rotation {
+    policy: RotationPolicy,
+    full: Artifact,
+    deltas: Vec<Artifact>,
+}
+
+impl Rotation {
+    /// Start a rotation with the serialized full clubcard `full`, which gets sequence number 0.
+    pub fn new(policy: RotationPolicy, full: Vec<u8>) -> Self {
+        assert!(policy.max_delta_ratio >= 0.0);
+        Rotation {
+            policy,
+            full: Artifact {
+                sequence: 0,
+                kind: ArtifactKind::Full,
+                bytes: full,
+            },
+            deltas: vec![],
+        }
+    }
+
+    pub fn policy(&self) -> &RotationPolicy {
+        &self.policy
+    }
+
+    /// The sequence number of the most recent artifact.
+    pub fn sequence(&self) -> u64 {
+        self.deltas.last().unwrap_or(&self.full).sequence
+    }
+
+    /// The total size of the deltas since the last full clubcard.
+    pub fn delta_len(&self) -> usize {
+        self.deltas.iter().map(|delta| delta.bytes.len()).sum()
+    }
+
+    /// Whether publishing a delta of `len` bytes would exceed the policy, in which case a full
+    /// clubcard should be built and published with push_full instead.
+    pub fn should_rebuild(&self, len: usize) -> bool {
+        let total = self.delta_len().saturating_add(len);
+        self.deltas.len() >= self.policy.max_deltas
+            || total as f64 > self.policy.max_delta_ratio * self.full.bytes.len() as f64
+    }
+
+    /// Publish a delta, returning its sequence number. The delta is recorded even if
+    /// should_rebuild would have returned true.
+    pub fn push_delta(&mut self, bytes: Vec<u8>) -> u64 {
+        self.push(ArtifactKind::Delta, bytes)
+    }
+
+    /// Publish a full clubcard, returning its sequence number. This discards the deltas.
+    pub fn push_full(&mut self, bytes: Vec<u8>) -> u64 {
+        self.push(ArtifactKind::Full, bytes)
+    }
+
+    /// Same as push_delta, but fails with ClubcardError::CoverageRegressed if the universe of
+    /// the delta does not include the universe of the most recent artifact, i.e. if an item
+    /// that clients could check before would fall out of the universe. Use push_delta for
+    /// intended reductions in coverage.
+    pub fn push_delta_checked<U, P>(&mut self, bytes: Vec<u8>) -> Result<u64, ClubcardError>
+    where
+        U: Coverage + Decode,
+        P: Decode,
+    {
+        self.check_coverage::<U, P>(&bytes)?;
+        Ok(self.push_delta(bytes))
+    }
+
+    /// Same as push_full, but checks coverage like push_delta_checked.
+    pub fn push_full_checked<U, P>(&mut self, bytes: Vec<u8>) -> Result<u64, ClubcardError>
+    where
+        U: Coverage + Decode,
+        P: Decode,
+    {
+        self.check_coverage::<U, P>(&bytes)?;
+        Ok(self.push_full(bytes))
+    }
+
+    fn check_coverage<U, P>(&self, bytes: &[u8]) -> Result<(), ClubcardError>
+    where
+        U: Coverage + Decode,
+        P: Decode,
+    {
+        let latest = self.deltas.last().unwrap_or(&self.full);
+        let older = ClubcardHeader::<U, P>::from_bytes(&latest.bytes)?;
+        let newer = ClubcardHeader::<U, P>::from_bytes(bytes)?;
+        if !newer.universe.is_superset_of(&older.universe) {
+            return Err(ClubcardError::CoverageRegressed);
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, kind: ArtifactKind, bytes: Vec<u8>) -> u64 {
+        let artifact = Artifact {
+            sequence: self.sequence() + 1,
+            kind,
+            bytes,
+        };
+        let sequence = artifact.sequence;
+        match kind {
+            ArtifactKind::Full => {
+                self.full = artifact;
+                self.deltas.clear();
+            }
+            ArtifactKind::Delta => self.deltas.push(artifact),
+        }
+        sequence
+    }
+
+    /// The artifacts that a client needs: the last full clubcard followed by the deltas since,
+    /// in order.
+    pub fn artifacts(&self) -> impl Iterator<Item = &Artifact> {
+        std::iter::once(&self.full).chain(&self.deltas)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domains::dates::DateRangeCoverage;
+    use crate::Clubcard;
+
+    #[test]
+    fn test_rotation() {
+        let policy = RotationPolicy {
+            max_deltas: 3,
+            max_delta_ratio: 0.5,
+        };
+        let mut rotation = Rotation::new(policy, vec![0; 100]);
+        assert!(rotation.sequence() == 0);
+        assert!(!rotation.should_rebuild(50));
+        assert!(rotation.should_rebuild(51));
+
+        assert!(rotation.push_delta(vec![0; 20]) == 1);
+        assert!(rotation.push_delta(vec![0; 20]) == 2);
+        assert!(rotation.delta_len() == 40);
+        assert!(!rotation.should_rebuild(10));
+        assert!(rotation.should_rebuild(11));
+
+        assert!(rotation.push_delta(vec![0; 1]) == 3);
+        assert!(rotation.should_rebuild(0));
+        let kinds: Vec<_> = rotation.artifacts().map(|a| (a.sequence, a.kind)).collect();
+        assert!(
+            kinds
+                == [
+                    (0, ArtifactKind::Full),
+                    (1, ArtifactKind::Delta),
+                    (2, ArtifactKind::Delta),
+                    (3, ArtifactKind::Delta)
+                ]
+        );
+
+        assert!(rotation.push_full(vec![0; 10]) == 4);
+        assert!(rotation.delta_len() == 0);
+        assert!(rotation.artifacts().count() == 1);
+        assert!(rotation.should_rebuild(6));
+    }
+
+    #[test]
+    fn test_coverage_checks() {
+        let serialize = |range: std::ops::Range<u64>| {
+            Clubcard::<1, DateRangeCoverage, ()>::empty(DateRangeCoverage::new([range]), ())
+                .to_bytes()
+        };
+        let policy = RotationPolicy {
+            max_deltas: 3,
+            max_delta_ratio: 0.5,
+        };
+        let mut rotation = Rotation::new(policy, serialize(0..10));
+        assert!(rotation.push_delta_checked::<DateRangeCoverage, ()>(serialize(0..20)) == Ok(1));
+        assert!(
+            rotation.push_full_checked::<DateRangeCoverage, ()>(serialize(5..20))
+                == Err(ClubcardError::CoverageRegressed)
+        );
+        assert!(rotation.sequence() == 1);
+        assert!(rotation.push_full(serialize(5..20)) == 2);
+        assert!(rotation
+            .push_full_checked::<DateRangeCoverage, ()>(vec![0; 10])
+            .is_err());
+    }
+}
@@ -0,0 +1,249 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! A multi-file layout for clubcards. The blocks of a clubcard are split into shards by a hash
+//! of the block identifier, each shard is written to its own file with Clubcard::to_bytes, and
+//! a manifest lists the shards.
+
+use crate::encoding::{Decode, Encode};
+use crate::{Clubcard, ClubcardError};
+use std::fs;
+use std::path::{Component, Path};
+
+/// The name of the manifest file written by Clubcard::write_sharded.
+pub const MANIFEST_FILE_NAME: &str = "manifest.bin";
+
+/// The version of the manifest encoding. This is independent of CLUBCARD_VERSION, since the
+/// shards carry their own versions.
+pub const MANIFEST_VERSION: u16 = 0xffff;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShardManifestEntry {
+    /// The name of the shard's file, relative to the directory of the manifest. This is a
+    /// single normal path component, so that a manifest cannot refer to files outside of
+    /// that directory.
+    pub name: String,
+    /// The length of the shard's file in bytes.
+    pub len: u64,
+    /// The blocks in the shard.
+    pub blocks: Vec<Vec<u8>>,
+}
+
+impl Encode for ShardManifestEntry {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.name.encode(out);
+        self.len.encode(out);
+        self.blocks.encode(out);
+    }
+}
+
+impl Decode for ShardManifestEntry {
+    fn decode(bytes: &mut &[u8]) -> Result<Self, ClubcardError> {
+        Ok(ShardManifestEntry {
+            name: Decode::decode(bytes)?,
+            len: Decode::decode(bytes)?,
+            blocks: Decode::decode(bytes)?,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShardManifest {
+    pub shards: Vec<ShardManifestEntry>,
+}
+
+impl ShardManifest {
+    /// Serialize this manifest. The output starts with a two byte little-endian version
+    /// (MANIFEST_VERSION) followed by the list of shards.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = vec![];
+        MANIFEST_VERSION.encode(&mut out);
+        self.shards.encode(&mut out);
+        out
+    }
+
+    /// Fails with ClubcardError::Malformed if the name of a shard is not a single normal path
+    /// component, e.g. if it is an absolute path or contains "..". Manifests may be fetched
+    /// from untrusted sources.
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<Self, ClubcardError> {
+        let bytes = &mut bytes;
+        let version = u16::decode(bytes)?;
+        if version != MANIFEST_VERSION {
+            return Err(ClubcardError::UnsupportedVersion(version));
+        }
+        let manifest = ShardManifest {
+            shards: Decode::decode(bytes)?,
+        };
+        if !bytes.is_empty()
+            || !manifest
+                .shards
+                .iter()
+                .all(|entry| is_file_name(&entry.name))
+        {
+            return Err(ClubcardError::Malformed);
+        }
+        Ok(manifest)
+    }
+}
+
+/// Whether `name` is a single normal path component.
+fn is_file_name(name: &str) -> bool {
+    let mut components = Path::new(name).components();
+    matches!(
+        (components.next(), components.next()),
+        (Some(Component::Normal(_)), None)
+    )
+}
+
+/// The shard that `block` belongs to. This is the 64-bit FNV-1a hash of the block identifier
+/// modulo the number of shards.
+pub fn shard_of(block: &[u8], shards: usize) -> usize {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in block {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    (hash % shards as u64) as usize
+}
+
+impl<const W: usize, UniverseMetadata, PartitionMetadata>
+    Clubcard<W, UniverseMetadata, PartitionMetadata>
+where
+    UniverseMetadata: Clone + Encode,
+    PartitionMetadata: Clone + Encode,
+{
+    /// Split this clubcard into `shards` serialized clubcards, and list them in a manifest.
+    /// Shard i is named "shard-i.bin". Every shard carries a copy of the universe and
+    /// partition metadata. Fails with ClubcardError::InvalidArgument if `shards` is 0.
+    pub fn to_shards(&self, shards: usize) -> Result<(ShardManifest, Vec<Vec<u8>>), ClubcardError> {
+        if shards == 0 {
+            return Err(ClubcardError::InvalidArgument(
+                "a clubcard must be split into at least one shard",
+            ));
+        }
+        let mut blocks = vec![vec![]; shards];
+        for block in self.index.keys() {
+            blocks[shard_of(block, shards)].push(block.clone());
+        }
+        let mut manifest = ShardManifest { shards: vec![] };
+        let mut files = vec![];
+        for (i, blocks) in blocks.into_iter().enumerate() {
+            let bytes = self
+                .export_blocks(blocks.iter().map(|block| &block[..]))
+                .to_bytes();
+            manifest.shards.push(ShardManifestEntry {
+                name: format!("shard-{}.bin", i),
+                len: bytes.len() as u64,
+                blocks,
+            });
+            files.push(bytes);
+        }
+        Ok((manifest, files))
+    }
+
+    /// Write the output of to_shards to `dir`, with the manifest in MANIFEST_FILE_NAME. Fails
+    /// with ClubcardError::InvalidArgument, before anything is written, if `shards` is 0.
+    pub fn write_sharded(
+        &self,
+        dir: impl AsRef<Path>,
+        shards: usize,
+    ) -> Result<ShardManifest, ClubcardError> {
+        let dir = dir.as_ref();
+        let (manifest, files) = self.to_shards(shards)?;
+        for (entry, bytes) in manifest.shards.iter().zip(files) {
+            fs::write(dir.join(&entry.name), bytes)?;
+        }
+        fs::write(dir.join(MANIFEST_FILE_NAME), manifest.to_bytes())?;
+        Ok(manifest)
+    }
+}
+
+impl<const W: usize, UniverseMetadata, PartitionMetadata>
+    Clubcard<W, UniverseMetadata, PartitionMetadata>
+where
+    UniverseMetadata: Decode + PartialEq,
+    PartitionMetadata: Decode + PartialEq,
+{
+    /// Read the manifest at `manifest` and the shards that it lists from `dir`, and combine
+    /// them into one clubcard. Fails with ClubcardError::Malformed if a shard does not have the
+    /// length or the blocks that the manifest says it has, and with ClubcardError::CorruptBlock
+    /// if a block does not match its checksum, in which case only the shard that holds the block
+    /// (see shard_of) needs to be fetched again.
+    pub fn open_sharded(
+        manifest: impl AsRef<Path>,
+        dir: impl AsRef<Path>,
+    ) -> Result<Self, ClubcardError> {
+        let manifest = ShardManifest::from_bytes(&fs::read(manifest)?)?;
+        let mut parts = vec![];
+        for entry in &manifest.shards {
+            let bytes = fs::read(dir.as_ref().join(&entry.name))?;
+            if bytes.len() as u64 != entry.len {
+                return Err(ClubcardError::Malformed);
+            }
+            let part = Self::from_bytes(&bytes)?;
+            if !part.index.keys().eq(entry.blocks.iter()) {
+                return Err(ClubcardError::Malformed);
+            }
+            part.validate()?;
+            parts.push(part);
+        }
+        Self::concat(parts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_vectors::{generated, W};
+
+    #[test]
+    fn test_manifest_names() {
+        for (name, ok) in [
+            ("shard-0.bin", true),
+            ("", false),
+            (".", false),
+            ("..", false),
+            ("../shard-0.bin", false),
+            ("./shard-0.bin", false),
+            ("/etc/passwd", false),
+            ("shards/shard-0.bin", false),
+        ] {
+            let manifest = ShardManifest {
+                shards: vec![ShardManifestEntry {
+                    name: name.to_string(),
+                    len: 0,
+                    blocks: vec![],
+                }],
+            };
+            let expected = match ok {
+                true => Ok(manifest.clone()),
+                false => Err(ClubcardError::Malformed),
+            };
+            assert!(ShardManifest::from_bytes(&manifest.to_bytes()) == expected);
+        }
+    }
+
+    #[test]
+    fn test_sharded() {
+        let (clubcard, queries) = generated(0);
+
+        let dir = std::env::temp_dir().join(format!("clubcard-shards-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        assert!(matches!(
+            clubcard.write_sharded(&dir, 0),
+            Err(ClubcardError::InvalidArgument(_))
+        ));
+        assert!(std::fs::read_dir(&dir).unwrap().next().is_none());
+        let manifest = clubcard.write_sharded(&dir, 3).unwrap();
+        assert!(manifest.shards.len() == 3);
+        let sharded = Clubcard::<W, u32, ()>::open_sharded(dir.join(MANIFEST_FILE_NAME), &dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let sharded = sharded.unwrap();
+        assert!(sharded.validate().is_ok());
+        for (query, expected) in &queries {
+            assert_eq!(sharded.contains(query), *expected);
+        }
+    }
+}
@@ -0,0 +1,146 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use crate::encoding::{Decode, Encode};
+use crate::{Clubcard, ClubcardError};
+use std::collections::BTreeMap;
+
+/// The version of the MembersSidecar encoding. This is independent of CLUBCARD_VERSION, since
+/// the sidecar is published separately.
+pub const SIDECAR_VERSION: u16 = 0xffff;
+
+/// The discriminants of the members of each block of a clubcard. A clubcard cannot enumerate
+/// its members, so a builder can optionally retain them (see
+/// ClubcardBuilder::set_retain_members) for auditors who need to reconcile a clubcard against
+/// its source data. The sidecar is not part of the clubcard's serialization; it is published
+/// separately with MembersSidecar::to_bytes.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MembersSidecar {
+    /// Sorted, deduplicated discriminants of the members of each block.
+    pub(crate) blocks: BTreeMap<Vec<u8>, Vec<Vec<u8>>>,
+}
+
+impl MembersSidecar {
+    /// The blocks whose members were retained.
+    pub fn blocks(&self) -> impl Iterator<Item = &[u8]> {
+        self.blocks.keys().map(|block| &block[..])
+    }
+
+    /// The sorted discriminants of the members of `block`. Returns None if the members of the
+    /// block were not retained.
+    pub fn members(&self, block: &[u8]) -> Option<&[Vec<u8>]> {
+        self.blocks.get(block).map(|members| &members[..])
+    }
+
+    /// Whether the item with `discriminant` is a member of `block`. Returns None if the members
+    /// of the block were not retained.
+    pub fn contains(&self, block: &[u8], discriminant: &[u8]) -> Option<bool> {
+        self.members(block).map(|members| {
+            members
+                .binary_search_by(|member| member[..].cmp(discriminant))
+                .is_ok()
+        })
+    }
+
+    #[cfg(feature = "builder")]
+    pub(crate) fn insert(&mut self, block: Vec<u8>, mut members: Vec<Vec<u8>>) {
+        members.sort_unstable();
+        members.dedup();
+        self.blocks.insert(block, members);
+    }
+
+    /// Serialize this sidecar. The output starts with a two byte little-endian version
+    /// (SIDECAR_VERSION) followed by the map from blocks to members.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = vec![];
+        SIDECAR_VERSION.encode(&mut out);
+        self.blocks.encode(&mut out);
+        out
+    }
+
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<Self, ClubcardError> {
+        let bytes = &mut bytes;
+        let version = u16::decode(bytes)?;
+        if version != SIDECAR_VERSION {
+            return Err(ClubcardError::UnsupportedVersion(version));
+        }
+        let blocks: BTreeMap<Vec<u8>, Vec<Vec<u8>>> = Decode::decode(bytes)?;
+        if !bytes.is_empty()
+            || blocks
+                .values()
+                .any(|members| !members.windows(2).all(|pair| pair[0] < pair[1]))
+        {
+            return Err(ClubcardError::Malformed);
+        }
+        Ok(MembersSidecar { blocks })
+    }
+}
+
+impl<const W: usize, UniverseMetadata, PartitionMetadata>
+    Clubcard<W, UniverseMetadata, PartitionMetadata>
+{
+    /// The members of this clubcard's blocks, if they were retained by the builder or attached
+    /// with with_members_sidecar.
+    pub fn members_sidecar(&self) -> Option<&MembersSidecar> {
+        self.members.as_ref()
+    }
+
+    /// Attach a sidecar, e.g. one read with MembersSidecar::from_bytes, to this clubcard. Fails
+    /// with ClubcardError::InvalidBlock if the sidecar has a block that is not in the clubcard.
+    pub fn with_members_sidecar(mut self, sidecar: MembersSidecar) -> Result<Self, ClubcardError> {
+        if let Some(block) = sidecar
+            .blocks()
+            .find(|block| !self.index.contains_key(*block))
+        {
+            return Err(ClubcardError::InvalidBlock(block.to_vec()));
+        }
+        self.members = Some(sidecar);
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::tests::{build_from_source, test_items};
+    use crate::builder::ClubcardBuilder;
+    use crate::{AsQuery, Filterable};
+
+    #[test]
+    fn test_members_sidecar() {
+        let items = test_items(128);
+        let clubcard = build_from_source(ClubcardBuilder::new(), &items);
+        assert!(clubcard.members_sidecar().is_none());
+
+        let mut clubcard_builder = ClubcardBuilder::new();
+        clubcard_builder.set_retain_members(true);
+        let clubcard = build_from_source(clubcard_builder, &items);
+        let sidecar = clubcard.members_sidecar().unwrap();
+        for item in &items {
+            assert!(sidecar.contains(&[], item.discriminant()) == Some(item.included()));
+        }
+        assert!(sidecar.contains(&[0], items[0].discriminant()).is_none());
+
+        let bytes = sidecar.to_bytes();
+        assert!(bytes[..2] == SIDECAR_VERSION.to_le_bytes());
+        let copy = MembersSidecar::from_bytes(&bytes).unwrap();
+        assert!(&copy == sidecar);
+        let clubcard = Clubcard::<1, (), ()>::from_bytes(&clubcard.to_bytes()).unwrap();
+        assert!(clubcard.members_sidecar().is_none());
+        let clubcard = clubcard.with_members_sidecar(copy).unwrap();
+        assert!(
+            clubcard
+                .export_blocks([])
+                .members_sidecar()
+                .unwrap()
+                .blocks()
+                .count()
+                == 0
+        );
+
+        let mut other = MembersSidecar::default();
+        other.insert(vec![0], vec![]);
+        assert!(clubcard.with_members_sidecar(other) == Err(ClubcardError::InvalidBlock(vec![0])));
+    }
+}
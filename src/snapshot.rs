@@ -0,0 +1,135 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use crate::{Clubcard, ClubcardError, Membership, Queryable};
+use std::sync::Arc;
+
+/// The last completed build of a clubcard, together with deltas that replace or add some of
+/// its blocks, as in Clubcard::replace_blocks. Queries are answered by the newest delta that
+/// has the item's block, and by the base otherwise.
+///
+/// This lets a service that rebuilds only the changed blocks (see
+/// ClubcardBuilder::set_block_filter) start answering from the new data as soon as the delta
+/// is built. The base is shared through an Arc rather than copied, so while the previous
+/// snapshot is still in use, the process holds one copy of the unchanged blocks and the
+/// changed blocks twice, rather than two full clubcards. Snapshots are immutable; swap them
+/// behind a lock and merge them with compact when convenient.
+pub struct BuilderSnapshot<const W: usize, UniverseMetadata, PartitionMetadata> {
+    base: Arc<Clubcard<W, UniverseMetadata, PartitionMetadata>>,
+    /// Oldest first.
+    deltas: Vec<Arc<Clubcard<W, UniverseMetadata, PartitionMetadata>>>,
+}
+
+impl<const W: usize, UniverseMetadata, PartitionMetadata> Clone
+    for BuilderSnapshot<W, UniverseMetadata, PartitionMetadata>
+{
+    fn clone(&self) -> Self {
+        BuilderSnapshot {
+            base: self.base.clone(),
+            deltas: self.deltas.clone(),
+        }
+    }
+}
+
+impl<const W: usize, UniverseMetadata, PartitionMetadata>
+    BuilderSnapshot<W, UniverseMetadata, PartitionMetadata>
+where
+    UniverseMetadata: Clone + PartialEq,
+    PartitionMetadata: Clone + PartialEq,
+{
+    pub fn new(base: Arc<Clubcard<W, UniverseMetadata, PartitionMetadata>>) -> Self {
+        BuilderSnapshot {
+            base,
+            deltas: vec![],
+        }
+    }
+
+    pub fn base(&self) -> &Arc<Clubcard<W, UniverseMetadata, PartitionMetadata>> {
+        &self.base
+    }
+
+    /// The number of deltas on top of the base.
+    pub fn deltas(&self) -> usize {
+        self.deltas.len()
+    }
+
+    /// A snapshot with `delta` on top of this one, sharing this snapshot's base and deltas.
+    /// The delta must have the same universe metadata, partition metadata, and build
+    /// configuration as the base (see Clubcard::replace_blocks); fails with
+    /// ClubcardError::Malformed otherwise.
+    pub fn with_delta(
+        &self,
+        delta: Clubcard<W, UniverseMetadata, PartitionMetadata>,
+    ) -> Result<Self, ClubcardError> {
+        if delta.universe != self.base.universe
+            || delta.partition != self.base.partition
+            || delta.build_config != self.base.build_config
+        {
+            return Err(ClubcardError::Malformed);
+        }
+        let mut out = self.clone();
+        out.deltas.push(Arc::new(delta));
+        Ok(out)
+    }
+
+    /// The clubcard that holds `block`: the newest delta that has it, or the base.
+    fn layer(&self, block: &[u8]) -> &Clubcard<W, UniverseMetadata, PartitionMetadata> {
+        self.deltas
+            .iter()
+            .rev()
+            .find(|delta| delta.index.contains_key(block))
+            .unwrap_or(&self.base)
+    }
+
+    pub fn contains<T>(&self, item: &T) -> Membership
+    where
+        T: Queryable<W, UniverseMetadata = UniverseMetadata, PartitionMetadata = PartitionMetadata>,
+    {
+        self.layer(item.block()).contains(item)
+    }
+
+    /// Merge the deltas into the base, e.g. to serialize the result or to release the blocks
+    /// that the deltas replaced.
+    pub fn compact(
+        &self,
+    ) -> Result<Clubcard<W, UniverseMetadata, PartitionMetadata>, ClubcardError> {
+        let mut out = self
+            .base
+            .export_blocks(self.base.index.keys().map(|x| &x[..]));
+        for delta in &self.deltas {
+            out = out.replace_blocks(delta.export_blocks(delta.index.keys().map(|x| &x[..])))?;
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_vectors::{generated, rebuild_block_1};
+
+    #[test]
+    fn test_builder_snapshot() {
+        let (base, queries) = generated(0);
+        let base = Arc::new(base);
+        let (replacement, block_1) = rebuild_block_1(&base, &queries);
+
+        let old = BuilderSnapshot::new(base.clone());
+        let new = old.with_delta(replacement).unwrap();
+        assert!(new.deltas() == 1 && Arc::ptr_eq(new.base(), &base));
+        let compacted = new.compact().unwrap();
+        for (query, expected) in &queries {
+            assert!(old.contains(query) == *expected);
+            let expected = match block_1.contains(query) {
+                true => Membership::Member,
+                false => *expected,
+            };
+            assert!(new.contains(query) == expected);
+            assert!(compacted.contains(query) == expected);
+        }
+
+        let (mismatched, _) = generated(1);
+        assert!(old.with_delta(mismatched).err() == Some(ClubcardError::Malformed));
+    }
+}
@@ -0,0 +1,198 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use crate::cipher::FilterColumn;
+use crate::clubcard::{query_block, ClubcardIndex};
+use crate::config::BuildConfig;
+use crate::encoding::{take, Decode};
+use crate::{
+    Clubcard, ClubcardError, ClubcardHeader, Equation, Membership, Queryable, MAX_SERIALIZED_LEN,
+    MAX_WIDTH,
+};
+use std::ops::Range;
+
+/// A serialized clubcard that is queried in place, without copying the filters out of the
+/// buffer that holds them. The buffer can be any `S: AsRef<[u8]>`: a `Vec<u8>`, an `Arc<[u8]>`
+/// shared between threads, a memory map, or a wrapper around a buffer that is owned by foreign
+/// code. See Clubcard::from_storage.
+pub struct StoredClubcard<const W: usize, UniverseMetadata, PartitionMetadata, S> {
+    universe: UniverseMetadata,
+    partition: PartitionMetadata,
+    build_config: BuildConfig,
+    index: ClubcardIndex,
+    /// The byte ranges of the words of each column of X in the storage.
+    approx_columns: Vec<Range<usize>>,
+    /// The byte range of the words of Y in the storage.
+    exact_column: Range<usize>,
+    storage: S,
+}
+
+impl<const W: usize, UniverseMetadata, PartitionMetadata>
+    Clubcard<W, UniverseMetadata, PartitionMetadata>
+where
+    UniverseMetadata: Decode,
+    PartitionMetadata: Decode,
+{
+    /// Read the header and the index of the clubcard serialized in `storage` with
+    /// Clubcard::to_bytes, and leave the filters where they are. The index entries are checked
+    /// against the number of columns in X, and queries read bits past the end of a column as
+    /// zero, so queries are safe on any input. Checksums are not checked; use
+    /// Clubcard::from_bytes and Clubcard::validate for that. Clubcards in the first version of
+    /// the format are not supported.
+    pub fn from_storage<S: AsRef<[u8]>>(
+        storage: S,
+    ) -> Result<StoredClubcard<W, UniverseMetadata, PartitionMetadata, S>, ClubcardError> {
+        let bytes = storage.as_ref();
+        if bytes.len() as u64 > MAX_SERIALIZED_LEN {
+            return Err(ClubcardError::LimitExceeded);
+        }
+        let mut rest = bytes;
+        let header = ClubcardHeader::decode(&mut rest)?;
+        header.check_width::<W>()?;
+
+        let column = |rest: &mut &[u8]| -> Result<Range<usize>, ClubcardError> {
+            let len = usize::decode(rest)?
+                .checked_mul(8)
+                .ok_or(ClubcardError::Malformed)?;
+            let start = bytes.len() - rest.len();
+            take(rest, len)?;
+            Ok(start..start + len)
+        };
+        let mut approx_columns = vec![];
+        for _ in 0..usize::decode(&mut rest)? {
+            approx_columns.push(column(&mut rest)?);
+        }
+        let exact_column = column(&mut rest)?;
+        if !rest.is_empty() {
+            return Err(ClubcardError::Malformed);
+        }
+        if let Some((block, _)) = header
+            .index
+            .iter()
+            .find(|(_, meta)| meta.approx_filter_rank > approx_columns.len())
+        {
+            return Err(ClubcardError::InvalidBlock(block.clone()));
+        }
+
+        Ok(StoredClubcard {
+            universe: header.universe,
+            partition: header.partition,
+            build_config: header.build_config,
+            index: header.index,
+            approx_columns,
+            exact_column,
+            storage,
+        })
+    }
+}
+
+/// The value of `query` on the column of X or Y whose words are `column`.
+fn eval_bytes<const W: usize>(query: &Equation<W>, column: &[u8]) -> u8 {
+    // The equation depends on W + 1 words of the column, starting from the one that holds bit
+    // query.s. Copy them out, since the storage need not be aligned.
+    let mut words = [0u64; MAX_WIDTH + 1];
+    let first = query.s / 64;
+    let len = (W + 1).min((column.len() / 8).saturating_sub(first));
+    for (i, word) in words[..len].iter_mut().enumerate() {
+        let start = 8 * (first + i);
+        *word = u64::from_le_bytes(column[start..start + 8].try_into().unwrap());
+    }
+    let mut local = query.clone();
+    local.s %= 64;
+    local.eval(&words[..len])
+}
+
+impl<const W: usize, UniverseMetadata, PartitionMetadata, S>
+    StoredClubcard<W, UniverseMetadata, PartitionMetadata, S>
+where
+    S: AsRef<[u8]>,
+{
+    pub fn universe(&self) -> &UniverseMetadata {
+        &self.universe
+    }
+
+    pub fn partition(&self) -> &PartitionMetadata {
+        &self.partition
+    }
+
+    pub fn build_config(&self) -> &BuildConfig {
+        &self.build_config
+    }
+
+    pub fn index(&self) -> &ClubcardIndex {
+        &self.index
+    }
+
+    pub fn storage(&self) -> &S {
+        &self.storage
+    }
+
+    pub fn into_storage(self) -> S {
+        self.storage
+    }
+
+    /// Same as Clubcard::contains.
+    pub fn contains<T>(&self, item: &T) -> Membership
+    where
+        T: Queryable<W, UniverseMetadata = UniverseMetadata, PartitionMetadata = PartitionMetadata>,
+    {
+        if !item.in_universe(&self.universe) {
+            return Membership::NotInUniverse;
+        }
+        let Some(meta) = self.index.get(item.block()) else {
+            return Membership::NoData;
+        };
+        let bytes = self.storage.as_ref();
+        query_block(meta, item, |column, query| {
+            let range = match column {
+                FilterColumn::Approx(i) => &self.approx_columns[i],
+                FilterColumn::Exact => &self.exact_column,
+            };
+            eval_bytes(query, &bytes[range.clone()])
+        })
+        .0
+        .into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_vectors::{generate, UNIVERSE_BOUND, W};
+
+    #[test]
+    fn test_from_storage() {
+        let (bytes, queries) = generate(0);
+        let shared: std::sync::Arc<[u8]> = bytes.clone().into();
+        let stored = Clubcard::<W, u32, ()>::from_storage(shared).unwrap();
+        let borrowed = Clubcard::<W, u32, ()>::from_storage(&bytes[..]).unwrap();
+        assert!(*stored.universe() == UNIVERSE_BOUND);
+        for (query, expected) in &queries {
+            assert!(stored.contains(query) == *expected);
+            assert!(borrowed.contains(query) == *expected);
+        }
+
+        // The storage need not be aligned.
+        let mut unaligned = vec![0u8];
+        unaligned.extend_from_slice(&bytes);
+        let stored = Clubcard::<W, u32, ()>::from_storage(&unaligned[1..]).unwrap();
+        for (query, expected) in &queries {
+            assert!(stored.contains(query) == *expected);
+        }
+
+        for len in [bytes.len() - 1, bytes.len() - 8, 100] {
+            assert!(
+                Clubcard::<W, u32, ()>::from_storage(&bytes[..len]).err()
+                    == Clubcard::<W, u32, ()>::from_bytes(&bytes[..len]).err()
+            );
+        }
+        assert!(
+            Clubcard::<2, u32, ()>::from_storage(&bytes[..]).err()
+                == Some(ClubcardError::WidthMismatch {
+                    expected: 2,
+                    found: W
+                })
+        );
+    }
+}
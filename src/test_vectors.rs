@@ -0,0 +1,270 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Known-answer test vectors for independent implementations of the clubcard format.
+//!
+//! The filter produced by `generate` has W = 4, universe metadata equal to a u32 bound (encoded
+//! as 4 little-endian bytes), and no partition metadata. Block identifiers are one byte long
+//! and discriminants are u32s encoded as 4 little-endian bytes. An item (block, discriminant) is
+//! in the universe iff discriminant < bound.
+//!
+//! To hash an item to an equation for a ribbon with m rows, let d = SHA-512(block ||
+//! discriminant), let a\[i\] be the little-endian u64 in bytes 8i..8i+8 of d for i < 4, set the
+//! low bit of a\[0\], and let s be the little-endian u64 in bytes 32..40 of d, mod max(1, m).
+
+#[cfg(feature = "test-vectors")]
+use crate::builder::{ApproximateRibbon, ClubcardBuilder, ExactRibbon};
+use crate::encoding::{Decode, Encode};
+#[cfg(test)]
+use crate::Clubcard;
+use crate::{AsQuery, ClubcardError, Equation, Queryable};
+#[cfg(feature = "test-vectors")]
+use crate::{Filterable, Membership};
+#[cfg(feature = "test-vectors")]
+use rand::{Rng, SeedableRng};
+#[cfg(feature = "test-vectors")]
+use rand_chacha::ChaCha20Rng;
+use sha2::{Digest, Sha512};
+
+pub(crate) const W: usize = 4;
+
+/// Upper bound (exclusive) on the discriminants in the universe.
+#[cfg(feature = "test-vectors")]
+pub(crate) const UNIVERSE_BOUND: u32 = 256;
+
+/// (universe size, probability of membership) for each block.
+#[cfg(feature = "test-vectors")]
+pub(crate) const BLOCKS: [(u32, f64); 4] = [(256, 0.05), (128, 0.5), (64, 1.0), (64, 0.0)];
+
+fn hash_to_equation(block: &[u8], discriminant: &[u8], m: usize) -> Equation<W> {
+    let mut hasher = Sha512::new();
+    hasher.update(block);
+    hasher.update(discriminant);
+    Equation::from_digest(&hasher.finalize(), m).unwrap()
+}
+
+/// A query against the test vector filter.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TestVectorQuery {
+    pub block: [u8; 1],
+    pub discriminant: [u8; 4],
+}
+
+impl TestVectorQuery {
+    #[cfg(feature = "test-vectors")]
+    pub(crate) fn new(block: u8, discriminant: u32) -> Self {
+        TestVectorQuery {
+            block: [block],
+            discriminant: discriminant.to_le_bytes(),
+        }
+    }
+}
+
+impl AsQuery<W> for TestVectorQuery {
+    fn as_query(&self, m: usize) -> Equation<W> {
+        hash_to_equation(&self.block, &self.discriminant, m)
+    }
+
+    fn block(&self) -> &[u8] {
+        &self.block
+    }
+
+    fn discriminant(&self) -> &[u8] {
+        &self.discriminant
+    }
+}
+
+impl Queryable<W> for TestVectorQuery {
+    type UniverseMetadata = u32;
+    type PartitionMetadata = ();
+
+    fn in_universe(&self, bound: &u32) -> bool {
+        u32::from_le_bytes(self.discriminant) < *bound
+    }
+}
+
+impl Encode for TestVectorQuery {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.block.encode(out);
+        self.discriminant.encode(out);
+    }
+}
+
+impl Decode for TestVectorQuery {
+    fn decode(bytes: &mut &[u8]) -> Result<Self, ClubcardError> {
+        Ok(TestVectorQuery {
+            block: Decode::decode(bytes)?,
+            discriminant: Decode::decode(bytes)?,
+        })
+    }
+}
+
+#[cfg(feature = "test-vectors")]
+struct TestVectorItem {
+    query: TestVectorQuery,
+    included: bool,
+}
+
+#[cfg(feature = "test-vectors")]
+impl AsQuery<W> for TestVectorItem {
+    fn as_query(&self, m: usize) -> Equation<W> {
+        self.query.as_query(m)
+    }
+
+    fn block(&self) -> &[u8] {
+        self.query.block()
+    }
+
+    fn discriminant(&self) -> &[u8] {
+        self.query.discriminant()
+    }
+}
+
+#[cfg(feature = "test-vectors")]
+impl Filterable<W> for TestVectorItem {
+    fn included(&self) -> bool {
+        self.included
+    }
+}
+
+/// Deterministically generate a small serialized clubcard along with a list of queries and their
+/// expected results. The output depends only on `seed` and on the version of this crate.
+#[cfg(feature = "test-vectors")]
+pub fn generate(seed: u64) -> (Vec<u8>, Vec<(TestVectorQuery, Membership)>) {
+    let mut rng = ChaCha20Rng::seed_from_u64(seed);
+
+    let mut dataset = vec![];
+    for (block, (universe_size, p)) in BLOCKS.iter().enumerate() {
+        let members: Vec<bool> = (0..*universe_size).map(|_| rng.gen_bool(*p)).collect();
+        dataset.push((block as u8, members));
+    }
+
+    let mut builder = ClubcardBuilder::new_with_seed(rng.gen());
+
+    let mut approx_ribbons = vec![];
+    for (block, members) in &dataset {
+        let mut approx_builder = builder.new_approx_builder(&[*block]);
+        for (i, _) in members.iter().enumerate().filter(|(_, x)| **x) {
+            approx_builder.insert(TestVectorItem {
+                query: TestVectorQuery::new(*block, i as u32),
+                included: true,
+            });
+        }
+        approx_builder.set_universe_size(members.len());
+        approx_ribbons.push(ApproximateRibbon::from(approx_builder));
+    }
+    builder.collect_approx_ribbons(approx_ribbons);
+
+    let mut exact_ribbons = vec![];
+    for (block, members) in &dataset {
+        let mut exact_builder = builder.new_exact_builder(&[*block]);
+        for (i, included) in members.iter().enumerate() {
+            exact_builder.insert(TestVectorItem {
+                query: TestVectorQuery::new(*block, i as u32),
+                included: *included,
+            });
+        }
+        exact_ribbons.push(ExactRibbon::from(exact_builder));
+    }
+    builder
+        .collect_exact_ribbons(exact_ribbons)
+        .expect("exact ribbons should match the declared universe sizes");
+
+    let clubcard = builder.build::<TestVectorQuery>(UNIVERSE_BOUND, ());
+
+    let mut queries = vec![];
+    for (block, members) in &dataset {
+        for (i, included) in members.iter().enumerate() {
+            queries.push((TestVectorQuery::new(*block, i as u32), (*included).into()));
+        }
+    }
+    queries.push((
+        TestVectorQuery::new(0, UNIVERSE_BOUND),
+        Membership::NotInUniverse,
+    ));
+    queries.push((
+        TestVectorQuery::new(BLOCKS.len() as u8, 0),
+        Membership::NoData,
+    ));
+
+    (clubcard.to_bytes(), queries)
+}
+
+/// The clubcard that generate(seed) serializes, and its queries, for the tests of other
+/// modules.
+#[cfg(test)]
+pub(crate) fn generated(seed: u64) -> (Clubcard<W, u32, ()>, Vec<(TestVectorQuery, Membership)>) {
+    let (bytes, queries) = generate(seed);
+    (Clubcard::from_bytes(&bytes).unwrap(), queries)
+}
+
+/// A clubcard with block 1 of `clubcard` rebuilt with every item included, and the items of
+/// block 1.
+#[cfg(test)]
+pub(crate) fn rebuild_block_1(
+    clubcard: &Clubcard<W, u32, ()>,
+    queries: &[(TestVectorQuery, Membership)],
+) -> (Clubcard<W, u32, ()>, Vec<TestVectorQuery>) {
+    let block_1: Vec<TestVectorQuery> = queries
+        .iter()
+        .map(|(query, _)| query.clone())
+        .filter(|query| query.block == [1] && query.in_universe(&UNIVERSE_BOUND))
+        .collect();
+    let item = |query: &TestVectorQuery| TestVectorItem {
+        query: query.clone(),
+        included: true,
+    };
+    let mut builder = ClubcardBuilder::new_with_config(clubcard.build_config().clone());
+    let mut approx_builder = builder.new_approx_builder(&[1]);
+    for query in &block_1 {
+        approx_builder.insert(item(query));
+    }
+    approx_builder.set_universe_size(block_1.len());
+    builder.collect_approx_ribbons(vec![ApproximateRibbon::from(approx_builder)]);
+    let mut exact_builder = builder.new_exact_builder(&[1]);
+    for query in &block_1 {
+        exact_builder.insert(item(query));
+    }
+    builder
+        .collect_exact_ribbons(vec![ExactRibbon::from(exact_builder)])
+        .unwrap();
+    let replacement = builder.build::<TestVectorQuery>(UNIVERSE_BOUND, ());
+    (replacement, block_1)
+}
+
+#[cfg(all(test, feature = "test-vectors"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_golden_bytes() {
+        let (bytes, _) = generate(0);
+        assert!(bytes == include_bytes!("../testdata/test_vectors_seed_0.bin"));
+    }
+
+    #[test]
+    fn test_fixtures() {
+        for fixture in crate::fixtures::all() {
+            let (bytes, queries) = generate(fixture.seed);
+            assert!(fixture.bytes == bytes);
+            assert!(fixture.answers() == queries);
+            let clubcard = fixture.clubcard();
+            for (query, expected) in &queries {
+                assert!(clubcard.contains(query) == *expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_expected_results() {
+        for seed in 0..4 {
+            let (bytes, queries) = generate(seed);
+            assert!(bytes == generate(seed).0);
+            let clubcard = Clubcard::<W, u32, ()>::from_bytes(&bytes).unwrap();
+            for (query, expected) in &queries {
+                assert_eq!(clubcard.contains(query), *expected);
+            }
+        }
+    }
+}
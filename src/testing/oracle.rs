@@ -0,0 +1,194 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Differential testing against a HashSet.
+//!
+//! check_against_oracle builds a clubcard from a universe of items and compares its answers
+//! with the set of (block, discriminant) pairs of the included items. Downstream crates can run
+//! it on samples of their own items to validate their Filterable and Queryable implementations.
+//! random_universe generates items of type OracleItem, whose hashes are random.
+
+use crate::builder::ClubcardBuilder;
+use crate::{AsQuery, Equation, Filterable, Membership, Queryable};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::collections::{BTreeMap, HashSet};
+
+/// A query on which the clubcard and the oracle disagree.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Disagreement {
+    pub block: Vec<u8>,
+    pub discriminant: Vec<u8>,
+    /// The oracle's answer.
+    pub expected: Membership,
+    /// The clubcard's answer, from contains or, for items in the universe, from
+    /// unchecked_contains.
+    pub actual: Membership,
+}
+
+/// The outcome of check_against_oracle.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OracleReport {
+    /// The number of items that were queried.
+    pub checked: usize,
+    pub disagreements: Vec<Disagreement>,
+}
+
+impl OracleReport {
+    pub fn is_ok(&self) -> bool {
+        self.disagreements.is_empty()
+    }
+}
+
+/// Build a clubcard, with a builder seeded with `seed`, from the items of `items` that are in
+/// `universe`, and query it with every item of `items`, or with `samples` items chosen at
+/// random if `samples` is not None. The expected answer is NotInUniverse for items that are not
+/// in `universe`, and otherwise Member if and only if the item is included. The (block,
+/// discriminant) pairs of the items must be distinct.
+pub fn check_against_oracle<const W: usize, T>(
+    items: &[T],
+    universe: T::UniverseMetadata,
+    partition: T::PartitionMetadata,
+    seed: u64,
+    samples: Option<usize>,
+) -> OracleReport
+where
+    T: Filterable<W> + Queryable<W> + Clone,
+{
+    let mut oracle = HashSet::new();
+    let mut source: BTreeMap<Vec<u8>, Vec<T>> = BTreeMap::new();
+    for item in items.iter().filter(|item| item.in_universe(&universe)) {
+        if item.included() {
+            oracle.insert((item.block(), item.discriminant()));
+        }
+        source
+            .entry(item.block().to_vec())
+            .or_default()
+            .push(item.clone());
+    }
+
+    let mut builder = ClubcardBuilder::new_with_seed(seed);
+    builder.collect_ribbons_from_source(&source).unwrap();
+    let clubcard = builder.build::<T>(universe, partition);
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    let queries: Vec<&T> = match samples {
+        Some(samples) => (0..samples)
+            .filter_map(|_| items.choose(&mut rng))
+            .collect(),
+        None => items.iter().collect(),
+    };
+
+    let mut report = OracleReport::default();
+    for item in queries {
+        report.checked += 1;
+        let expected = if !item.in_universe(clubcard.universe()) {
+            Membership::NotInUniverse
+        } else {
+            oracle.contains(&(item.block(), item.discriminant())).into()
+        };
+        let mut actual = clubcard.contains(item);
+        if actual == expected && expected != Membership::NotInUniverse {
+            actual = clubcard.unchecked_contains(item).into();
+        }
+        if actual != expected {
+            report.disagreements.push(Disagreement {
+                block: item.block().to_vec(),
+                discriminant: item.discriminant().to_vec(),
+                expected,
+                actual,
+            });
+        }
+    }
+    report
+}
+
+/// An item with a random hash, generated by random_universe. It is in the universe if its
+/// timestamp is less than the universe metadata.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OracleItem<const W: usize> {
+    pub block: Vec<u8>,
+    pub discriminant: Vec<u8>,
+    pub included: bool,
+    pub timestamp: u64,
+    pub(crate) s: u64,
+    pub(crate) a: [u64; W],
+}
+
+impl<const W: usize> AsQuery<W> for OracleItem<W> {
+    fn as_query(&self, m: usize) -> Equation<W> {
+        Equation::homogeneous((self.s % m as u64) as usize, self.a)
+    }
+
+    fn block(&self) -> &[u8] {
+        &self.block
+    }
+
+    fn discriminant(&self) -> &[u8] {
+        &self.discriminant
+    }
+}
+
+impl<const W: usize> Filterable<W> for OracleItem<W> {
+    fn included(&self) -> bool {
+        self.included
+    }
+}
+
+impl<const W: usize> Queryable<W> for OracleItem<W> {
+    type UniverseMetadata = u64;
+    type PartitionMetadata = ();
+
+    fn in_universe(&self, bound: &u64) -> bool {
+        self.timestamp < *bound
+    }
+}
+
+/// `block_sizes.len()` blocks, where block i has block_sizes\[i\] items, each included with
+/// probability `p` and with a timestamp in 0..100.
+pub fn random_universe<const W: usize>(
+    seed: u64,
+    block_sizes: &[usize],
+    p: f64,
+) -> Vec<OracleItem<W>> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut items = vec![];
+    for (block, size) in block_sizes.iter().enumerate() {
+        for i in 0..*size {
+            let mut a = [0u64; W];
+            rng.fill(&mut a[..]);
+            a[0] |= 1;
+            items.push(OracleItem {
+                block: (block as u32).to_le_bytes().to_vec(),
+                discriminant: (i as u64).to_le_bytes().to_vec(),
+                included: rng.gen_bool(p),
+                timestamp: rng.gen_range(0..100),
+                s: rng.gen(),
+                a,
+            });
+        }
+    }
+    items
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_oracle() {
+        let items = random_universe::<2>(0, &[0, 1, 100, 1000], 0.1);
+        for bound in [0, 50, 100] {
+            let report = check_against_oracle(&items, bound, (), 0, None);
+            assert!(report.checked == items.len());
+            assert!(report.is_ok());
+        }
+        for p in [0.0, 1.0] {
+            let items = random_universe::<1>(1, &[10, 100], p);
+            assert!(check_against_oracle(&items, 100, (), 1, Some(50)).checked == 50);
+            assert!(check_against_oracle(&items, 100, (), 1, Some(50)).is_ok());
+        }
+    }
+}
@@ -0,0 +1,79 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Reproducible datasets for scale testing, shaped like certificate revocation data.
+//!
+//! Each block is a 32 byte issuer identifier (e.g. a hash of the issuer's public key), and each
+//! discriminant is a serial number: a positive DER integer of 8 to 20 bytes without leading
+//! zeros. Since every item is also a query, a dataset can be fed to a builder and then replayed
+//! against the clubcard.
+
+use super::oracle::OracleItem;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::HashSet;
+
+/// `blocks * items_per_block` items, block by block, each included with probability
+/// `member_rate` and with a timestamp in 0..100 (see OracleItem). The serial numbers within a
+/// block are distinct. The same arguments always produce the same items, so results obtained
+/// with the same dataset are comparable.
+pub fn dataset<const W: usize>(
+    seed: u64,
+    blocks: usize,
+    items_per_block: usize,
+    member_rate: f64,
+) -> impl Iterator<Item = OracleItem<W>> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    (0..blocks).flat_map(move |_| {
+        let mut issuer = vec![0u8; 32];
+        rng.fill(&mut issuer[..]);
+        let mut serials = HashSet::with_capacity(items_per_block);
+        let mut items = Vec::with_capacity(items_per_block);
+        while items.len() < items_per_block {
+            let mut serial = vec![0u8; rng.gen_range(8..=20)];
+            rng.fill(&mut serial[..]);
+            serial[0] = rng.gen_range(1..0x80);
+            if !serials.insert(serial.clone()) {
+                continue;
+            }
+            let mut a = [0u64; W];
+            rng.fill(&mut a[..]);
+            a[0] |= 1;
+            items.push(OracleItem {
+                block: issuer.clone(),
+                discriminant: serial,
+                included: rng.gen_bool(member_rate),
+                timestamp: rng.gen_range(0..100),
+                s: rng.gen(),
+                a,
+            });
+        }
+        items
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::oracle::check_against_oracle;
+
+    #[test]
+    fn test_dataset() {
+        let items: Vec<OracleItem<2>> = dataset(0, 3, 200, 0.1).collect();
+        assert!(items == dataset(0, 3, 200, 0.1).collect::<Vec<_>>());
+        assert!(items != dataset(1, 3, 200, 0.1).collect::<Vec<_>>());
+        assert!(items.len() == 600);
+        for block in items.chunks(200) {
+            assert!(block.iter().all(|item| item.block == block[0].block));
+            assert!(block[0].block.len() == 32);
+            let serials: HashSet<_> = block.iter().map(|item| &item.discriminant).collect();
+            assert!(serials.len() == 200);
+        }
+        assert!(items
+            .iter()
+            .all(|item| (8..=20).contains(&item.discriminant.len())
+                && (1..0x80).contains(&item.discriminant[0])));
+        assert!(check_against_oracle(&items, 50, (), 0, None).is_ok());
+    }
+}
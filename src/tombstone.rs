@@ -0,0 +1,120 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use crate::clubcard::ClubcardIndex;
+use crate::encoding::{Decode, Encode};
+use crate::{Clubcard, ClubcardError};
+
+/// The version of the TombstonePatch encoding. This is independent of CLUBCARD_VERSION, since
+/// patches are shipped on their own.
+pub const TOMBSTONE_VERSION: u16 = 0xffff;
+
+/// A list of blocks to mark as tombstoned, e.g. issuers that are pending removal from the next
+/// clubcard. A patch is much smaller than a rebuilt clubcard, so it can be shipped on its own
+/// and applied with Clubcard::apply_tombstones. Queries to a tombstoned block return
+/// NotInUniverse.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TombstonePatch {
+    blocks: Vec<Vec<u8>>,
+}
+
+impl TombstonePatch {
+    pub fn new(blocks: impl IntoIterator<Item = Vec<u8>>) -> Self {
+        let mut blocks: Vec<Vec<u8>> = blocks.into_iter().collect();
+        blocks.sort_unstable();
+        blocks.dedup();
+        TombstonePatch { blocks }
+    }
+
+    pub fn blocks(&self) -> impl Iterator<Item = &[u8]> {
+        self.blocks.iter().map(|block| &block[..])
+    }
+
+    /// Serialize this patch. The output starts with a two byte little-endian version
+    /// (TOMBSTONE_VERSION) followed by the list of blocks.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = vec![];
+        TOMBSTONE_VERSION.encode(&mut out);
+        self.blocks.encode(&mut out);
+        out
+    }
+
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<Self, ClubcardError> {
+        let bytes = &mut bytes;
+        let version = u16::decode(bytes)?;
+        if version != TOMBSTONE_VERSION {
+            return Err(ClubcardError::UnsupportedVersion(version));
+        }
+        let blocks: Vec<Vec<u8>> = Decode::decode(bytes)?;
+        if !bytes.is_empty() || !blocks.windows(2).all(|pair| pair[0] < pair[1]) {
+            return Err(ClubcardError::Malformed);
+        }
+        Ok(TombstonePatch { blocks })
+    }
+
+    pub(crate) fn apply(&self, index: &mut ClubcardIndex) -> Result<(), ClubcardError> {
+        if let Some(block) = self.blocks.iter().find(|b| !index.contains_key(*b)) {
+            return Err(ClubcardError::InvalidBlock(block.clone()));
+        }
+        for block in &self.blocks {
+            index.get_mut(block).unwrap().tombstoned = true;
+        }
+        Ok(())
+    }
+}
+
+impl<const W: usize, UniverseMetadata, PartitionMetadata>
+    Clubcard<W, UniverseMetadata, PartitionMetadata>
+{
+    /// Mark the blocks of `patch` as tombstoned. Fails, without marking any block, if a block
+    /// of the patch is not in the index. Tombstones are not written by to_bytes, so the patch
+    /// has to be applied again after the clubcard is deserialized.
+    pub fn apply_tombstones(&mut self, patch: &TombstonePatch) -> Result<(), ClubcardError> {
+        patch.apply(&mut self.index)
+    }
+
+    /// Whether `block` has been tombstoned by apply_tombstones.
+    pub fn is_tombstoned(&self, block: &[u8]) -> bool {
+        self.index.get(block).is_some_and(|meta| meta.tombstoned)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_vectors::{generated, W};
+    use crate::Membership;
+
+    #[test]
+    fn test_tombstones() {
+        let (mut clubcard, queries) = generated(0);
+        let patch = TombstonePatch::new([vec![2u8], vec![0u8], vec![2u8]]);
+        assert!(patch.blocks().eq([&[0u8][..], &[2u8][..]]));
+        let bytes = patch.to_bytes();
+        assert!(bytes[..2] == TOMBSTONE_VERSION.to_le_bytes());
+        let decoded = TombstonePatch::from_bytes(&bytes).unwrap();
+        assert!(decoded == patch);
+
+        let unknown = TombstonePatch::new([vec![1u8], vec![0xff]]);
+        assert!(
+            clubcard.apply_tombstones(&unknown) == Err(ClubcardError::InvalidBlock(vec![0xff]))
+        );
+        assert!(!clubcard.is_tombstoned(&[1]));
+
+        clubcard.apply_tombstones(&decoded).unwrap();
+        assert!(clubcard.is_tombstoned(&[0]) && !clubcard.is_tombstoned(&[1]));
+        for (query, expected) in &queries {
+            let expected = match query.block {
+                [0] | [2] => Membership::NotInUniverse,
+                _ => *expected,
+            };
+            assert!(clubcard.contains(query) == expected);
+            assert!(clubcard.contains_uniform_time(query) == expected);
+        }
+
+        // Tombstones are not serialized.
+        let reloaded = Clubcard::<W, u32, ()>::from_bytes(&clubcard.to_bytes()).unwrap();
+        assert!(!reloaded.is_tombstoned(&[0]));
+    }
+}
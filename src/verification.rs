@@ -0,0 +1,237 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use crate::encoding::{take, Decode, Encode};
+use crate::{Clubcard, ClubcardError, MembersSidecar, Membership, Queryable};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+/// The version of the VerificationLayer encoding. This is independent of CLUBCARD_VERSION,
+/// since the layer is published separately.
+pub const VERIFICATION_VERSION: u16 = 0xffff;
+
+/// A short hash of the members of each block of a clubcard, which Clubcard::contains_verified
+/// consults to turn a wrong answer from the filters into an error. Each member costs `bits`
+/// bits. Like MembersSidecar, the layer is not part of the clubcard's serialization; it is
+/// published separately with VerificationLayer::to_bytes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VerificationLayer {
+    bits: u8,
+    /// Sorted, deduplicated fingerprints of the members of each block.
+    blocks: BTreeMap<Vec<u8>, Vec<u64>>,
+}
+
+impl VerificationLayer {
+    /// Hash the members in `sidecar` (see ClubcardBuilder::set_retain_members) to fingerprints
+    /// of `bits` bits. Fails with ClubcardError::InvalidArgument unless 1 <= bits <= 64.
+    pub fn new(sidecar: &MembersSidecar, bits: u8) -> Result<Self, ClubcardError> {
+        if !(1..=64).contains(&bits) {
+            return Err(ClubcardError::InvalidArgument(
+                "fingerprints must have between 1 and 64 bits",
+            ));
+        }
+        let blocks = sidecar
+            .blocks
+            .iter()
+            .map(|(block, members)| {
+                let mut fingerprints: Vec<u64> = members
+                    .iter()
+                    .map(|discriminant| fingerprint(bits, block, discriminant))
+                    .collect();
+                fingerprints.sort_unstable();
+                fingerprints.dedup();
+                (block.clone(), fingerprints)
+            })
+            .collect();
+        Ok(VerificationLayer { bits, blocks })
+    }
+
+    pub fn bits(&self) -> u8 {
+        self.bits
+    }
+
+    /// Whether the fingerprint of (block, discriminant) is in the layer. This is true for every
+    /// member, and for a non-member with probability about members / 2^bits. Returns None if the
+    /// block is not in the layer.
+    pub fn contains(&self, block: &[u8], discriminant: &[u8]) -> Option<bool> {
+        let fingerprints = self.blocks.get(block)?;
+        Some(
+            fingerprints
+                .binary_search(&fingerprint(self.bits, block, discriminant))
+                .is_ok(),
+        )
+    }
+
+    /// Serialize this layer. The output starts with a two byte little-endian version
+    /// (VERIFICATION_VERSION) and the number of bits per fingerprint, followed by the number of
+    /// blocks and, for each block, its identifier, the number of fingerprints, and the
+    /// fingerprints as little-endian integers of ceil(bits / 8) bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let width = self.width();
+        let mut out = vec![];
+        VERIFICATION_VERSION.encode(&mut out);
+        self.bits.encode(&mut out);
+        self.blocks.len().encode(&mut out);
+        for (block, fingerprints) in &self.blocks {
+            block.encode(&mut out);
+            fingerprints.len().encode(&mut out);
+            for fingerprint in fingerprints {
+                out.extend_from_slice(&fingerprint.to_le_bytes()[..width]);
+            }
+        }
+        out
+    }
+
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<Self, ClubcardError> {
+        let bytes = &mut bytes;
+        let version = u16::decode(bytes)?;
+        if version != VERIFICATION_VERSION {
+            return Err(ClubcardError::UnsupportedVersion(version));
+        }
+        let bits = u8::decode(bytes)?;
+        if !(1..=64).contains(&bits) {
+            return Err(ClubcardError::Malformed);
+        }
+        let mut layer = VerificationLayer {
+            bits,
+            blocks: BTreeMap::new(),
+        };
+        let width = layer.width();
+        for _ in 0..usize::decode(bytes)? {
+            let block = Vec::<u8>::decode(bytes)?;
+            let len = usize::decode(bytes)?;
+            let data = take(
+                bytes,
+                len.checked_mul(width).ok_or(ClubcardError::Malformed)?,
+            )?;
+            let fingerprints: Vec<u64> = data
+                .chunks_exact(width)
+                .map(|chunk| {
+                    let mut buf = [0u8; 8];
+                    buf[..width].copy_from_slice(chunk);
+                    u64::from_le_bytes(buf)
+                })
+                .collect();
+            if fingerprints.iter().any(|x| bits < 64 && *x >> bits != 0)
+                || !fingerprints.windows(2).all(|pair| pair[0] < pair[1])
+                || layer.blocks.insert(block, fingerprints).is_some()
+            {
+                return Err(ClubcardError::Malformed);
+            }
+        }
+        if !bytes.is_empty() {
+            return Err(ClubcardError::Malformed);
+        }
+        Ok(layer)
+    }
+
+    /// The number of bytes used to serialize a fingerprint.
+    fn width(&self) -> usize {
+        (self.bits as usize).div_ceil(8)
+    }
+}
+
+/// The top `bits` bits of the first 8 bytes of SHA-256(len(block) || block || discriminant),
+/// with the length written as a little-endian u64.
+fn fingerprint(bits: u8, block: &[u8], discriminant: &[u8]) -> u64 {
+    let mut hasher = Sha256::new();
+    hasher.update((block.len() as u64).to_le_bytes());
+    hasher.update(block);
+    hasher.update(discriminant);
+    let digest = hasher.finalize();
+    u64::from_le_bytes(digest[..8].try_into().unwrap()) >> (64 - bits as u32)
+}
+
+impl<const W: usize, UniverseMetadata, PartitionMetadata>
+    Clubcard<W, UniverseMetadata, PartitionMetadata>
+{
+    /// A verification layer for the members retained by the builder. Returns Ok(None) if the
+    /// members were not retained. Fails as described in VerificationLayer::new.
+    pub fn verification_layer(&self, bits: u8) -> Result<Option<VerificationLayer>, ClubcardError> {
+        self.members_sidecar()
+            .map(|sidecar| VerificationLayer::new(sidecar, bits))
+            .transpose()
+    }
+
+    /// Same as contains, but checks Member and Nonmember results against `layer`. Fails with
+    /// ClubcardError::VerificationFailed if the filters report a member whose fingerprint is
+    /// not in the layer, which means that the clubcard or the layer is wrong, or a non-member
+    /// whose fingerprint is, which means the same unless the fingerprints collide (with
+    /// probability about members / 2^bits). Blocks that are not in the layer are not checked.
+    pub fn contains_verified<T>(
+        &self,
+        item: &T,
+        layer: &VerificationLayer,
+    ) -> Result<Membership, ClubcardError>
+    where
+        T: Queryable<W, UniverseMetadata = UniverseMetadata, PartitionMetadata = PartitionMetadata>,
+    {
+        let result = self.contains(item);
+        let expected = match result {
+            Membership::Member => true,
+            Membership::Nonmember => false,
+            _ => return Ok(result),
+        };
+        match layer.contains(item.block(), item.discriminant()) {
+            Some(found) if found != expected => {
+                Err(ClubcardError::VerificationFailed(item.block().to_vec()))
+            }
+            _ => Ok(result),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::tests::{build_from_source, test_items};
+    use crate::builder::ClubcardBuilder;
+    use crate::AsQuery;
+
+    #[test]
+    fn test_verification_layer() {
+        let items = test_items(128);
+        let clubcard = build_from_source(ClubcardBuilder::new(), &items);
+        assert!(clubcard.verification_layer(32) == Ok(None));
+
+        let mut clubcard_builder = ClubcardBuilder::new();
+        clubcard_builder.set_retain_members(true);
+        let clubcard = build_from_source(clubcard_builder, &items);
+        for bits in [0, 65] {
+            assert!(matches!(
+                clubcard.verification_layer(bits),
+                Err(ClubcardError::InvalidArgument(_))
+            ));
+        }
+        for bits in [1, 12, 64] {
+            let layer = clubcard.verification_layer(bits).unwrap().unwrap();
+            let bytes = layer.to_bytes();
+            assert!(bytes[..2] == VERIFICATION_VERSION.to_le_bytes());
+            assert!(VerificationLayer::from_bytes(&bytes).unwrap() == layer);
+        }
+        let layer = clubcard.verification_layer(32).unwrap().unwrap();
+        assert!(layer.to_bytes().len() < 32 + 4 * items.len());
+        for item in &items {
+            assert!(clubcard.contains_verified(item, &layer) == Ok(clubcard.contains(item)));
+        }
+
+        // A layer built from the wrong members catches the disagreement.
+        let mut sidecar = clubcard.members_sidecar().unwrap().clone();
+        let members = sidecar.blocks.get_mut(&vec![]).unwrap();
+        let removed = members.pop().unwrap();
+        let layer = VerificationLayer::new(&sidecar, 32).unwrap();
+        let item = items
+            .iter()
+            .find(|item| item.discriminant() == removed)
+            .unwrap();
+        assert!(
+            clubcard.contains_verified(item, &layer)
+                == Err(ClubcardError::VerificationFailed(vec![]))
+        );
+
+        let mut bytes = layer.to_bytes();
+        bytes[2] = 0;
+        assert!(VerificationLayer::from_bytes(&bytes) == Err(ClubcardError::Malformed));
+    }
+}
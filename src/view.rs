@@ -0,0 +1,102 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use crate::{Clubcard, Membership, Queryable};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Op {
+    And,
+    Or,
+}
+
+/// The intersection or union of two clubcards, evaluated at query time. See
+/// Clubcard::intersect_view and Clubcard::union_view.
+pub struct ClubcardView<'a, const W: usize, UniverseMetadata, PartitionMetadata> {
+    left: &'a Clubcard<W, UniverseMetadata, PartitionMetadata>,
+    right: &'a Clubcard<W, UniverseMetadata, PartitionMetadata>,
+    op: Op,
+}
+
+impl<const W: usize, UniverseMetadata, PartitionMetadata>
+    Clubcard<W, UniverseMetadata, PartitionMetadata>
+{
+    /// A view whose members are the items that are members of both this clubcard and `other`.
+    pub fn intersect_view<'a>(
+        &'a self,
+        other: &'a Self,
+    ) -> ClubcardView<'a, W, UniverseMetadata, PartitionMetadata> {
+        ClubcardView {
+            left: self,
+            right: other,
+            op: Op::And,
+        }
+    }
+
+    /// A view whose members are the items that are members of this clubcard or `other`.
+    pub fn union_view<'a>(
+        &'a self,
+        other: &'a Self,
+    ) -> ClubcardView<'a, W, UniverseMetadata, PartitionMetadata> {
+        ClubcardView {
+            left: self,
+            right: other,
+            op: Op::Or,
+        }
+    }
+}
+
+impl<const W: usize, UniverseMetadata, PartitionMetadata>
+    ClubcardView<'_, W, UniverseMetadata, PartitionMetadata>
+{
+    /// Query both clubcards and combine the results. Member and Nonmember are combined with
+    /// the view's operation. Any other result means that the clubcard has no answer for the
+    /// item, so the combination is only definite if the other clubcard's answer decides it on
+    /// its own (Nonmember for an intersection, Member for a union). Otherwise the result is the
+    /// first clubcard's answer if it has none, and the second clubcard's answer if not.
+    pub fn contains<T>(&self, item: &T) -> Membership
+    where
+        T: Queryable<W, UniverseMetadata = UniverseMetadata, PartitionMetadata = PartitionMetadata>,
+    {
+        combine(self.op, self.left.contains(item), self.right.contains(item))
+    }
+}
+
+fn combine(op: Op, left: Membership, right: Membership) -> Membership {
+    let absorbing = match op {
+        Op::And => Membership::Nonmember,
+        Op::Or => Membership::Member,
+    };
+    let definite = |x| matches!(x, Membership::Member | Membership::Nonmember);
+    if left == absorbing || right == absorbing {
+        absorbing
+    } else if !definite(left) {
+        left
+    } else {
+        // Either right has no answer, or both answers are the identity for op.
+        right
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Membership::*;
+
+    #[test]
+    fn test_combine() {
+        for (left, right, and, or) in [
+            (Member, Member, Member, Member),
+            (Member, Nonmember, Nonmember, Member),
+            (Nonmember, Nonmember, Nonmember, Nonmember),
+            (Member, NotInUniverse, NotInUniverse, Member),
+            (Nonmember, NotInUniverse, Nonmember, NotInUniverse),
+            (NoData, Member, NoData, Member),
+            (NoData, Nonmember, Nonmember, NoData),
+            (NoData, NotInUniverse, NoData, NoData),
+        ] {
+            assert_eq!(combine(Op::And, left, right), and);
+            assert_eq!(combine(Op::Or, left, right), or);
+        }
+    }
+}
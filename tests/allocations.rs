@@ -0,0 +1,47 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Checks that queries do not allocate. This is its own test binary because the counting
+//! allocator replaces the global allocator of every test in the binary.
+
+use clubcard::fixtures::SEED_0;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+/// Counts the allocations made by the current thread.
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+#[test]
+fn test_contains_does_not_allocate() {
+    let clubcard = SEED_0.clubcard();
+    let queries = SEED_0.answers();
+    let handle = clubcard.block_handle(&[1]).unwrap();
+    let before = ALLOCATIONS.with(|count| count.get());
+    for (query, expected) in &queries {
+        assert!(clubcard.contains(query) == *expected);
+        assert!(clubcard.contains_uniform_time(query) == *expected);
+        if query.block == [1] {
+            assert!(handle.contains(query) == *expected);
+        }
+    }
+    assert!(ALLOCATIONS.with(|count| count.get()) == before);
+}